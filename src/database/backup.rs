@@ -0,0 +1,186 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{backup::Backup, Connection};
+use tokio::{sync::watch, time::Duration};
+
+use crate::timing::uk_datetime_now::uk_datetime_now;
+
+/// How many pages are copied at a time. Between steps the database is unlocked, so the scraper
+/// never waits on a backup for longer than one step.
+const PAGES_PER_STEP: i32 = 256;
+
+/// How long to leave the database alone between steps.
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// Backups are named `data-<time>.db`, with a time that sorts the same as a string.
+const PREFIX: &str = "data-";
+const EXTENSION: &str = ".db";
+const TIME_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+/// A backup that has been written.
+pub struct BackupFile {
+    pub path: PathBuf,
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+/// Copies of the database in `dir`, made every `interval` and on demand through
+/// POST /admin/backup. Only the newest `keep` are kept.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    dir: PathBuf,
+    keep: usize,
+    /// 0 only makes backups on demand.
+    interval: Duration,
+    /// Held while a backup is made, so a scheduled and an on demand one can't write at once.
+    running: Arc<Mutex<()>>,
+}
+
+impl Backups {
+    pub fn new(dir: PathBuf, keep: usize, interval: Duration) -> Self {
+        Self {
+            dir,
+            keep: keep.max(1),
+            interval,
+            running: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Holds off backups until the returned guard is dropped. `None` if one is being made.
+    pub fn hold(&self) -> Option<MutexGuard<'_, ()>> {
+        self.running.try_lock().ok()
+    }
+
+    /// Makes a backup whenever the newest one is `interval` old, until `shutdown` is set. One is
+    /// made straight away if there are none yet.
+    pub async fn run(
+        self,
+        connection_pool: Arc<Pool<SqliteConnectionManager>>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        if self.interval.is_zero() {
+            return;
+        }
+        while !*shutdown.borrow() {
+            let due = self.interval.saturating_sub(self.newest_age());
+            tokio::select! {
+                _ = tokio::time::sleep(due) => (),
+                _ = shutdown.changed() => return,
+            }
+            let backups = self.clone();
+            let pool = connection_pool.clone();
+            let made = tokio::task::spawn_blocking(move || match pool.get() {
+                Ok(connection) => backups.backup(&connection),
+                Err(_) => Err("Could not get connection to back up the database.".to_string()),
+            })
+            .await;
+            match made {
+                Ok(Ok(file)) => println!(
+                    "Backed up the database to '{}', {} bytes.",
+                    file.path.display(),
+                    file.size
+                ),
+                Ok(Err(err)) => println!("Backing up the database failed.\n{}", err),
+                Err(err) => println!("Backing up the database failed.\n{}", err),
+            }
+        }
+    }
+
+    /**
+    Copies the database behind `connection` into a new file in `dir` and deletes all but the
+    newest `keep` backups.
+
+    The copy is made a few pages at a time with SQLite's online backup, so writes can happen in
+    between. A write from another connection makes it start over, which with a reading every few
+    minutes only costs a little time. It is written under a temporary name first, so a backup
+    cut short never looks like a finished one.
+    */
+    pub fn backup(&self, connection: &Connection) -> Result<BackupFile, String> {
+        let _running = self.running.lock().unwrap();
+        if let Err(err) = fs::create_dir_all(&self.dir) {
+            return Err(format!(
+                "Could not create '{}'.\n{}",
+                self.dir.display(),
+                err
+            ));
+        }
+
+        let name = format!(
+            "{}{}{}",
+            PREFIX,
+            uk_datetime_now().format(TIME_FORMAT),
+            EXTENSION
+        );
+        let path = self.dir.join(&name);
+        let partial = self.dir.join(name + ".partial");
+        let copied = Connection::open(&partial).and_then(|mut destination| {
+            let backup = Backup::new(connection, &mut destination)?;
+            backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)
+        });
+        if let Err(err) = copied {
+            let _ = fs::remove_file(&partial);
+            return Err(format!("Could not copy the database.\n{}", err));
+        }
+
+        let size = match fs::metadata(&partial) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => return Err(format!("Could not read '{}'.\n{}", partial.display(), err)),
+        };
+        if let Err(err) = fs::rename(&partial, &path) {
+            return Err(format!(
+                "Could not rename '{}'.\n{}",
+                partial.display(),
+                err
+            ));
+        }
+        self.prune();
+        Ok(BackupFile { path, size })
+    }
+
+    /// The backups in `dir`, oldest first.
+    fn list(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut backups: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| Self::is_backup(path))
+            .collect();
+        backups.sort();
+        backups
+    }
+
+    fn is_backup(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(EXTENSION))
+    }
+
+    /// Deletes all but the newest `keep` backups.
+    fn prune(&self) {
+        let backups = self.list();
+        let excess = backups.len().saturating_sub(self.keep);
+        for path in &backups[..excess] {
+            if let Err(err) = fs::remove_file(path) {
+                println!("Could not delete old backup '{}'.\n{}", path.display(), err);
+            }
+        }
+    }
+
+    /// How long ago the newest backup was made, as long as `interval` if there are none.
+    fn newest_age(&self) -> Duration {
+        self.list()
+            .last()
+            .and_then(|path| fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or(self.interval)
+    }
+}
@@ -0,0 +1,658 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use chrono::{offset::LocalResult, Duration, NaiveDateTime, TimeZone};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{types::Value, Connection, Transaction, TransactionBehavior};
+
+use crate::{
+    scraper::scraper::{
+        FEEDBACK_COLUMNS, HEADCOUNT_COLUMNS, HOURLY_COLUMNS, LOCATIONS, LOCATION_COLUMNS,
+        PREDICTION_COLUMNS, PREDICTION_TABLES, REPORT_COLUMNS, SCRAPER_META_COLUMNS,
+    },
+    settings::settings::Settings,
+    timing::timezone::{uk_local_to_epoch, uk_local_to_stored, UK_TIMEZONE},
+    ISO_FORMAT,
+};
+
+/// What a readings table is converted into. Its CHECK is added afterwards by
+/// `Scraper::add_occupancy_check`, which drops the readings out of range first.
+const READING_COLUMNS: &str = "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, \
+    occupancy INTEGER NOT NULL, is_anomaly INTEGER NOT NULL DEFAULT 0";
+
+/// Where the rows with a time that can't be read are moved, as JSON along with the table they
+/// were in and why.
+const UNCONVERTED_TABLE: &str = "unconverted_times";
+
+/// How close a time has to be to the one of the row written before it for that row to tell
+/// which of the repeated hour it is in, see `Converted::time`.
+const SAME_SERIES: Duration = Duration::hours(2);
+
+/// A table that stored its times as ISO_FORMAT text.
+struct TextTable {
+    table_name: String,
+    /// Its columns once converted, as in its CREATE TABLE.
+    columns: &'static str,
+    /// The columns that hold a time, the first of which is never NULL.
+    times: &'static [&'static str],
+}
+
+/// What converting a table did, or would do in a dry run.
+pub struct Converted {
+    pub table_name: String,
+    /// How many rows were converted.
+    pub rows: usize,
+    /// How many times were in the hour that happens twice when the clocks go back.
+    pub repeated_hour: usize,
+    /// How many of those were put in the second time round the hour, as the row before them
+    /// had already gone round it.
+    pub second_time_round: usize,
+    /// How many times were in the hour skipped when the clocks go forward, see
+    /// `uk_local_to_stored`.
+    pub skipped_hour: usize,
+    /// The rows that couldn't be converted, by rowid along with why. They are moved to
+    /// `UNCONVERTED_TABLE`.
+    pub unconverted: Vec<(i64, String)>,
+}
+
+impl Converted {
+    fn new(table_name: &str) -> Self {
+        Self {
+            table_name: table_name.to_string(),
+            rows: 0,
+            repeated_hour: 0,
+            second_time_round: 0,
+            skipped_hour: 0,
+            unconverted: Vec::new(),
+        }
+    }
+
+    /**
+    Converts `value`, a UK local time in ISO_FORMAT, into the timestamp it is stored as now.
+    `previous` is the time of the same column in the row written before it, along with what it
+    was converted into. NULL stays NULL.
+
+    The times in the hour that happens twice when the clocks go back are put in the first time
+    round, as `uk_local_to_stored` does, unless `previous` is close by and already in it or
+    after it. Then the scraper has gone round the hour a second time, since a row is never
+    written with a time before the last.
+    */
+    fn time(
+        &mut self,
+        value: &Value,
+        previous: Option<(NaiveDateTime, i64)>,
+    ) -> Result<Option<(NaiveDateTime, i64)>, String> {
+        let text = match value {
+            Value::Null => return Ok(None),
+            Value::Text(text) => text,
+            other => return Err(format!("A time of type {}.", other.data_type())),
+        };
+        let Ok(time) = NaiveDateTime::parse_from_str(text, ISO_FORMAT) else {
+            return Err(format!("Malformed time '{}'.", text));
+        };
+        let timestamp = match UK_TIMEZONE.from_local_datetime(&time) {
+            LocalResult::Single(time) => time.timestamp(),
+            LocalResult::Ambiguous(earlier, _) => {
+                self.repeated_hour += 1;
+                let timestamp = match previous {
+                    // Such as the predictions generated together
+                    Some((naive, timestamp)) if naive == time => timestamp,
+                    Some((naive, timestamp)) if (time - naive).abs() < SAME_SERIES => {
+                        uk_local_to_epoch(time, Some(timestamp)).unwrap_or(earlier.timestamp())
+                    }
+                    _ => earlier.timestamp(),
+                };
+                if timestamp != earlier.timestamp() {
+                    self.second_time_round += 1;
+                }
+                timestamp
+            }
+            LocalResult::None => {
+                self.skipped_hour += 1;
+                uk_local_to_stored(time)
+            }
+        };
+        Ok(Some((time, timestamp)))
+    }
+}
+
+/**
+Converts every table that still stores its times as ISO_FORMAT text to store them as Unix
+timestamps instead, see `uk_local_to_stored`, all in one transaction. Nothing is committed with
+`dry_run`, so what it would do can be looked at first.
+
+The tables of every location in `LOCATIONS` or the `locations` table are converted, along with
+the `locations` and `scraper_meta` tables. Tables that are already converted, or were made
+since, are left alone. A table is rebuilt to convert it, so its indexes have to be made again
+afterwards, see `Scraper::create_tables`. Returns what was done to each table converted.
+*/
+pub fn convert(connection: &Connection, dry_run: bool) -> rusqlite::Result<Vec<Converted>> {
+    let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+    let mut converted = Vec::new();
+    for name in location_names(&transaction)? {
+        for table in location_tables(&name) {
+            converted.extend(convert_table(&transaction, &table)?);
+        }
+        converted.extend(convert_hourly(&transaction, &format!("{}_hourly", name))?);
+    }
+    for table in shared_tables() {
+        converted.extend(convert_table(&transaction, &table)?);
+    }
+    // Dropping the transaction rolls it back
+    if !dry_run {
+        transaction.commit()?;
+    }
+    Ok(converted)
+}
+
+/**
+Runs `occupancy-backend convert-times`, which converts the tables as the server does on startup
+and prints what it did. With `--dry-run` nothing is written, but every row is still read and
+converted, so the output is what converting would do.
+*/
+pub fn run(connection_pool: &Arc<Pool<SqliteConnectionManager>>) -> Result<(), String> {
+    let dry_run = Settings::read_flag("--dry-run");
+    let connection = match connection_pool.get() {
+        Ok(connection) => connection,
+        Err(_) => return Err("Could not get a database connection.".to_string()),
+    };
+    let converted = match convert(&connection, dry_run) {
+        Ok(converted) => converted,
+        Err(err) => return Err(format!("Could not convert the times.\n{}", err)),
+    };
+    if converted.is_empty() {
+        println!("Every table already stores its times as Unix timestamps.");
+        return Ok(());
+    }
+    log(&converted, dry_run);
+    if dry_run {
+        for table in &converted {
+            for (rowid, reason) in &table.unconverted {
+                println!("'{}' row {}: {}", table.table_name, rowid, reason);
+            }
+        }
+        println!("This was a dry run, nothing was written.");
+    }
+    Ok(())
+}
+
+/// Prints what converting did to each table, or would do with `dry_run`.
+pub fn log(converted: &[Converted], dry_run: bool) {
+    let verb = if dry_run {
+        "Would convert"
+    } else {
+        "Converted"
+    };
+    for table in converted {
+        println!(
+            "{} {} rows of '{}' to Unix timestamps.",
+            verb, table.rows, table.table_name
+        );
+        if table.repeated_hour > 0 {
+            println!(
+                "  {} times were in the hour the clocks go back, {} of them the second time round.",
+                table.repeated_hour, table.second_time_round
+            );
+        }
+        if table.skipped_hour > 0 {
+            println!(
+                "  {} times were in the hour skipped when the clocks go forward, taken to be GMT.",
+                table.skipped_hour
+            );
+        }
+        if !table.unconverted.is_empty() {
+            println!(
+                "  {} rows have a time that can't be read, they are kept in '{}'.",
+                table.unconverted.len(),
+                UNCONVERTED_TABLE
+            );
+        }
+    }
+}
+
+/// The locations in `LOCATIONS` along with the ones in the `locations` table, if there is one.
+fn location_names(connection: &Connection) -> rusqlite::Result<BTreeSet<String>> {
+    let mut names: BTreeSet<String> = LOCATIONS.iter().map(|name| name.to_string()).collect();
+    let registered: bool = connection.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'locations')",
+        (),
+        |row| row.get(0),
+    )?;
+    if registered {
+        let mut statement = connection.prepare("SELECT name FROM locations")?;
+        let rows = statement.query_map((), |row| row.get(0))?;
+        for name in rows {
+            names.insert(name?);
+        }
+    }
+    Ok(names)
+}
+
+/// The tables of the location `name` with times in them, other than `{name}_hourly`.
+fn location_tables(name: &str) -> Vec<TextTable> {
+    let table = |suffix: &str, columns, times| TextTable {
+        table_name: name.to_string() + suffix,
+        columns,
+        times,
+    };
+    let mut tables = vec![
+        table("", READING_COLUMNS, &["time"]),
+        table("_reports", REPORT_COLUMNS, &["time"]),
+        table("_headcount", HEADCOUNT_COLUMNS, &["time"]),
+        table("_feedback", FEEDBACK_COLUMNS, &["time"]),
+    ];
+    for suffix in PREDICTION_TABLES {
+        tables.push(table(suffix, PREDICTION_COLUMNS, &["time", "generated_at"]));
+    }
+    tables
+}
+
+/// The tables with times in them that aren't any one location's.
+fn shared_tables() -> Vec<TextTable> {
+    vec![
+        TextTable {
+            table_name: "locations".to_string(),
+            columns: LOCATION_COLUMNS,
+            times: &["created_at"],
+        },
+        TextTable {
+            table_name: "scraper_meta".to_string(),
+            columns: SCRAPER_META_COLUMNS,
+            times: &["last_success_at", "last_error_at"],
+        },
+    ]
+}
+
+/// The names and declared types of the columns of `table_name`, none if there is no such table.
+fn table_columns(
+    connection: &Connection,
+    table_name: &str,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let mut statement = connection.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+    let rows = statement.query_map([table_name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/**
+Rebuilds `table` with its times converted, if it still stores them as text, see
+`Converted::time`. Rows are converted in the order they were written.
+
+Only the columns it has both before and after are copied, so the columns added since it was
+made are left NULL. A row with a time that can't be converted is moved to `UNCONVERTED_TABLE`.
+*/
+fn convert_table(
+    connection: &Connection,
+    table: &TextTable,
+) -> rusqlite::Result<Option<Converted>> {
+    let old = table_columns(connection, &table.table_name)?;
+    let is_text = old
+        .iter()
+        .any(|(name, kind)| name == table.times[0] && kind.eq_ignore_ascii_case("TEXT"));
+    if !is_text {
+        return Ok(None);
+    }
+    let converted_name = format!("{}_converted", table.table_name);
+    connection.execute(
+        &format!("CREATE TABLE {} ({})", converted_name, table.columns),
+        (),
+    )?;
+    let columns: Vec<String> = table_columns(connection, &converted_name)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| old.iter().any(|(old, _)| old == name))
+        .collect();
+    let times: Vec<usize> = (0..columns.len())
+        .filter(|i| table.times.contains(&columns[*i].as_str()))
+        .collect();
+
+    let list = columns.join(", ");
+    let mut insert = connection.prepare(&format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        converted_name,
+        list,
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+    let mut select = connection.prepare(&format!(
+        "SELECT rowid, {} FROM {} ORDER BY rowid",
+        list, table.table_name
+    ))?;
+    let mut rows = select.query(())?;
+    let mut converted = Converted::new(&table.table_name);
+    let mut previous: Vec<Option<(NaiveDateTime, i64)>> = vec![None; columns.len()];
+    while let Some(row) = rows.next()? {
+        let rowid: i64 = row.get(0)?;
+        let mut values = (0..columns.len())
+            .map(|i| row.get::<_, Value>(i + 1))
+            .collect::<rusqlite::Result<Vec<Value>>>()?;
+        let mut times_converted = Vec::with_capacity(times.len());
+        for i in &times {
+            times_converted.push(converted.time(&values[*i], previous[*i]));
+        }
+        if let Some(Err(reason)) = times_converted.iter().find(|time| time.is_err()) {
+            unconvertible(connection, &table.table_name, &columns, &values, reason)?;
+            converted.unconverted.push((rowid, reason.clone()));
+            continue;
+        }
+        for (i, time) in times.iter().zip(times_converted) {
+            if let Ok(Some((naive, timestamp))) = time {
+                values[*i] = Value::Integer(timestamp);
+                previous[*i] = Some((naive, timestamp));
+            }
+        }
+        insert.execute(rusqlite::params_from_iter(values))?;
+        converted.rows += 1;
+    }
+    // Statements still open on the table would keep it from being dropped
+    drop(rows);
+    drop(select);
+    drop(insert);
+    connection.execute_batch(&format!(
+        "DROP TABLE {table}; ALTER TABLE {converted_name} RENAME TO {table};",
+        table = table.table_name
+    ))?;
+    Ok(Some(converted))
+}
+
+/**
+Rebuilds the hourly aggregates `table_name` from when they were stored by date and hour, so
+each hour is stored by the timestamp it starts at.
+
+The hour the clocks go back at was only stored once, for both times round, and is put in the
+first one.
+*/
+fn convert_hourly(
+    connection: &Connection,
+    table_name: &str,
+) -> rusqlite::Result<Option<Converted>> {
+    let old = table_columns(connection, table_name)?;
+    if !old.iter().any(|(name, _)| name == "date") {
+        return Ok(None);
+    }
+    let converted_name = format!("{}_converted", table_name);
+    connection.execute(
+        &format!("CREATE TABLE {} ({})", converted_name, HOURLY_COLUMNS),
+        (),
+    )?;
+    let mut insert = connection.prepare(&format!(
+        "INSERT OR REPLACE INTO {} (time, mean, min, max, samples) VALUES (?1, ?2, ?3, ?4, ?5)",
+        converted_name
+    ))?;
+    let mut select = connection.prepare(&format!(
+        "SELECT rowid, date, hour, mean, min, max, samples FROM {} ORDER BY date, hour",
+        table_name
+    ))?;
+    let mut rows = select.query(())?;
+    let mut converted = Converted::new(table_name);
+    while let Some(row) = rows.next()? {
+        let rowid: i64 = row.get(0)?;
+        let values = (1..7)
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<rusqlite::Result<Vec<Value>>>()?;
+        let time = match (&values[0], &values[1]) {
+            (Value::Text(date), Value::Integer(hour)) => {
+                let time = Value::Text(format!("{}T{:02}:00:00", date, hour));
+                converted.time(&time, None)
+            }
+            _ => Err("A date that isn't text or an hour that isn't a number.".to_string()),
+        };
+        match time {
+            Ok(Some((_, timestamp))) => {
+                insert.execute(rusqlite::params![
+                    timestamp, &values[2], &values[3], &values[4], &values[5]
+                ])?;
+                converted.rows += 1;
+            }
+            // A time that is text is never NULL
+            Ok(None) => (),
+            Err(reason) => {
+                let columns = ["date", "hour", "mean", "min", "max", "samples"].map(str::to_string);
+                unconvertible(connection, table_name, &columns, &values, &reason)?;
+                converted.unconverted.push((rowid, reason));
+            }
+        }
+    }
+    // Statements still open on the table would keep it from being dropped
+    drop(rows);
+    drop(select);
+    drop(insert);
+    connection.execute_batch(&format!(
+        "DROP TABLE {table_name}; ALTER TABLE {converted_name} RENAME TO {table_name};"
+    ))?;
+    Ok(Some(converted))
+}
+
+/// Keeps a row of `table_name` that couldn't be converted in `UNCONVERTED_TABLE`, as a JSON
+/// object of its `columns` and `values`.
+fn unconvertible(
+    connection: &Connection,
+    table_name: &str,
+    columns: &[String],
+    values: &[Value],
+    reason: &str,
+) -> rusqlite::Result<()> {
+    let row: serde_json::Map<String, serde_json::Value> = columns
+        .iter()
+        .zip(values)
+        .map(|(column, value)| {
+            let value = match value {
+                Value::Null => serde_json::Value::Null,
+                Value::Integer(value) => (*value).into(),
+                Value::Real(value) => (*value).into(),
+                Value::Text(value) => value.clone().into(),
+                Value::Blob(value) => value.clone().into(),
+            };
+            (column.clone(), value)
+        })
+        .collect();
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                id INTEGER PRIMARY KEY,
+                table_name TEXT NOT NULL,
+                row TEXT NOT NULL,
+                reason TEXT NOT NULL
+            )",
+            UNCONVERTED_TABLE
+        ),
+        (),
+    )?;
+    connection.execute(
+        &format!(
+            "INSERT INTO {} (table_name, row, reason) VALUES (?1, ?2, ?3)",
+            UNCONVERTED_TABLE
+        ),
+        rusqlite::params![
+            table_name,
+            serde_json::Value::Object(row).to_string(),
+            reason
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    /// A database from before times were stored as timestamps, with a reading in each of the
+    /// cases `Converted::time` tells apart and one that can't be read.
+    fn fixture() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE gym (id INTEGER PRIMARY KEY, time TEXT NOT NULL, occupancy INTEGER NOT NULL);
+                INSERT INTO gym (time, occupancy) VALUES
+                    ('2024-05-01T10:00:00', 40),
+                    ('2024-03-31T01:30:00', 5),
+                    ('2024-10-27T00:55:00', 10),
+                    ('2024-10-27T01:30:00', 11),
+                    ('2024-10-27T01:55:00', 12),
+                    ('2024-10-27T01:05:00', 13),
+                    ('2024-10-27T01:30:00', 14),
+                    ('2024-10-27T02:10:00', 15),
+                    ('garbage', 20);
+                CREATE TABLE gym_prediction_knn (id INTEGER PRIMARY KEY, time TEXT NOT NULL,
+                    occupancy INTEGER NOT NULL, generated_at TEXT, model_version TEXT);
+                INSERT INTO gym_prediction_knn (time, occupancy, generated_at, model_version) VALUES
+                    ('2024-05-02T10:00:00', 30, '2024-05-01T23:00:00', 'knn-1'),
+                    ('2024-05-02T10:05:00', 31, NULL, NULL);
+                CREATE TABLE gym_hourly (date TEXT NOT NULL, hour INTEGER NOT NULL, mean REAL NOT NULL,
+                    min INTEGER NOT NULL, max INTEGER NOT NULL, samples INTEGER NOT NULL,
+                    PRIMARY KEY (date, hour));
+                INSERT INTO gym_hourly VALUES ('2024-05-01', 10, 40.0, 40, 40, 1);",
+            )
+            .unwrap();
+        connection
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    fn times(connection: &Connection, table_name: &str) -> Vec<Value> {
+        let mut statement = connection
+            .prepare(&format!("SELECT time FROM {} ORDER BY id", table_name))
+            .unwrap();
+        let rows = statement.query_map((), |row| row.get(0)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    /// Every row of every table, to tell whether anything was written.
+    fn dump(connection: &Connection) -> Vec<String> {
+        let mut statement = connection
+            .prepare("SELECT name, sql FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let tables: Vec<(String, String)> = statement
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let mut dump = Vec::new();
+        for (name, sql) in tables {
+            dump.push(sql);
+            let mut statement = connection
+                .prepare(&format!("SELECT * FROM {} ORDER BY rowid", name))
+                .unwrap();
+            let columns = statement.column_count();
+            let mut rows = statement.query(()).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                let values: Vec<Value> = (0..columns).map(|i| row.get(i).unwrap()).collect();
+                dump.push(format!("{}: {:?}", name, values));
+            }
+        }
+        dump
+    }
+
+    #[test]
+    fn converts_text_times_to_timestamps() {
+        let connection = fixture();
+        let converted = convert(&connection, false).unwrap();
+        let gym = converted
+            .iter()
+            .find(|table| table.table_name == "gym")
+            .unwrap();
+        assert_eq!(gym.rows, 8);
+
+        let times = times(&connection, "gym");
+        assert!(times.iter().all(|time| matches!(time, Value::Integer(_))));
+        assert_eq!(times[0], Value::Integer(utc(2024, 5, 1, 9, 0)));
+        let predictions: Value = connection
+            .query_row(
+                "SELECT generated_at FROM gym_prediction_knn WHERE id = 1",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(predictions, Value::Integer(utc(2024, 5, 1, 22, 0)));
+        let hourly: i64 = connection
+            .query_row("SELECT time FROM gym_hourly", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(hourly, utc(2024, 5, 1, 9, 0));
+    }
+
+    #[test]
+    fn puts_the_repeated_hour_in_the_right_time_round() {
+        let connection = fixture();
+        let converted = convert(&connection, false).unwrap();
+        let gym = converted
+            .iter()
+            .find(|table| table.table_name == "gym")
+            .unwrap();
+        assert_eq!(gym.repeated_hour, 4);
+        assert_eq!(gym.second_time_round, 2);
+
+        let times = times(&connection, "gym");
+        let expected = [
+            utc(2024, 10, 26, 23, 55),
+            // The first time round, still BST
+            utc(2024, 10, 27, 0, 30),
+            utc(2024, 10, 27, 0, 55),
+            // Before the last one, so round the hour again in GMT
+            utc(2024, 10, 27, 1, 5),
+            utc(2024, 10, 27, 1, 30),
+            utc(2024, 10, 27, 2, 10),
+        ];
+        assert_eq!(times[2..], expected.map(Value::Integer));
+    }
+
+    #[test]
+    fn takes_the_skipped_hour_to_be_gmt() {
+        let connection = fixture();
+        let converted = convert(&connection, false).unwrap();
+        let gym = converted
+            .iter()
+            .find(|table| table.table_name == "gym")
+            .unwrap();
+        assert_eq!(gym.skipped_hour, 1);
+        assert_eq!(
+            times(&connection, "gym")[1],
+            Value::Integer(utc(2024, 3, 31, 1, 30))
+        );
+    }
+
+    #[test]
+    fn moves_unreadable_times_aside() {
+        let connection = fixture();
+        let converted = convert(&connection, false).unwrap();
+        let gym = converted
+            .iter()
+            .find(|table| table.table_name == "gym")
+            .unwrap();
+        assert_eq!(
+            gym.unconverted,
+            vec![(9, "Malformed time 'garbage'.".to_string())]
+        );
+
+        let (table_name, row): (String, String) = connection
+            .query_row(
+                &format!("SELECT table_name, row FROM {}", UNCONVERTED_TABLE),
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(table_name, "gym");
+        assert_eq!(row, r#"{"id":9,"occupancy":20,"time":"garbage"}"#);
+    }
+
+    #[test]
+    fn converting_again_changes_nothing() {
+        let connection = fixture();
+        convert(&connection, false).unwrap();
+        let once = dump(&connection);
+        assert!(convert(&connection, false).unwrap().is_empty());
+        assert_eq!(dump(&connection), once);
+    }
+
+    #[test]
+    fn a_dry_run_writes_nothing() {
+        let connection = fixture();
+        let before = dump(&connection);
+        let converted = convert(&connection, true).unwrap();
+        assert_eq!(converted.iter().map(|table| table.rows).sum::<usize>(), 11);
+        assert_eq!(dump(&connection), before);
+    }
+}
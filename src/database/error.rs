@@ -0,0 +1,158 @@
+use std::{
+    fmt::{self, Display},
+    ops::RangeInclusive,
+};
+
+use hyper::StatusCode;
+use rusqlite::ErrorCode;
+
+/// Why a database call failed, in the detail callers need to react to it.
+#[derive(Debug, PartialEq)]
+pub enum DatabaseError {
+    /// A query that has to return a row returned none.
+    NotFound,
+    /// The database stayed locked by another connection for the whole busy timeout. Retrying
+    /// shortly should work.
+    Busy,
+    /// The file is damaged or isn't a database at all.
+    Corrupt,
+    /// Reading or writing the file failed, such as on a full disk.
+    Io(String),
+    /// An occupancy the location can't have, see `Capacity::occupancy_range`. Nothing was
+    /// written.
+    OutOfRange {
+        table_name: String,
+        occupancy: u16,
+        range: RangeInclusive<u16>,
+    },
+    Other(String),
+}
+
+/// The result of every `SqliteDatabase` call.
+pub type DatabaseResult<T> = Result<T, DatabaseError>;
+
+impl DatabaseError {
+    /// The status a request that failed with this is answered with, see `Server::database_error`.
+    ///
+    /// A locked database is a 503 as retrying shortly should work, and an occupancy out of range
+    /// is the client's mistake. Anything else is the server's.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            Self::OutOfRange { .. } => StatusCode::BAD_REQUEST,
+            Self::Corrupt | Self::Io(_) | Self::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(err: rusqlite::Error) -> Self {
+        let code = match &err {
+            rusqlite::Error::QueryReturnedNoRows => return Self::NotFound,
+            rusqlite::Error::SqliteFailure(failure, _) => failure.code,
+            _ => return Self::Other(err.to_string()),
+        };
+        match code {
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => Self::Busy,
+            ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => Self::Corrupt,
+            ErrorCode::SystemIoFailure | ErrorCode::DiskFull | ErrorCode::CannotOpen => {
+                Self::Io(err.to_string())
+            }
+            _ => Self::Other(err.to_string()),
+        }
+    }
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Query returned no rows."),
+            Self::Busy => write!(f, "The database is locked."),
+            Self::Corrupt => write!(f, "The database is corrupt."),
+            Self::Io(err) => write!(f, "Could not access the database.\n{}", err),
+            Self::OutOfRange {
+                table_name,
+                occupancy,
+                range,
+            } => write!(
+                f,
+                "Occupancy {} is out of range for '{}', expected {} to {}.",
+                occupancy,
+                table_name,
+                range.start(),
+                range.end()
+            ),
+            Self::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::ffi;
+
+    use super::*;
+
+    fn failure(code: i32) -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(code), None)
+    }
+
+    #[test]
+    fn each_error_is_answered_with_its_status() {
+        let out_of_range = DatabaseError::OutOfRange {
+            table_name: "gym".to_string(),
+            occupancy: 101,
+            range: 0..=100,
+        };
+        let cases = [
+            (DatabaseError::NotFound, StatusCode::NOT_FOUND),
+            (DatabaseError::Busy, StatusCode::SERVICE_UNAVAILABLE),
+            (out_of_range, StatusCode::BAD_REQUEST),
+            (DatabaseError::Corrupt, StatusCode::INTERNAL_SERVER_ERROR),
+            (
+                DatabaseError::Io("disk full".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::Other("no such table".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, status) in cases {
+            assert_eq!(err.status(), status, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn rusqlite_errors_are_classified_by_their_code() {
+        assert_eq!(
+            DatabaseError::from(rusqlite::Error::QueryReturnedNoRows),
+            DatabaseError::NotFound
+        );
+        assert_eq!(
+            DatabaseError::from(failure(ffi::SQLITE_BUSY)),
+            DatabaseError::Busy
+        );
+        assert_eq!(
+            DatabaseError::from(failure(ffi::SQLITE_LOCKED)),
+            DatabaseError::Busy
+        );
+        assert_eq!(
+            DatabaseError::from(failure(ffi::SQLITE_CORRUPT)),
+            DatabaseError::Corrupt
+        );
+        assert_eq!(
+            DatabaseError::from(failure(ffi::SQLITE_NOTADB)),
+            DatabaseError::Corrupt
+        );
+        assert!(matches!(
+            DatabaseError::from(failure(ffi::SQLITE_FULL)),
+            DatabaseError::Io(_)
+        ));
+        assert!(matches!(
+            DatabaseError::from(failure(ffi::SQLITE_CONSTRAINT)),
+            DatabaseError::Other(_)
+        ));
+    }
+}
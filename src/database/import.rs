@@ -0,0 +1,225 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+};
+
+use chrono::NaiveDateTime;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Transaction, TransactionBehavior};
+
+use crate::{
+    scraper::scraper::{occupancy_range, Scraper},
+    settings::settings::Settings,
+    ISO_FORMAT,
+};
+
+use super::{error::DatabaseResult, sqlite::SqliteDatabase};
+
+/// How many rows are written per transaction. Other connections only wait for one batch.
+const BATCH_SIZE: usize = 1000;
+
+/// A line that wasn't imported.
+pub struct Rejected {
+    /// Counting from 1, as an editor shows it.
+    pub line: usize,
+    pub reason: String,
+}
+
+/// What an import did.
+pub struct ImportReport {
+    pub imported: usize,
+    pub rejected: Vec<Rejected>,
+}
+
+/**
+Runs `occupancy-backend import --name NAME --file FILE`, which imports a CSV of old readings
+into the readings of `NAME` and prints what was imported and which lines were skipped.
+
+The tables are set up first, so a new database can be imported into before the server has
+ever started on it.
+*/
+pub fn run(connection_pool: &Arc<Pool<SqliteConnectionManager>>) -> Result<(), String> {
+    let name = Settings::read_arg("--name")?.ok_or("--name not provided.")?;
+    let path = Settings::read_arg("--file")?.ok_or("--file not provided.")?;
+
+    let locations = Scraper::create_tables(connection_pool)?;
+    if !locations.contains(&name) {
+        return Err(format!("Unknown location '{}'.", name));
+    }
+    let connection = match connection_pool.get() {
+        Ok(connection) => connection,
+        Err(_) => return Err("Could not get a database connection.".to_string()),
+    };
+
+    let report = import_csv(&connection, &name, Path::new(&path))?;
+    for rejected in &report.rejected {
+        println!("Skipped line {}: {}", rejected.line, rejected.reason);
+    }
+    println!(
+        "Imported {} readings into '{}', skipped {} lines.",
+        report.imported,
+        name,
+        report.rejected.len()
+    );
+    Ok(())
+}
+
+/**
+Imports the `time,occupancy` CSV at `path` into `table_name`, a line at a time.
+
+Times have to be in ISO_FORMAT and occupancies in the location's `occupancy_range`, 0 to 100
+for most. Other lines are skipped and reported along with why. A header line and blank lines are
+skipped without a report. A time that is already stored is overwritten, as in
+`SqliteDatabase::insert_one_occupancy`.
+
+Rows are written `BATCH_SIZE` at a time, each batch in its own transaction, and the hourly
+aggregates of its hours are refreshed after it. An error stops the import, with the batches
+before it kept.
+*/
+pub fn import_csv(
+    connection: &PooledConnection<SqliteConnectionManager>,
+    table_name: &str,
+    path: &Path,
+) -> Result<ImportReport, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not open '{}'.\n{}", path.display(), err)),
+    };
+
+    let mut report = ImportReport {
+        imported: 0,
+        rejected: Vec::new(),
+    };
+    let range = occupancy_range(table_name);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => return Err(format!("Could not read line {}.\n{}", i + 1, err)),
+        };
+        if line.trim().is_empty() || (i == 0 && is_header(&line)) {
+            continue;
+        }
+        match parse_line(&line, &range) {
+            Ok(reading) => batch.push(reading),
+            Err(reason) => report.rejected.push(Rejected {
+                line: i + 1,
+                reason,
+            }),
+        }
+        if batch.len() == BATCH_SIZE {
+            report.imported += write_batch(connection, table_name, &mut batch)
+                .map_err(|e| format!("Could not write up to line {}.\n{}", i + 1, e))?;
+        }
+    }
+    report.imported += write_batch(connection, table_name, &mut batch)
+        .map_err(|e| format!("Could not write the last lines.\n{}", e))?;
+    Ok(report)
+}
+
+fn is_header(line: &str) -> bool {
+    line.split(',')
+        .next()
+        .is_some_and(|field| unquote(field).eq_ignore_ascii_case("time"))
+}
+
+/// A field with the whitespace and the quotes around it removed.
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+fn parse_line(line: &str, range: &RangeInclusive<u16>) -> Result<(NaiveDateTime, u16), String> {
+    let fields: Vec<&str> = line.split(',').map(unquote).collect();
+    let [time, occupancy] = fields[..] else {
+        return Err(format!("Expected 2 fields, found {}.", fields.len()));
+    };
+    let Ok(time) = NaiveDateTime::parse_from_str(time, ISO_FORMAT) else {
+        return Err(format!("Malformed time '{}'.", time));
+    };
+    match occupancy.parse::<u16>() {
+        Ok(occupancy) if range.contains(&occupancy) => Ok((time, occupancy)),
+        _ => Err(format!(
+            "Malformed occupancy '{}'. Expected {} to {}.",
+            occupancy,
+            range.start(),
+            range.end()
+        )),
+    }
+}
+
+/// Writes and empties `batch` in one transaction. Returns how many rows were written.
+fn write_batch(
+    connection: &PooledConnection<SqliteConnectionManager>,
+    table_name: &str,
+    batch: &mut Vec<(NaiveDateTime, u16)>,
+) -> DatabaseResult<usize> {
+    let (Some(from), Some(to)) = (
+        batch.iter().map(|(time, _)| *time).min(),
+        batch.iter().map(|(time, _)| *time).max(),
+    ) else {
+        return Ok(0);
+    };
+    let count = batch.len();
+    let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+    SqliteDatabase::insert_many_occupancy(connection, table_name, std::mem::take(batch))?;
+    transaction.commit()?;
+    SqliteDatabase::refresh_hourly(connection, table_name, from, to)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::database::{
+        sqlite::ExportFormat,
+        test_support::{date, memory_pool, seed_readings},
+    };
+
+    use super::*;
+
+    #[test]
+    fn exported_readings_are_imported_back_as_they_were() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        // Every 5 minutes for over a week, so both take several pages and batches
+        let start = date(2024, 5, 1).and_hms_opt(6, 0, 0).unwrap();
+        let readings: Vec<(NaiveDateTime, u16)> = (0..2500)
+            .map(|i| (start + chrono::Duration::minutes(5 * i), (i % 101) as u16))
+            .collect();
+        seed_readings(&connection, "gym", &readings);
+
+        let path =
+            std::env::temp_dir().join(format!("occupancy-test-{}-export.csv", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        let count =
+            SqliteDatabase::export(&connection, "gym", &mut file, ExportFormat::Csv).unwrap();
+        assert_eq!(count, readings.len());
+
+        let other = memory_pool(1);
+        let imported = other.get().unwrap();
+        let report = import_csv(&imported, "gym", &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(report.imported, readings.len());
+        assert!(report.rejected.is_empty());
+
+        let end = readings.last().unwrap().0;
+        let read = |connection: &PooledConnection<SqliteConnectionManager>| {
+            SqliteDatabase::query_range(connection, "gym", start, end).unwrap()
+        };
+        assert_eq!(read(&connection).len(), readings.len());
+        assert_eq!(read(&imported), read(&connection));
+        let hourly = |connection: &PooledConnection<SqliteConnectionManager>| {
+            SqliteDatabase::query_hourly(connection, "gym", start.date(), end.date()).unwrap()
+        };
+        assert_eq!(hourly(&imported), hourly(&connection));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rusqlite::Connection;
+
+use crate::timing::uk_datetime_now::uk_datetime_now;
+
+use super::error::{DatabaseError, DatabaseResult};
+
+/// How long /api/health goes by the last check before checking again, a check reads the whole
+/// file.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How many problems a check lists at most, a damaged page alone can have hundreds.
+const MAX_PROBLEMS: usize = 10;
+
+/// The files SQLite keeps next to the database in WAL mode, which belong to it.
+const COMPANION_SUFFIXES: &[&str] = &["-wal", "-shm"];
+
+/**
+Runs `PRAGMA quick_check` on the database behind `connection`.
+
+Returns up to `MAX_PROBLEMS` of the problems it found, none if the database is fine. A file that
+isn't a database at all is a problem too rather than an error.
+*/
+pub fn quick_check(connection: &Connection) -> DatabaseResult<Vec<String>> {
+    let checked = (|| -> DatabaseResult<Vec<String>> {
+        let mut statement = connection.prepare(&format!("PRAGMA quick_check({})", MAX_PROBLEMS))?;
+        let rows = statement.query_map((), |row| row.get::<_, String>(0))?;
+        let rows = rows.collect::<rusqlite::Result<Vec<String>>>()?;
+        // Problems found in the same table can come back together in one row
+        Ok(rows
+            .iter()
+            .flat_map(|row| row.lines())
+            .map(str::to_string)
+            .collect())
+    })();
+    match checked {
+        Ok(rows) if rows == ["ok"] => Ok(Vec::new()),
+        Ok(rows) => Ok(rows),
+        Err(DatabaseError::Corrupt) => Ok(vec![DatabaseError::Corrupt.to_string()]),
+        Err(err) => Err(err),
+    }
+}
+
+/**
+Checks the database at `path` before anything else opens it.
+
+A damaged database is logged along with what is wrong with it. It is then moved aside
+along with its WAL if `recover` is set, so a new one is started in its place, and otherwise
+the returned error keeps the server from starting. Nothing is ever discarded without
+`recover`.
+*/
+pub fn check_at_startup(path: &Path, recover: bool) -> Result<(), String> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let problems = Connection::open(path)
+        .map_err(DatabaseError::from)
+        .and_then(|connection| quick_check(&connection));
+    let problems = match problems {
+        Ok(problems) if problems.is_empty() => return Ok(()),
+        Ok(problems) => problems,
+        Err(err) => vec![err.to_string()],
+    };
+
+    eprintln!("!!! '{}' failed its integrity check:", path.display());
+    for problem in &problems {
+        eprintln!("!!!   {}", problem);
+    }
+    if !recover {
+        return Err(format!(
+            "Not starting with a damaged '{}'. Restore a backup, or start with --recover to move \
+            it aside and start with an empty database.",
+            path.display()
+        ));
+    }
+
+    let aside = format!(
+        "{}.corrupt-{}",
+        path.display(),
+        uk_datetime_now().format("%Y-%m-%dT%H-%M-%S")
+    );
+    for suffix in std::iter::once(&"").chain(COMPANION_SUFFIXES) {
+        let from = PathBuf::from(format!("{}{}", path.display(), suffix));
+        if !from.exists() {
+            continue;
+        }
+        let to = format!("{}{}", aside, suffix);
+        if let Err(err) = fs::rename(&from, &to) {
+            return Err(format!(
+                "Could not move '{}' aside.\n{}",
+                from.display(),
+                err
+            ));
+        }
+    }
+    eprintln!(
+        "!!! Moved the damaged database to '{}', starting with an empty one.",
+        aside
+    );
+    Ok(())
+}
+
+/// The result of the last `quick_check` made for /api/health, so a check is only made every
+/// `CHECK_INTERVAL` however often it is asked.
+#[derive(Debug, Default)]
+pub struct HealthCheck {
+    last: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+impl HealthCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The problems with the database behind `connection`, checked again if the last check is
+    /// older than `CHECK_INTERVAL`.
+    pub fn problems(&self, connection: &Connection) -> DatabaseResult<Vec<String>> {
+        // Held during the check, so requests that arrive meanwhile wait for its result
+        let mut last = self.last.lock().unwrap();
+        if let Some((checked, problems)) = last.as_ref() {
+            if checked.elapsed() < CHECK_INTERVAL {
+                return Ok(problems.clone());
+            }
+        }
+        let problems = quick_check(connection)?;
+        *last = Some((Instant::now(), problems.clone()));
+        Ok(problems)
+    }
+}
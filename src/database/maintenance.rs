@@ -0,0 +1,199 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use chrono::{Datelike, Days, NaiveDateTime, Utc, Weekday};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use tokio::{sync::watch, time::Duration};
+
+use crate::timing::{timezone::uk_local_to_utc, uk_datetime_now::uk_datetime_now};
+
+use super::{backup::Backups, error::DatabaseResult};
+
+/// How many rows of each index ANALYZE looks at, which keeps it quick on large tables while the
+/// statistics still come out close.
+const ANALYSIS_LIMIT: i64 = 1000;
+
+/// How the file was vacuumed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vacuum {
+    /// There weren't enough free pages to be worth it.
+    None,
+    /// The free pages were handed back with `incremental_vacuum`, the database has
+    /// `auto_vacuum=INCREMENTAL`.
+    Incremental,
+    /// The whole file was rebuilt with VACUUM.
+    Full,
+}
+
+impl Vacuum {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Incremental => "incremental",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// What a maintenance run did.
+pub struct MaintenanceReport {
+    pub vacuum: Vacuum,
+    /// How many bytes smaller the database is.
+    pub reclaimed: u64,
+    pub duration: std::time::Duration,
+}
+
+/// Keeps the database in shape once a week, at `hour` on `weekday` UK time when hardly anyone
+/// is looking. The query planner statistics are refreshed and the file is vacuumed once at least
+/// `vacuum_percent` of it is free pages, such as after pruning.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    weekday: Weekday,
+    hour: u32,
+    /// 0 never vacuums.
+    vacuum_percent: u32,
+    /// Backups wait while maintenance runs, and maintenance is skipped while one is made.
+    backups: Option<Backups>,
+    /// Held while maintenance runs, so a scheduled and an on demand run can't overlap.
+    running: Arc<Mutex<()>>,
+}
+
+impl Maintenance {
+    pub fn new(weekday: Weekday, hour: u32, vacuum_percent: u32, backups: Option<Backups>) -> Self {
+        Self {
+            weekday,
+            hour: hour.min(23),
+            vacuum_percent,
+            backups,
+            running: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Runs maintenance every week at the configured time until `shutdown` is set.
+    pub async fn run(
+        self,
+        connection_pool: Arc<Pool<SqliteConnectionManager>>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        while !*shutdown.borrow() {
+            tokio::select! {
+                _ = tokio::time::sleep(self.until_next()) => (),
+                _ = shutdown.changed() => return,
+            }
+            let maintenance = self.clone();
+            let pool = connection_pool.clone();
+            let ran = tokio::task::spawn_blocking(move || match pool.get() {
+                Ok(connection) => maintenance.maintain(&connection).map_err(|e| e.to_string()),
+                Err(_) => Err("Could not get connection for maintenance.".to_string()),
+            })
+            .await;
+            match ran {
+                Ok(Ok(Some(_))) => (),
+                Ok(Ok(None)) => println!("Skipped maintenance, a backup is being made."),
+                Ok(Err(err)) => println!("Maintenance failed.\n{}", err),
+                Err(err) => println!("Maintenance failed.\n{}", err),
+            }
+            // Don't run twice in the same hour if the sleep came back a little early
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }
+    }
+
+    /// How long until the next `hour` on `weekday` in UK time.
+    fn until_next(&self) -> Duration {
+        let now = uk_datetime_now().naive_local();
+        let days_until =
+            (7 + self.weekday.num_days_from_monday() - now.weekday().num_days_from_monday()) % 7;
+        let mut next = (now.date() + Days::new(days_until as u64))
+            .and_hms_opt(self.hour, 0, 0)
+            .unwrap();
+        if next <= now {
+            next = next + Days::new(7);
+        }
+        Self::until(next)
+    }
+
+    /// How long until the UK local time `time`. An hour skipped by the clocks going forward
+    /// counts as the one after.
+    fn until(time: NaiveDateTime) -> Duration {
+        let utc = uk_local_to_utc(time)
+            .or_else(|| uk_local_to_utc(time + chrono::Duration::hours(1)))
+            .unwrap_or_else(Utc::now);
+        (utc - Utc::now()).to_std().unwrap_or_default()
+    }
+
+    /**
+    Refreshes the query planner statistics and vacuums the database if enough of it is free.
+
+    Returns `Ok(None)` without doing anything if a backup is being made, as a vacuum would make
+    it start over. Otherwise logs and returns what was done.
+
+    PRAGMA optimize only analyzes the tables the connection it runs on has queried, which on a
+    pooled connection is often none, so ANALYZE is run directly with an `ANALYSIS_LIMIT`.
+    */
+    pub fn maintain(&self, connection: &Connection) -> DatabaseResult<Option<MaintenanceReport>> {
+        let _running = self.running.lock().unwrap();
+        let _backups = match &self.backups {
+            Some(backups) => match backups.hold() {
+                Some(held) => Some(held),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+
+        let start = Instant::now();
+        let size_before = Self::size(connection)?;
+        connection.pragma_update(None, "analysis_limit", ANALYSIS_LIMIT)?;
+        connection.execute_batch("ANALYZE; PRAGMA optimize;")?;
+
+        let vacuum = self.vacuum(connection)?;
+        let reclaimed = size_before.saturating_sub(Self::size(connection)?);
+        let report = MaintenanceReport {
+            vacuum,
+            reclaimed,
+            duration: start.elapsed(),
+        };
+        println!(
+            "Maintenance took {:?}, vacuum: {}, reclaimed {} bytes.",
+            report.duration,
+            vacuum.as_str(),
+            reclaimed
+        );
+        Ok(Some(report))
+    }
+
+    /// Vacuums if at least `vacuum_percent` of the pages are free, incrementally when the
+    /// database allows it.
+    fn vacuum(&self, connection: &Connection) -> DatabaseResult<Vacuum> {
+        let free: u64 = connection.pragma_query_value(None, "freelist_count", |row| row.get(0))?;
+        let pages: u64 = connection.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        if self.vacuum_percent == 0 || free == 0 || free * 100 < pages * self.vacuum_percent as u64
+        {
+            return Ok(Vacuum::None);
+        }
+
+        // 2 is INCREMENTAL, which only databases created with it or vacuumed since have
+        let auto_vacuum: i64 =
+            connection.pragma_query_value(None, "auto_vacuum", |row| row.get(0))?;
+        let vacuum = if auto_vacuum == 2 {
+            connection.execute_batch("PRAGMA incremental_vacuum;")?;
+            Vacuum::Incremental
+        } else {
+            connection.execute_batch("VACUUM;")?;
+            Vacuum::Full
+        };
+        // The file only shrinks once the WAL is checkpointed into it
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", (), |_| Ok(()))?;
+        Ok(vacuum)
+    }
+
+    /// The size of the database in bytes.
+    fn size(connection: &Connection) -> DatabaseResult<u64> {
+        let pages: u64 = connection.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = connection.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+}
@@ -1 +1,12 @@
 pub mod sqlite;
+pub mod storage;
+pub mod error;
+pub mod backup;
+pub mod maintenance;
+pub mod import;
+pub mod epoch;
+pub mod integrity;
+pub mod pool;
+pub mod writer;
+#[cfg(test)]
+pub mod test_support;
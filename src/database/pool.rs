@@ -0,0 +1,180 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use r2d2::{
+    event::{CheckoutEvent, HandleEvent, TimeoutEvent},
+    Pool,
+};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
+
+use super::sqlite::SqliteDatabase;
+
+/// A checkout that takes at least this long is counted as having waited. Taking an idle
+/// connection is much quicker, so this is roughly the checkouts that found none.
+const WAITED: Duration = Duration::from_millis(1);
+
+/// How the connection pool is sized, see `Settings::pool`.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// How many connections are open at most. SQLite only has one writer at a time, so more
+    /// mostly helps reads.
+    pub max_size: u32,
+    /// How many idle connections are kept open, ready for a burst of requests.
+    pub min_idle: u32,
+    /// How long a checkout waits for a connection to come free before failing.
+    pub connection_timeout: Duration,
+}
+
+/// The connection pools of the database, see `build`.
+pub struct Pools {
+    /// For everything that writes: the scraper, backups, maintenance and the admin endpoints.
+    pub read_write: Arc<Pool<SqliteConnectionManager>>,
+    /// For the server's requests, which can't change anything through it.
+    pub read_only: Arc<Pool<SqliteConnectionManager>>,
+    /// How checkouts from `read_only` have been going.
+    pub read_only_stats: Arc<PoolStats>,
+}
+
+/// How checkouts from the pool have been going since startup.
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    checkouts: AtomicU64,
+    waits: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+impl PoolStats {
+    /// How many connections have been taken from the pool.
+    pub fn checkouts(&self) -> u64 {
+        self.checkouts.load(Ordering::Relaxed)
+    }
+
+    /// How many checkouts had to wait for a connection, see `WAITED`.
+    pub fn waits(&self) -> u64 {
+        self.waits.load(Ordering::Relaxed)
+    }
+
+    /// How many checkouts gave up after the whole `connection_timeout`.
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the pool's events into a `PoolStats`.
+#[derive(Debug)]
+struct StatsHandler(Arc<PoolStats>);
+
+impl HandleEvent for StatsHandler {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        self.0.checkouts.fetch_add(1, Ordering::Relaxed);
+        if event.duration() >= WAITED {
+            self.0.waits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn handle_timeout(&self, _: TimeoutEvent) {
+        self.0.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+Builds the connection pools of the database at `path`, both sized by `config`. Its tables are
+set up by `Scraper::create_tables`.
+
+The read-write pool is built first, so a database that doesn't exist yet is created before the
+read-only one opens it. A connection is taken from each straight away, so a database that can't
+be opened at all is an error here rather than in the first request.
+*/
+pub fn build(path: &Path, config: &PoolConfig) -> Result<Pools, String> {
+    let manager = SqliteConnectionManager::file(path).with_init(SqliteDatabase::init_connection);
+    let read_write = build_pool(path, config, manager, None)?;
+
+    let read_only_stats = Arc::new(PoolStats::default());
+    let manager = SqliteConnectionManager::file(path)
+        .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .with_init(SqliteDatabase::init_connection);
+    let read_only = build_pool(path, config, manager, Some(read_only_stats.clone()))?;
+    Ok(Pools {
+        read_write: Arc::new(read_write),
+        read_only: Arc::new(read_only),
+        read_only_stats,
+    })
+}
+
+fn build_pool(
+    path: &Path,
+    config: &PoolConfig,
+    manager: SqliteConnectionManager,
+    stats: Option<Arc<PoolStats>>,
+) -> Result<Pool<SqliteConnectionManager>, String> {
+    let mut builder = Pool::builder()
+        .max_size(config.max_size.max(1))
+        .min_idle(Some(config.min_idle.min(config.max_size)))
+        .connection_timeout(config.connection_timeout);
+    if let Some(stats) = stats {
+        builder = builder.event_handler(Box::new(StatsHandler(stats)));
+    }
+    let pool = builder.build_unchecked(manager);
+    if let Err(err) = pool.get() {
+        return Err(format!(
+            "Could not open the database '{}'.\n{}",
+            path.display(),
+            err
+        ));
+    }
+    Ok(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use r2d2::PooledConnection;
+
+    use super::*;
+
+    fn pragma<T: rusqlite::types::FromSql>(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        name: &str,
+    ) -> T {
+        connection
+            .pragma_query_value(None, name, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn pooled_connections_are_set_up_by_init_connection() {
+        let dir = std::env::temp_dir().join(format!("occupancy-pool-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = PoolConfig {
+            max_size: 2,
+            min_idle: 0,
+            connection_timeout: Duration::from_secs(1),
+        };
+        let pools = build(&dir.join("data.db"), &config).unwrap();
+
+        for pool in [&pools.read_write, &pools.read_only] {
+            let connection = pool.get().unwrap();
+            assert_eq!(pragma::<String>(&connection, "journal_mode"), "wal");
+            assert_eq!(pragma::<i64>(&connection, "busy_timeout"), 5000);
+            // NORMAL
+            assert_eq!(pragma::<i64>(&connection, "synchronous"), 1);
+            assert_eq!(pragma::<i64>(&connection, "foreign_keys"), 1);
+        }
+        let read_only = pools.read_only.get().unwrap();
+        assert!(read_only
+            .execute("CREATE TABLE t (id INTEGER)", ())
+            .is_err());
+
+        drop(read_only);
+        drop(pools);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,374 @@
 
-use chrono::{NaiveDate, NaiveDateTime};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    str::FromStr,
+};
+
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{types::Value, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use serde::{Serialize, Serializer};
+
+use crate::{
+    scraper::{headcount::Headcount, scraper::occupancy_range},
+    timing::{
+        daily::Daily,
+        schedule::Schedule,
+        timezone::{stored_to_uk_local, uk_local_to_stored},
+    },
+    ISO_FORMAT,
+};
 
-use crate::{timing::schedule::Schedule, ISO_FORMAT};
+use super::{
+    error::{DatabaseError, DatabaseResult},
+    storage::Database,
+    writer::ScrapedReading,
+};
 
 pub struct SqliteDatabase {}
 
+/// Makes an insert into a readings or prediction table overwrite the row for the same time,
+/// which can only be there once.
+const OVERWRITE_OCCUPANCY: &str = "ON CONFLICT(time) DO UPDATE SET occupancy = excluded.occupancy";
+
+/// How many seconds apart two readings have to be at least for both to be kept. A reading written
+/// closer than that to one already stored replaces it, such as when the scraper is restarted
+/// right after a scrape and scrapes again straight away.
+const REPLACE_WITHIN: i64 = 60;
+
+/// How long a connection waits for another one's lock before giving up with "database is locked".
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How many prepared statements each connection keeps for reuse. The SQL only ever differs by
+/// the table it names, and tables only come from the registered locations, so this holds every
+/// statement of a few dozen tables instead of preparing them again on every call.
+const STATEMENT_CACHE_CAPACITY: usize = 256;
+
+/// How many seconds there are in an hour. Every UK offset is a whole number of hours, so the
+/// hours of the stored timestamps start when the hours of UK time do.
+const HOUR: i64 = 60 * 60;
+
+/// The days of `SqliteDatabase::day_buckets`, given as ?1, as rows of `d` with the index of the
+/// date as `key` and its `day_bounds` as `day_start` and `day_end`. Joining a table on them reads
+/// the rows of every day in one statement, which can then be grouped by `d.key`.
+const DAY_BUCKETS: &str = "(SELECT key, value ->> 0 AS day_start, value ->> 1 AS day_end FROM json_each(?1)) d";
+
+/// How many rows `SqliteDatabase::export` reads at a time.
+const EXPORT_PAGE_SIZE: usize = 1000;
+
+/// How `SqliteDatabase::export` and `SqliteDatabase::export_schedule` write a table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A header line and then a line of comma separated values per row. Exported readings can be
+    /// imported again, see `database::import`.
+    Csv,
+    /// A JSON object per line.
+    Ndjson,
+}
+
+/// A reading as exported.
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    time: &'a str,
+    occupancy: u16,
+}
+
+/// A weekday of a schedule as exported.
+#[derive(Serialize)]
+struct ScheduleExportRow {
+    effective_from: String,
+    weekday: u8,
+    open: bool,
+    opening: Option<u16>,
+    closing: Option<u16>,
+}
+
+/// An hour of a `{name}_hourly` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HourlyRow {
+    pub date: String,
+    /// The hour of the day, 0 to 23. The hour the clocks go back at is there twice.
+    pub hour: u8,
+    pub mean: f64,
+    pub min: u16,
+    pub max: u16,
+    /// How many readings were taken in the hour.
+    pub samples: usize,
+}
+
+/// Where a row of `SqliteDatabase::query_range_agnostic` comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Provenance {
+    /// A scraped reading.
+    Actual,
+    /// A KNN prediction, for a minute without a reading.
+    Predicted,
+}
+
+/// A reading, or a prediction, of a readings or prediction table.
+///
+/// Serialized as `[time, occupancy]` with the time in ISO_FORMAT, as the series of every response
+/// are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OccupancyReading {
+    pub time: NaiveDateTime,
+    pub occupancy: u16,
+}
+
+impl Serialize for OccupancyReading {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.time.format(ISO_FORMAT).to_string(), self.occupancy).serialize(serializer)
+    }
+}
+
+impl From<OccupancyReading> for (NaiveDateTime, u16) {
+    fn from(reading: OccupancyReading) -> Self {
+        (reading.time, reading.occupancy)
+    }
+}
+
+/// The rows a query read, along with how many it skipped as they couldn't be read, see
+/// `SqliteDatabase::readings`. Callers can tell from `skipped` whether to trust the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parsed<T> {
+    pub rows: Vec<T>,
+    /// How many rows were skipped. Each was logged along with its rowid as it was.
+    pub skipped: usize,
+}
+
+impl<T> Parsed<T> {
+    fn new() -> Self {
+        Self {
+            rows: Vec::new(),
+            skipped: 0,
+        }
+    }
+}
+
+impl Parsed<OccupancyReading> {
+    /**
+    Adds a `(rowid, time, occupancy)` row read from `table_name`, see `SqliteDatabase::reading_row`.
+
+    A row the scraper can't have written, such as one with a time that isn't the timestamp of a
+    date (see `SqliteDatabase::local`) or written by an older version as text, is skipped and
+    logged instead.
+    */
+    fn push_row(&mut self, table_name: &str, (rowid, time, occupancy): (i64, Value, Value)) {
+        let reading = match (&time, &occupancy) {
+            (Value::Integer(time), Value::Integer(occupancy)) => stored_to_uk_local(*time)
+                .zip(u16::try_from(*occupancy).ok())
+                .map(|(time, occupancy)| OccupancyReading { time, occupancy }),
+            _ => None,
+        };
+        match reading {
+            Some(reading) => self.rows.push(reading),
+            None => {
+                println!(
+                    "Skipped row {} of '{}' with the time {} and the occupancy {}, which can't be read.",
+                    rowid, table_name, describe(&time), describe(&occupancy)
+                );
+                self.skipped += 1;
+            }
+        }
+    }
+}
+
+/// The hourly aggregates of the readings in `table_name` matched by `filter`, a WHERE clause or
+/// nothing, as selected into its `{name}_hourly` table.
+fn select_hourly(table_name: &str, filter: &str) -> String {
+    format!(
+        "SELECT time - time % {hour}, AVG(occupancy), MIN(occupancy), MAX(occupancy), COUNT(*) FROM {table} {filter} GROUP BY time / {hour}",
+        hour = HOUR,
+        table = table_name,
+        filter = filter
+    )
+}
+
+/// A value read from the database as it is logged.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::Real(value) => value.to_string(),
+        Value::Text(value) => format!("'{}'", value),
+        Value::Blob(value) => format!("of {} bytes", value.len()),
+    }
+}
+
+/// The highest reading of a day, see `SqliteDatabase::query_daily_peaks`.
+pub struct PeakRow {
+    pub date: String,
+    pub time: String,
+    pub occupancy: u16,
+    /// How many people were inside, for locations that publish it.
+    pub total: Option<u32>,
+    /// How many people the location could hold at the time.
+    pub capacity: Option<u32>,
+}
+
+/// A stretch of opening hours without readings, see `SqliteDatabase::find_gaps`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    /// The reading before it, or when the location opened.
+    pub from: NaiveDateTime,
+    /// The reading after it, or when the location closed.
+    pub to: NaiveDateTime,
+    /// How long it was, counted on the stored times so a change of the clocks in it counts.
+    pub seconds: i64,
+}
+
+/// A reading of `SqliteDatabase::query_page`.
+pub struct PageRow {
+    pub id: i64,
+    /// The time as stored, which the next page starts after along with the id.
+    pub timestamp: i64,
+    /// The time in ISO_FORMAT.
+    pub time: String,
+    pub occupancy: u16,
+}
+
+/// A row of the `locations` table, a location that has tables in the database.
+pub struct LocationRow {
+    /// The name used in requests and as the table name.
+    pub name: String,
+    pub display_name: String,
+    /// When the location was first set up.
+    pub created_at: String,
+}
+
+/// A row of the `scraper_meta` table, how scraping a location has been going.
+pub struct ScraperMetaRow {
+    /// When it was last scraped and stored without any errors.
+    pub last_success_at: Option<String>,
+    pub last_error_at: Option<String>,
+    pub last_error_text: Option<String>,
+    /// How many scrapes in a row have failed, 0 after a successful one.
+    pub consecutive_failures: u32,
+}
+
+/// A row of a `{name}_feedback` table.
+pub struct FeedbackRow {
+    pub id: i64,
+    /// When the feedback was given.
+    pub time: String,
+    /// The day of predictions the feedback is about.
+    pub date: String,
+    pub model: String,
+    pub rating: String,
+    pub comment: Option<String>,
+}
+
 impl SqliteDatabase {
+    /**
+    The bounds of the days from `from` to `to` (inclusive), for a `time >= ?1 AND time < ?2`
+    that the time index can be used for.
+
+    They are the timestamps of the midnight `from` starts at and of the one after `to`. The
+    clocks never change at midnight in the UK, so those are never ambiguous.
+    */
+    fn day_bounds(from: NaiveDate, to: NaiveDate) -> (i64, i64) {
+        let after = to.succ_opt().unwrap_or(NaiveDate::MAX);
+        let midnight = |date: NaiveDate| uk_local_to_stored(date.and_time(NaiveTime::MIN));
+        (midnight(from), midnight(after))
+    }
+
+    /// The `day_bounds` of each of `dates` as the JSON array `DAY_BUCKETS` reads.
+    fn day_buckets(dates: &[NaiveDate]) -> String {
+        let bounds: Vec<(i64, i64)> = dates.iter().map(|&date| Self::day_bounds(date, date)).collect();
+        serde_json::to_string(&bounds).unwrap_or_default()
+    }
+
+    /// The dates from `from` to `to` (inclusive) that are a `weekday`.
+    fn weekdays(weekday: Weekday, from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+        let ahead = (weekday.num_days_from_monday() + 7 - from.weekday().num_days_from_monday()) % 7;
+        from.checked_add_days(Days::new(ahead as u64))
+            .into_iter()
+            .flat_map(|first| first.iter_weeks())
+            .take_while(move |date| *date <= to)
+    }
+
+    /**
+    The UK local time of the stored timestamp `timestamp`.
+
+    Returns an `Err` for one that is too far from today to be a date, which can't have been
+    written by `uk_local_to_stored`.
+    */
+    fn local(timestamp: i64) -> DatabaseResult<NaiveDateTime> {
+        match stored_to_uk_local(timestamp) {
+            Some(time) => Ok(time),
+            None => Err(DatabaseError::Other(format!("Malformed time {}.", timestamp))),
+        }
+    }
+
+    /// The stored timestamp `timestamp` in ISO_FORMAT, as the times of every response are. See
+    /// `local`.
+    fn iso(timestamp: i64) -> DatabaseResult<String> {
+        Ok(Self::local(timestamp)?.format(ISO_FORMAT).to_string())
+    }
+
+    /**
+    Checks that `occupancy` is one the location `table_name` can have before it is written, see
+    `occupancy_range`. The CHECK on the table would reject it as well, but without saying why.
+    */
+    fn validate_occupancy(table_name: &str, occupancy: u16) -> DatabaseResult<()> {
+        let range = occupancy_range(table_name);
+        if range.contains(&occupancy) {
+            return Ok(());
+        }
+        Err(DatabaseError::OutOfRange {
+            table_name: table_name.to_string(),
+            occupancy,
+            range,
+        })
+    }
+
+    /**
+    Sets up a connection as it is opened, for every connection of the pool.
+
+    WAL lets the server read while the scraper writes, and with `synchronous=NORMAL` a commit
+    only waits for the WAL to be written rather than the whole database to be synced. What is
+    still locked is waited on for `BUSY_TIMEOUT` instead of failing straight away.
+    */
+    pub fn init_connection(connection: &mut Connection) -> rusqlite::Result<()> {
+        // Returns the mode it ended up in, which pragma_update can't take
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        connection.busy_timeout(BUSY_TIMEOUT)
+    }
+
+    /**
+    `Connection::execute` on a statement from the connection's cache, see
+    `STATEMENT_CACHE_CAPACITY`.
+    */
+    fn execute_cached<P: rusqlite::Params>(
+        connection: &Connection,
+        sql: &str,
+        params: P
+    ) -> rusqlite::Result<usize> {
+        connection.prepare_cached(sql)?.execute(params)
+    }
+
+    /**
+    `Connection::query_row` on a statement from the connection's cache, see
+    `STATEMENT_CACHE_CAPACITY`.
+    */
+    fn query_row_cached<T, P, F>(
+        connection: &Connection,
+        sql: &str,
+        params: P,
+        f: F
+    ) -> rusqlite::Result<T>
+    where
+        P: rusqlite::Params,
+        F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T>,
+    {
+        connection.prepare_cached(sql)?.query_row(params, f)
+    }
+
     /**
     Get the most recent date in the database.
 
@@ -17,204 +378,2222 @@ impl SqliteDatabase {
     pub fn query_last_day(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
-    ) -> rusqlite::Result<Option<String>> {
+    ) -> DatabaseResult<Option<String>> {
+        let last = Self::query_last_n_readings(connection, table_name, 1)?;
+        Ok(last.rows.first().map(|reading| reading.time.date().to_string()))
+    }
+
+    /**
+    Get the `n` most recent readings, newest first.
+
+    Returns fewer if the table doesn't have `n`, and none if it is empty.
+    Rows that can't be read are skipped and don't count towards `n`, see `Parsed::push_row`.
+    */
+    pub fn query_last_n_readings(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        n: usize
+    ) -> DatabaseResult<Parsed<OccupancyReading>> {
         // Name should already be sanitized!
-        let mut statement = connection.prepare(&format!(
-            "SELECT time FROM {} ORDER BY time DESC LIMIT 1",
+        // Text sorts after every number, so times written as text come first and are skipped
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} ORDER BY time DESC, id DESC LIMIT ?1 OFFSET ?2",
             table_name
         ))?;
-        let mut data = statement.query(())?;
-        match data.next()? {
-            Some(data) => {
-                let data: String = data.get(0)?;
-                let data = NaiveDateTime::parse_from_str(&data, ISO_FORMAT).unwrap();
-                Ok(Some(data.date().to_string()))
+
+        let mut parsed = Parsed::new();
+        let mut offset = 0;
+        while parsed.rows.len() < n {
+            // Read at least as many again as were skipped, so a run of them takes few rounds
+            let limit = (n - parsed.rows.len()).max(parsed.skipped);
+            let rows = statement
+                .query_map(rusqlite::params![limit as i64, offset as i64], Self::reading_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            offset += rows.len();
+            let exhausted = rows.len() < limit;
+            for row in rows {
+                parsed.push_row(table_name, row);
+            }
+            if exhausted {
+                break;
             }
-            None => Ok(None),
         }
+        parsed.rows.truncate(n);
+        Ok(parsed)
+    }
+
+    /**
+    Get the most recent reading in the database as (time, occupancy).
+
+    Returns an `Ok(Some((String, u16)))` if successful.
+    Returns an `Ok(None)` if the table is empty.
+    */
+    pub fn query_last_reading(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+    ) -> DatabaseResult<Option<(String, u16)>> {
+        let last = Self::query_last_n_readings(connection, table_name, 1)?;
+        Ok(last.rows.first().map(|reading| {
+            (reading.time.format(ISO_FORMAT).to_string(), reading.occupancy)
+        }))
+    }
+
+    /**
+    Get the time of the most recent row on `date`.
+
+    Returns an `Ok(None)` if there are no rows on that day.
+    */
+    pub fn query_last_time_on_day(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<String>> {
+        // Name should already be sanitized!
+        let (start, end) = Self::day_bounds(date, date);
+        let last: Option<i64> = Self::query_row_cached(
+            connection,
+            &format!("SELECT MAX(time) FROM {} WHERE time >= ?1 AND time < ?2", table_name),
+            rusqlite::params![start, end],
+            |row| row.get(0),
+        )?;
+        last.map(Self::iso).transpose()
     }
 
+    /**
+    Get the newest schedule, the one in effect from the latest date.
 
+    Returns an `Ok(None)` if no schedule is stored.
+    Returns an `Err` if the date doesn't have a row for every weekday.
+    */
     pub fn query_last_day_schedule(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
-    ) -> rusqlite::Result<Option<Schedule>> {
+    ) -> DatabaseResult<Option<Schedule>> {
         // Name should already be sanitized!
-        let mut statement = connection.prepare(&format!(
-            "SELECT schedule FROM {}_schedule ORDER BY date DESC LIMIT 1",
-            table_name
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT weekday, open, opening, closing FROM {}_schedule WHERE effective_from = (SELECT MAX(effective_from) FROM {}_schedule)",
+            table_name, table_name
         ))?;
-        let mut data = statement.query(())?;
-        match data.next()? {
-            Some(data) => {
-                let data: String = data.get(0)?;
-                let data: Schedule = serde_json::from_str(&data).unwrap();
-                Ok(Some(data))
-            }
-            None => Ok(None),
-        }
+        let rows = statement.query_map((), Self::schedule_row)?;
+        Self::schedule_from_rows(rows.collect::<rusqlite::Result<_>>()?)
     }
 
     /**
-    Get the occupancy for a single day.
+    Get the occupancy for a single day, ordered by time.
     
-    Uses the LIKE operator to get all rows that start with the date.
+    Compares the time against the bounds of the day, see `day_bounds`.
+    Readings that share a minute are deduplicated, see `dedup_minutes`.
     */
     pub fn query_single_day(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
         date: NaiveDate,
-    ) -> rusqlite::Result<Vec<(String, u16)>> {
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
         // SQL Injections are automatically handled by rusqlite
         // Name should already be sanitized!
-        let mut statement = connection.prepare(&format!(
-            "SELECT time,occupancy FROM {} WHERE time LIKE ?1 || '%'",
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} WHERE time >= ?1 AND time < ?2 ORDER BY time, id",
             table_name
         ))?;
 
-        let mut data: Vec<(String, u16)> = Vec::new();
-        let rows = statement.query_map(
-            rusqlite::params![date.to_string()],
-            |row| {
-                let time: String = row.get(0)?;
-                let occupancy: u16 = row.get(1)?;
-                Ok((time, occupancy))
-            }
-        )?;
-        
-        for row in rows {
-            data.push(row?);
-        }
-
-        Ok(data)
+        let (start, end) = Self::day_bounds(date, date);
+        let rows = statement.query_map(rusqlite::params![start, end], Self::reading_row)?;
+        Ok(Self::dedup_minutes(Self::readings(table_name, rows)?.rows))
     }
 
     
     /**
-    Get the schedule for a single day.
+    Get the schedule that applied on a single day, the newest one in effect from `date` or
+    before. A schedule stored later doesn't change what an earlier day had.
 
-    Returns an `Ok(Some(String))` if successful.
-    Returns an `Ok(None)` if data is not found for that date.
-
-    Parsing of Schedule by serde is left out for better error handling.
+    Returns an `Ok(Some(Schedule))` if successful.
+    Returns an `Ok(None)` if no schedule was in effect yet on that date.
+    Returns an `Err` if the schedule doesn't have a row for every weekday.
     */
     pub fn query_single_day_schedule(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
         date: NaiveDate,
-    ) -> rusqlite::Result<Option<String>> {
-    
-        let mut statement = connection.prepare(&format!(
-            "SELECT schedule FROM {}_schedule WHERE date LIKE ?1",
-            table_name
+    ) -> DatabaseResult<Option<Schedule>> {
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT weekday, open, opening, closing FROM {table}_schedule WHERE effective_from = (
+                SELECT MAX(effective_from) FROM {table}_schedule WHERE effective_from <= ?1
+            )",
+            table = table_name
+        ))?;
+        let rows = statement.query_map(rusqlite::params![date.to_string()], Self::schedule_row)?;
+        Self::schedule_from_rows(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /**
+    Get the schedule of every date from `from` to `to` (inclusive), in one query.
+
+    Each date has the schedule in effect on it, as `query_single_day_schedule` gives it, or else
+    the oldest one stored, for the dates before the first was scraped. So every date in the range
+    is in the map, unless no schedule is stored at all, when it is empty.
+    Returns an `Err` if a stored date doesn't have a row for every weekday.
+    */
+    pub fn query_schedule_range(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<BTreeMap<NaiveDate, Schedule>> {
+        // Name should already be sanitized!
+        // The schedule in effect on `from` and every one stored after it up to `to`
+        let mut statement = connection.prepare_cached(&format!(
+            "WITH first AS (SELECT COALESCE(
+                (SELECT MAX(effective_from) FROM {table}_schedule WHERE effective_from <= ?1),
+                (SELECT MIN(effective_from) FROM {table}_schedule)
+            ) AS date)
+            SELECT effective_from, weekday, open, opening, closing FROM {table}_schedule, first
+            WHERE effective_from >= first.date AND (effective_from <= ?2 OR effective_from = first.date)
+            ORDER BY effective_from, weekday",
+            table = table_name
         ))?;
+        let rows = statement.query_map(
+            rusqlite::params![from.to_string(), to.to_string()],
+            |row| Ok((row.get::<_, String>(0)?, Self::schedule_row_from(row, 1)?)),
+        )?;
+
+        let mut stored: Vec<(NaiveDate, Vec<(u8, Daily)>)> = Vec::new();
+        for row in rows {
+            let (date, row) = row?;
+            let date = NaiveDate::from_str(&date)
+                .map_err(|_| DatabaseError::Other(format!("Malformed schedule date {}.", date)))?;
+            match stored.last_mut() {
+                Some((last, rows)) if *last == date => rows.push(row),
+                _ => stored.push((date, vec![row])),
+            }
+        }
+        let mut stored = stored.into_iter().peekable();
+        let Some((_, rows)) = stored.next() else {
+            return Ok(BTreeMap::new());
+        };
+        let mut current = Self::schedule_from_rows(rows)?;
+
+        let mut schedules = BTreeMap::new();
+        for date in from.iter_days().take_while(|date| *date <= to) {
+            while let Some((_, rows)) = stored.next_if(|(since, _)| *since <= date) {
+                current = Self::schedule_from_rows(rows)?;
+            }
+            if let Some(schedule) = &current {
+                schedules.insert(date, schedule.clone());
+            }
+        }
+        Ok(schedules)
+    }
+
+    /// A row of a `{name}_schedule` table as (weekday, day).
+    fn schedule_row(row: &rusqlite::Row) -> rusqlite::Result<(u8, Daily)> {
+        Self::schedule_row_from(row, 0)
+    }
+
+    /// The (weekday, day) selected by a row from its column `first` on, see `schedule_row`.
+    fn schedule_row_from(row: &rusqlite::Row, first: usize) -> rusqlite::Result<(u8, Daily)> {
+        Ok((
+            row.get(first)?,
+            Daily::from_parts(row.get(first + 1)?, row.get(first + 2)?, row.get(first + 3)?),
+        ))
+    }
 
-        let mut data = statement.query(rusqlite::params![date.to_string()])?;
-        match data.next()? {
-            Some(data) => {
-                let data: String = data.get(0)?;
-                Ok(Some(data))
+    /**
+    Put the rows of one date of a `{name}_schedule` table together into a Schedule.
+
+    Returns an `Ok(None)` if there are no rows.
+    Returns an `Err` unless there is exactly one row for each weekday.
+    */
+    fn schedule_from_rows(rows: Vec<(u8, Daily)>) -> DatabaseResult<Option<Schedule>> {
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let mut timings: [Option<Daily>; 7] = [None; 7];
+        for (weekday, daily) in rows {
+            match timings.get_mut(weekday as usize) {
+                Some(timing @ None) => *timing = Some(daily),
+                _ => return Err(DatabaseError::Other(format!("Invalid schedule weekday {}.", weekday))),
             }
-            None => Ok(None),
         }
+        if timings.iter().any(Option::is_none) {
+            return Err(DatabaseError::Other("The schedule is missing a weekday.".to_string()));
+        }
+        Ok(Some(Schedule::from_timings(timings.map(Option::unwrap))))
     }
 
     /**
     Get the time and occupancy% for a range.
 
-    Given a start and end date, return the occupancy data for that range.
+    Given a start and end date, return the occupancy data for that range, ordered by time.
     
-    It uses the sqlite strftime function to compare the dates with the BETWEEN operator.
+    The bounds are converted to timestamps as the times are, see `uk_local_to_stored`, and
+    compared as integers. Both are inclusive, and any fraction of a second is dropped from them.
+    Readings that share a minute are deduplicated, see `dedup_minutes`.
     */
     pub fn query_range(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
         from: NaiveDateTime,
         to: NaiveDateTime
-    ) -> rusqlite::Result<Vec<(String, u16)>> {
-        // let to = to.to_string();
-        // let from = from.to_string();
-        let mut statement = connection.prepare(&format!(
-            "SELECT time,occupancy FROM {} WHERE strftime('%s', time) BETWEEN strftime('%s', ?1) AND strftime('%s', ?2)",
-           table_name 
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Ok(Self::parsed_range(connection, table_name, from, to, "")?.rows)
+    }
+
+    /**
+    The readings `query_range` returns, along with how many rows were skipped. `condition` is
+    added to the WHERE clause, such as to leave out the anomalies.
+    */
+    fn parsed_range(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        condition: &str
+    ) -> DatabaseResult<Parsed<OccupancyReading>> {
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} WHERE time BETWEEN ?1 AND ?2 {} ORDER BY time, id",
+            table_name, condition
         ))?;
 
-        let rows = statement.query_map(rusqlite::params![from.to_string(), to.to_string()], |row| {
-            let time: String = row.get(0)?;
-            let occupancy: u16 = row.get(1)?;
-            Ok((time, occupancy))
-        })?;
-        
-        let mut data: Vec<(String, u16)> = Vec::new();
+        let (from, to) = (uk_local_to_stored(from), uk_local_to_stored(to));
+        let rows = statement.query_map(rusqlite::params![from, to], Self::reading_row)?;
+        let parsed = Self::readings(table_name, rows)?;
+        Ok(Parsed { rows: Self::dedup_minutes(parsed.rows), skipped: parsed.skipped })
+    }
+
+    /**
+    Get the time and occupancy% for a range, using the KNN predictions where there are no
+    readings, ordered by time.
+
+    `table_name` is the base table, its predictions are read from `{table_name}_prediction_knn`.
+    Both are read as in `query_range`, less the readings marked as anomalies, see `mark_anomaly`.
+    A reading and a prediction in the same minute are for the same time, and only the reading is
+    kept. Each row is tagged with which of the two it is. How many rows of either were skipped
+    is counted together.
+    */
+    pub fn query_range_agnostic(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime
+    ) -> DatabaseResult<Parsed<(OccupancyReading, Provenance)>> {
+        let actual = Self::parsed_range(connection, table_name, from, to, "AND is_anomaly = 0")?;
+        let predicted = Self::parsed_range(
+            connection,
+            &format!("{}_prediction_knn", table_name),
+            from,
+            to,
+            ""
+        )?;
+        let skipped = actual.skipped + predicted.skipped;
+        let (actual, predicted) = (actual.rows, predicted.rows);
+
+        let mut data = Vec::with_capacity(actual.len().max(predicted.len()));
+        let mut predicted = predicted.into_iter().peekable();
+        for reading in actual {
+            let minute = Self::minute(reading.time);
+            while let Some(prediction) =
+                predicted.next_if(|prediction| Self::minute(prediction.time) <= minute)
+            {
+                if Self::minute(prediction.time) != minute {
+                    data.push((prediction, Provenance::Predicted));
+                }
+            }
+            data.push((reading, Provenance::Actual));
+        }
+        data.extend(predicted.map(|prediction| (prediction, Provenance::Predicted)));
+        Ok(Parsed { rows: data, skipped })
+    }
+
+    /**
+    Keep only the last of the readings that were taken in the same minute.
+
+    A scraper restart can write a reading twice, which shows up as a spike in charts. `data` has
+    to be ordered by time and then id, so the one kept is the latest and the result is strictly
+    increasing in time.
+    */
+    fn dedup_minutes(data: Vec<OccupancyReading>) -> Vec<OccupancyReading> {
+        let mut deduped: Vec<OccupancyReading> = Vec::with_capacity(data.len());
+        for reading in data {
+            let same_minute = deduped
+                .last()
+                .is_some_and(|last| Self::minute(last.time) == Self::minute(reading.time));
+            if same_minute {
+                deduped.pop();
+            }
+            deduped.push(reading);
+        }
+        deduped
+    }
+
+    /// The minute `time` is in.
+    fn minute(time: NaiveDateTime) -> NaiveDateTime {
+        time.with_second(0).unwrap_or(time)
+    }
+
+    /**
+    Reads the `(id, time, occupancy)` selected by a row, see `readings`.
+
+    The values are read as they are stored, whatever their type, so that a row that can't be a
+    reading is skipped by `Parsed::push_row` rather than failing the whole query.
+    */
+    fn reading_row(row: &rusqlite::Row) -> rusqlite::Result<(i64, Value, Value)> {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    }
+
+    /**
+    Parse the `(id, time, occupancy)` rows read from `table_name` into readings, in the order
+    they were read.
+
+    A row that can't have been written by the scraper is skipped with a warning and counted, see
+    `Parsed::push_row`.
+    */
+    fn readings(
+        table_name: &str,
+        rows: impl Iterator<Item = rusqlite::Result<(i64, Value, Value)>>
+    ) -> DatabaseResult<Parsed<OccupancyReading>> {
+        let mut parsed = Parsed::new();
         for row in rows {
-            data.push(row?);
+            parsed.push_row(table_name, row?);
         }
-        Ok(data)
+        Ok(parsed)
     }
 
     /**
-    Deletes all records specified by the range.
+    Get the readings taken on `weekday` between two dates (inclusive), ordered by time.
 
-    Uses the sqlite strftime function to compare the dates with the BETWEEN operator.
+    The readings are read within the `day_bounds` of each of those days in one statement, see
+    `DAY_BUCKETS`, so only the rows for that weekday are read.
     */
-    pub fn delete_range(
+    pub fn query_weekday(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        weekday: Weekday,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT r.id,r.time,r.occupancy FROM {} JOIN {} r ON r.time >= d.day_start AND r.time < d.day_end \
+            ORDER BY r.time",
+            DAY_BUCKETS, table_name
+        ))?;
+
+        let dates: Vec<NaiveDate> = Self::weekdays(weekday, from, to).collect();
+        let rows = statement.query_map([Self::day_buckets(&dates)], Self::reading_row)?;
+        Ok(Self::readings(table_name, rows)?.rows)
+    }
+
+    /**
+    Get the readings taken on `weekday` between two times (inclusive), ordered by time.
+
+    The same readings `query_range` returns for the range, less those on other weekdays and those
+    marked as anomalies, see `mark_anomaly`. The days are read in one statement, as in
+    `query_weekday`. Along with them is how many rows were skipped, see `Parsed`.
+    */
+    pub fn query_weekday_range(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
+        weekday: Weekday,
         from: NaiveDateTime,
-        to: NaiveDateTime,
-    ) -> rusqlite::Result<()> {
-        let from = from.format(ISO_FORMAT).to_string();
-        let to = to.format(ISO_FORMAT).to_string();
-        connection.execute(
-            &format!(
-                "DELETE FROM {} WHERE strftime('%s', time) BETWEEN strftime('%s', ?1) AND strftime('%s', ?2)",
-                table_name
-            ),
-            rusqlite::params![from, to],
+        to: NaiveDateTime
+    ) -> DatabaseResult<Parsed<OccupancyReading>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT r.id,r.time,r.occupancy FROM {} JOIN {} r ON r.time >= d.day_start AND r.time < d.day_end \
+            WHERE r.time BETWEEN ?2 AND ?3 AND r.is_anomaly = 0 ORDER BY r.time, r.id",
+            DAY_BUCKETS, table_name
+        ))?;
+
+        let dates: Vec<NaiveDate> = Self::weekdays(weekday, from.date(), to.date()).collect();
+        let rows = statement.query_map(
+            rusqlite::params![Self::day_buckets(&dates), uk_local_to_stored(from), uk_local_to_stored(to)],
+            Self::reading_row
         )?;
-        Ok(())
+        let mut data = Self::readings(table_name, rows)?;
+        data.rows = Self::dedup_minutes(data.rows);
+        Ok(data)
     }
 
-    
     /**
-    Insert one occupancy data into the database.
+    Check whether there are any readings after `since`.
+
+    This is much cheaper than fetching them, so pollers can be turned away early.
     */
-    pub fn insert_one_occupancy(
+    pub fn query_has_newer(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
-        time: NaiveDateTime,
-        occupancy: u16
-    ) -> rusqlite::Result<()> {
-        connection.execute(
-            &format!(
-                "INSERT INTO {} (time, occupancy) VALUES (?1, ?2)",
-                table_name
-            ),
-            rusqlite::params![time.format(ISO_FORMAT).to_string(), occupancy],
-        )?;
-        Ok(())
+        since: NaiveDateTime
+    ) -> DatabaseResult<bool> {
+        // Name should already be sanitized!
+        Ok(Self::query_row_cached(
+            connection,
+            &format!("SELECT EXISTS(SELECT 1 FROM {} WHERE time > ?1)", table_name),
+            rusqlite::params![uk_local_to_stored(since)],
+            |row| row.get(0),
+        )?)
     }
 
-    
     /**
-    Insert many occupancy data into the database.
+    Get the predictions in the prediction table `table_name` that were generated after `since`,
+    ordered by time.
 
-    `data` is a `Vec` of tuples of (time, occupancy).
+    Predictions from before `generated_at` was stored are never included.
     */
-    pub fn insert_many_occupancy(
+    pub fn query_regenerated(
         connection: &PooledConnection<SqliteConnectionManager>,
         table_name: &str,
-        data: Vec<(NaiveDateTime, u16)>
-    ) -> rusqlite::Result<()> {
-        let mut statement = connection.prepare(&format!(
-            "INSERT INTO {} (time, occupancy) VALUES (?1, ?2)",
+        since: NaiveDateTime
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} WHERE generated_at > ?1 ORDER BY time, id",
             table_name
         ))?;
-
-        for (time, occupancy) in data {
-            statement.execute(rusqlite::params![time.format(ISO_FORMAT).to_string(), occupancy])?;
-        }
-        Ok(())
+        let rows = statement.query_map(
+            rusqlite::params![uk_local_to_stored(since)],
+            Self::reading_row
+        )?;
+        Ok(Self::readings(table_name, rows)?.rows)
     }
 
+    /**
+    Get when the newest of the predictions on `date` in the prediction table `table_name` were
+    generated.
+
+    Returns `Ok(None)` if there are none, or only ones from before `generated_at` was stored.
+    */
+    pub fn query_generated_at(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<String>> {
+        // Name should already be sanitized!
+        let (from, to) = Self::day_bounds(date, date);
+        let generated_at: Option<i64> = Self::query_row_cached(
+            connection,
+            &format!(
+                "SELECT MAX(generated_at) FROM {} WHERE time >= ?1 AND time < ?2",
+                table_name
+            ),
+            rusqlite::params![from, to],
+            |row| row.get(0),
+        )?;
+        generated_at.map(Self::iso).transpose()
+    }
+
+    /**
+    Get the highest occupancy of each day between two dates (inclusive) and the time it occurred,
+    along with the headcount in `{table_name}_headcount` at that time if there is one.
+
+    The capacity is the one stored with the reading, as it has changed over time. The readings
+    are grouped by the `day_bounds` they are within, see `DAY_BUCKETS`.
+    Returns the peaks ordered by date. Days without any readings are not included.
+    */
+    pub fn query_daily_peaks(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<Vec<PeakRow>> {
+        // Name should already be sanitized!
+        // SQLite takes the bare columns from the row that has the MAX.
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT d.key, r.time, MAX(r.occupancy), h.total, h.capacity FROM {} \
+            JOIN {} r ON r.time >= d.day_start AND r.time < d.day_end \
+            LEFT JOIN {}_headcount h ON h.time = r.time GROUP BY d.key ORDER BY d.key",
+            DAY_BUCKETS, table_name, table_name
+        ))?;
+
+        let dates: Vec<NaiveDate> = from.iter_days().take_while(|date| *date <= to).collect();
+        let peaks = statement.query_map([Self::day_buckets(&dates)], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, i64>(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        })?;
+        let mut data: Vec<PeakRow> = Vec::new();
+        for peak in peaks {
+            let (day, time, occupancy, total, capacity) = peak?;
+            data.push(PeakRow {
+                date: dates[day].to_string(),
+                time: Self::iso(time)?,
+                occupancy,
+                total,
+                capacity,
+            });
+        }
+        Ok(data)
+    }
+
+    /**
+    Count the rows of each day between two dates (inclusive), grouped by the `day_bounds` they
+    are within, see `DAY_BUCKETS`.
+
+    Returns `(date, count)` ordered by date. Days without any rows are not included.
+    */
+    pub fn query_daily_counts(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<Vec<(String, usize)>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT d.key, COUNT(*) FROM {} JOIN {} r ON r.time >= d.day_start AND r.time < d.day_end \
+            GROUP BY d.key ORDER BY d.key",
+            DAY_BUCKETS, table_name
+        ))?;
+
+        let dates: Vec<NaiveDate> = from.iter_days().take_while(|date| *date <= to).collect();
+        let counts = statement.query_map([Self::day_buckets(&dates)], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?))
+        })?;
+        let mut data: Vec<(String, usize)> = Vec::new();
+        for count in counts {
+            let (day, count) = count?;
+            data.push((dates[day].to_string(), count));
+        }
+        Ok(data)
+    }
+
+    /**
+    Count the readings of each day between two dates (inclusive) from `{table_name}_hourly`.
+
+    Gives the same counts as `query_daily_counts` on the readings, without reading every one of
+    them. Days pruned from the readings keep their count, since their hours are kept.
+    Returns `(date, count)` ordered by date. Days without any readings are not included.
+    */
+    pub fn query_daily_samples(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<Vec<(String, usize)>> {
+        // The hours come ordered by time, so the hours of a date are next to each other
+        let mut data: Vec<(String, usize)> = Vec::new();
+        for hour in Self::query_hourly(connection, table_name, from, to)? {
+            match data.last_mut() {
+                Some((date, count)) if *date == hour.date => *count += hour.samples,
+                _ => data.push((hour.date, hour.samples)),
+            }
+        }
+        Ok(data)
+    }
+
+    /**
+    Get the hourly aggregates between two dates (inclusive) from `{table_name}_hourly`.
+
+    Returns them ordered by time, within the `day_bounds` of the dates. Hours without any
+    readings are not included.
+    */
+    pub fn query_hourly(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<Vec<HourlyRow>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT time, mean, min, max, samples FROM {}_hourly WHERE time >= ?1 AND time < ?2 ORDER BY time",
+            table_name
+        ))?;
+
+        let (start, end) = Self::day_bounds(from, to);
+        let rows = statement.query_map(rusqlite::params![start, end], |row| {
+            let time: i64 = row.get(0)?;
+            Ok((time, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+        })?;
+
+        let mut data: Vec<HourlyRow> = Vec::new();
+        for row in rows {
+            let (time, mean, min, max, samples) = row?;
+            let time = Self::local(time)?;
+            data.push(HourlyRow {
+                date: time.date().to_string(),
+                hour: time.hour() as u8,
+                mean,
+                min,
+                max,
+                samples,
+            });
+        }
+        Ok(data)
+    }
+
+    /**
+    Get the opening hours of `date` from the stored schedules, as the times the location opens
+    and closes at.
+
+    The schedule of `date` is the one `query_schedule_range` gives it. Without any stored schedule
+    the whole day counts as open.
+    Returns an `Ok(None)` if the location is closed that day.
+    */
+    pub fn open_hours(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<(NaiveDateTime, NaiveDateTime)>> {
+        let schedules = Self::query_schedule_range(connection, table_name, date, date)?;
+        Ok(Self::hours_on(schedules.get(&date), date))
+    }
+
+    /// The opening hours of `date` in `schedule`, see `open_hours` and `Daily::hours_on`.
+    fn hours_on(
+        schedule: Option<&Schedule>,
+        date: NaiveDate
+    ) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        let Some(schedule) = schedule else {
+            return Some((date.and_time(NaiveTime::MIN), date.and_hms_opt(23, 59, 59).unwrap()));
+        };
+        schedule.get_timings()[date.weekday().num_days_from_monday() as usize].hours_on(date)
+    }
+
+    /**
+    Find where readings are missing between `from` and `to` (inclusive), such as when scrapes
+    failed.
+
+    Only the opening hours count, see `open_hours`, since nothing is scraped while closed. A gap
+    is where two readings next to each other are more than twice `expected_interval` apart, as
+    are the opening time and the first reading, or the last reading and the closing time.
+    Returns the gaps ordered by time.
+    */
+    pub fn find_gaps(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        expected_interval: std::time::Duration
+    ) -> DatabaseResult<Vec<Gap>> {
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT time FROM {} WHERE time BETWEEN ?1 AND ?2 ORDER BY time",
+            table_name
+        ))?;
+
+        let limit = 2 * expected_interval.as_secs() as i64;
+        let (start, end) = (uk_local_to_stored(from), uk_local_to_stored(to));
+        let schedules = Self::query_schedule_range(connection, table_name, from.date(), to.date())?;
+        let mut gaps = Vec::new();
+        for date in from.date().iter_days().take_while(|date| *date <= to.date()) {
+            let Some((opening, closing)) = Self::hours_on(schedules.get(&date), date) else {
+                continue;
+            };
+            let opening = uk_local_to_stored(opening).max(start);
+            let closing = uk_local_to_stored(closing).min(end);
+            if opening >= closing {
+                continue;
+            }
+            let times = statement
+                .query_map(rusqlite::params![opening, closing], |row| row.get::<_, i64>(0))?
+                .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+            let mut previous = opening;
+            for time in times.into_iter().chain([closing]) {
+                if time - previous > limit {
+                    gaps.push(Gap {
+                        from: Self::local(previous)?,
+                        to: Self::local(time)?,
+                        seconds: time - previous,
+                    });
+                }
+                previous = time;
+            }
+        }
+        Ok(gaps)
+    }
+
+    /**
+    Get every row of the `locations` table ordered by name.
+    */
+    pub fn query_locations(
+        connection: &PooledConnection<SqliteConnectionManager>
+    ) -> DatabaseResult<Vec<LocationRow>> {
+        let mut statement = connection.prepare_cached(
+            "SELECT name,display_name,created_at FROM locations ORDER BY name"
+        )?;
+
+        let rows = statement.query_map((), |row| {
+            let created_at: i64 = row.get(2)?;
+            Ok((row.get(0)?, row.get(1)?, created_at))
+        })?;
+
+        let mut data: Vec<LocationRow> = Vec::new();
+        for row in rows {
+            let (name, display_name, created_at) = row?;
+            data.push(LocationRow {
+                name,
+                display_name,
+                created_at: Self::iso(created_at)?,
+            });
+        }
+        Ok(data)
+    }
+
+    /**
+    Get the row of `target` in the `scraper_meta` table, `None` if it was never scraped.
+    */
+    pub fn query_scraper_meta(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        target: &str
+    ) -> DatabaseResult<Option<ScraperMetaRow>> {
+        let row = Self::query_row_cached(
+            connection,
+            "SELECT last_success_at,last_error_at,last_error_text,consecutive_failures FROM scraper_meta WHERE target = ?1",
+            [target],
+            |row| {
+                let last_success_at: Option<i64> = row.get(0)?;
+                let last_error_at: Option<i64> = row.get(1)?;
+                Ok((last_success_at, last_error_at, row.get(2)?, row.get(3)?))
+            },
+        ).optional()?;
+        let Some((last_success_at, last_error_at, last_error_text, consecutive_failures)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(ScraperMetaRow {
+            last_success_at: last_success_at.map(Self::iso).transpose()?,
+            last_error_at: last_error_at.map(Self::iso).transpose()?,
+            last_error_text,
+            consecutive_failures,
+        }))
+    }
+
+    /**
+    Get up to `limit` readings ordered by time, starting after the reading `after`.
+
+    Used to page through a whole table without loading it all at once. `after` is the
+    (id, timestamp) of the last reading of the previous page, or `None` for the first page.
+    */
+    pub fn query_page(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        after: Option<(i64, i64)>,
+        limit: usize
+    ) -> DatabaseResult<Vec<PageRow>> {
+        // Name should already be sanitized!
+        // Paging on (time, id) rather than OFFSET keeps every page as cheap as the first, and the
+        // id breaks ties between readings at the same time.
+        let (after_id, after_time) = after.unwrap_or((i64::MIN, i64::MIN));
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} WHERE (time, id) > (?1, ?2) ORDER BY time, id LIMIT ?3",
+            table_name
+        ))?;
+
+        let rows = statement.query_map(rusqlite::params![after_time, after_id, limit], |row| {
+            let id: i64 = row.get(0)?;
+            let timestamp: i64 = row.get(1)?;
+            let occupancy: u16 = row.get(2)?;
+            Ok((id, timestamp, occupancy))
+        })?;
+
+        let mut data: Vec<PageRow> = Vec::new();
+        for row in rows {
+            let (id, timestamp, occupancy) = row?;
+            data.push(PageRow { id, timestamp, time: Self::iso(timestamp)?, occupancy });
+        }
+        Ok(data)
+    }
+
+    /**
+    Write every reading of `table_name` to `writer` in `format`, oldest first.
+
+    The table is read `EXPORT_PAGE_SIZE` rows at a time as in `query_page`, and each page is
+    written before the next is read, so neither the table nor a read of it is held on to for the
+    whole export. Returns the number of rows written.
+    */
+    pub fn export(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        writer: &mut impl Write,
+        format: ExportFormat
+    ) -> DatabaseResult<usize> {
+        if format == ExportFormat::Csv {
+            writeln!(writer, "time,occupancy").map_err(Self::export_error)?;
+        }
+        let mut count = 0;
+        let mut after: Option<(i64, i64)> = None;
+        loop {
+            let page = Self::query_page(connection, table_name, after, EXPORT_PAGE_SIZE)?;
+            for reading in &page {
+                let row = ExportRow { time: &reading.time, occupancy: reading.occupancy };
+                Self::write_row(writer, format, &row, || format!("{},{}", reading.time, reading.occupancy))?;
+            }
+            count += page.len();
+            match page.last() {
+                Some(last) if page.len() == EXPORT_PAGE_SIZE => after = Some((last.id, last.timestamp)),
+                _ => break,
+            }
+        }
+        writer.flush().map_err(Self::export_error)?;
+        Ok(count)
+    }
+
+    /**
+    Write every row of `{table_name}_schedule` to `writer` in `format`, by the date it is in
+    effect from and then weekday. A missing opening or closing time is empty in CSV and null in JSON.
+
+    Returns the number of rows written.
+    */
+    pub fn export_schedule(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        writer: &mut impl Write,
+        format: ExportFormat
+    ) -> DatabaseResult<usize> {
+        if format == ExportFormat::Csv {
+            writeln!(writer, "effective_from,weekday,open,opening,closing")
+                .map_err(Self::export_error)?;
+        }
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT effective_from, weekday, open, opening, closing FROM {}_schedule ORDER BY effective_from, weekday",
+            table_name
+        ))?;
+        let mut rows = statement.query(())?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let row = ScheduleExportRow {
+                effective_from: row.get(0)?,
+                weekday: row.get(1)?,
+                open: row.get(2)?,
+                opening: row.get(3)?,
+                closing: row.get(4)?,
+            };
+            let time = |time: Option<u16>| time.map(|time| time.to_string()).unwrap_or_default();
+            Self::write_row(writer, format, &row, || format!(
+                "{},{},{},{},{}",
+                row.effective_from, row.weekday, row.open, time(row.opening), time(row.closing)
+            ))?;
+            count += 1;
+        }
+        writer.flush().map_err(Self::export_error)?;
+        Ok(count)
+    }
+
+    /// Write one exported row as a line, as JSON or as the line `csv` makes.
+    fn write_row(
+        writer: &mut impl Write,
+        format: ExportFormat,
+        row: &impl Serialize,
+        csv: impl FnOnce() -> String
+    ) -> DatabaseResult<()> {
+        match format {
+            ExportFormat::Csv => writer.write_all(csv().as_bytes()).map_err(Self::export_error)?,
+            ExportFormat::Ndjson => serde_json::to_writer(&mut *writer, row)
+                .map_err(|err| Self::export_error(err.into()))?,
+        }
+        writer.write_all(b"\n").map_err(Self::export_error)
+    }
+
+    fn export_error(err: std::io::Error) -> DatabaseError {
+        DatabaseError::Other(format!("Could not write the export.\n{}", err))
+    }
+
+    /**
+    Get the headcounts taken on `date` ordered by time.
+
+    `table_name` is the location, the headcounts are read from `{table_name}_headcount`.
+    */
+    pub fn query_headcount_on_day(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<(String, Headcount)>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT time,total,capacity,staff,student,other FROM {}_headcount WHERE time >= ?1 AND time < ?2 ORDER BY time",
+            table_name
+        ))?;
+
+        let (start, end) = Self::day_bounds(date, date);
+        let rows = statement.query_map(rusqlite::params![start, end], |row| {
+            let time: i64 = row.get(0)?;
+            let headcount = Headcount {
+                total: row.get(1)?,
+                capacity: row.get(2)?,
+                staff: row.get(3)?,
+                student: row.get(4)?,
+                other: row.get(5)?,
+            };
+            Ok((time, headcount))
+        })?;
+
+        let mut data: Vec<(String, Headcount)> = Vec::new();
+        for row in rows {
+            let (time, headcount) = row?;
+            data.push((Self::iso(time)?, headcount));
+        }
+        Ok(data)
+    }
+
+    /**
+    Get the readings of `date` that are marked as anomalies, see `mark_anomaly`, ordered by time.
+
+    They are among the readings `query_single_day` returns, this only tells which they are.
+    */
+    pub fn query_anomalies_on_day(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,occupancy FROM {} WHERE time >= ?1 AND time < ?2 AND is_anomaly = 1 ORDER BY time, id",
+            table_name
+        ))?;
+
+        let (start, end) = Self::day_bounds(date, date);
+        let rows = statement.query_map(rusqlite::params![start, end], Self::reading_row)?;
+        Ok(Self::readings(table_name, rows)?.rows)
+    }
+
+    /**
+    Get the crowdsourced reports made on `date` ordered by time.
+
+    `table_name` is the location, the reports are read from `{table_name}_reports`.
+    Returns `(time, occupancy, note)`.
+    */
+    pub fn query_reports_on_day(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<(String, u16, Option<String>)>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT time,occupancy,note FROM {}_reports WHERE time >= ?1 AND time < ?2 ORDER BY time",
+            table_name
+        ))?;
+
+        let (start, end) = Self::day_bounds(date, date);
+        let rows = statement.query_map(rusqlite::params![start, end], |row| {
+            let time: i64 = row.get(0)?;
+            let occupancy: u16 = row.get(1)?;
+            let note: Option<String> = row.get(2)?;
+            Ok((time, occupancy, note))
+        })?;
+
+        let mut data: Vec<(String, u16, Option<String>)> = Vec::new();
+        for row in rows {
+            let (time, occupancy, note) = row?;
+            data.push((Self::iso(time)?, occupancy, note));
+        }
+        Ok(data)
+    }
+
+    /**
+    Get up to `limit` rows of `{table_name}_feedback`, newest first, starting before the row
+    with the id `before`, or from the newest when it is `None`.
+    */
+    pub fn query_feedback_page(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        before: Option<i64>,
+        limit: usize
+    ) -> DatabaseResult<Vec<FeedbackRow>> {
+        // Name should already be sanitized!
+        let mut statement = connection.prepare_cached(&format!(
+            "SELECT id,time,date,model,rating,comment FROM {}_feedback WHERE id < ?1 ORDER BY id DESC LIMIT ?2",
+            table_name
+        ))?;
+
+        let rows = statement.query_map(rusqlite::params![before.unwrap_or(i64::MAX), limit], |row| {
+            let time: i64 = row.get(1)?;
+            Ok((row.get(0)?, time, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+        })?;
+
+        let mut data: Vec<FeedbackRow> = Vec::new();
+        for row in rows {
+            let (id, time, date, model, rating, comment) = row?;
+            data.push(FeedbackRow {
+                id,
+                time: Self::iso(time)?,
+                date,
+                model,
+                rating,
+                comment,
+            });
+        }
+        Ok(data)
+    }
+
+    /**
+    Deletes every row from before `before`, using the time index.
+
+    Returns the number of rows deleted.
+    */
+    pub fn delete_before(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        before: NaiveDateTime
+    ) -> DatabaseResult<usize> {
+        // Name should already be sanitized!
+        Ok(Self::execute_cached(
+            connection,
+            &format!("DELETE FROM {} WHERE time < ?1", table_name),
+            rusqlite::params![uk_local_to_stored(before)],
+        )?)
+    }
+
+    /**
+    Deletes all records specified by the range.
+
+    Both ends are inclusive and compared as timestamps, as in `query_range`.
+    Returns the number of rows deleted.
+    */
+    pub fn delete_range(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> DatabaseResult<usize> {
+        let (from, to) = (uk_local_to_stored(from), uk_local_to_stored(to));
+        Ok(Self::execute_cached(
+            connection,
+            &format!(
+                "DELETE FROM {} WHERE time BETWEEN ?1 AND ?2",
+                table_name
+            ),
+            rusqlite::params![from, to],
+        )?)
+    }
+
+    /**
+    Marks the readings between `from` and `to` (inclusive) as anomalies, or unmarks them if
+    `is_anomaly` is false. Both ends are compared as in `query_range`.
+
+    Marked readings are still stored and served, but left out of what the predictions are made
+    from, see `query_range_agnostic` and `query_weekday_range`, so a suspicious reading doesn't
+    have to be deleted to keep it out of them. Returns the number of readings in the range.
+    */
+    pub fn mark_anomaly(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        is_anomaly: bool,
+    ) -> DatabaseResult<usize> {
+        let (from, to) = (uk_local_to_stored(from), uk_local_to_stored(to));
+        Ok(Self::execute_cached(
+            connection,
+            &format!(
+                "UPDATE {} SET is_anomaly = ?3 WHERE time BETWEEN ?1 AND ?2",
+                table_name
+            ),
+            rusqlite::params![from, to, is_anomaly],
+        )?)
+    }
+
+    /**
+    Deletes every row of `date` from `table_name`, and from each of its `{table_name}_prediction_*`
+    tables as well if `include_predictions` is set, all in one transaction.
+
+    Returns the number of rows deleted from each table, `table_name` first.
+    */
+    pub fn delete_day(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate,
+        include_predictions: bool
+    ) -> DatabaseResult<Vec<(String, usize)>> {
+        // Name should already be sanitized!
+        let mut tables = vec![table_name.to_string()];
+        if include_predictions {
+            let mut statement = connection.prepare_cached(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name GLOB ?1 ORDER BY name"
+            )?;
+            let names = statement.query_map([format!("{}_prediction_*", table_name)], |row| row.get(0))?;
+            for name in names {
+                tables.push(name?);
+            }
+        }
+
+        let (start, end) = Self::day_bounds(date, date);
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        let mut deleted = Vec::with_capacity(tables.len());
+        for table in tables {
+            let count = Self::execute_cached(
+                &transaction,
+                &format!("DELETE FROM {} WHERE time >= ?1 AND time < ?2", table),
+                rusqlite::params![start, end],
+            )?;
+            deleted.push((table, count));
+        }
+        transaction.commit()?;
+        Ok(deleted)
+    }
+
+    /**
+    Insert one occupancy data into the database.
+
+    If `time` is already stored its occupancy is overwritten instead, and readings less than
+    `REPLACE_WITHIN` seconds either side of it are replaced by it. Both are made by deleting them
+    first, so run it in a transaction, as `insert_readings` does, for the readings replaced to only
+    go along with the new one written. An occupancy out of range is an error, see
+    `validate_occupancy`.
+    */
+    pub fn insert_one_occupancy(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime,
+        occupancy: u16
+    ) -> DatabaseResult<()> {
+        Self::validate_occupancy(table_name, occupancy)?;
+        let time = uk_local_to_stored(time);
+        Self::execute_cached(
+            connection,
+            &format!(
+                "DELETE FROM {} WHERE time > ?1 - ?2 AND time < ?1 + ?2 AND time != ?1",
+                table_name
+            ),
+            rusqlite::params![time, REPLACE_WITHIN],
+        )?;
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {} (time, occupancy) VALUES (?1, ?2) {}",
+                table_name, OVERWRITE_OCCUPANCY
+            ),
+            rusqlite::params![time, occupancy],
+        )?;
+        Ok(())
+    }
+
+    /**
+    Insert the schedule scraped on `date` into `{table_name}_schedule` as in effect from `date`,
+    one row per weekday, until a later one is.
+
+    A schedule already in effect from `date` is overwritten, see `Writer::store_schedule` for
+    skipping the write when the one in effect hasn't changed.
+    */
+    pub fn insert_one_schedule(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        date: NaiveDate,
+        schedule: &Schedule
+    ) -> DatabaseResult<()> {
+        // A single statement, so the weekdays are written all together or not at all
+        let values = ["(?, ?, ?, ?, ?)"; 7].join(", ");
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        for (weekday, daily) in schedule.get_timings().iter().enumerate() {
+            params.push(Box::new(date.to_string()));
+            params.push(Box::new(weekday as u8));
+            params.push(Box::new(daily.open()));
+            params.push(Box::new(daily.opening()));
+            params.push(Box::new(daily.closing()));
+        }
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {}_schedule (effective_from, weekday, open, opening, closing) VALUES {} ON CONFLICT(effective_from, weekday) DO UPDATE SET open = excluded.open, opening = excluded.opening, closing = excluded.closing",
+                table_name, values
+            ),
+            rusqlite::params_from_iter(params),
+        )?;
+        Ok(())
+    }
+
+    /**
+    Record `name` in the `locations` table, created at `created_at`.
+
+    A location that is already there keeps its id and creation time, only its display name is
+    updated.
+    */
+    pub fn register_location(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        name: &str,
+        display_name: &str,
+        created_at: NaiveDateTime
+    ) -> DatabaseResult<()> {
+        Self::execute_cached(
+            connection,
+            "INSERT INTO locations (name, display_name, created_at) VALUES (?1, ?2, ?3) ON CONFLICT(name) DO UPDATE SET display_name = excluded.display_name",
+            rusqlite::params![name, display_name, uk_local_to_stored(created_at)],
+        )?;
+        Ok(())
+    }
+
+    /**
+    Record how scraping `target` at `time` went in the `scraper_meta` table.
+
+    `error` is `None` for a scrape that was stored without errors, which resets
+    `consecutive_failures`. The last error is kept after a success, along with when it happened.
+    */
+    pub fn record_scrape(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        target: &str,
+        time: NaiveDateTime,
+        error: Option<&str>
+    ) -> DatabaseResult<()> {
+        let time = uk_local_to_stored(time);
+        match error {
+            None => Self::execute_cached(
+                connection,
+                "INSERT INTO scraper_meta (target, last_success_at, consecutive_failures) VALUES (?1, ?2, 0) ON CONFLICT(target) DO UPDATE SET last_success_at = excluded.last_success_at, consecutive_failures = 0",
+                rusqlite::params![target, time],
+            )?,
+            Some(error) => Self::execute_cached(
+                connection,
+                "INSERT INTO scraper_meta (target, last_error_at, last_error_text, consecutive_failures) VALUES (?1, ?2, ?3, 1) ON CONFLICT(target) DO UPDATE SET last_error_at = excluded.last_error_at, last_error_text = excluded.last_error_text, consecutive_failures = consecutive_failures + 1",
+                rusqlite::params![target, time, error],
+            )?,
+        };
+        Ok(())
+    }
+
+    /**
+    Insert the headcount behind the occupancy at `time` into `{table_name}_headcount`.
+    */
+    pub fn insert_headcount(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime,
+        headcount: &Headcount
+    ) -> DatabaseResult<()> {
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {}_headcount (time, total, capacity, staff, student, other) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                table_name
+            ),
+            rusqlite::params![
+                uk_local_to_stored(time),
+                headcount.total,
+                headcount.capacity,
+                headcount.staff,
+                headcount.student,
+                headcount.other
+            ],
+        )?;
+        Ok(())
+    }
+
+    /**
+    Insert a crowdsourced report into `{table_name}_reports`.
+    */
+    pub fn insert_report(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime,
+        occupancy: u16,
+        note: Option<&str>
+    ) -> DatabaseResult<()> {
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {}_reports (time, occupancy, note) VALUES (?1, ?2, ?3)",
+                table_name
+            ),
+            rusqlite::params![uk_local_to_stored(time), occupancy, note],
+        )?;
+        Ok(())
+    }
+    
+    /**
+    Insert feedback on the `model` predictions for `date` into `{table_name}_feedback`.
+
+    Returns the id of the new row.
+    */
+    pub fn insert_feedback(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime,
+        date: NaiveDate,
+        model: &str,
+        rating: &str,
+        comment: Option<&str>
+    ) -> DatabaseResult<i64> {
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {}_feedback (time, date, model, rating, comment) VALUES (?1, ?2, ?3, ?4, ?5)",
+                table_name
+            ),
+            rusqlite::params![uk_local_to_stored(time), date.to_string(), model, rating, comment],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    /**
+    Overwrite the rating and comment of the feedback with the id `id`.
+
+    Returns `Ok(false)` if there is no such row.
+    */
+    pub fn update_feedback(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        id: i64,
+        time: NaiveDateTime,
+        rating: &str,
+        comment: Option<&str>
+    ) -> DatabaseResult<bool> {
+        let updated = Self::execute_cached(
+            connection,
+            &format!(
+                "UPDATE {}_feedback SET time = ?2, rating = ?3, comment = ?4 WHERE id = ?1",
+                table_name
+            ),
+            rusqlite::params![id, uk_local_to_stored(time), rating, comment],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /**
+    Insert or overwrite the occupancy at exactly `time`.
+
+    Returns `Ok(Some(u16))` with the previous occupancy if a row was overwritten.
+    Returns `Ok(None)` if a new row was inserted.
+    Returns an `Err` if the occupancy is out of range, see `validate_occupancy`.
+    */
+    pub fn upsert_occupancy(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime,
+        occupancy: u16,
+    ) -> DatabaseResult<Option<u16>> {
+        Self::validate_occupancy(table_name, occupancy)?;
+        let time = uk_local_to_stored(time);
+        // Taking the write lock up front means waiting on another writer goes through the busy
+        // timeout, where upgrading a read lock would fail straight away
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        let previous: Option<u16> = Self::query_row_cached(
+            &transaction,
+            &format!("SELECT occupancy FROM {} WHERE time = ?1", table_name),
+            rusqlite::params![time],
+            |row| row.get(0),
+        )
+        .optional()?;
+        match previous {
+            Some(_) => Self::execute_cached(
+                &transaction,
+                &format!("UPDATE {} SET occupancy = ?2 WHERE time = ?1", table_name),
+                rusqlite::params![time, occupancy],
+            )?,
+            None => Self::execute_cached(
+                &transaction,
+                &format!("INSERT INTO {} (time, occupancy) VALUES (?1, ?2)", table_name),
+                rusqlite::params![time, occupancy],
+            )?,
+        };
+        transaction.commit()?;
+        Ok(previous)
+    }
+
+    /**
+    Recompute the hours of `{table_name}_hourly` that `from` to `to` (inclusive) touch from the
+    readings.
+
+    Each of those hours is rebuilt whole, so after any change to the readings in the range the
+    aggregates are the same as if computed from scratch. Hours left without readings lose
+    their row.
+    */
+    pub fn refresh_hourly(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime
+    ) -> DatabaseResult<()> {
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        Self::rebuild_hours(&transaction, table_name, from, to)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /**
+    `refresh_hourly` in the transaction the caller has open, so the hours change along with the
+    readings they are computed from or not at all.
+    */
+    fn rebuild_hours(
+        connection: &Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime
+    ) -> DatabaseResult<()> {
+        let start = uk_local_to_stored(from).div_euclid(HOUR) * HOUR;
+        let end = uk_local_to_stored(to).div_euclid(HOUR) * HOUR + HOUR;
+        Self::execute_cached(
+            connection,
+            &format!("DELETE FROM {}_hourly WHERE time >= ?1 AND time < ?2", table_name),
+            rusqlite::params![start, end],
+        )?;
+        Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT INTO {}_hourly (time, mean, min, max, samples) {}",
+                table_name, select_hourly(table_name, "WHERE time >= ?1 AND time < ?2")
+            ),
+            rusqlite::params![start, end],
+        )?;
+        Ok(())
+    }
+
+    /**
+    Rebuild all of `{table_name}_hourly` from the readings.
+
+    Hours whose readings have been pruned are kept, as there is nothing left to rebuild them from.
+    Returns the number of hours written.
+    */
+    pub fn backfill_hourly(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str
+    ) -> DatabaseResult<usize> {
+        Ok(Self::execute_cached(
+            connection,
+            &format!(
+                "INSERT OR REPLACE INTO {}_hourly (time, mean, min, max, samples) {}",
+                table_name, select_hourly(table_name, "")
+            ),
+            (),
+        )?)
+    }
+
+    /**
+    Insert many occupancy data into the database.
+
+    `data` is a `Vec` of tuples of (time, occupancy). Times that are already stored are
+    overwritten, as in `insert_one_occupancy`, but readings close to them are kept. Nothing is
+    written if any occupancy is out of range.
+    */
+    pub fn insert_many_occupancy(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        data: Vec<(NaiveDateTime, u16)>
+    ) -> DatabaseResult<()> {
+        for (_, occupancy) in &data {
+            Self::validate_occupancy(table_name, *occupancy)?;
+        }
+        let mut statement = connection.prepare_cached(&format!(
+            "INSERT INTO {} (time, occupancy) VALUES (?1, ?2) {}",
+            table_name, OVERWRITE_OCCUPANCY
+        ))?;
+
+        for (time, occupancy) in data {
+            statement.execute(rusqlite::params![uk_local_to_stored(time), occupancy])?;
+        }
+        Ok(())
+    }
+
+    /**
+    Insert predictions into the prediction table `table_name`, recording that they were generated
+    at `generated_at` by `model_version`.
+
+    A prediction already stored for a time is overwritten along with when and by what it was
+    generated.
+    */
+    pub fn insert_many_predictions(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        data: Vec<(NaiveDateTime, u16)>,
+        generated_at: NaiveDateTime,
+        model_version: &str
+    ) -> DatabaseResult<()> {
+        let mut statement = connection.prepare_cached(&format!(
+            "INSERT INTO {} (time, occupancy, generated_at, model_version) VALUES (?1, ?2, ?3, ?4) \
+            ON CONFLICT(time) DO UPDATE SET occupancy = excluded.occupancy, \
+            generated_at = excluded.generated_at, model_version = excluded.model_version",
+            table_name
+        ))?;
+
+        let generated_at = uk_local_to_stored(generated_at);
+        for (time, occupancy) in data {
+            statement.execute(rusqlite::params![
+                uk_local_to_stored(time),
+                occupancy,
+                generated_at,
+                model_version
+            ])?;
+        }
+        Ok(())
+    }
+
+}
+
+impl Database for SqliteDatabase {
+    type Connection = PooledConnection<SqliteConnectionManager>;
+
+    /**
+    The reading and its headcount are written in one transaction, the hourly aggregates of its
+    hour are refreshed after it.
+    */
+    fn insert_readings(
+        connection: &Self::Connection,
+        readings: &[ScrapedReading]
+    ) -> DatabaseResult<()> {
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        // The span of the readings of each table, to refresh its hours once at the end
+        let mut spans: HashMap<&str, (NaiveDateTime, NaiveDateTime)> = HashMap::new();
+        for reading in readings {
+            let (table_name, time) = (reading.table_name.as_str(), reading.time);
+            Self::insert_one_occupancy(connection, table_name, time, reading.occupancy)?;
+            // The headcounts of the readings replaced go with them
+            Self::execute_cached(
+                connection,
+                &format!(
+                    "DELETE FROM {}_headcount WHERE time > ?1 - ?2 AND time < ?1 + ?2",
+                    table_name
+                ),
+                rusqlite::params![uk_local_to_stored(time), REPLACE_WITHIN],
+            )?;
+            if let Some(headcount) = &reading.headcount {
+                Self::insert_headcount(connection, table_name, time, headcount)?;
+            }
+            let span = spans.entry(table_name).or_insert((time, time));
+            *span = (span.0.min(time), span.1.max(time));
+        }
+        // A reading replaced can be in the hour before or after
+        let window = chrono::Duration::seconds(REPLACE_WITHIN);
+        for (table_name, (from, to)) in spans {
+            Self::rebuild_hours(&transaction, table_name, from - window, to + window)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn replace_predictions(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        rows: Vec<(NaiveDateTime, u16)>,
+        generated_at: NaiveDateTime,
+        model_version: &str
+    ) -> DatabaseResult<()> {
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        Self::delete_range(connection, table_name, from, to)?;
+        Self::insert_many_predictions(connection, table_name, rows, generated_at, model_version)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn insert_one_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+        schedule: &Schedule
+    ) -> DatabaseResult<()> {
+        Self::insert_one_schedule(connection, table_name, date, schedule)
+    }
+
+    fn delete_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime
+    ) -> DatabaseResult<usize> {
+        let transaction = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+        let deleted = Self::delete_range(connection, table_name, from, to)?;
+        Self::rebuild_hours(&transaction, table_name, from, to)?;
+        transaction.commit()?;
+        Ok(deleted)
+    }
+
+    fn record_scrape(
+        connection: &Self::Connection,
+        target: &str,
+        time: NaiveDateTime,
+        error: Option<&str>
+    ) -> DatabaseResult<()> {
+        Self::record_scrape(connection, target, time, error)
+    }
+
+    fn query_single_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Self::query_single_day(connection, table_name, date)
+    }
+
+    fn query_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Self::query_range(connection, table_name, from, to)
+    }
+
+    fn query_last_day(
+        connection: &Self::Connection,
+        table_name: &str
+    ) -> DatabaseResult<Option<String>> {
+        Self::query_last_day(connection, table_name)
+    }
+
+    fn query_last_reading(
+        connection: &Self::Connection,
+        table_name: &str
+    ) -> DatabaseResult<Option<(String, u16)>> {
+        Self::query_last_reading(connection, table_name)
+    }
+
+    fn query_last_time_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<String>> {
+        Self::query_last_time_on_day(connection, table_name, date)
+    }
+
+    fn query_generated_at(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<String>> {
+        Self::query_generated_at(connection, table_name, date)
+    }
+
+    fn query_headcount_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<(String, Headcount)>> {
+        Self::query_headcount_on_day(connection, table_name, date)
+    }
+
+    fn query_anomalies_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Self::query_anomalies_on_day(connection, table_name, date)
+    }
+
+    fn query_single_day_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate
+    ) -> DatabaseResult<Option<Schedule>> {
+        Self::query_single_day_schedule(connection, table_name, date)
+    }
+
+    fn query_schedule_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate
+    ) -> DatabaseResult<BTreeMap<NaiveDate, Schedule>> {
+        Self::query_schedule_range(connection, table_name, from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::test_support::{date, memory_pool, seed_readings, seed_schedule, week};
+
+    use super::*;
+
+    /// The occupancy stored for `time` in `table_name`, if there is a reading.
+    fn stored_occupancy(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        time: NaiveDateTime
+    ) -> Option<u16> {
+        connection
+            .query_row(
+                &format!("SELECT occupancy FROM {} WHERE time = ?1", table_name),
+                rusqlite::params![uk_local_to_stored(time)],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+    }
+
+    #[test]
+    fn upserting_a_new_time_inserts_it() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let time = date(2024, 5, 1).and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(SqliteDatabase::upsert_occupancy(&connection, "gym", time, 40), Ok(None));
+        assert_eq!(stored_occupancy(&connection, "gym", time), Some(40));
+    }
+
+    #[test]
+    fn upserting_a_stored_time_overwrites_it() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let time = date(2024, 5, 1).and_hms_opt(10, 0, 0).unwrap();
+        SqliteDatabase::upsert_occupancy(&connection, "gym", time, 40).unwrap();
+        assert_eq!(SqliteDatabase::upsert_occupancy(&connection, "gym", time, 55), Ok(Some(40)));
+        assert_eq!(stored_occupancy(&connection, "gym", time), Some(55));
+        let rows: i64 = connection.query_row("SELECT COUNT(*) FROM gym", (), |row| row.get(0)).unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn upserting_an_occupancy_out_of_range_is_refused() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let time = date(2024, 5, 1).and_hms_opt(10, 0, 0).unwrap();
+        let range = occupancy_range("gym");
+        let occupancy = range.end() + 1;
+        assert_eq!(
+            SqliteDatabase::upsert_occupancy(&connection, "gym", time, occupancy),
+            Err(DatabaseError::OutOfRange { table_name: "gym".to_string(), occupancy, range })
+        );
+        assert_eq!(stored_occupancy(&connection, "gym", time), None);
+    }
+
+    #[test]
+    fn a_schedule_range_crossing_a_change_has_each_schedule_from_its_date() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        SqliteDatabase::insert_one_schedule(&connection, "gym", date(2024, 5, 1), &week(700)).unwrap();
+        SqliteDatabase::insert_one_schedule(&connection, "gym", date(2024, 5, 10), &week(800)).unwrap();
+
+        let schedules =
+            SqliteDatabase::query_schedule_range(&connection, "gym", date(2024, 5, 6), date(2024, 5, 12)).unwrap();
+        assert_eq!(schedules.len(), 7);
+        assert_eq!(schedules[&date(2024, 5, 6)], week(700));
+        assert_eq!(schedules[&date(2024, 5, 9)], week(700));
+        assert_eq!(schedules[&date(2024, 5, 10)], week(800));
+        assert_eq!(schedules[&date(2024, 5, 12)], week(800));
+    }
+
+    #[test]
+    fn a_schedule_range_before_the_first_schedule_has_the_oldest() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        SqliteDatabase::insert_one_schedule(&connection, "gym", date(2024, 5, 1), &week(700)).unwrap();
+        SqliteDatabase::insert_one_schedule(&connection, "gym", date(2024, 5, 10), &week(800)).unwrap();
+
+        let schedules =
+            SqliteDatabase::query_schedule_range(&connection, "gym", date(2024, 4, 29), date(2024, 5, 2)).unwrap();
+        assert_eq!(schedules.len(), 4);
+        assert!(schedules.values().all(|schedule| *schedule == week(700)));
+    }
+
+    #[test]
+    fn a_schedule_range_is_empty_without_any_schedule() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let schedules =
+            SqliteDatabase::query_schedule_range(&connection, "gym", date(2024, 5, 6), date(2024, 5, 12)).unwrap();
+        assert!(schedules.is_empty());
+    }
+
+    /// The rows of `{table_name}_hourly` as (hour, mean, min, max, samples), oldest first.
+    fn stored_hours(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str
+    ) -> Vec<(i64, f64, u16, u16, usize)> {
+        let mut statement = connection
+            .prepare(&format!("SELECT time, mean, min, max, samples FROM {}_hourly ORDER BY time", table_name))
+            .unwrap();
+        let rows = statement
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    /// The hourly aggregates of the readings of `table_name`, worked out one reading at a time.
+    fn brute_force_hours(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str
+    ) -> Vec<(i64, f64, u16, u16, usize)> {
+        let mut statement = connection.prepare(&format!("SELECT time, occupancy FROM {}", table_name)).unwrap();
+        let readings = statement
+            .query_map((), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u16>(1)?)))
+            .unwrap();
+        let mut hours: BTreeMap<i64, Vec<u16>> = BTreeMap::new();
+        for reading in readings {
+            let (time, occupancy) = reading.unwrap();
+            hours.entry(time.div_euclid(HOUR) * HOUR).or_default().push(occupancy);
+        }
+        hours
+            .into_iter()
+            .map(|(hour, occupancies)| {
+                let sum: f64 = occupancies.iter().map(|&occupancy| f64::from(occupancy)).sum();
+                (
+                    hour,
+                    sum / occupancies.len() as f64,
+                    *occupancies.iter().min().unwrap(),
+                    *occupancies.iter().max().unwrap(),
+                    occupancies.len(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_hourly_aggregates_are_those_of_the_readings() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        // Readings every few minutes through the day the clocks go back, written in batches
+        let start = date(2024, 10, 27).and_hms_opt(0, 0, 0).unwrap();
+        let mut state: u32 = 7;
+        let mut next = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            state >> 16
+        };
+        let mut minute = 0;
+        for _ in 0..40 {
+            let batch: Vec<(NaiveDateTime, u16)> = (0..8)
+                .map(|_| {
+                    minute += 2 + i64::from(next() % 7);
+                    (start + chrono::Duration::minutes(minute), (next() % 101) as u16)
+                })
+                .collect();
+            seed_readings(&connection, "gym", &batch);
+        }
+        // Overwriting a reading, replacing one close to it and deleting a few hours
+        seed_readings(&connection, "gym", &[(start + chrono::Duration::minutes(minute), 100)]);
+        seed_readings(&connection, "gym", &[(start + chrono::Duration::seconds(minute * 60 - 30), 0)]);
+        let deleted = <SqliteDatabase as Database>::delete_range(
+            &connection,
+            "gym",
+            start + chrono::Duration::minutes(200),
+            start + chrono::Duration::minutes(330)
+        )
+        .unwrap();
+        assert!(deleted > 0);
+
+        let stored = stored_hours(&connection, "gym");
+        let expected = brute_force_hours(&connection, "gym");
+        assert!(expected.len() > 10);
+        assert_eq!(stored.len(), expected.len());
+        for (stored, expected) in stored.iter().zip(&expected) {
+            assert_eq!((stored.0, stored.2, stored.3, stored.4), (expected.0, expected.2, expected.3, expected.4));
+            assert!((stored.1 - expected.1).abs() < 1e-9, "{:?} {:?}", stored, expected);
+        }
+    }
+
+    #[test]
+    fn readings_are_not_stored_without_their_hour() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        connection.execute_batch("DROP TABLE gym_hourly").unwrap();
+        let time = date(2024, 5, 1).and_hms_opt(10, 0, 0).unwrap();
+        let reading = ScrapedReading { table_name: "gym".to_string(), time, occupancy: 40, headcount: None };
+        assert!(SqliteDatabase::insert_readings(&connection, &[reading]).is_err());
+        assert_eq!(stored_occupancy(&connection, "gym", time), None);
+    }
+
+    /// Readings around the night the clocks went back, with none on the 28th.
+    fn readings_around_the_clocks_going_back(connection: &PooledConnection<SqliteConnectionManager>) {
+        let readings = [
+            (date(2024, 10, 26).and_hms_opt(23, 30, 0).unwrap(), 80),
+            (date(2024, 10, 27).and_hms_opt(0, 30, 0).unwrap(), 50),
+            (date(2024, 10, 27).and_hms_opt(10, 0, 0).unwrap(), 70),
+            (date(2024, 10, 27).and_hms_opt(23, 59, 0).unwrap(), 10),
+            (date(2024, 10, 29).and_hms_opt(9, 0, 0).unwrap(), 20),
+        ];
+        seed_readings(connection, "gym", &readings);
+    }
+
+    #[test]
+    fn daily_peaks_are_the_highest_reading_of_each_uk_day() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        readings_around_the_clocks_going_back(&connection);
+
+        let peaks: Vec<(String, String, u16)> =
+            SqliteDatabase::query_daily_peaks(&connection, "gym", date(2024, 10, 25), date(2024, 10, 29))
+                .unwrap()
+                .into_iter()
+                .map(|peak| (peak.date, peak.time, peak.occupancy))
+                .collect();
+        let expected = [
+            ("2024-10-26", "2024-10-26T23:30:00", 80),
+            ("2024-10-27", "2024-10-27T10:00:00", 70),
+            ("2024-10-29", "2024-10-29T09:00:00", 20),
+        ];
+        assert_eq!(peaks, expected.map(|(day, time, occupancy)| (day.to_string(), time.to_string(), occupancy)));
+    }
+
+    #[test]
+    fn daily_counts_are_of_each_uk_day_and_match_the_hourly_samples() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        readings_around_the_clocks_going_back(&connection);
+
+        let (from, to) = (date(2024, 10, 25), date(2024, 10, 29));
+        let counts = SqliteDatabase::query_daily_counts(&connection, "gym", from, to).unwrap();
+        let expected = [("2024-10-26", 1), ("2024-10-27", 3), ("2024-10-29", 1)];
+        assert_eq!(counts, expected.map(|(day, count)| (day.to_string(), count)));
+        assert_eq!(SqliteDatabase::query_daily_samples(&connection, "gym", from, to).unwrap(), counts);
+    }
+
+    #[test]
+    fn the_last_n_readings_are_the_newest_first() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        assert_eq!(SqliteDatabase::query_last_n_readings(&connection, "gym", 3).unwrap(), Parsed::new());
+
+        let day = date(2024, 5, 8);
+        let readings: Vec<(NaiveDateTime, u16)> =
+            (0..5).map(|i| (day.and_hms_opt(9 + i, 0, 0).unwrap(), i as u16)).collect();
+        seed_readings(&connection, "gym", &readings);
+        let newest: Vec<OccupancyReading> =
+            readings.iter().rev().map(|&(time, occupancy)| OccupancyReading { time, occupancy }).collect();
+
+        let last = SqliteDatabase::query_last_n_readings(&connection, "gym", 3).unwrap();
+        assert_eq!(last.rows, newest[..3]);
+        // More than there are is all of them
+        let last = SqliteDatabase::query_last_n_readings(&connection, "gym", 50).unwrap();
+        assert_eq!(last.rows, newest);
+        assert_eq!(last.skipped, 0);
+    }
+
+    #[test]
+    fn skipped_readings_do_not_count_towards_the_last_n() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 5, 8);
+        let readings: Vec<(NaiveDateTime, u16)> =
+            (0..4).map(|i| (day.and_hms_opt(9 + i, 0, 0).unwrap(), i as u16)).collect();
+        seed_readings(&connection, "gym", &readings);
+        // Text sorts before every number, so these are read first
+        for hour in 13..18 {
+            connection
+                .execute("INSERT INTO gym (time, occupancy) VALUES (?1, 7)", [format!("2024-05-08 {}:00:00", hour)])
+                .unwrap();
+        }
+
+        let last = SqliteDatabase::query_last_n_readings(&connection, "gym", 2).unwrap();
+        assert_eq!(last.rows.iter().map(|reading| reading.occupancy).collect::<Vec<u16>>(), [3, 2]);
+        assert_eq!(last.skipped, 5);
+        let last = SqliteDatabase::query_last_n_readings(&connection, "gym", 10).unwrap();
+        assert_eq!(last.rows.len(), 4);
+        assert_eq!(last.skipped, 5);
+    }
+
+    /// How many statements `connection` has prepared and not yet finalized, cached ones included.
+    fn prepared_statements(connection: &Connection) -> usize {
+        // Only walks SQLite's list of the connection's statements, which nothing changes meanwhile
+        let handle = unsafe { connection.handle() };
+        let mut count = 0;
+        let mut statement = unsafe { rusqlite::ffi::sqlite3_next_stmt(handle, std::ptr::null_mut()) };
+        while !statement.is_null() {
+            count += 1;
+            statement = unsafe { rusqlite::ffi::sqlite3_next_stmt(handle, statement) };
+        }
+        count
+    }
+
+    #[test]
+    fn statements_are_prepared_once_and_kept_in_a_cache_of_the_configured_size() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        seed_readings(&connection, "gym", &[(date(2024, 5, 8).and_hms_opt(9, 0, 0).unwrap(), 40)]);
+        let prepared = prepared_statements(&connection);
+
+        // The first query prepares its statement and keeps it, the ones after use it again
+        SqliteDatabase::query_last_reading(&connection, "gym").unwrap();
+        assert_eq!(prepared_statements(&connection), prepared + 1);
+        for _ in 0..5 {
+            SqliteDatabase::query_last_reading(&connection, "gym").unwrap();
+        }
+        assert_eq!(prepared_statements(&connection), prepared + 1);
+
+        // Past the capacity the least recently used ones are finalized
+        for i in 0..2 * STATEMENT_CACHE_CAPACITY {
+            connection.prepare_cached(&format!("SELECT {}", i)).unwrap();
+        }
+        assert_eq!(prepared_statements(&connection), STATEMENT_CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn a_reading_scraped_again_after_a_restart_replaces_the_one_before() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 5, 8);
+        let headcount = |total: u32| Headcount { total, capacity: 100, staff: 0, student: total, other: 0 };
+        let scraped = |time: NaiveDateTime, occupancy: u16| ScrapedReading {
+            table_name: "gym".to_string(),
+            time,
+            occupancy,
+            headcount: Some(headcount(occupancy as u32)),
+        };
+        let before = day.and_hms_opt(10, 57, 40).unwrap();
+        let scrape = day.and_hms_opt(10, 59, 40).unwrap();
+        // Restarted by systemd and scraped again straight away, in the next hour
+        let restarted = day.and_hms_opt(11, 0, 10).unwrap();
+        let after = restarted + chrono::Duration::seconds(REPLACE_WITHIN);
+        SqliteDatabase::insert_readings(&connection, &[scraped(before, 30), scraped(scrape, 40)]).unwrap();
+        SqliteDatabase::insert_readings(&connection, &[scraped(restarted, 45)]).unwrap();
+        SqliteDatabase::insert_readings(&connection, &[scraped(after, 50)]).unwrap();
+
+        let stored = SqliteDatabase::query_single_day(&connection, "gym", day).unwrap();
+        assert_eq!(
+            stored,
+            [
+                OccupancyReading { time: before, occupancy: 30 },
+                OccupancyReading { time: restarted, occupancy: 45 },
+                OccupancyReading { time: after, occupancy: 50 },
+            ]
+        );
+        let headcounts = SqliteDatabase::query_headcount_on_day(&connection, "gym", day).unwrap();
+        assert_eq!(
+            headcounts.into_iter().map(|(_, headcount)| headcount).collect::<Vec<Headcount>>(),
+            [headcount(30), headcount(45), headcount(50)]
+        );
+        // The hour the replaced reading was in doesn't keep it either
+        assert_eq!(stored_hours(&connection, "gym"), brute_force_hours(&connection, "gym"));
+    }
+
+    #[test]
+    fn midnight_starts_the_new_day_and_a_second_before_ends_the_old_one() {
+        // A day with the clocks going forward, a normal one and one with them going back
+        for (day, hours) in [(date(2024, 3, 31), 23), (date(2024, 5, 8), 24), (date(2024, 10, 27), 25)] {
+            let (start, end) = SqliteDatabase::day_bounds(day, day);
+            assert_eq!(end - start, hours * HOUR, "{}", day);
+
+            let last_second = |date: NaiveDate| uk_local_to_stored(date.and_hms_opt(23, 59, 59).unwrap());
+            let midnight = |date: NaiveDate| uk_local_to_stored(date.and_time(NaiveTime::MIN));
+            let (before, after) = (day.pred_opt().unwrap(), day.succ_opt().unwrap());
+            assert!(last_second(before) < start);
+            assert_eq!(midnight(day), start);
+            assert!(last_second(day) < end);
+            assert_eq!(midnight(after), end);
+        }
+
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 5, 8);
+        let (before, after) = (day.pred_opt().unwrap(), day.succ_opt().unwrap());
+        let readings = [
+            (before.and_hms_opt(23, 59, 59).unwrap(), 10),
+            (day.and_time(NaiveTime::MIN), 20),
+            (day.and_hms_opt(23, 59, 59).unwrap(), 30),
+            (after.and_time(NaiveTime::MIN), 40),
+        ];
+        // Not replacing the readings a second apart as the scraper's would be, see `REPLACE_WITHIN`
+        SqliteDatabase::insert_many_occupancy(&connection, "gym", readings.to_vec()).unwrap();
+        let occupancies = |date: NaiveDate| -> Vec<u16> {
+            SqliteDatabase::query_single_day(&connection, "gym", date)
+                .unwrap()
+                .iter()
+                .map(|reading| reading.occupancy)
+                .collect()
+        };
+        assert_eq!(occupancies(before), [10]);
+        assert_eq!(occupancies(day), [20, 30]);
+        assert_eq!(occupancies(after), [40]);
+    }
+
+    #[test]
+    fn readings_in_the_same_minute_are_deduplicated_to_the_last() {
+        let at = |minute: u32, second: u32| date(2024, 5, 8).and_hms_opt(10, minute, second).unwrap();
+        let reading = |time: NaiveDateTime, occupancy: u16| OccupancyReading { time, occupancy };
+        // Ordered by time and then id, the same time twice being two rows
+        let data = vec![
+            reading(at(0, 5), 10),
+            reading(at(0, 30), 11),
+            reading(at(0, 30), 12),
+            reading(at(1, 0), 20),
+            reading(at(2, 10), 30),
+            reading(at(2, 50), 31),
+            reading(at(2, 59), 32),
+            reading(at(4, 0), 40),
+        ];
+        let deduped = SqliteDatabase::dedup_minutes(data);
+        assert_eq!(deduped, [reading(at(0, 30), 12), reading(at(1, 0), 20), reading(at(2, 59), 32), reading(at(4, 0), 40)]);
+        assert!(deduped.windows(2).all(|pair| SqliteDatabase::minute(pair[0].time) < SqliteDatabase::minute(pair[1].time)));
+        assert!(SqliteDatabase::dedup_minutes(Vec::new()).is_empty());
+
+        // Read back, a range is the same
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let stored = [(at(0, 5), 10), (at(0, 30), 11), (at(1, 0), 20), (at(2, 10), 30), (at(2, 59), 32), (at(4, 0), 40)];
+        SqliteDatabase::insert_many_occupancy(&connection, "gym", stored.to_vec()).unwrap();
+        let range = SqliteDatabase::query_range(&connection, "gym", at(0, 0), at(5, 0)).unwrap();
+        assert_eq!(range, [reading(at(0, 30), 11), reading(at(1, 0), 20), reading(at(2, 59), 32), reading(at(4, 0), 40)]);
+    }
+
+    #[test]
+    fn corrupted_rows_are_skipped_and_counted() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let at = |minute: u32| date(2024, 5, 8).and_hms_opt(9, minute, 0).unwrap();
+        seed_readings(&connection, "gym", &[(at(0), 40), (at(20), 50)]);
+        // Rows the scraper can't have written, as left by hand or an older version
+        connection.execute_batch("PRAGMA ignore_check_constraints = ON").unwrap();
+        let corrupt = [
+            ("gym", at(10), Value::Integer(-5)),
+            ("gym_prediction_knn", at(40), Value::Text("lots".to_string())),
+            ("gym_prediction_knn", at(50), Value::Integer(70000)),
+        ];
+        for (table_name, time, occupancy) in corrupt {
+            connection
+                .execute(
+                    &format!("INSERT INTO {} (time, occupancy) VALUES (?1, ?2)", table_name),
+                    rusqlite::params![uk_local_to_stored(time), occupancy],
+                )
+                .unwrap();
+        }
+        connection
+            .execute("INSERT INTO gym_prediction_knn (time, occupancy) VALUES (?1, 60)", [uk_local_to_stored(at(30))])
+            .unwrap();
+
+        let agnostic = SqliteDatabase::query_range_agnostic(&connection, "gym", at(0), at(59)).unwrap();
+        assert_eq!(
+            agnostic.rows,
+            [
+                (OccupancyReading { time: at(0), occupancy: 40 }, Provenance::Actual),
+                (OccupancyReading { time: at(20), occupancy: 50 }, Provenance::Actual),
+                (OccupancyReading { time: at(30), occupancy: 60 }, Provenance::Predicted),
+            ]
+        );
+        assert_eq!(agnostic.skipped, 3);
+
+        let range = SqliteDatabase::query_range(&connection, "gym", at(0), at(59)).unwrap();
+        assert_eq!(range.iter().map(|reading| reading.occupancy).collect::<Vec<u16>>(), [40, 50]);
+    }
+
+    #[test]
+    fn opening_hours_closing_at_or_past_midnight_run_into_the_next_day() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let (wednesday, thursday) = (date(2024, 5, 8), date(2024, 5, 9));
+        let mut timings = [Daily::new_open(1800, 2400); 7];
+        timings[thursday.weekday().num_days_from_monday() as usize] = Daily::new_open(2200, 130);
+        seed_schedule(&connection, "gym", wednesday, &Schedule::from_timings(timings));
+
+        assert_eq!(
+            SqliteDatabase::open_hours(&connection, "gym", wednesday).unwrap(),
+            Some((wednesday.and_hms_opt(18, 0, 0).unwrap(), thursday.and_time(NaiveTime::MIN)))
+        );
+        assert_eq!(
+            SqliteDatabase::open_hours(&connection, "gym", thursday).unwrap(),
+            Some((thursday.and_hms_opt(22, 0, 0).unwrap(), date(2024, 5, 10).and_hms_opt(1, 30, 0).unwrap()))
+        );
+
+        // Readings every 10 minutes through both evenings leave no gap before either closing
+        let readings: Vec<(NaiveDateTime, u16)> = (0..=36)
+            .map(|i| (wednesday.and_hms_opt(18, 0, 0).unwrap() + chrono::Duration::minutes(i * 10), 20))
+            .chain((0..=21).map(|i| (thursday.and_hms_opt(22, 0, 0).unwrap() + chrono::Duration::minutes(i * 10), 20)))
+            .collect();
+        seed_readings(&connection, "gym", &readings);
+        let gaps = SqliteDatabase::find_gaps(
+            &connection,
+            "gym",
+            wednesday.and_time(NaiveTime::MIN),
+            date(2024, 5, 10).and_hms_opt(6, 0, 0).unwrap(),
+            std::time::Duration::from_secs(600),
+        )
+        .unwrap();
+        assert_eq!(gaps, Vec::new());
+    }
+
+    #[test]
+    fn weekdays_grouped_in_sql_are_the_readings_of_a_range_on_that_weekday() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        // Every three hours and a bit for three weeks across the clocks going back
+        let start = date(2024, 10, 14).and_hms_opt(0, 7, 0).unwrap();
+        let readings: Vec<(NaiveDateTime, u16)> = (0..170)
+            .map(|i| (start + chrono::Duration::minutes(i * 187), (i % 101) as u16))
+            .collect();
+        seed_readings(&connection, "gym", &readings);
+
+        let (from, to) = (date(2024, 10, 15), date(2024, 10, 31));
+        let (first, last) = (from.and_hms_opt(6, 0, 0).unwrap(), to.and_hms_opt(18, 0, 0).unwrap());
+        let range = SqliteDatabase::query_range(&connection, "gym", from.and_time(NaiveTime::MIN), to.and_hms_opt(23, 59, 59).unwrap()).unwrap();
+        for weekday in [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Sat, Weekday::Sun] {
+            let on_weekday: Vec<OccupancyReading> =
+                range.iter().copied().filter(|reading| reading.time.weekday() == weekday).collect();
+            assert!(!on_weekday.is_empty());
+            assert_eq!(SqliteDatabase::query_weekday(&connection, "gym", weekday, from, to).unwrap(), on_weekday);
+
+            let within: Vec<OccupancyReading> =
+                on_weekday.into_iter().filter(|reading| reading.time >= first && reading.time <= last).collect();
+            let parsed = SqliteDatabase::query_weekday_range(&connection, "gym", weekday, first, last).unwrap();
+            assert_eq!(parsed.rows, within);
+            assert_eq!(parsed.skipped, 0);
+        }
+    }
 }
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{scraper::headcount::Headcount, timing::schedule::Schedule};
+
+use super::{error::DatabaseResult, sqlite::OccupancyReading, writer::ScrapedReading};
+
+/**
+The storage the scraper writes through and the server's /api/day and /api/from read from, so
+they can run on something other than SQLite, e.g. an in-memory fake.
+
+Like `SqliteDatabase`'s, every operation takes the connection it runs on, which the caller gets
+from its pool. Table names are the sanitized names of the registered locations, with
+`{name}_prediction_{model}` for predictions, and times are UK local as everywhere else.
+
+Setting up the tables, backups, maintenance, exports and the admin endpoints are still SQLite
+only, and use `SqliteDatabase` directly.
+*/
+pub trait Database {
+    /// What the operations run on.
+    type Connection;
+
+    /// Stores scraped readings along with the headcounts behind them, all at once, each
+    /// overwriting any already stored for its time.
+    fn insert_readings(
+        connection: &Self::Connection,
+        readings: &[ScrapedReading],
+    ) -> DatabaseResult<()>;
+
+    /// Replaces the predictions from `from` to `to` (inclusive) with `rows`, all at once, noting
+    /// they were generated at `generated_at` by `model_version`.
+    fn replace_predictions(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        rows: Vec<(NaiveDateTime, u16)>,
+        generated_at: NaiveDateTime,
+        model_version: &str,
+    ) -> DatabaseResult<()>;
+
+    /// Stores `schedule` as in effect from `date`, overwriting any already stored from then.
+    fn insert_one_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+        schedule: &Schedule,
+    ) -> DatabaseResult<()>;
+
+    /// Deletes the readings of `table_name` from `from` to `to` (inclusive), along with anything
+    /// kept about them such as hourly aggregates. Returns how many readings were deleted.
+    fn delete_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> DatabaseResult<usize>;
+
+    /// Notes how scraping `target` at `time` went, `error` is `None` for a successful scrape.
+    fn record_scrape(
+        connection: &Self::Connection,
+        target: &str,
+        time: NaiveDateTime,
+        error: Option<&str>,
+    ) -> DatabaseResult<()>;
+
+    /// The rows of `date`, ordered by time with one per minute.
+    fn query_single_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Vec<OccupancyReading>>;
+
+    /// The rows from `from` to `to` (inclusive), ordered by time with one per minute.
+    fn query_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> DatabaseResult<Vec<OccupancyReading>>;
+
+    /// The date of the newest reading, as `YYYY-MM-DD`. `None` if there are none.
+    fn query_last_day(
+        connection: &Self::Connection,
+        table_name: &str,
+    ) -> DatabaseResult<Option<String>>;
+
+    /// The newest reading with its time in `ISO_FORMAT`. `None` if there are none.
+    fn query_last_reading(
+        connection: &Self::Connection,
+        table_name: &str,
+    ) -> DatabaseResult<Option<(String, u16)>>;
+
+    /// The time of the newest row of `date` in `ISO_FORMAT`. `None` if there are none.
+    fn query_last_time_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<String>>;
+
+    /// When the newest predictions of `date` were generated. `None` if there are none, or they
+    /// were stored before it was noted.
+    fn query_generated_at(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<String>>;
+
+    /// The headcounts behind the readings of `date`, ordered by time.
+    fn query_headcount_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Vec<(String, Headcount)>>;
+
+    /// The readings of `date` marked as anomalies, ordered by time. They are among the ones
+    /// `query_single_day` returns.
+    fn query_anomalies_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Vec<OccupancyReading>>;
+
+    /// The schedule in effect on `date`, the newest stored from then or before. `None` if none
+    /// was yet.
+    fn query_single_day_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<Schedule>>;
+
+    /// The schedule in effect on each date from `from` to `to` (inclusive), or the oldest stored
+    /// for the dates before it. Empty if none was ever scraped.
+    fn query_schedule_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> DatabaseResult<BTreeMap<NaiveDate, Schedule>>;
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
+
+use crate::{
+    scraper::{headcount::Headcount, scraper::Scraper},
+    timing::{daily::Daily, schedule::Schedule},
+    ISO_FORMAT,
+};
+
+use super::{
+    error::DatabaseResult,
+    pool::{self, PoolConfig, Pools},
+    sqlite::{OccupancyReading, SqliteDatabase},
+    storage::Database,
+    writer::ScrapedReading,
+};
+
+/// Tells the databases of `memory_pool` and `TempDatabase` apart, as the tests run at the same
+/// time.
+static NEXT_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+/**
+A database set up the way the scraper does it, in memory, with every table created. It is a
+named one in shared-cache mode, so all `max_size` connections of the pool see the same data, and
+it is gone once the pool is dropped.
+*/
+pub fn memory_pool(max_size: u32) -> Pool<SqliteConnectionManager> {
+    let uri = format!(
+        "file:occupancy-test-{}-{}?mode=memory&cache=shared",
+        std::process::id(),
+        NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+    );
+    let manager = SqliteConnectionManager::file(uri)
+        .with_flags(
+            OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_CREATE,
+        )
+        .with_init(SqliteDatabase::init_connection);
+    // Every connection stays open, the database would go with the last one
+    let pool = Pool::builder()
+        .max_size(max_size)
+        .min_idle(Some(max_size))
+        .idle_timeout(None)
+        .build(manager)
+        .unwrap();
+    Scraper::create_tables(&Arc::new(pool.clone())).unwrap();
+    pool
+}
+
+/**
+A database file set up the way the scraper does it, with every table created, for tests that need
+the pools `main` uses or more than one process-wide connection, such as to another's lock.
+It is in a directory of its own under the temp directory, which is removed when it is dropped.
+*/
+pub struct TempDatabase {
+    pub pools: Pools,
+    pub path: PathBuf,
+    dir: PathBuf,
+}
+
+impl TempDatabase {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "occupancy-test-{}-{}",
+            std::process::id(),
+            NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.db");
+        let config = PoolConfig {
+            max_size: 4,
+            min_idle: 0,
+            connection_timeout: std::time::Duration::from_secs(1),
+        };
+        let pools = pool::build(&path, &config).unwrap();
+        Scraper::create_tables(&pools.read_write).unwrap();
+        Self { pools, path, dir }
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+/// A schedule open from `opening` until 22:00 every day.
+pub fn week(opening: u16) -> Schedule {
+    Schedule::from_timings([Daily::new_open(opening, 2200); 7])
+}
+
+/// Stores `readings` of `table_name` the way the scraper's writer does, without headcounts.
+pub fn seed_readings(
+    connection: &PooledConnection<SqliteConnectionManager>,
+    table_name: &str,
+    readings: &[(NaiveDateTime, u16)],
+) {
+    let readings: Vec<ScrapedReading> = readings
+        .iter()
+        .map(|&(time, occupancy)| ScrapedReading {
+            table_name: table_name.to_string(),
+            time,
+            occupancy,
+            headcount: None,
+        })
+        .collect();
+    SqliteDatabase::insert_readings(connection, &readings).unwrap();
+}
+
+/// Stores `schedule` of `table_name` as in effect from `date`.
+pub fn seed_schedule(
+    connection: &PooledConnection<SqliteConnectionManager>,
+    table_name: &str,
+    date: NaiveDate,
+    schedule: &Schedule,
+) {
+    SqliteDatabase::insert_one_schedule(connection, table_name, date, schedule).unwrap();
+}
+
+/// Stores `rows` as the predictions of `model` for `table_name`, generated at the first of them.
+pub fn seed_predictions(
+    connection: &PooledConnection<SqliteConnectionManager>,
+    table_name: &str,
+    model: &str,
+    rows: &[(NaiveDateTime, u16)],
+) {
+    let generated_at = rows[0].0;
+    SqliteDatabase::insert_many_predictions(
+        connection,
+        &format!("{}_prediction_{}", table_name, model),
+        rows.to_vec(),
+        generated_at,
+        "test",
+    )
+    .unwrap();
+}
+
+/**
+A `Database` kept in maps, for testing what runs on top of one without SQLite. A table exists
+once something is written to it, and reading one that doesn't reads nothing.
+
+Headcounts and anomalies aren't kept, so there are never any.
+*/
+pub struct MemoryDatabase {}
+
+/// What a `MemoryDatabase` holds. Clones of a connection share it, each new one is empty.
+#[derive(Default)]
+pub struct MemoryTables {
+    /// The rows of each readings and prediction table by time.
+    pub rows: HashMap<String, BTreeMap<NaiveDateTime, u16>>,
+    /// When the predictions of each prediction table were last generated.
+    pub generated_at: HashMap<String, NaiveDateTime>,
+    /// The schedules of each location by the date they are in effect from.
+    pub schedules: HashMap<String, BTreeMap<NaiveDate, Schedule>>,
+    /// The scrapes noted, in order.
+    pub scrapes: Vec<(String, NaiveDateTime, Option<String>)>,
+}
+
+impl MemoryDatabase {
+    fn range(
+        connection: &Mutex<MemoryTables>,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<OccupancyReading> {
+        connection
+            .lock()
+            .unwrap()
+            .rows
+            .get(table_name)
+            .map(|rows| {
+                rows.range(from..=to)
+                    .map(|(&time, &occupancy)| OccupancyReading { time, occupancy })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn day(date: NaiveDate) -> (NaiveDateTime, NaiveDateTime) {
+        (
+            date.and_time(NaiveTime::MIN),
+            date.and_hms_opt(23, 59, 59).unwrap(),
+        )
+    }
+}
+
+impl Database for MemoryDatabase {
+    type Connection = Arc<Mutex<MemoryTables>>;
+
+    fn insert_readings(
+        connection: &Self::Connection,
+        readings: &[ScrapedReading],
+    ) -> DatabaseResult<()> {
+        let mut tables = connection.lock().unwrap();
+        for reading in readings {
+            tables
+                .rows
+                .entry(reading.table_name.clone())
+                .or_default()
+                .insert(reading.time, reading.occupancy);
+        }
+        Ok(())
+    }
+
+    fn replace_predictions(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        rows: Vec<(NaiveDateTime, u16)>,
+        generated_at: NaiveDateTime,
+        _model_version: &str,
+    ) -> DatabaseResult<()> {
+        let mut tables = connection.lock().unwrap();
+        let table = tables.rows.entry(table_name.to_string()).or_default();
+        table.retain(|time, _| *time < from || *time > to);
+        table.extend(rows);
+        tables
+            .generated_at
+            .insert(table_name.to_string(), generated_at);
+        Ok(())
+    }
+
+    fn insert_one_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+        schedule: &Schedule,
+    ) -> DatabaseResult<()> {
+        connection
+            .lock()
+            .unwrap()
+            .schedules
+            .entry(table_name.to_string())
+            .or_default()
+            .insert(date, schedule.clone());
+        Ok(())
+    }
+
+    fn delete_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> DatabaseResult<usize> {
+        let mut tables = connection.lock().unwrap();
+        let Some(table) = tables.rows.get_mut(table_name) else {
+            return Ok(0);
+        };
+        let before = table.len();
+        table.retain(|time, _| *time < from || *time > to);
+        Ok(before - table.len())
+    }
+
+    fn record_scrape(
+        connection: &Self::Connection,
+        target: &str,
+        time: NaiveDateTime,
+        error: Option<&str>,
+    ) -> DatabaseResult<()> {
+        connection.lock().unwrap().scrapes.push((
+            target.to_string(),
+            time,
+            error.map(str::to_string),
+        ));
+        Ok(())
+    }
+
+    fn query_single_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        let (from, to) = Self::day(date);
+        Ok(Self::range(connection, table_name, from, to))
+    }
+
+    fn query_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Ok(Self::range(connection, table_name, from, to))
+    }
+
+    fn query_last_day(
+        connection: &Self::Connection,
+        table_name: &str,
+    ) -> DatabaseResult<Option<String>> {
+        Ok(Self::query_last_reading(connection, table_name)?
+            .map(|(time, _)| time[..10].to_string()))
+    }
+
+    fn query_last_reading(
+        connection: &Self::Connection,
+        table_name: &str,
+    ) -> DatabaseResult<Option<(String, u16)>> {
+        Ok(connection
+            .lock()
+            .unwrap()
+            .rows
+            .get(table_name)
+            .and_then(|rows| rows.last_key_value())
+            .map(|(time, &occupancy)| (time.format(ISO_FORMAT).to_string(), occupancy)))
+    }
+
+    fn query_last_time_on_day(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<String>> {
+        Ok(Self::query_single_day(connection, table_name, date)?
+            .last()
+            .map(|reading| reading.time.format(ISO_FORMAT).to_string()))
+    }
+
+    fn query_generated_at(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<String>> {
+        if Self::query_single_day(connection, table_name, date)?.is_empty() {
+            return Ok(None);
+        }
+        Ok(connection
+            .lock()
+            .unwrap()
+            .generated_at
+            .get(table_name)
+            .map(|time| time.format(ISO_FORMAT).to_string()))
+    }
+
+    fn query_headcount_on_day(
+        _connection: &Self::Connection,
+        _table_name: &str,
+        _date: NaiveDate,
+    ) -> DatabaseResult<Vec<(String, Headcount)>> {
+        Ok(Vec::new())
+    }
+
+    fn query_anomalies_on_day(
+        _connection: &Self::Connection,
+        _table_name: &str,
+        _date: NaiveDate,
+    ) -> DatabaseResult<Vec<OccupancyReading>> {
+        Ok(Vec::new())
+    }
+
+    fn query_single_day_schedule(
+        connection: &Self::Connection,
+        table_name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<Schedule>> {
+        Ok(connection
+            .lock()
+            .unwrap()
+            .schedules
+            .get(table_name)
+            .and_then(|schedules| schedules.range(..=date).next_back())
+            .map(|(_, schedule)| schedule.clone()))
+    }
+
+    fn query_schedule_range(
+        connection: &Self::Connection,
+        table_name: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> DatabaseResult<BTreeMap<NaiveDate, Schedule>> {
+        let tables = connection.lock().unwrap();
+        let Some(schedules) = tables.schedules.get(table_name) else {
+            return Ok(BTreeMap::new());
+        };
+        Ok(from
+            .iter_days()
+            .take_while(|date| *date <= to)
+            .filter_map(|date| {
+                let schedule = schedules
+                    .range(..=date)
+                    .next_back()
+                    .or_else(|| schedules.first_key_value())?;
+                Some((date, schedule.1.clone()))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{scraper::headcount::Headcount, timing::schedule::Schedule};
+
+use super::{
+    error::{DatabaseError, DatabaseResult},
+    storage::Database,
+};
+
+/// A scraped reading of `table_name`, along with the headcount behind it.
+pub struct ScrapedReading {
+    pub table_name: String,
+    pub time: NaiveDateTime,
+    pub occupancy: u16,
+    pub headcount: Option<Headcount>,
+}
+
+/// A write the scraper makes, applied by the `Writer`.
+pub enum WriteCommand {
+    /// A scraped reading. The hourly aggregates of its hour are refreshed after it. It can be
+    /// held back to be written along with others, see `Batching`.
+    Reading(ScrapedReading),
+    /// The schedule scraped on `date`, which is only written if it differs from the one in effect
+    /// on it.
+    Schedule {
+        table_name: String,
+        date: NaiveDate,
+        schedule: Schedule,
+    },
+    /// Predictions from `from` to `to` that replace the ones already in `table_name`, generated
+    /// at `generated_at` by `model_version`.
+    Predictions {
+        table_name: String,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        rows: Vec<(NaiveDateTime, u16)>,
+        generated_at: NaiveDateTime,
+        model_version: &'static str,
+    },
+    /// How scraping `target` at `time` went, see `SqliteDatabase::record_scrape`.
+    Scrape {
+        target: String,
+        time: NaiveDateTime,
+        error: Option<String>,
+    },
+}
+
+type Queued = (WriteCommand, oneshot::Sender<DatabaseResult<()>>);
+
+/// How the `Writer` batches readings, so that scraping often doesn't mean a transaction, and a
+/// WAL commit, for every reading.
+#[derive(Debug, Clone, Copy)]
+pub struct Batching {
+    /// How many readings are written together in one transaction. 1 writes each as it comes.
+    pub rows: usize,
+    /// The longest a reading is held back before it is written, however few there are.
+    pub interval: Duration,
+}
+
+/**
+Makes every write of the scraper on one connection, one command at a time.
+
+Commands are queued from any task with `write` and applied in order on a blocking thread, each in
+its own transaction. The scrape targets never wait on each other's writes for the database lock,
+and the runtime's workers never wait on the database at all.
+
+Readings are the exception when they are batched, see `Batching`. They are collected and written
+together once there are enough of them or the first has waited long enough, and once the `Writer`
+stops. One is acknowledged as soon as it is collected, so an error writing it is only logged.
+*/
+#[derive(Clone)]
+pub struct Writer {
+    sender: mpsc::Sender<Queued>,
+}
+
+impl Writer {
+    /// Starts applying commands on `connection` of `D`, batching readings as `batching` says. The
+    /// returned handle finishes once every clone of the `Writer` is dropped and the commands
+    /// queued before are applied, along with the readings still being collected.
+    pub fn start<D>(connection: D::Connection, batching: Batching) -> (Self, JoinHandle<()>)
+    where
+        D: Database,
+        D::Connection: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<Queued>();
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut batch = Vec::new();
+            // When the first reading of `batch` has to be written by
+            let mut deadline: Option<Instant> = None;
+            loop {
+                let queued = match deadline {
+                    Some(deadline) => {
+                        receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                // Whoever queued it may have stopped waiting, which is fine
+                match queued {
+                    Ok((WriteCommand::Reading(reading), reply)) if batching.rows <= 1 => {
+                        let _ = reply.send(D::insert_readings(&connection, &[reading]));
+                    }
+                    Ok((WriteCommand::Reading(reading), reply)) => {
+                        let _ = reply.send(Ok(()));
+                        batch.push(reading);
+                        deadline.get_or_insert_with(|| Instant::now() + batching.interval);
+                        if batch.len() >= batching.rows {
+                            Self::flush::<D>(&connection, &mut batch);
+                            deadline = None;
+                        }
+                    }
+                    Ok((command, reply)) => {
+                        let _ = reply.send(Self::apply::<D>(&connection, command));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        Self::flush::<D>(&connection, &mut batch);
+                        deadline = None;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        Self::flush::<D>(&connection, &mut batch);
+                        return;
+                    }
+                }
+            }
+        });
+        (Self { sender }, handle)
+    }
+
+    /**
+    Writes the readings collected in `batch` in one transaction, emptying it.
+
+    If that fails they are written one by one instead, so one that can't be written, such as one
+    out of range, doesn't take the others with it. Those that still fail are logged.
+    */
+    fn flush<D: Database>(connection: &D::Connection, batch: &mut Vec<ScrapedReading>) {
+        if batch.is_empty() || D::insert_readings(connection, batch).is_ok() {
+            batch.clear();
+            return;
+        }
+        for reading in batch.drain(..) {
+            if let Err(err) = D::insert_readings(connection, std::slice::from_ref(&reading)) {
+                println!(
+                    "Could not write the reading of {} at {}.\n{}",
+                    reading.table_name, reading.time, err
+                );
+            }
+        }
+    }
+
+    /// Queues `command` and waits for it to be applied.
+    pub async fn write(&self, command: WriteCommand) -> DatabaseResult<()> {
+        let (reply, applied) = oneshot::channel();
+        let stopped = || DatabaseError::Other("The database writer has stopped.".to_string());
+        self.sender.send((command, reply)).map_err(|_| stopped())?;
+        applied.await.map_err(|_| stopped())?
+    }
+
+    fn apply<D: Database>(connection: &D::Connection, command: WriteCommand) -> DatabaseResult<()> {
+        match command {
+            WriteCommand::Reading(reading) => D::insert_readings(connection, &[reading]),
+            WriteCommand::Schedule {
+                table_name,
+                date,
+                schedule,
+            } => Self::store_schedule::<D>(connection, &table_name, date, &schedule),
+            WriteCommand::Predictions {
+                table_name,
+                from,
+                to,
+                rows,
+                generated_at,
+                model_version,
+            } => D::replace_predictions(
+                connection,
+                &table_name,
+                from,
+                to,
+                rows,
+                generated_at,
+                model_version,
+            ),
+            WriteCommand::Scrape {
+                target,
+                time,
+                error,
+            } => D::record_scrape(connection, &target, time, error.as_deref()),
+        }
+    }
+
+    /// Stores `schedule` as in effect from `date`, unless the same one already is in effect on
+    /// it, so only changes to the opening hours are stored.
+    ///
+    /// A different one in effect means the opening hours changed, which is logged. One that
+    /// can't be read is overwritten.
+    fn store_schedule<D: Database>(
+        connection: &D::Connection,
+        name: &str,
+        date: NaiveDate,
+        schedule: &Schedule,
+    ) -> DatabaseResult<()> {
+        match D::query_single_day_schedule(connection, name, date) {
+            Ok(Some(stored)) if stored == *schedule => return Ok(()),
+            Ok(Some(_)) => println!("The schedule of {} changed on {}.", name, date),
+            Ok(None) | Err(DatabaseError::Other(_)) => (),
+            Err(err) => return Err(err),
+        }
+        D::insert_one_schedule(connection, name, date, schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::database::test_support::{date, week, MemoryDatabase, MemoryTables};
+
+    use super::*;
+
+    fn start(connection: &Arc<Mutex<MemoryTables>>) -> (Writer, JoinHandle<()>) {
+        let batching = Batching {
+            rows: 1,
+            interval: Duration::ZERO,
+        };
+        Writer::start::<MemoryDatabase>(connection.clone(), batching)
+    }
+
+    fn schedule(day: NaiveDate, opening: u16) -> WriteCommand {
+        WriteCommand::Schedule {
+            table_name: "gym".to_string(),
+            date: day,
+            schedule: week(opening),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_schedule_is_only_stored_when_it_changes() {
+        let connection = Arc::default();
+        let (writer, written) = start(&connection);
+        writer.write(schedule(date(2024, 5, 1), 700)).await.unwrap();
+        writer.write(schedule(date(2024, 5, 2), 700)).await.unwrap();
+        writer.write(schedule(date(2024, 5, 3), 800)).await.unwrap();
+        drop(writer);
+        written.await.unwrap();
+
+        let tables = connection.lock().unwrap();
+        let stored: Vec<(NaiveDate, Option<u16>)> = tables.schedules["gym"]
+            .iter()
+            .map(|(day, schedule)| (*day, schedule.get_timings()[0].opening()))
+            .collect();
+        assert_eq!(
+            stored,
+            [(date(2024, 5, 1), Some(700)), (date(2024, 5, 3), Some(800))]
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_are_applied_in_the_order_they_were_written() {
+        let connection = Arc::default();
+        let (writer, written) = start(&connection);
+        let time = date(2024, 5, 8).and_hms_opt(10, 0, 0).unwrap();
+        for error in [None, Some("timed out")] {
+            let scrape = WriteCommand::Scrape {
+                target: "gym".to_string(),
+                time,
+                error: error.map(str::to_string),
+            };
+            writer.write(scrape).await.unwrap();
+        }
+        let reading = WriteCommand::Reading(ScrapedReading {
+            table_name: "gym".to_string(),
+            time,
+            occupancy: 40,
+            headcount: None,
+        });
+        writer.write(reading).await.unwrap();
+        drop(writer);
+        written.await.unwrap();
+
+        let tables = connection.lock().unwrap();
+        let errors: Vec<Option<&str>> = tables
+            .scrapes
+            .iter()
+            .map(|(_, _, error)| error.as_deref())
+            .collect();
+        assert_eq!(errors, [None, Some("timed out")]);
+        assert_eq!(tables.rows["gym"][&time], 40);
+    }
+}
@@ -3,45 +3,337 @@ mod server;
 mod timing;
 mod predictor;
 mod database;
+mod settings;
 
-use std::sync::Arc;
+use std::{pin::pin, sync::Arc, time::Duration};
 
-use hyper::server::conn::http1;
-use hyper_util::rt::TokioIo;
-use r2d2_sqlite::SqliteConnectionManager;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use database::{integrity, pool};
 use scraper::scraper::Scraper;
-use server::server::Server;
-use tokio::net::TcpListener;
+use server::{
+    access_log::AccessLog,
+    connections::{ConnectionLimit, BUSY_RESPONSE},
+    listener::Listeners,
+    server::Server,
+    tls::TlsCertificates,
+};
+use settings::settings::Settings;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch, OwnedSemaphorePermit},
+    task::JoinHandle,
+};
 
 pub const ISO_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
 pub const ISO_FORMAT_DATE: &str = "%Y-%m-%d";
+pub const ISO_FORMAT_OFFSET: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// How long a client gets to finish the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() {
-    let manager = SqliteConnectionManager::file("data.db");
-    let pool = r2d2::Pool::builder().build(manager).unwrap();
-    let pool = Arc::new(pool);
+    let settings = Arc::new(Settings::load().unwrap());
+    if let Err(err) = integrity::check_at_startup(settings.database(), settings.recover()) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 
-    let scraper = Scraper::setup(pool.clone()).unwrap();
-    let server = Server::setup(pool.clone());
+    let pools = match pool::build(settings.database(), settings.pool()) {
+        Ok(pools) => pools,
+        Err(err) => {
+            eprintln!("{}\nNot starting.", err);
+            std::process::exit(1);
+        }
+    };
+    let pool = pools.read_write.clone();
 
-    tokio::spawn(async move {
-        scraper.run().await;
+    // `occupancy-backend import --name gym --file old.csv` imports old readings and exits
+    if std::env::args().nth(1).as_deref() == Some("import") {
+        if let Err(err) = database::import::run(&pool) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `occupancy-backend convert-times --dry-run` shows what converting the times would do
+    if std::env::args().nth(1).as_deref() == Some("convert-times") {
+        if let Err(err) = database::epoch::run(&pool) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let tls = settings.tls().map(|(cert, key)| match TlsCertificates::load(cert, key) {
+        Ok(tls) => Arc::new(tls),
+        Err(err) => {
+            eprintln!("Could not set up TLS, not starting.\n{}", err);
+            std::process::exit(1);
+        }
     });
+    if let Some(tls) = &tls {
+        tls.clone().reload_on_hangup();
+    }
+
+    let scraper = Scraper::setup(pool.clone(), settings.retention(), settings.write_batching()).unwrap();
+    let connections = Arc::new(ConnectionLimit::new(settings.max_connections()));
+    let access_log = match settings.access_log() {
+        Some(path) => Some(Arc::new(AccessLog::open(path.to_path_buf()).await.unwrap())),
+        None => None,
+    };
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let server = Server::setup(
+        &pools,
+        settings.clone(),
+        &scraper,
+        connections.clone(),
+        access_log,
+        shutdown.clone(),
+    );
 
-    let listener = TcpListener::bind("127.0.0.1:7878").await.unwrap();
+    let backups = settings
+        .backups()
+        .cloned()
+        .map(|backups| tokio::spawn(backups.run(pool.clone(), shutdown.clone())));
+    let maintenance = settings.maintenance().clone();
+    let maintenance = tokio::spawn(maintenance.run(pool.clone(), shutdown.clone()));
+    let scraper = tokio::spawn(scraper.run(shutdown));
+
+    let mut listener = match Listeners::bind(settings.listen(), settings.socket_mode()) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    for listen in settings.listen() {
+        println!("Listening on {}.", listen);
+    }
+    let graceful = GracefulShutdown::new();
+    // Each connection is negotiated as HTTP/1.1 or HTTP/2 (including h2c from reverse proxies)
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let mut signal = pin!(shutdown_signal());
+    // TLS handshakes happen off the accept loop and come back here to be served
+    let (handshaken, mut handshakes) = mpsc::unbounded_channel();
 
     loop {
-        let (stream, _) = listener.accept().await.unwrap();
-        let io = TokioIo::new(stream);
-        let server_clone = server.clone();
-        tokio::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(io, server_clone)
-                .await
-            {
-                println!("{}", err);
+        let (mut stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted.unwrap(),
+            Some((stream, server, permit)) = handshakes.recv() => {
+                serve(&builder, &graceful, stream, server, permit);
+                continue;
+            }
+            _ = &mut signal => break,
+        };
+        let Some(permit) = connections.try_acquire() else {
+            println!(
+                "Turned away {}, {} connections are open.",
+                peer,
+                connections.limit()
+            );
+            // A TLS client can't read a plain response, so it is just closed
+            if tls.is_none() {
+                tokio::spawn(async move {
+                    // Don't let a client that isn't reading hold on to the task
+                    let write = stream.write_all(BUSY_RESPONSE);
+                    let _ = tokio::time::timeout(Duration::from_secs(1), write).await;
+                });
             }
+            continue;
+        };
+        let server_clone = server.for_peer(peer.ip());
+        match &tls {
+            None => serve(&builder, &graceful, stream, server_clone, permit),
+            Some(tls) => {
+                let acceptor = tls.acceptor();
+                let handshaken = handshaken.clone();
+                tokio::spawn(async move {
+                    let handshake = acceptor.accept(stream);
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                        Ok(Ok(stream)) => {
+                            let _ = handshaken.send((stream, server_clone, permit));
+                        }
+                        Ok(Err(err)) => println!("TLS handshake with {} failed.\n{}", peer, err),
+                        Err(_) => println!("TLS handshake with {} timed out.", peer),
+                    }
+                });
+            }
+        }
+    }
+
+    // Stop taking new connections, let the open ones finish their requests and the scraper
+    // finish its current iteration.
+    println!("Shutting down.");
+    drop(listener);
+    let tasks = [Some(scraper), backups, Some(maintenance)].into_iter().flatten().collect();
+    if !shut_down(&shutdown_sender, graceful, tasks, settings.shutdown_grace()).await {
+        println!("Grace period is over, exiting with work still in flight.");
+        // Returning would wait on any handler still running on a blocking thread
+        std::process::exit(0);
+    }
+    println!("Shut down cleanly.");
+}
+
+/// Tells the background `tasks` to stop through `shutdown` and waits up to `grace` for them and
+/// the open connections to finish. False if the grace period ran out first.
+async fn shut_down(
+    shutdown: &watch::Sender<bool>,
+    graceful: GracefulShutdown,
+    tasks: Vec<JoinHandle<()>>,
+    grace: Duration,
+) -> bool {
+    let _ = shutdown.send(true);
+    let drained = tokio::time::timeout(grace, async {
+        graceful.shutdown().await;
+        for task in tasks {
+            let _ = task.await;
+        }
+    })
+    .await;
+    drained.is_ok()
+}
+
+/// Serves HTTP on `stream` until the client goes away, holding on to its connection `permit`.
+fn serve<S>(
+    builder: &auto::Builder<TokioExecutor>,
+    graceful: &GracefulShutdown,
+    stream: S,
+    server: Server,
+    permit: OwnedSemaphorePermit,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+    let connection = builder.serve_connection(io, server).into_owned();
+    let connection = graceful.watch(connection);
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            println!("{}", err);
+        }
+        drop(permit);
+    });
+}
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{
+        client::conn::{http1, http2},
+        header::CONTENT_TYPE,
+        Method, Request, Response, StatusCode, Version,
+    };
+    use tokio::{io::DuplexStream, sync::oneshot};
+
+    use server::test_support::{request, TestServer};
+
+    use super::*;
+
+    /// Connects to `test` over an in-memory stream served with `serve`, the way `main` serves a
+    /// connection it accepted.
+    fn connect(test: &TestServer, graceful: &GracefulShutdown) -> TokioIo<DuplexStream> {
+        let (client, connection) = tokio::io::duplex(64 * 1024);
+        let permit = ConnectionLimit::new(1).try_acquire().unwrap();
+        let builder = auto::Builder::new(TokioExecutor::new());
+        serve(&builder, graceful, connection, test.server.clone(), permit);
+        TokioIo::new(client)
+    }
+
+    async fn collect(response: Response<hyper::body::Incoming>) -> Response<Bytes> {
+        let (parts, body) = response.into_parts();
+        Response::from_parts(parts, body.collect().await.unwrap().to_bytes())
+    }
+
+    async fn send_http1(test: &TestServer, request: Request<Full<Bytes>>) -> Response<Bytes> {
+        let graceful = GracefulShutdown::new();
+        let (mut sender, connection) = http1::handshake(connect(test, &graceful)).await.unwrap();
+        tokio::spawn(connection);
+        collect(sender.send_request(request).await.unwrap()).await
+    }
+
+    async fn send_h2(test: &TestServer, request: Request<Full<Bytes>>) -> Response<Bytes> {
+        let graceful = GracefulShutdown::new();
+        let io = connect(test, &graceful);
+        let (mut sender, connection) = http2::handshake(TokioExecutor::new(), io).await.unwrap();
+        tokio::spawn(connection);
+        collect(sender.send_request(request).await.unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn a_request_is_answered_the_same_over_http1_and_h2() {
+        let test = TestServer::new();
+        for (method, uri) in [
+            (Method::GET, "http://localhost/api/locations"),
+            (Method::GET, "http://localhost/api/day?name=gym&date=nope"),
+            (Method::DELETE, "http://localhost/api/locations"),
+        ] {
+            // Error bodies have the request ID in them, so both get the same one
+            let request = || {
+                let mut request = request(method.clone(), uri);
+                request.headers_mut().insert("x-request-id", "same-request".parse().unwrap());
+                request
+            };
+            let http1 = send_http1(&test, request()).await;
+            let h2 = send_h2(&test, request()).await;
+            assert_eq!(http1.version(), Version::HTTP_11);
+            assert_eq!(h2.version(), Version::HTTP_2);
+            assert_ne!(http1.status(), StatusCode::INTERNAL_SERVER_ERROR, "{} {}", method, uri);
+            assert_eq!(h2.status(), http1.status(), "{} {}", method, uri);
+            assert_eq!(h2.headers().get(CONTENT_TYPE), http1.headers().get(CONTENT_TYPE));
+            assert_eq!(h2.body(), http1.body(), "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_request_in_flight_is_answered_before_shutting_down() {
+        let test = TestServer::new();
+        let graceful = GracefulShutdown::new();
+        let (mut sender, connection) = http1::handshake(connect(&test, &graceful)).await.unwrap();
+        tokio::spawn(connection);
+        // Held until a reading comes in or the server shuts down
+        let wait = request(Method::GET, "/api/wait?name=gym&after=2024-01-01T00:00:00");
+        let waiting = tokio::spawn(sender.send_request(wait));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Stands in for the scraper, which stops once it is told to
+        let mut told = test.shutdown.subscribe();
+        let scraper = tokio::spawn(async move {
+            let _ = told.wait_for(|shutdown| *shutdown).await;
         });
+        // Stands in for SIGTERM
+        let (signal, signalled) = oneshot::channel();
+        let shutdown = &test.shutdown;
+        let shut_down = async move {
+            signalled.await.unwrap();
+            shut_down(shutdown, graceful, vec![scraper], Duration::from_secs(5)).await
+        };
+        signal.send(()).unwrap();
+        assert!(shut_down.await);
+
+        let response = waiting.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(sender.is_closed());
+    }
+
+    #[tokio::test]
+    async fn shutting_down_gives_up_once_the_grace_period_is_over() {
+        let (shutdown, _) = watch::channel(false);
+        let stuck = tokio::spawn(std::future::pending());
+        let graceful = GracefulShutdown::new();
+        assert!(!shut_down(&shutdown, graceful, vec![stuck], Duration::from_millis(50)).await);
     }
 }
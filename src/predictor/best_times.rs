@@ -0,0 +1,212 @@
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+
+use crate::timing::iso_format::serialize_iso;
+
+/// A stretch of a prediction series and its mean predicted occupancy.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Window {
+    #[serde(serialize_with = "serialize_iso")]
+    pub start: NaiveDateTime,
+    #[serde(serialize_with = "serialize_iso")]
+    pub end: NaiveDateTime,
+    pub mean: f64,
+}
+
+/// The quietest and busiest windows of a day.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct BestTimes {
+    pub quietest: Window,
+    pub busiest: Window,
+}
+
+/**
+Finds the quietest and busiest windows of at least `min_length` in a prediction series.
+
+Only points between `opening` and `closing` (inclusive) are considered. A window starts at a point
+and runs until the first point that is at least `min_length` later, and is scored by the mean of
+the points in it. Windows have to be contiguous, so they never span a gap between points of more
+than half of `min_length`. When windows score the same, the earlier one wins.
+
+The series does not have to be sorted.
+Returns `None` if no stretch of points within opening hours spans `min_length`.
+*/
+pub fn find_best_times(
+    series: &[(NaiveDateTime, u16)],
+    opening: NaiveDateTime,
+    closing: NaiveDateTime,
+    min_length: Duration,
+) -> Option<BestTimes> {
+    let mut points: Vec<(NaiveDateTime, u16)> = series
+        .iter()
+        .filter(|(time, _)| opening <= *time && *time <= closing)
+        .copied()
+        .collect();
+    points.sort_by_key(|(time, _)| *time);
+
+    let mut quietest: Option<Window> = None;
+    let mut busiest: Option<Window> = None;
+    for run in points.chunk_by(|a, b| b.0 - a.0 <= min_length / 2) {
+        for window in windows(run, min_length) {
+            if quietest.as_ref().is_none_or(|best| window.mean < best.mean) {
+                quietest = Some(window.clone());
+            }
+            if busiest.as_ref().is_none_or(|best| window.mean > best.mean) {
+                busiest = Some(window);
+            }
+        }
+    }
+
+    Some(BestTimes {
+        quietest: quietest?,
+        busiest: busiest?,
+    })
+}
+
+/// Every window of at least `min_length` in a sorted run of points, in order of their start.
+fn windows(points: &[(NaiveDateTime, u16)], min_length: Duration) -> Vec<Window> {
+    let mut windows = Vec::new();
+    let mut end = 0;
+    let mut total: u64 = 0;
+    for start in 0..points.len() {
+        // Grow the window until it is long enough. The end never moves backwards, so this is a
+        // single pass over the points.
+        if end < start {
+            end = start;
+            total = 0;
+        }
+        while end < points.len() && points[end].0 - points[start].0 < min_length {
+            total += points[end].1 as u64;
+            end += 1;
+        }
+        if end == points.len() {
+            break;
+        }
+
+        let sum = total + points[end].1 as u64;
+        windows.push(Window {
+            start: points[start].0,
+            end: points[end].0,
+            mean: sum as f64 / (end - start + 1) as f64,
+        });
+
+        // With a zero length window the start point was never added to the total
+        if end > start {
+            total -= points[start].1 as u64;
+        }
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    /// A prediction every 5 minutes from `from` until before `to`, of `occupancy(time)`.
+    fn series(
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        occupancy: impl Fn(NaiveDateTime) -> u16,
+    ) -> Vec<(NaiveDateTime, u16)> {
+        let mut series = Vec::new();
+        let mut time = from;
+        while time < to {
+            series.push((time, occupancy(time)));
+            time += Duration::minutes(5);
+        }
+        series
+    }
+
+    fn best(series: &[(NaiveDateTime, u16)]) -> Option<BestTimes> {
+        find_best_times(series, at(8, 0), at(20, 0), Duration::minutes(30))
+    }
+
+    #[test]
+    fn finds_the_quietest_and_busiest_windows() {
+        let series = series(at(10, 0), at(13, 0), |time| {
+            if (at(10, 30)..=at(11, 0)).contains(&time) {
+                10
+            } else if (at(11, 30)..=at(12, 0)).contains(&time) {
+                90
+            } else {
+                50
+            }
+        });
+        let best = best(&series).unwrap();
+        assert_eq!(
+            best.quietest,
+            Window {
+                start: at(10, 30),
+                end: at(11, 0),
+                mean: 10.0
+            }
+        );
+        assert_eq!(
+            best.busiest,
+            Window {
+                start: at(11, 30),
+                end: at(12, 0),
+                mean: 90.0
+            }
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_earlier_window() {
+        let best = best(&series(at(10, 0), at(12, 0), |_| 20)).unwrap();
+        assert_eq!(best.quietest.start, at(10, 0));
+        assert_eq!(best.busiest.start, at(10, 0));
+    }
+
+    #[test]
+    fn only_opening_hours_are_considered() {
+        let series = series(
+            at(6, 0),
+            at(10, 0),
+            |time| if time < at(8, 0) { 0 } else { 40 },
+        );
+        let best = best(&series).unwrap();
+        assert_eq!(best.quietest.start, at(8, 0));
+        assert_eq!(best.quietest.mean, 40.0);
+    }
+
+    #[test]
+    fn windows_never_span_a_gap() {
+        // A run too short for a window of its own, a 20 minute gap, and a long enough run
+        let mut points = series(at(10, 0), at(10, 30), |_| 5);
+        points.extend(series(at(10, 50), at(11, 30), |_| 60));
+        let best = best(&points).unwrap();
+        assert_eq!(best.quietest.start, at(10, 50));
+        assert_eq!(best.quietest.mean, 60.0);
+    }
+
+    #[test]
+    fn the_series_does_not_have_to_be_sorted() {
+        let mut points = series(at(10, 0), at(11, 0), |time| {
+            if time < at(10, 30) {
+                10
+            } else {
+                30
+            }
+        });
+        points.reverse();
+        let best = best(&points).unwrap();
+        assert_eq!(best.quietest.start, at(10, 0));
+        assert_eq!(best.busiest.end, at(10, 55));
+    }
+
+    #[test]
+    fn is_none_without_a_long_enough_stretch() {
+        assert_eq!(best(&[]), None);
+        assert_eq!(best(&series(at(10, 0), at(10, 30), |_| 20)), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::timing::iso_format::serialize_iso;
+
+/// An actual reading paired with the prediction closest to it in time.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ComparedPoint {
+    #[serde(serialize_with = "serialize_iso")]
+    pub time: NaiveDateTime,
+    pub actual: u16,
+    #[serde(serialize_with = "serialize_iso")]
+    pub predicted_time: NaiveDateTime,
+    pub predicted: u16,
+}
+
+impl ComparedPoint {
+    pub fn error(&self) -> u16 {
+        self.actual.abs_diff(self.predicted)
+    }
+}
+
+/// Summary error metrics over a set of compared points.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ErrorMetrics {
+    pub mae: f64,
+    pub rmse: f64,
+    pub max_error: u16,
+    pub count: usize,
+}
+
+impl ErrorMetrics {
+    /// Returns `None` if there are no points to summarise.
+    pub fn from_points(points: &[ComparedPoint]) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+        let total: u64 = points.iter().map(|point| point.error() as u64).sum();
+        let squared: u64 = points
+            .iter()
+            .map(|point| (point.error() as u64).pow(2))
+            .sum();
+        Some(Self {
+            mae: total as f64 / points.len() as f64,
+            rmse: (squared as f64 / points.len() as f64).sqrt(),
+            max_error: points.iter().map(ComparedPoint::error).max().unwrap(),
+            count: points.len(),
+        })
+    }
+}
+
+/// The error metrics of a single day.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DayMetrics {
+    pub date: String,
+    #[serde(flatten)]
+    pub metrics: ErrorMetrics,
+}
+
+/// Splits compared points up by the day of the reading and summarises each day, in date order.
+pub fn metrics_by_day(points: &[ComparedPoint]) -> Vec<DayMetrics> {
+    let mut days: BTreeMap<NaiveDate, Vec<ComparedPoint>> = BTreeMap::new();
+    for point in points {
+        days.entry(point.time.date())
+            .or_default()
+            .push(point.clone());
+    }
+    days.into_iter()
+        .filter_map(|(date, points)| {
+            Some(DayMetrics {
+                date: date.to_string(),
+                metrics: ErrorMetrics::from_points(&points)?,
+            })
+        })
+        .collect()
+}
+
+/**
+Pairs each actual reading with the nearest prediction in time.
+
+Scrape times never line up exactly with the prediction grid, so a reading is matched to whichever
+prediction is closest, as long as it is at most `tolerance` away. Readings without a prediction
+that close are left out. When two predictions are equally close, the earlier one wins.
+
+Neither series has to be sorted.
+*/
+pub fn match_nearest(
+    actual: &[(NaiveDateTime, u16)],
+    predicted: &[(NaiveDateTime, u16)],
+    tolerance: Duration,
+) -> Vec<ComparedPoint> {
+    let mut predicted = predicted.to_vec();
+    predicted.sort_by_key(|(time, _)| *time);
+
+    let mut points = Vec::new();
+    for (time, occupancy) in actual {
+        // Index of the first prediction at or after the reading
+        let after = predicted.partition_point(|(predicted_time, _)| predicted_time < time);
+        let before = after.checked_sub(1).map(|index| predicted[index]);
+        let after = predicted.get(after).copied();
+
+        let nearest = match (before, after) {
+            (Some(before), Some(after)) => {
+                if *time - before.0 <= after.0 - *time {
+                    before
+                } else {
+                    after
+                }
+            }
+            (Some(nearest), None) | (None, Some(nearest)) => nearest,
+            (None, None) => continue,
+        };
+
+        if (nearest.0 - *time).abs() <= tolerance {
+            points.push(ComparedPoint {
+                time: *time,
+                actual: *occupancy,
+                predicted_time: nearest.0,
+                predicted: nearest.1,
+            });
+        }
+    }
+    points.sort_by_key(|point| point.time);
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn point(time: NaiveDateTime, actual: u16, predicted: u16) -> ComparedPoint {
+        ComparedPoint {
+            time,
+            actual,
+            predicted_time: time,
+            predicted,
+        }
+    }
+
+    #[test]
+    fn metrics_of_no_points_are_none() {
+        assert_eq!(ErrorMetrics::from_points(&[]), None);
+    }
+
+    #[test]
+    fn metrics_summarise_the_errors() {
+        let points = [
+            point(at(1, 10, 0), 10, 13),
+            point(at(1, 10, 5), 20, 16),
+            point(at(1, 10, 10), 30, 30),
+        ];
+        let metrics = ErrorMetrics::from_points(&points).unwrap();
+        assert_eq!(metrics.count, 3);
+        assert_eq!(metrics.max_error, 4);
+        assert!((metrics.mae - 7.0 / 3.0).abs() < 1e-9);
+        assert!((metrics.rmse - (25.0f64 / 3.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn metrics_by_day_are_in_date_order() {
+        let points = [
+            point(at(2, 10, 0), 10, 12),
+            point(at(1, 10, 0), 10, 11),
+            point(at(2, 11, 0), 10, 16),
+        ];
+        let days = metrics_by_day(&points);
+        let summary: Vec<(&str, usize, u16)> = days
+            .iter()
+            .map(|day| (day.date.as_str(), day.metrics.count, day.metrics.max_error))
+            .collect();
+        assert_eq!(summary, [("2024-05-01", 1, 1), ("2024-05-02", 2, 6)]);
+    }
+
+    #[test]
+    fn readings_are_matched_to_the_nearest_prediction() {
+        let actual = [(at(1, 10, 4), 20), (at(1, 10, 1), 10)];
+        // Unsorted, like the readings
+        let predicted = [(at(1, 10, 5), 25), (at(1, 10, 0), 15)];
+        let points = match_nearest(&actual, &predicted, Duration::minutes(3));
+        assert_eq!(
+            points,
+            [
+                ComparedPoint {
+                    time: at(1, 10, 1),
+                    actual: 10,
+                    predicted_time: at(1, 10, 0),
+                    predicted: 15,
+                },
+                ComparedPoint {
+                    time: at(1, 10, 4),
+                    actual: 20,
+                    predicted_time: at(1, 10, 5),
+                    predicted: 25,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_tie_goes_to_the_earlier_prediction() {
+        let actual = [(at(1, 10, 5), 20)];
+        let predicted = [(at(1, 10, 10), 30), (at(1, 10, 0), 10)];
+        let points = match_nearest(&actual, &predicted, Duration::minutes(5));
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].predicted_time, at(1, 10, 0));
+    }
+
+    #[test]
+    fn readings_without_a_prediction_close_enough_are_left_out() {
+        let actual = [(at(1, 9, 50), 10), (at(1, 10, 3), 20), (at(1, 10, 20), 30)];
+        let predicted = [(at(1, 10, 0), 15), (at(1, 10, 5), 25)];
+        let points = match_nearest(&actual, &predicted, Duration::minutes(3));
+        let times: Vec<NaiveDateTime> = points.iter().map(|point| point.time).collect();
+        assert_eq!(times, [at(1, 10, 3)]);
+        assert!(match_nearest(&actual, &[], Duration::minutes(3)).is_empty());
+    }
+
+    #[test]
+    fn only_the_overlap_of_the_series_is_paired() {
+        let actual: Vec<(NaiveDateTime, u16)> =
+            (0..6).map(|i| (at(1, 9 + i, 0), 10 * i as u16)).collect();
+        let predicted: Vec<(NaiveDateTime, u16)> = (3..10)
+            .map(|i| (at(1, 9 + i, 1), 10 * i as u16 + 2))
+            .collect();
+        let points = match_nearest(&actual, &predicted, Duration::minutes(3));
+        let times: Vec<NaiveDateTime> = points.iter().map(|point| point.time).collect();
+        assert_eq!(times, [at(1, 12, 0), at(1, 13, 0), at(1, 14, 0)]);
+        let metrics = ErrorMetrics::from_points(&points).unwrap();
+        assert_eq!((metrics.count, metrics.max_error), (3, 2));
+    }
+
+    #[test]
+    fn a_series_on_its_own_has_nothing_to_pair() {
+        let series: Vec<(NaiveDateTime, u16)> = (0..6).map(|i| (at(1, 9 + i, 0), 50)).collect();
+        let actual_only = match_nearest(&series, &[], Duration::minutes(3));
+        let predicted_only = match_nearest(&[], &series, Duration::minutes(3));
+        assert!(actual_only.is_empty() && predicted_only.is_empty());
+        assert_eq!(ErrorMetrics::from_points(&actual_only), None);
+        assert!(metrics_by_day(&predicted_only).is_empty());
+    }
+}
@@ -2,10 +2,12 @@ use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct KNNConfig {
     last_scraped: String,
 }
 
+#[allow(dead_code)]
 impl KNNConfig {
     pub fn new(last_scraped: String) -> Self {
         Self { last_scraped }
@@ -27,7 +27,7 @@ impl LSTMRegressor {
             .spawn()
         {
             Ok(command) => command,
-            Err(e) => return Err(format!("Failed to spawn. {}", e.to_string())),
+            Err(e) => return Err(format!("Failed to spawn. {}", e)),
         };
 
         match command.wait_with_output() {
@@ -36,12 +36,12 @@ impl LSTMRegressor {
                     return Err("Failed to execute the command".to_string());
                 }
             }
-            Err(e) => return Err(format!("Failed to wait. {}", e.to_string())),
+            Err(e) => return Err(format!("Failed to wait. {}", e)),
         }
 
         let output = match fs::read_to_string("lstm_prediction/output") {
             Ok(output) => output,
-            Err(e) => return Err(format!("Failed to read output. {}", e.to_string())),
+            Err(e) => return Err(format!("Failed to read output. {}", e)),
         };
 
         let mut predictions: Vec<(NaiveDateTime, f64)> = Vec::new();
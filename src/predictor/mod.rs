@@ -1,3 +1,6 @@
 pub mod knn_regressor;
 pub mod lstm_regressor;
 mod knn_config;
+pub mod evaluation;
+pub mod best_times;
+pub mod typical;
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDateTime, NaiveTime, Timelike};
+use serde::Serialize;
+
+/// Buckets with fewer readings than this are left out, a band from one or two days is noise.
+pub const MIN_OBSERVATIONS: usize = 3;
+
+/// The spread of occupancy at one time of day.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TypicalBand {
+    /// The start of the bucket as HH:MM.
+    pub time: String,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    /// How many readings fell into the bucket.
+    pub observations: usize,
+}
+
+/**
+Computes the 25th, 50th and 75th percentile of occupancy for each `bucket_minutes` long bucket
+of the day, across every reading in `readings`.
+
+The readings are expected to come from several days, such as the same weekday of the last few
+weeks, and only their time of day counts. Buckets with fewer than `MIN_OBSERVATIONS` readings
+are left out. Percentiles are interpolated linearly between the closest ranks.
+
+Returns the bands in time of day order.
+*/
+pub fn typical_bands(readings: &[(NaiveDateTime, u16)], bucket_minutes: u32) -> Vec<TypicalBand> {
+    let bucket_minutes = bucket_minutes.max(1);
+    let mut buckets: BTreeMap<u32, Vec<u16>> = BTreeMap::new();
+    for (time, occupancy) in readings {
+        let minute = time.hour() * 60 + time.minute();
+        buckets
+            .entry(minute / bucket_minutes * bucket_minutes)
+            .or_default()
+            .push(*occupancy);
+    }
+
+    buckets
+        .into_iter()
+        .filter(|(_, values)| values.len() >= MIN_OBSERVATIONS)
+        .map(|(start, mut values)| {
+            values.sort_unstable();
+            let time = NaiveTime::from_hms_opt(start / 60, start % 60, 0).unwrap();
+            TypicalBand {
+                time: time.format("%H:%M").to_string(),
+                p25: percentile(&values, 0.25),
+                p50: percentile(&values, 0.5),
+                p75: percentile(&values, 0.75),
+                observations: values.len(),
+            }
+        })
+        .collect()
+}
+
+/// The `p` quantile of `sorted`, which must not be empty.
+fn percentile(sorted: &[u16], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let weight = rank - lower as f64;
+    sorted[lower] as f64 * (1.0 - weight) + sorted[upper] as f64 * weight
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn percentiles_are_interpolated_between_ranks() {
+        let sorted = [10, 20, 30, 40, 50];
+        assert_eq!(percentile(&sorted, 0.25), 20.0);
+        assert_eq!(percentile(&sorted, 0.5), 30.0);
+        assert_eq!(percentile(&[10, 20, 30, 40], 0.5), 25.0);
+        assert_eq!(percentile(&[10, 20, 30, 40], 0.25), 17.5);
+        assert_eq!(percentile(&[7], 0.75), 7.0);
+    }
+
+    #[test]
+    fn readings_from_several_days_share_a_bucket() {
+        let readings = [
+            (at(1, 10, 0), 40),
+            (at(8, 10, 5), 20),
+            (at(15, 10, 10), 30),
+            (at(22, 10, 14), 10),
+        ];
+        let bands = typical_bands(&readings, 15);
+        assert_eq!(
+            bands,
+            [TypicalBand {
+                time: "10:00".to_string(),
+                p25: 17.5,
+                p50: 25.0,
+                p75: 32.5,
+                observations: 4,
+            }]
+        );
+    }
+
+    #[test]
+    fn buckets_with_too_few_readings_are_left_out() {
+        let mut readings = vec![(at(1, 9, 0), 5), (at(8, 9, 0), 15)];
+        readings.extend([(at(1, 11, 0), 50), (at(8, 11, 0), 60), (at(15, 11, 0), 70)]);
+        let bands = typical_bands(&readings, 30);
+        let times: Vec<&str> = bands.iter().map(|band| band.time.as_str()).collect();
+        assert_eq!(times, ["11:00"]);
+        assert_eq!(bands[0].p50, 60.0);
+    }
+
+    #[test]
+    fn bands_are_in_time_of_day_order() {
+        let readings: Vec<(NaiveDateTime, u16)> = [22, 7, 15]
+            .into_iter()
+            .flat_map(|hour| (1..=3).map(move |day| (at(day, hour, 30), hour as u16)))
+            .collect();
+        let bands = typical_bands(&readings, 60);
+        let times: Vec<&str> = bands.iter().map(|band| band.time.as_str()).collect();
+        assert_eq!(times, ["07:00", "15:00", "22:00"]);
+    }
+
+    #[test]
+    fn a_zero_bucket_is_taken_as_a_minute() {
+        let readings = [(at(1, 10, 1), 10), (at(8, 10, 1), 20), (at(15, 10, 1), 30)];
+        let bands = typical_bands(&readings, 0);
+        assert_eq!(bands.len(), 1);
+        assert_eq!(bands[0].time, "10:01");
+    }
+
+    #[test]
+    fn no_readings_have_no_bands() {
+        assert!(typical_bands(&[], 15).is_empty());
+    }
+}
@@ -1,17 +1,19 @@
 use serde::Deserialize;
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub struct Config {
     pub url: String,
     pub headers: String,
     pub scrape_regex: String,
 }
 
+#[allow(dead_code)]
 impl Config {
     pub fn from_config(config: String) -> Result<Self, String> {
         match serde_json::from_str(&config) {
             Ok(data) => Ok(data),
-            Err(err) => Err(format!("Could not deserialize.\n{}",err.to_string()).to_owned()),
+            Err(err) => Err(format!("Could not deserialize.\n{}", err)),
         }
     }
 }
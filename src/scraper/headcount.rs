@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+/// The absolute numbers behind an occupancy percentage, for locations that publish them.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Headcount {
+    /// Everyone inside, `staff + student + other`.
+    pub total: u32,
+    /// How many people the location can hold.
+    pub capacity: u32,
+    pub staff: u32,
+    pub student: u32,
+    pub other: u32,
+}
@@ -0,0 +1,41 @@
+use std::ops::RangeInclusive;
+
+use serde::Serialize;
+
+/// The highest occupancy of a headcount location. More people can be let in than it was counted
+/// for, so the percentage worked out from its headcount can go over 100.
+const MAX_HEADCOUNT_OCCUPANCY: u16 = 200;
+
+/// What the occupancy of a location is measured in.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Capacity {
+    /// Occupancy is a percentage of the location's capacity, 0 to 100.
+    Percentage,
+    /// Occupancy is a percentage as well, with the headcount it was worked out from stored
+    /// alongside it.
+    Headcount,
+}
+
+impl Capacity {
+    /// The occupancies a location measured this way can have. Anything else is a scraping or
+    /// parsing bug, and never stored.
+    pub fn occupancy_range(&self) -> RangeInclusive<u16> {
+        match self {
+            Self::Percentage => 0..=100,
+            Self::Headcount => 0..=MAX_HEADCOUNT_OCCUPANCY,
+        }
+    }
+}
+
+/// Describes a scraped location, so clients don't have to hardcode what a name means.
+#[derive(Serialize, Clone, Debug)]
+pub struct LocationMetadata {
+    /// The name used in requests and as the table name.
+    pub name: &'static str,
+    pub display_name: &'static str,
+    /// Where the occupancy is scraped from.
+    pub source_url: &'static str,
+    pub capacity: Capacity,
+    pub scrape_interval_seconds: u64,
+}
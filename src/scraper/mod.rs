@@ -1,3 +1,11 @@
+#[allow(clippy::module_inception)]
 pub mod scraper;
+pub mod headcount;
+pub mod metadata;
+pub mod new_readings;
+pub mod repredict;
+pub mod retention;
+pub mod schedule_cache;
+pub mod status;
 mod config;
 mod sta;
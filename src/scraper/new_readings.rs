@@ -0,0 +1,58 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::NaiveDateTime;
+use tokio::sync::broadcast;
+
+/// How many readings a slow listener can fall behind by before it misses some. There are only a
+/// couple of readings per scrape interval, so listeners never get near it.
+const CAPACITY: usize = 64;
+
+/// A reading the scraper has just scraped and handed to be stored.
+#[derive(Clone, Debug)]
+pub struct NewReading {
+    /// The location it was taken at.
+    pub name: String,
+    pub time: NaiveDateTime,
+    pub occupancy: u16,
+}
+
+/// Announces every reading the scraper stores to whoever is listening, such as clients
+/// long-polling /api/wait.
+///
+/// Every listener gets every reading, so any number of them can wait on the same location.
+///
+/// The last reading of each location is kept as well, as it may not be in the database yet when
+/// the writer batches them, see `Batching`.
+pub struct NewReadings {
+    sender: broadcast::Sender<NewReading>,
+    latest: Mutex<HashMap<String, NewReading>>,
+}
+
+impl NewReadings {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self {
+            sender,
+            latest: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Announces `reading`. It is simply dropped when nobody is listening.
+    pub fn publish(&self, reading: NewReading) {
+        self.latest
+            .lock()
+            .unwrap()
+            .insert(reading.name.clone(), reading.clone());
+        let _ = self.sender.send(reading);
+    }
+
+    /// The last reading announced for the location `name` since startup.
+    pub fn latest(&self, name: &str) -> Option<NewReading> {
+        self.latest.lock().unwrap().get(name).cloned()
+    }
+
+    /// Starts listening for the readings stored from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<NewReading> {
+        self.sender.subscribe()
+    }
+}
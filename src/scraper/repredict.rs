@@ -0,0 +1,129 @@
+use std::{collections::HashMap, str::FromStr, sync::Mutex};
+
+use tokio::sync::Notify;
+
+/// The prediction models that can be regenerated on demand.
+///
+/// GB predictions aren't made by the scraper, so there is nothing it can rerun for `Gb` and the
+/// server doesn't queue it. It is still a model to compare against.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PredictionModel {
+    Knn,
+    Lstm,
+    Gb,
+    All,
+}
+
+impl PredictionModel {
+    pub fn includes_knn(&self) -> bool {
+        matches!(self, Self::Knn | Self::All)
+    }
+
+    pub fn includes_lstm(&self) -> bool {
+        matches!(self, Self::Lstm | Self::All)
+    }
+
+    /// The suffix of the table the model's predictions are stored in.
+    ///
+    /// Returns `None` for `All`, which isn't a single model.
+    pub fn table_suffix(&self) -> Option<&'static str> {
+        match self {
+            Self::Knn => Some("_prediction_knn"),
+            Self::Lstm => Some("_prediction_lstm"),
+            Self::Gb => Some("_prediction_gb"),
+            Self::All => None,
+        }
+    }
+}
+
+impl FromStr for PredictionModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "knn" => Ok(Self::Knn),
+            "lstm" => Ok(Self::Lstm),
+            "gb" => Ok(Self::Gb),
+            "all" => Ok(Self::All),
+            _ => Err(format!(
+                "Unknown model '{}'. Expected knn, lstm, gb or all.",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RepredictState {
+    pending: Option<PredictionModel>,
+    running: bool,
+}
+
+#[derive(Default)]
+struct RepredictTarget {
+    state: Mutex<RepredictState>,
+    notify: Notify,
+}
+
+/// Requests to regenerate predictions, shared between the Server and the Scraper.
+///
+/// The Server queues a request with `request` and the scraper loop for that target picks it up
+/// with `take`, regardless of when it last made predictions. At most one request per target can
+/// be pending or running at a time, further requests are turned away until it is done.
+pub struct RepredictQueue {
+    targets: HashMap<String, RepredictTarget>,
+}
+
+impl RepredictQueue {
+    pub fn new(names: &[&str]) -> Self {
+        let targets = names
+            .iter()
+            .map(|name| (name.to_string(), RepredictTarget::default()))
+            .collect();
+        Self { targets }
+    }
+
+    /// Queue a prediction run for `name`.
+    ///
+    /// Returns `Ok(true)` if the job was queued and `Ok(false)` if one is already pending or
+    /// running for that target.
+    /// Returns an `Err` if `name` is not a known target.
+    pub fn request(&self, name: &str, model: PredictionModel) -> Result<bool, String> {
+        let Some(target) = self.targets.get(name) else {
+            return Err(format!("Unknown name '{}'.", name));
+        };
+        let mut state = target.state.lock().unwrap();
+        if state.pending.is_some() || state.running {
+            return Ok(false);
+        }
+        state.pending = Some(model);
+        target.notify.notify_one();
+        Ok(true)
+    }
+
+    /// Wait until a request is queued for `name`.
+    ///
+    /// Never resolves for unknown names.
+    pub async fn notified(&self, name: &str) {
+        match self.targets.get(name) {
+            Some(target) => target.notify.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Take the pending request for `name` and mark it as running.
+    pub fn take(&self, name: &str) -> Option<PredictionModel> {
+        let target = self.targets.get(name)?;
+        let mut state = target.state.lock().unwrap();
+        let model = state.pending.take()?;
+        state.running = true;
+        Some(model)
+    }
+
+    /// Mark the running request for `name` as done.
+    pub fn finish(&self, name: &str) {
+        if let Some(target) = self.targets.get(name) {
+            target.state.lock().unwrap().running = false;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use chrono::{Days, Months, NaiveDateTime};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use tokio::{sync::watch, time::Duration};
+
+use crate::{
+    database::{error::DatabaseResult, sqlite::SqliteDatabase},
+    timing::uk_datetime_now::uk_datetime_now,
+};
+
+use super::scraper::{LOCATIONS, PREDICTION_TABLES};
+
+/// How often old rows are looked for. The first run is right after startup.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long stored rows are kept. 0 keeps them forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// How many months of readings, and the headcounts behind them, are kept.
+    pub reading_months: u32,
+    /// How many weeks of past predictions are kept. They can be made again from the readings.
+    pub prediction_weeks: u32,
+}
+
+impl Retention {
+    fn keeps_everything(&self) -> bool {
+        self.reading_months == 0 && self.prediction_weeks == 0
+    }
+
+    /// Deletes the rows older than the retention from every location's tables, every
+    /// `PRUNE_INTERVAL` until `shutdown` is set.
+    pub async fn run(
+        self,
+        connection_pool: Arc<Pool<SqliteConnectionManager>>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        if self.keeps_everything() {
+            return;
+        }
+        while !*shutdown.borrow() {
+            let pool = connection_pool.clone();
+            // A first prune of a large table can take a while, so it stays off the runtime
+            let pruned = tokio::task::spawn_blocking(move || self.prune_all(&pool)).await;
+            if let Err(err) = pruned {
+                println!("Pruning old rows failed.\n{}", err);
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(PRUNE_INTERVAL) => (),
+                _ = shutdown.changed() => (),
+            }
+        }
+    }
+
+    fn prune_all(&self, connection_pool: &Pool<SqliteConnectionManager>) {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                println!("Could not get connection to prune old rows - Scraper.");
+                return;
+            }
+        };
+        let now = uk_datetime_now().naive_local();
+        for name in LOCATIONS {
+            match self.prune(&connection, name, now) {
+                Ok((0, 0)) => (),
+                Ok((readings, predictions)) => println!(
+                    "Pruned {} readings and {} predictions of {}.",
+                    readings, predictions, name
+                ),
+                Err(err) => println!("Could not prune old rows of {}.\n{}", name, err),
+            }
+        }
+    }
+
+    /// Deletes the rows of `name` that are too old at `now`. Returns how many readings and how
+    /// many predictions were deleted.
+    fn prune(
+        &self,
+        connection: &PooledConnection<SqliteConnectionManager>,
+        name: &str,
+        now: NaiveDateTime,
+    ) -> DatabaseResult<(usize, usize)> {
+        let mut readings = 0;
+        let before = now.checked_sub_months(Months::new(self.reading_months));
+        if let (1.., Some(before)) = (self.reading_months, before) {
+            readings = SqliteDatabase::delete_before(connection, name, before)?;
+            SqliteDatabase::delete_before(connection, &format!("{}_headcount", name), before)?;
+        }
+
+        let mut predictions = 0;
+        let before = now.checked_sub_days(Days::new(self.prediction_weeks as u64 * 7));
+        if let (1.., Some(before)) = (self.prediction_weeks, before) {
+            for suffix in PREDICTION_TABLES {
+                let table = format!("{}{}", name, suffix);
+                predictions += SqliteDatabase::delete_before(connection, &table, before)?;
+            }
+        }
+        Ok((readings, predictions))
+    }
+}
@@ -0,0 +1,43 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+use crate::timing::schedule::Schedule;
+
+/// The last Schedule scraped for each target and when, shared between the Scraper and the Server.
+///
+/// Lets the Server answer whether a location is open without reading the schedule table.
+/// Empty until the first successful scrape of each target.
+#[derive(Default)]
+pub struct ScheduleCache {
+    schedules: Mutex<HashMap<String, (Schedule, DateTime<Tz>)>>,
+}
+
+impl ScheduleCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the cached Schedule for `name` with one scraped at `scraped_at`.
+    pub fn set(&self, name: &str, schedule: Schedule, scraped_at: DateTime<Tz>) {
+        self.schedules
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (schedule, scraped_at));
+    }
+
+    /// Whether `name` is open at `timestamp`.
+    ///
+    /// Returns `None` if no Schedule has been scraped for it yet.
+    pub fn is_open(&self, name: &str, timestamp: DateTime<Tz>) -> Option<bool> {
+        let schedules = self.schedules.lock().unwrap();
+        Some(schedules.get(name)?.0.is_open(timestamp))
+    }
+
+    /// When `name` was last scraped successfully, if it has been since startup.
+    pub fn last_scrape(&self, name: &str) -> Option<DateTime<Tz>> {
+        let schedules = self.schedules.lock().unwrap();
+        Some(schedules.get(name)?.1)
+    }
+}
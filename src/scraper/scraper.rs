@@ -1,41 +1,247 @@
-use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, Timelike, Weekday};
 use chrono_tz::Tz;
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use reqwest::RequestBuilder;
-use tokio::time::{sleep_until, Duration, Instant};
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep_until, Duration, Instant},
+};
 
-use core::panic;
-use std::{collections::HashMap, f64, fs, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    f64, fs,
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+};
 
 use crate::{
-    database::sqlite::SqliteDatabase,
+    database::{
+        epoch,
+        sqlite::SqliteDatabase,
+        writer::{Batching, ScrapedReading, WriteCommand, Writer},
+    },
     predictor::{knn_regressor::KNNRegressor, lstm_regressor::LSTMRegressor},
     scraper::sta::main_library::MainLibrary,
-    timing::{schedule::Schedule, uk_datetime_now::uk_datetime_now},
-    ISO_FORMAT,
+    timing::{
+        schedule::Schedule,
+        timezone::{stored_to_uk_local, uk_local_to_stored},
+        uk_datetime_now::uk_datetime_now,
+    },
+};
+
+use super::{
+    headcount::Headcount,
+    metadata::LocationMetadata,
+    new_readings::{NewReading, NewReadings},
+    repredict::RepredictQueue,
+    retention::Retention,
+    schedule_cache::ScheduleCache,
+    sta::gym::Gym,
+    status::ScraperStatus,
 };
 
-use super::sta::gym::Gym;
+/// The table names of our hardcoded scrapers.
+pub const LOCATIONS: &[&str] = &["gym", "main_library"];
+
+/// The columns of a `{name}_schedule` table, one row per weekday of the schedule in effect from
+/// `effective_from` until the next one. Weekdays count from 0 for Monday and the times are HHMM.
+const SCHEDULE_COLUMNS: &str = "effective_from TEXT NOT NULL, weekday INTEGER NOT NULL, \
+    open INTEGER NOT NULL, opening INTEGER, closing INTEGER, PRIMARY KEY (effective_from, weekday)";
+
+/// The columns of a `{name}_prediction_*` table. `generated_at` and `model_version` are NULL in
+/// the rows from before they were stored.
+pub const PREDICTION_COLUMNS: &str = "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, \
+    occupancy INTEGER NOT NULL, generated_at INTEGER, model_version TEXT";
+
+/// The columns of a `{name}_reports` table, crowdsourced reports from users kept apart from the
+/// scraped readings.
+pub const REPORT_COLUMNS: &str =
+    "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, occupancy INTEGER NOT NULL, note TEXT";
+
+/// The columns of a `{name}_headcount` table, the absolute numbers behind the occupancy. Only
+/// filled in for locations that have them.
+pub const HEADCOUNT_COLUMNS: &str = "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, \
+    total INTEGER NOT NULL, capacity INTEGER NOT NULL, staff INTEGER NOT NULL, \
+    student INTEGER NOT NULL, other INTEGER NOT NULL";
+
+/// The columns of a `{name}_feedback` table, feedback from users on how good the predictions of
+/// `date` were.
+pub const FEEDBACK_COLUMNS: &str = "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, \
+    date TEXT NOT NULL, model TEXT NOT NULL, rating TEXT NOT NULL, comment TEXT";
+
+/// The columns of a `{name}_hourly` table, one row per hour that starts at `time`.
+pub const HOURLY_COLUMNS: &str = "time INTEGER PRIMARY KEY, mean REAL NOT NULL, \
+    min INTEGER NOT NULL, max INTEGER NOT NULL, samples INTEGER NOT NULL";
+
+/// The columns of the `locations` table, see `register_locations`.
+pub const LOCATION_COLUMNS: &str = "id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE, \
+    display_name TEXT NOT NULL, created_at INTEGER NOT NULL";
+
+/// The columns of the `scraper_meta` table, see `create_scraper_meta_table`.
+pub const SCRAPER_META_COLUMNS: &str = "target TEXT PRIMARY KEY, last_success_at INTEGER, \
+    last_error_at INTEGER, last_error_text TEXT, consecutive_failures INTEGER NOT NULL DEFAULT 0";
+
+/// The prediction tables of a location, by suffix.
+pub const PREDICTION_TABLES: &[&str] = &["_prediction_knn", "_prediction_lstm", "_prediction_gb"];
+
+/// Stored with every KNN prediction, so the rows of different versions of the model can be told
+/// apart. Change it along with how the predictions are made, such as the 3 nearest neighbours
+/// from the last 3 weeks every 5 minutes.
+const KNN_MODEL_VERSION: &str = "knn-1";
+
+/// Stored with every LSTM prediction, see `KNN_MODEL_VERSION`.
+const LSTM_MODEL_VERSION: &str = "lstm-1";
+
+/// How often each target is scraped.
+pub const SCRAPE_INTERVAL: Duration = Duration::from_secs(30 * 10);
+
+/// The metadata of every location in `LOCATIONS`.
+pub fn locations_metadata() -> Vec<LocationMetadata> {
+    vec![Gym::metadata(), MainLibrary::metadata()]
+}
+
+/// The metadata of the location called `name`, if there is one.
+pub fn location_metadata(name: &str) -> Option<LocationMetadata> {
+    locations_metadata()
+        .into_iter()
+        .find(|location| location.name == name)
+}
+
+/// The occupancies the readings of the location `name` can have, see `Capacity::occupancy_range`.
+/// A percentage for a name without metadata.
+pub fn occupancy_range(name: &str) -> RangeInclusive<u16> {
+    match location_metadata(name) {
+        Some(location) => location.capacity.occupancy_range(),
+        None => 0..=100,
+    }
+}
+
+/// The CHECK that keeps the readings of the location `name` within its `occupancy_range`.
+fn occupancy_check(name: &str) -> String {
+    let range = occupancy_range(name);
+    format!(
+        "CHECK (occupancy BETWEEN {} AND {})",
+        range.start(),
+        range.end()
+    )
+}
+
+/// The columns of the readings table of the location `name`, which can't hold an occupancy
+/// outside of its `occupancy_range`. `is_anomaly` marks the readings left out of the predictions,
+/// see `SqliteDatabase::mark_anomaly`.
+fn reading_columns(name: &str) -> String {
+    format!(
+        "id INTEGER PRIMARY KEY, time INTEGER NOT NULL, occupancy INTEGER NOT NULL {}, \
+        is_anomaly INTEGER NOT NULL DEFAULT 0",
+        occupancy_check(name)
+    )
+}
 
 pub struct Scraper {
     connection_pool: Arc<Pool<SqliteConnectionManager>>,
+    /// Makes every write of the scrape targets, on a connection of its own.
+    writer: Writer,
+    writer_task: JoinHandle<()>,
     knn_config: HashMap<String, String>,
+    repredict: Arc<RepredictQueue>,
+    schedules: Arc<ScheduleCache>,
+    status: Arc<ScraperStatus>,
+    new_readings: Arc<NewReadings>,
+    retention: Retention,
+    /// The names in the `locations` table.
+    locations: Arc<HashSet<String>>,
 }
 
 impl Scraper {
-    pub fn setup(connection_pool: Arc<Pool<SqliteConnectionManager>>) -> Result<Self, String> {
-        // Our hardcoded scrapers
-        Self::create_table(&connection_pool, "gym")?;
-        Self::create_table(&connection_pool, "main_library")?;
+    pub fn setup(
+        connection_pool: Arc<Pool<SqliteConnectionManager>>,
+        retention: Retention,
+        batching: Batching,
+    ) -> Result<Self, String> {
+        let locations = Self::create_tables(&connection_pool)?;
         let knn_config = Self::read_knn_config()?;
+        let (writer, writer_task) = match connection_pool.get() {
+            Ok(connection) => Writer::start::<SqliteDatabase>(connection, batching),
+            Err(_) => {
+                return Err(
+                    "Couldn't obtain a connection for the database writer - Scraper.".to_owned(),
+                )
+            }
+        };
 
         Ok(Self {
             connection_pool,
+            writer,
+            writer_task,
             knn_config,
+            repredict: Arc::new(RepredictQueue::new(LOCATIONS)),
+            schedules: Arc::new(ScheduleCache::new()),
+            status: Arc::new(ScraperStatus::new()),
+            new_readings: Arc::new(NewReadings::new()),
+            retention,
+            locations: Arc::new(locations),
         })
     }
 
+    /**
+    Creates the tables of every location in `LOCATIONS`, along with the `locations` and
+    `scraper_meta` tables, and migrates the ones that are already there. A new database is ready
+    to be scraped into and served from afterwards.
+
+    Returns the names of the registered locations, see `register_locations`.
+    */
+    pub fn create_tables(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+    ) -> Result<HashSet<String>, String> {
+        for name in LOCATIONS {
+            Self::create_table(connection_pool, name)?;
+            Self::migrate_schedule_table(connection_pool, name)?;
+            Self::add_effective_from(connection_pool, name)?;
+        }
+        // Rebuilds the tables from before times were stored as timestamps, so it comes before
+        // any are filled in from the readings or indexed
+        Self::convert_times(connection_pool)?;
+        for name in LOCATIONS {
+            Self::create_hourly_table(connection_pool, name)?;
+            Self::add_anomaly_flag(connection_pool, name)?;
+            // Rebuilds the readings table, so it comes before the indexes are made
+            Self::add_occupancy_check(connection_pool, name)?;
+            Self::create_time_indexes(connection_pool, name)?;
+            Self::add_prediction_provenance(connection_pool, name)?;
+        }
+        let locations = Self::register_locations(connection_pool)?;
+        Self::create_scraper_meta_table(connection_pool)?;
+        Ok(locations)
+    }
+
+    /// The queue used to request prediction runs from outside the scraper.
+    pub fn repredict_queue(&self) -> Arc<RepredictQueue> {
+        self.repredict.clone()
+    }
+
+    /// The last Schedule scraped for each target.
+    pub fn schedule_cache(&self) -> Arc<ScheduleCache> {
+        self.schedules.clone()
+    }
+
+    /// How scraping each target has been going.
+    pub fn status(&self) -> Arc<ScraperStatus> {
+        self.status.clone()
+    }
+
+    /// The locations that have tables, read from the `locations` table at setup.
+    pub fn locations(&self) -> Arc<HashSet<String>> {
+        self.locations.clone()
+    }
+
+    /// Where every reading is announced as it is stored.
+    pub fn new_readings(&self) -> Arc<NewReadings> {
+        self.new_readings.clone()
+    }
+
     fn read_knn_config() -> Result<HashMap<String, String>, String> {
         let mut map = HashMap::new();
         let path = Path::new("knn_config/");
@@ -44,14 +250,17 @@ impl Scraper {
             return Ok(map);
         }
 
-        for entry in path.read_dir().expect("Could not read knn_config.") {
-            if let Ok(entry) = entry {
-                let entry = entry.path();
-                let name = path.file_name().unwrap().to_str().unwrap();
-                let data = fs::read_to_string(entry).unwrap();
-                map.insert(name.to_string(), data);
-                return Ok(map);
-            }
+        if let Some(entry) = path
+            .read_dir()
+            .expect("Could not read knn_config.")
+            .flatten()
+            .next()
+        {
+            let entry = entry.path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let data = fs::read_to_string(entry).unwrap();
+            map.insert(name.to_string(), data);
+            return Ok(map);
         }
 
         Ok(map)
@@ -65,64 +274,310 @@ impl Scraper {
         }
     }
 
-    pub async fn run(self) {
+    /// Run the scraper for every target until `shutdown` is set, and prune rows older than the
+    /// retention alongside.
+    ///
+    /// Each target finishes the iteration it is in before stopping, so a scrape is never cut off
+    /// halfway through writing to the database. Returns once they have all stopped and their
+    /// writes are applied.
+    pub async fn run(self, shutdown: watch::Receiver<bool>) {
         let gym = Gym::new(self.knn_config.get("gym").cloned());
         let library = MainLibrary::new(self.knn_config.get("main_library").cloned());
         println!("Running!");
-        tokio::spawn(Self::run_scraper(self.connection_pool.clone(), gym));
-        tokio::spawn(Self::run_scraper(self.connection_pool.clone(), library));
+        let gym = tokio::spawn(Self::run_scraper(
+            self.connection_pool.clone(),
+            self.writer.clone(),
+            self.repredict.clone(),
+            self.schedules.clone(),
+            self.status.clone(),
+            self.new_readings.clone(),
+            gym,
+            shutdown.clone(),
+        ));
+        let library = tokio::spawn(Self::run_scraper(
+            self.connection_pool.clone(),
+            self.writer.clone(),
+            self.repredict.clone(),
+            self.schedules.clone(),
+            self.status.clone(),
+            self.new_readings.clone(),
+            library,
+            shutdown.clone(),
+        ));
+        let prune = tokio::spawn(self.retention.run(self.connection_pool.clone(), shutdown));
+        let _ = tokio::join!(gym, library, prune);
+        // The writer stops once the targets' clones and this one are gone, after their writes
+        drop(self.writer);
+        let _ = self.writer_task.await;
+        println!("Scraper stopped.");
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run_scraper<T: Scrape<T>>(
         connection_pool: Arc<Pool<SqliteConnectionManager>>,
+        writer: Writer,
+        repredict: Arc<RepredictQueue>,
+        schedules: Arc<ScheduleCache>,
+        status: Arc<ScraperStatus>,
+        new_readings: Arc<NewReadings>,
         mut target: T,
+        mut shutdown: watch::Receiver<bool>,
     ) {
-        loop {
-            let (occupancy, schedule, timestamp) = match target.scrape(target.get_request()).await {
-                Err(err) => {
-                    println!("{}", err);
-                    Self::standard_sleep().await;
-                    continue;
-                }
-                Ok(data) => data,
-            };
-
-            if occupancy.is_none() || schedule.is_none() {
-                Self::standard_sleep().await;
-                continue;
+        let name = T::table_name();
+        // Needed to serve prediction requests that arrive in between scrapes
+        // Starts out as the last one stored, until the first scrape
+        let mut last_schedule = match connection_pool.get() {
+            Ok(connection) => SqliteDatabase::query_last_day_schedule(&connection, &name)
+                .unwrap_or_else(|err| {
+                    println!("Could not read the last schedule of {}.\n{}", name, err);
+                    None
+                }),
+            Err(_) => None,
+        };
+        let mut day = uk_datetime_now().date_naive();
+        while !*shutdown.borrow() {
+            let today = uk_datetime_now().date_naive();
+            if today != day {
+                Self::log_completeness(&connection_pool, &name, day);
+                day = today;
             }
+            let (occupancy, headcount, schedule, timestamp) =
+                match target.scrape(target.get_request()).await {
+                    Err(err) => {
+                        println!("{}", err);
+                        Self::record_scrape(&writer, &name, uk_datetime_now(), Some(err)).await;
+                        Self::standard_sleep(
+                            &mut target,
+                            &connection_pool,
+                            &writer,
+                            &repredict,
+                            &status,
+                            last_schedule.as_ref(),
+                            &mut shutdown,
+                        )
+                        .await;
+                        continue;
+                    }
+                    Ok(data) => data,
+                };
 
-            let occupancy = occupancy.unwrap();
-            let schedule = schedule.unwrap();
-
-            let connection = match connection_pool.get() {
-                Ok(conn) => conn,
-                Err(_) => {
-                    println!("Could not get database connection - Scrape.");
-                    return;
-                }
+            let (Some(occupancy), Some(schedule)) = (occupancy, schedule) else {
+                let err = "Could not find the occupancy or the schedule on the page.".to_string();
+                Self::record_scrape(&writer, &name, timestamp, Some(err)).await;
+                Self::standard_sleep(
+                    &mut target,
+                    &connection_pool,
+                    &writer,
+                    &repredict,
+                    &status,
+                    last_schedule.as_ref(),
+                    &mut shutdown,
+                )
+                .await;
+                continue;
             };
 
+            let mut write_error = None;
             if schedule.is_open(timestamp) {
-                match SqliteDatabase::insert_one_occupancy(
-                    &connection,
-                    &T::table_name(),
-                    timestamp.naive_local(),
+                let reading = WriteCommand::Reading(ScrapedReading {
+                    table_name: name.clone(),
+                    time: timestamp.naive_local(),
                     occupancy,
-                ) {
-                    Err(err) => println!("Error writing to database.\n{}", err.to_string()),
-                    _ => (),
+                    headcount,
+                });
+                match writer.write(reading).await {
+                    Ok(()) => new_readings.publish(NewReading {
+                        name: name.clone(),
+                        time: timestamp.naive_local(),
+                        occupancy,
+                    }),
+                    Err(err) => {
+                        println!("Error writing to database.\n{}", err);
+                        write_error = Some(format!("Error writing to database.\n{}", err));
+                    }
+                }
+                let stored = WriteCommand::Schedule {
+                    table_name: name.clone(),
+                    date: timestamp.date_naive(),
+                    schedule: schedule.clone(),
                 };
+                if let Err(err) = writer.write(stored).await {
+                    println!("Error writing to database.\n{}", err);
+                    write_error = Some(format!("Error writing to database.\n{}", err));
+                }
+            }
+            Self::record_scrape(&writer, &name, timestamp, write_error).await;
+
+            if Self::check_and_predict(&mut target, &connection_pool, &writer, &schedule).await {
+                status.predicted(&name, uk_datetime_now());
+            }
+            schedules.set(&name, schedule.clone(), timestamp);
+            last_schedule = Some(schedule);
+
+            Self::standard_sleep(
+                &mut target,
+                &connection_pool,
+                &writer,
+                &repredict,
+                &status,
+                last_schedule.as_ref(),
+                &mut shutdown,
+            )
+            .await;
+        }
+    }
+
+    /// Logs how much of the opening hours of `date` have readings of `name`, once the day is over.
+    /// See `SqliteDatabase::find_gaps`, nothing is logged for a closed day.
+    fn log_completeness(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+        date: NaiveDate,
+    ) {
+        let Ok(connection) = connection_pool.get() else {
+            println!(
+                "Could not check the readings of {} on {}. No connection.",
+                name, date
+            );
+            return;
+        };
+        let hours = SqliteDatabase::open_hours(&connection, name, date).and_then(|hours| {
+            let Some((opening, closing)) = hours else {
+                return Ok(None);
+            };
+            let gaps =
+                SqliteDatabase::find_gaps(&connection, name, opening, closing, SCRAPE_INTERVAL)?;
+            Ok(Some((opening, closing, gaps)))
+        });
+        let (opening, closing, gaps) = match hours {
+            Ok(Some(hours)) => hours,
+            Ok(None) => return,
+            Err(err) => {
+                println!(
+                    "Could not check the readings of {} on {}.\n{}",
+                    name, date, err
+                );
+                return;
+            }
+        };
+        if gaps.is_empty() {
+            println!(
+                "Readings of {} on {} cover all of its opening hours.",
+                name, date
+            );
+            return;
+        }
+        let open = uk_local_to_stored(closing) - uk_local_to_stored(opening);
+        let missing: i64 = gaps.iter().map(|gap| gap.seconds).sum();
+        println!(
+            "Readings of {} on {} cover {:.1}% of its opening hours, {} gaps {} minutes long in all.",
+            name,
+            date,
+            100.0 * (open - missing) as f64 / open as f64,
+            gaps.len(),
+            missing / 60
+        );
+    }
+
+    /// Logs that `skipped` rows of `name` couldn't be read for a prediction, which is made from
+    /// the `kept` others. Each row was logged as it was skipped, see `Parsed`.
+    fn log_skipped(name: &str, skipped: usize, kept: usize) {
+        if skipped > 0 {
+            println!(
+                "Skipped {} rows of '{}' that couldn't be read, predicting from the other {}.",
+                skipped, name, kept
+            );
+        }
+    }
+
+    /// Records how scraping `name` at `time` went in `scraper_meta`, see
+    /// `SqliteDatabase::record_scrape`. Only logged if it can't be, the scraper keeps going.
+    async fn record_scrape(writer: &Writer, name: &str, time: DateTime<Tz>, error: Option<String>) {
+        let scrape = WriteCommand::Scrape {
+            target: name.to_string(),
+            time: time.naive_local(),
+            error,
+        };
+        if let Err(err) = writer.write(scrape).await {
+            println!("Could not record the scrape of {}.\n{}", name, err);
+        }
+    }
+
+    /// Sleep until the next scrape is due.
+    ///
+    /// Prediction requests queued in the meantime are handled straight away without delaying the
+    /// next scrape. Returns early when `shutdown` is set.
+    async fn standard_sleep<T: Scrape<T>>(
+        target: &mut T,
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        writer: &Writer,
+        repredict: &RepredictQueue,
+        status: &ScraperStatus,
+        schedule: Option<&Schedule>,
+        shutdown: &mut watch::Receiver<bool>,
+    ) {
+        let name = T::table_name();
+        let deadline = Instant::now() + SCRAPE_INTERVAL;
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline) => return,
+                _ = shutdown.changed() => return,
+                _ = repredict.notified(&name) => {
+                    Self::repredict(target, connection_pool, writer, repredict, status, schedule)
+                        .await;
+                }
             }
+        }
+    }
 
-            Self::check_and_predict(&mut target, &connection_pool, &schedule);
+    /// Run a queued prediction request for the next 7 days, ignoring when the predictions were
+    /// last updated.
+    async fn repredict<T: Scrape<T>>(
+        target: &mut T,
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        writer: &Writer,
+        repredict: &RepredictQueue,
+        status: &ScraperStatus,
+        schedule: Option<&Schedule>,
+    ) {
+        let name = T::table_name();
+        let Some(model) = repredict.take(&name) else {
+            return;
+        };
+        let Some(schedule) = schedule else {
+            println!("Could not repredict {}. No schedule scraped yet.", name);
+            repredict.finish(&name);
+            return;
+        };
 
-            Self::standard_sleep().await;
+        let today = uk_datetime_now().naive_local().date();
+        let next_week = today.checked_add_days(Days::new(7)).unwrap();
+        println!("Repredicting {} ({:?}).", name, model);
+        let mut predicted = false;
+        if model.includes_knn() {
+            predicted |= Self::make_knn_predictions(
+                target,
+                connection_pool,
+                writer,
+                today,
+                next_week,
+                schedule,
+            )
+            .await;
+        }
+        if model.includes_lstm() && Self::has_lstm::<T>() {
+            predicted |=
+                Self::make_lstm_predictions(target, writer, today, next_week, schedule).await;
         }
+        if predicted {
+            status.predicted(&name, uk_datetime_now());
+        }
+        repredict.finish(&name);
     }
 
-    async fn standard_sleep() {
-        sleep_until(Instant::now() + Duration::from_secs(30 * 10)).await;
+    /// Whether LSTM predictions are made for the target. Only the gym has a model so far.
+    fn has_lstm<T: Scrape<T>>() -> bool {
+        T::table_name() == "gym"
     }
 
     fn create_table(
@@ -135,117 +590,707 @@ impl Scraper {
                 return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
             }
         };
-        match connection.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    time TEXT NOT NULL,
-                    occupancy INTEGER NOT NULL
-                )",
-                name
-            ),
-            (),
-        ) {
-            Err(_) => return Err(format!("Could not create table '{}'.", name).to_string()),
-            _ => (),
-        };
+        if connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} ({})",
+                    name,
+                    reading_columns(name)
+                ),
+                (),
+            )
+            .is_err()
+        {
+            return Err(format!("Could not create table '{}'.", name));
+        }
         let table_name = name.to_string() + "_schedule";
-        match connection.execute(
-            &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    date TEXT NOT NULL,
-                    schedule NOT NULL
-                )",
-                table_name
-            ),
-            (),
-        ) {
-            Err(_) => return Err(format!("Could not create table '{}'.", name).to_string()),
-            _ => (),
+        if connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} ({})",
+                    table_name, SCHEDULE_COLUMNS
+                ),
+                (),
+            )
+            .is_err()
+        {
+            return Err(format!("Could not create table '{}'.", name));
+        }
+        let tables = [
+            ("_prediction_knn", PREDICTION_COLUMNS),
+            ("_prediction_lstm", PREDICTION_COLUMNS),
+            ("_prediction_gb", PREDICTION_COLUMNS),
+            ("_reports", REPORT_COLUMNS),
+            ("_headcount", HEADCOUNT_COLUMNS),
+            ("_feedback", FEEDBACK_COLUMNS),
+        ];
+        for (suffix, columns) in tables {
+            let table_name = name.to_string() + suffix;
+            if connection
+                .execute(
+                    &format!("CREATE TABLE IF NOT EXISTS {} ({})", table_name, columns),
+                    (),
+                )
+                .is_err()
+            {
+                return Err(format!("Could not create table '{}'.", name));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Records every location in `LOCATIONS` in the `locations` table, once its tables are set up,
+    and returns the names the table holds.
+
+    The server only uses a requested name as a table name if it is in there. Locations stay
+    after they stop being scraped, since their tables and data do too.
+    */
+    fn register_locations(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+    ) -> Result<HashSet<String>, String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
         };
-        let table_name = name.to_string() + "_prediction_knn";
-        match connection.execute(
+        if let Err(err) = connection.execute(
             &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    time TEXT NOT NULL,
-                    occupancy INTEGER NOT NULL
-                )",
-                table_name
+                "CREATE TABLE IF NOT EXISTS locations ({})",
+                LOCATION_COLUMNS
             ),
             (),
         ) {
-            Err(_) => return Err(format!("Could not create table '{}'.", name).to_string()),
-            _ => (),
+            return Err(format!("Could not create table 'locations'.\n{}", err));
+        }
+        let now = uk_datetime_now().naive_local();
+        for location in locations_metadata() {
+            let registered = SqliteDatabase::register_location(
+                &connection,
+                location.name,
+                location.display_name,
+                now,
+            );
+            if let Err(err) = registered {
+                return Err(format!(
+                    "Could not register location '{}'.\n{}",
+                    location.name, err
+                ));
+            }
+        }
+        match SqliteDatabase::query_locations(&connection) {
+            Ok(rows) => Ok(rows.into_iter().map(|row| row.name).collect()),
+            Err(err) => Err(format!("Could not read the locations.\n{}", err)),
+        }
+    }
+
+    /**
+    Creates the `scraper_meta` table, where each location's row records when it was last scraped
+    and the last error, see `SqliteDatabase::record_scrape`. Unlike the in-memory
+    `ScraperStatus`, this survives a restart.
+    */
+    fn create_scraper_meta_table(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
         };
-        let table_name = name.to_string() + "_prediction_lstm";
-        match connection.execute(
+        if let Err(err) = connection.execute(
             &format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    id INTEGER PRIMARY KEY,
-                    time TEXT NOT NULL,
-                    occupancy INTEGER NOT NULL
-                )",
-                table_name
+                "CREATE TABLE IF NOT EXISTS scraper_meta ({})",
+                SCRAPER_META_COLUMNS
             ),
             (),
         ) {
-            Err(_) => return Err(format!("Could not create table '{}'.", name).to_string()),
-            _ => (),
+            return Err(format!("Could not create table 'scraper_meta'.\n{}", err));
+        }
+        Ok(())
+    }
+
+    /**
+    Converts a `{name}_schedule` table from before schedules were stored in columns, when each
+    row held a Schedule serialized to JSON, into one row per weekday.
+
+    Rows whose date or JSON can't be parsed are moved to `{name}_schedule_quarantine` along with
+    why, rather than keeping the server from starting. The conversion is one transaction, so a
+    restart half way through starts it over.
+    */
+    fn migrate_schedule_table(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        let table_name = name.to_string() + "_schedule";
+        let old_table = name.to_string() + "_schedule_json";
+        let quarantine = name.to_string() + "_schedule_quarantine";
+        let migrate = || -> Result<Option<(usize, usize)>, String> {
+            let transaction = connection
+                .unchecked_transaction()
+                .map_err(|e| e.to_string())?;
+            let is_old: bool = transaction
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = 'schedule')",
+                    [&table_name],
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+            if !is_old {
+                return Ok(None);
+            }
+            transaction
+                .execute_batch(&format!(
+                    "ALTER TABLE {} RENAME TO {};
+                    CREATE TABLE {} ({});
+                    CREATE TABLE IF NOT EXISTS {} (
+                        id INTEGER PRIMARY KEY,
+                        date,
+                        schedule,
+                        reason TEXT NOT NULL
+                    );",
+                    table_name, old_table, table_name, SCHEDULE_COLUMNS, quarantine
+                ))
+                .map_err(|e| e.to_string())?;
+
+            let rows: Vec<(i64, Option<String>, Option<String>)> = {
+                let mut statement = transaction
+                    .prepare(&format!(
+                        "SELECT id, CAST(date AS TEXT), CAST(schedule AS TEXT) FROM {} ORDER BY id",
+                        old_table
+                    ))
+                    .map_err(|e| e.to_string())?;
+                let rows = statement
+                    .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                    .map_err(|e| e.to_string())?;
+                rows.collect::<rusqlite::Result<_>>()
+                    .map_err(|e| e.to_string())?
+            };
+            let (mut converted, mut quarantined) = (0, 0);
+            for (id, date, schedule) in rows {
+                let date = date
+                    .unwrap_or_default()
+                    .parse::<NaiveDate>()
+                    .map_err(|e| format!("Invalid date: {}", e));
+                let schedule = serde_json::from_str::<Schedule>(&schedule.unwrap_or_default())
+                    .map_err(|e| format!("Invalid schedule: {}", e));
+                match (date, schedule) {
+                    (Ok(date), Ok(schedule)) => {
+                        SqliteDatabase::insert_one_schedule(&connection, name, date, &schedule)
+                            .map_err(|e| e.to_string())?;
+                        converted += 1;
+                    }
+                    (Err(reason), _) | (_, Err(reason)) => {
+                        transaction
+                            .execute(
+                                &format!(
+                                    "INSERT INTO {} (date, schedule, reason) SELECT date, schedule, ?2 FROM {} WHERE id = ?1",
+                                    quarantine, old_table
+                                ),
+                                rusqlite::params![id, reason],
+                            )
+                            .map_err(|e| e.to_string())?;
+                        quarantined += 1;
+                    }
+                }
+            }
+
+            transaction
+                .execute(&format!("DROP TABLE {}", old_table), ())
+                .map_err(|e| e.to_string())?;
+            transaction.commit().map_err(|e| e.to_string())?;
+            Ok(Some((converted, quarantined)))
+        };
+        match migrate() {
+            Ok(Some((converted, quarantined))) => {
+                println!("Converted {} schedules of '{}'.", converted, table_name);
+                if quarantined > 0 {
+                    println!(
+                        "Could not convert {} schedules, they are kept in '{}'.",
+                        quarantined, quarantine
+                    );
+                }
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not convert the schedules of '{}'.\n{}",
+                table_name, err
+            )),
+        }
+    }
+
+    /// Converts the tables that still store times as text to Unix timestamps, see
+    /// `epoch::convert`, and logs what was converted.
+    fn convert_times(connection_pool: &Arc<Pool<SqliteConnectionManager>>) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        match epoch::convert(&connection, false) {
+            Ok(converted) => {
+                epoch::log(&converted, false);
+                Ok(())
+            }
+            Err(err) => Err(format!(
+                "Could not convert the times to Unix timestamps.\n{}",
+                err
+            )),
+        }
+    }
+
+    /// Index the time column of every table of `name` that is queried by time. The readings and
+    /// predictions can only have one row per time, see `make_time_unique`.
+    ///
+    /// Run on every startup, so databases made before the indexes existed get them too. Building
+    /// them on a large existing table can take a few seconds, but only happens once.
+    fn create_time_indexes(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        for suffix in std::iter::once(&"").chain(PREDICTION_TABLES) {
+            Self::make_time_unique(&connection, &(name.to_string() + suffix))?;
+        }
+        for suffix in ["_reports", "_headcount"] {
+            let table_name = name.to_string() + suffix;
+            let created = connection.execute(
+                &format!(
+                    "CREATE INDEX IF NOT EXISTS {}_time ON {} (time)",
+                    table_name, table_name
+                ),
+                (),
+            );
+            if let Err(err) = created {
+                return Err(format!(
+                    "Could not index the time of '{}'.\n{}",
+                    table_name, err
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Adds the `generated_at` and `model_version` columns to the prediction tables of `name` that
+    are from before they were stored. The rows already there are left with NULLs.
+    */
+    fn add_prediction_provenance(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        for suffix in PREDICTION_TABLES {
+            let table_name = name.to_string() + suffix;
+            let migrate = || -> rusqlite::Result<bool> {
+                let is_old: bool = connection.query_row(
+                    "SELECT NOT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = 'generated_at')",
+                    [&table_name],
+                    |row| row.get(0),
+                )?;
+                if !is_old {
+                    return Ok(false);
+                }
+                connection.execute_batch(&format!(
+                    "BEGIN;
+                    ALTER TABLE {} ADD COLUMN generated_at INTEGER;
+                    ALTER TABLE {} ADD COLUMN model_version TEXT;
+                    COMMIT;",
+                    table_name, table_name
+                ))?;
+                Ok(true)
+            };
+            match migrate() {
+                Ok(true) => println!("Added the model version to '{}'.", table_name),
+                Ok(false) => (),
+                Err(err) => {
+                    return Err(format!(
+                        "Could not add the model version to '{}'.\n{}",
+                        table_name, err
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+    Puts a unique index on the time column of `table_name`, so writing a time again overwrites
+    the row instead of adding a second one.
+
+    Tables from before the index existed can already have several rows for a time, such as after
+    a restart mid-interval or predictions made twice. Those are removed first, keeping the one
+    written last, and the plain index it replaces is dropped.
+    */
+    fn make_time_unique(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+    ) -> Result<(), String> {
+        let index = format!("{}_time_unique", table_name);
+        let migrate = || -> rusqlite::Result<Option<usize>> {
+            let exists: bool = connection.query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1)",
+                [&index],
+                |row| row.get(0),
+            )?;
+            if exists {
+                return Ok(None);
+            }
+            let transaction = connection.unchecked_transaction()?;
+            let removed = transaction.execute(
+                &format!(
+                    "DELETE FROM {} WHERE id NOT IN (SELECT MAX(id) FROM {} GROUP BY time)",
+                    table_name, table_name
+                ),
+                (),
+            )?;
+            transaction.execute(&format!("DROP INDEX IF EXISTS {}_time", table_name), ())?;
+            transaction.execute(
+                &format!("CREATE UNIQUE INDEX {} ON {} (time)", index, table_name),
+                (),
+            )?;
+            transaction.commit()?;
+            Ok(Some(removed))
+        };
+        match migrate() {
+            Ok(Some(removed)) if removed > 0 => {
+                println!(
+                    "Removed {} rows with a duplicate time from '{}'.",
+                    removed, table_name
+                );
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not make the time of '{}' unique.\n{}",
+                table_name, err
+            )),
+        }
+    }
+
+    /**
+    Renames the `date` column of the `{name}_schedule` table to `effective_from` if it is from
+    before schedules were effective-dated, see `SCHEDULE_COLUMNS`. Each schedule was stored on the
+    date it was scraped, so it takes effect from that date.
+    */
+    fn add_effective_from(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        let table_name = name.to_string() + "_schedule";
+        let migrate = || -> rusqlite::Result<bool> {
+            let is_old: bool = connection.query_row(
+                "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = 'date')",
+                [&table_name],
+                |row| row.get(0),
+            )?;
+            if !is_old {
+                return Ok(false);
+            }
+            connection.execute(
+                &format!(
+                    "ALTER TABLE {} RENAME COLUMN date TO effective_from",
+                    table_name
+                ),
+                (),
+            )?;
+            Ok(true)
+        };
+        match migrate() {
+            Ok(true) => {
+                println!("Made the schedules of '{}' effective-dated.", name);
+                Ok(())
+            }
+            Ok(false) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not make the schedules of '{}' effective-dated.\n{}",
+                name, err
+            )),
+        }
+    }
+
+    /**
+    Adds the `is_anomaly` column of `reading_columns` to the readings table of `name` if it is
+    from before readings could be marked. None of the readings already there are marked.
+    */
+    fn add_anomaly_flag(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        let migrate = || -> rusqlite::Result<bool> {
+            let is_old: bool = connection.query_row(
+                "SELECT NOT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = 'is_anomaly')",
+                [name],
+                |row| row.get(0),
+            )?;
+            if !is_old {
+                return Ok(false);
+            }
+            connection.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN is_anomaly INTEGER NOT NULL DEFAULT 0",
+                    name
+                ),
+                (),
+            )?;
+            Ok(true)
+        };
+        match migrate() {
+            Ok(true) => {
+                println!("Added the anomaly flag to '{}'.", name);
+                Ok(())
+            }
+            Ok(false) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not add the anomaly flag to '{}'.\n{}",
+                name, err
+            )),
+        }
+    }
+
+    /**
+    Rebuilds the readings table of `name` with the CHECK of `reading_columns` if it was made
+    before it, or with a different range. SQLite can't add a CHECK to an existing table.
+
+    Readings outside of the range are dropped, logged, and the hours they were in are refreshed
+    in `{name}_hourly`. The indexes go with the old table, `create_time_indexes` makes them again.
+    */
+    fn add_occupancy_check(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        let range = occupancy_range(name);
+        let (check, columns) = (occupancy_check(name), reading_columns(name));
+        // The times of the first and last reading removed, if any were
+        let mut removed_span: Option<(i64, i64)> = None;
+        let mut migrate = || -> rusqlite::Result<Option<usize>> {
+            let sql: String = connection.query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [name],
+                |row| row.get(0),
+            )?;
+            if sql.contains(&check) {
+                return Ok(None);
+            }
+            let outside = format!(
+                "occupancy NOT BETWEEN {} AND {}",
+                range.start(),
+                range.end()
+            );
+            let transaction = connection.unchecked_transaction()?;
+            removed_span = transaction.query_row(
+                &format!(
+                    "SELECT MIN(time), MAX(time) FROM {} WHERE {}",
+                    name, outside
+                ),
+                (),
+                |row| Ok(row.get::<_, Option<i64>>(0)?.zip(row.get(1)?)),
+            )?;
+            let removed =
+                transaction.execute(&format!("DELETE FROM {} WHERE {}", name, outside), ())?;
+            transaction.execute_batch(&format!(
+                "CREATE TABLE {name}_checked ({columns});
+                INSERT INTO {name}_checked (id, time, occupancy, is_anomaly)
+                SELECT id, time, occupancy, is_anomaly FROM {name};
+                DROP TABLE {name};
+                ALTER TABLE {name}_checked RENAME TO {name};"
+            ))?;
+            transaction.commit()?;
+            Ok(Some(removed))
+        };
+        let removed = match migrate() {
+            Ok(Some(removed)) => removed,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                return Err(format!(
+                    "Could not limit the occupancy of '{}'.\n{}",
+                    name, err
+                ))
+            }
         };
+        println!(
+            "Limited the occupancy of '{}' to {} to {}.",
+            name,
+            range.start(),
+            range.end()
+        );
+        let Some((from, to)) = removed_span else {
+            return Ok(());
+        };
+        println!(
+            "Removed {} readings outside of {} to {} from '{}'.",
+            removed,
+            range.start(),
+            range.end(),
+            name
+        );
+        if let (Some(from), Some(to)) = (stored_to_uk_local(from), stored_to_uk_local(to)) {
+            if let Err(err) = SqliteDatabase::refresh_hourly(&connection, name, from, to) {
+                return Err(format!(
+                    "Could not refresh the hourly aggregates of '{}'.\n{}",
+                    name, err
+                ));
+            }
+        }
         Ok(())
     }
 
-    fn check_and_predict<T: Scrape<T>>(
+    /**
+    Creates `{name}_hourly`, the mean, min, max and number of the readings of every hour.
+
+    It is kept up to date as readings are written, see `SqliteDatabase::refresh_hourly`, so
+    long ranges can be summarised without reading every reading. When the table is new it is
+    filled from the readings already stored. Hours stay after their readings are pruned.
+    */
+    fn create_hourly_table(
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        name: &str,
+    ) -> Result<(), String> {
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => {
+                return Err("Couldn't obtain a connection for database setup - Scraper.".to_owned())
+            }
+        };
+        let table_name = name.to_string() + "_hourly";
+        // The table is created and filled in one transaction, so a restart half way through
+        // fills it in again
+        let create = || -> Result<Option<usize>, String> {
+            let transaction = connection
+                .unchecked_transaction()
+                .map_err(|e| e.to_string())?;
+            let exists: bool = transaction
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+                    [&table_name],
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+            if exists {
+                return Ok(None);
+            }
+            transaction
+                .execute(
+                    &format!("CREATE TABLE {} ({})", table_name, HOURLY_COLUMNS),
+                    (),
+                )
+                .map_err(|e| e.to_string())?;
+            let hours =
+                SqliteDatabase::backfill_hourly(&connection, name).map_err(|e| e.to_string())?;
+            transaction.commit().map_err(|e| e.to_string())?;
+            Ok(Some(hours))
+        };
+        match create() {
+            Ok(Some(hours)) if hours > 0 => {
+                println!("Filled in {} hours of '{}'.", hours, table_name);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not create table '{}'.\n{}", table_name, err)),
+        }
+    }
+
+    /// Make the predictions up to next week if they aren't already.
+    ///
+    /// Returns whether any predictions were stored.
+    async fn check_and_predict<T: Scrape<T>>(
         target: &mut T,
         connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        writer: &Writer,
         schedule: &Schedule,
-    ) {
+    ) -> bool {
         let today = uk_datetime_now().naive_local().date();
         let next_week = today.checked_add_days(Days::new(7)).unwrap();
         let last_updated = target.get_last_updated();
 
-        match last_updated {
+        let from = match last_updated {
             Some(last_updated) => {
-                if last_updated>= next_week {
+                if last_updated >= next_week {
                     // Already up to date with the predictions, nothing to do.
-                    return;
+                    return false;
                 }
                 // last_updated is less than next_week
-                Self::make_knn_predictions(
-                    target,
-                    connection_pool,
-                    last_updated,
-                    next_week,
-                    schedule,
-                );
-                if T::table_name() == "gym" {
-                    Self::make_lstm_predictions(
-                        target,
-                        connection_pool,
-                        last_updated,
-                        next_week,
-                        schedule,
-                    );
-                }
-            }
-            None => {
-                // Assume data is not there.
-                Self::make_knn_predictions(target, connection_pool, today, next_week, schedule);
-                if T::table_name() == "gym" {
-                    Self::make_lstm_predictions(
-                        target,
-                        connection_pool,
-                        today,
-                        next_week,
-                        schedule,
-                    );
-                }
+                last_updated
             }
+            // Assume data is not there.
+            None => today,
+        };
+        let mut predicted =
+            Self::make_knn_predictions(target, connection_pool, writer, from, next_week, schedule)
+                .await;
+        if Self::has_lstm::<T>() {
+            predicted |=
+                Self::make_lstm_predictions(target, writer, from, next_week, schedule).await;
         }
+        predicted
+    }
+
+    /// The readings of the last `n` weeks that were taken on `weekday`, ordered by time. The
+    /// same as that weekday's group from `get_last_n_weeks_data_grouped`, without fetching the
+    /// other six.
+    fn get_last_n_weeks_weekday<T: Scrape<T>>(
+        _target: &T,
+        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        weekday: Weekday,
+        n: usize,
+    ) -> Result<Vec<(NaiveDateTime, u16)>, String> {
+        let to = uk_datetime_now().naive_local();
+        let from = to.checked_sub_days(Days::new(n as u64 * 7)).unwrap();
+
+        let connection = match connection_pool.get() {
+            Ok(connection) => connection,
+            Err(_) => return Err("Could not get connection.".to_string()),
+        };
+        let table_name = &T::table_name();
+        let data =
+            match SqliteDatabase::query_weekday_range(&connection, table_name, weekday, from, to) {
+                Ok(data) => data,
+                Err(err) => return Err(err.to_string()),
+            };
+        Self::log_skipped(table_name, data.skipped, data.rows.len());
+
+        Ok(data.rows.into_iter().map(Into::into).collect())
     }
 
+    /// The readings of the last `n` weeks grouped by weekday, Monday first. Only used when
+    /// `get_last_n_weeks_weekday` fails, as it fetches every reading of the weeks.
     fn get_last_n_weeks_data_grouped<T: Scrape<T>>(
         _target: &T,
         connection_pool: &Arc<Pool<SqliteConnectionManager>>,
@@ -259,34 +1304,29 @@ impl Scraper {
             Err(_) => return Err("Could not get connection.".to_string()),
         };
         let table_name = &T::table_name();
-        let data = match SqliteDatabase::query_range(&connection, &table_name, from, to) {
+        // Missing readings are filled in with the predictions made for them
+        let data = match SqliteDatabase::query_range_agnostic(&connection, table_name, from, to) {
             Ok(data) => data,
             Err(err) => return Err(err.to_string()),
         };
-
-        let data: Vec<(NaiveDateTime, u16)> = data
-            .iter()
-            .map(|(time, occu)| {
-                let time = NaiveDateTime::parse_from_str(time, ISO_FORMAT).unwrap();
-                (time, *occu)
-            })
-            .collect();
+        Self::log_skipped(table_name, data.skipped, data.rows.len());
 
         let mut grouped_data: Vec<Vec<(NaiveDateTime, u16)>> = vec![Vec::new(); 7];
-        for element in data {
-            let day = element.0.weekday().number_from_monday() - 1;
-            grouped_data[day as usize].push(element);
+        for (reading, _) in data.rows {
+            let day = reading.time.weekday().number_from_monday() - 1;
+            grouped_data[day as usize].push(reading.into());
         }
         Ok(grouped_data)
     }
 
-    fn make_lstm_predictions<T: Scrape<T>>(
-        target: &mut T,
-        connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+    /// Returns whether the predictions were stored.
+    async fn make_lstm_predictions<T: Scrape<T>>(
+        _target: &mut T,
+        writer: &Writer,
         from: NaiveDate,
         to: NaiveDate,
         schedule: &Schedule,
-    ) {
+    ) -> bool {
         let timings = schedule.get_timings();
         let mut current_date = from;
         let mut final_predictions = Vec::new();
@@ -305,7 +1345,7 @@ impl Scraper {
                 Ok(predictions) => predictions,
                 Err(err) => {
                     println!("Could not get LSTM predictions.\n{}", err);
-                    return;
+                    return false;
                 }
             };
 
@@ -316,48 +1356,30 @@ impl Scraper {
             current_date = current_date.checked_add_days(Days::new(1)).unwrap();
         }
 
-        let connection = match connection_pool.get() {
-            Ok(connection) => connection,
-            Err(err) => {
-                println!("Could not get connection for LSTM predictions.\n{}", err);
-                return;
-            }
-        };
-
-        match SqliteDatabase::delete_range(
-            &connection,
-            &format!("{}{}", T::table_name(), "_prediction_lstm"),
-            from.and_hms_opt(0, 0, 0).unwrap(),
-            to.and_hms_opt(0, 0, 0).unwrap(),
-        ) {
-            Err(err) => println!("Could not delete lstm predictions.\n{}", err),
-            _ => (),
-        };
-        match SqliteDatabase::insert_many_occupancy(
-            &connection,
-            &format!("{}{}", T::table_name(), "_prediction_lstm"),
-            final_predictions,
-        ) {
-            Err(err) => println!("Could not insert lstm predictions.\n{}", err),
-            _ => (),
+        let predictions = WriteCommand::Predictions {
+            table_name: format!("{}{}", T::table_name(), "_prediction_lstm"),
+            from: from.and_hms_opt(0, 0, 0).unwrap(),
+            to: to.and_hms_opt(0, 0, 0).unwrap(),
+            rows: final_predictions,
+            generated_at: uk_datetime_now().naive_local(),
+            model_version: LSTM_MODEL_VERSION,
         };
+        if let Err(err) = writer.write(predictions).await {
+            println!("Could not store lstm predictions.\n{}", err);
+            return false;
+        }
+        true
     }
 
-    fn make_knn_predictions<T: Scrape<T>>(
+    /// Returns whether the predictions were stored.
+    async fn make_knn_predictions<T: Scrape<T>>(
         target: &mut T,
         connection_pool: &Arc<Pool<SqliteConnectionManager>>,
+        writer: &Writer,
         from: NaiveDate,
         to: NaiveDate,
         schedule: &Schedule,
-    ) {
-        let data = match Self::get_last_n_weeks_data_grouped(target, connection_pool, 3) {
-            Ok(data) => data,
-            Err(err) => {
-                println!("Could not get data for KNN predictions.\n{}", err);
-                return;
-            }
-        };
-
+    ) -> bool {
         let mut final_predictions: Vec<(NaiveDateTime, u16)> = Vec::new();
 
         let timings = schedule.get_timings();
@@ -367,6 +1389,23 @@ impl Scraper {
             let mut x: Vec<(f64, f64)> = Vec::new();
             let mut y: Vec<f64> = Vec::new();
             let index = (current_date.weekday().number_from_monday() - 1) as usize;
+            let weekday = current_date.weekday();
+            let data = match Self::get_last_n_weeks_weekday(target, connection_pool, weekday, 3) {
+                Ok(data) => data,
+                Err(err) => {
+                    println!(
+                        "Could not get {} data for KNN predictions, fetching every day.\n{}",
+                        weekday, err
+                    );
+                    match Self::get_last_n_weeks_data_grouped(target, connection_pool, 3) {
+                        Ok(mut data) => std::mem::take(&mut data[index]),
+                        Err(err) => {
+                            println!("Could not get data for KNN predictions.\n{}", err);
+                            return false;
+                        }
+                    }
+                }
+            };
 
             // Default if closed
             let opening_hm = timings[index].opening().unwrap_or(630) as u32;
@@ -375,13 +1414,13 @@ impl Scraper {
             // HM should not be invalid!
             // If so, something went wrong in the scraper or database
             let opening = current_date
-                .and_hms_opt(opening_hm / 100 as u32, opening_hm % 100, 0)
+                .and_hms_opt(opening_hm / 100_u32, opening_hm % 100, 0)
                 .unwrap();
             let closing = current_date
-                .and_hms_opt(closing_hm / 100 as u32, closing_hm % 100, 0)
+                .and_hms_opt(closing_hm / 100_u32, closing_hm % 100, 0)
                 .unwrap();
 
-            for (time, occupancy) in &data[index] {
+            for (time, occupancy) in &data {
                 let weight: f64 = 1.0 / ((opening - *time).num_weeks() + 1) as f64;
                 let time = time.num_seconds_from_midnight() as f64;
                 let occupancy = *occupancy as f64;
@@ -409,31 +1448,18 @@ impl Scraper {
             current_date = current_date.checked_add_days(Days::new(1)).unwrap();
         }
 
-        let connection = match connection_pool.get() {
-            Ok(connection) => connection,
-            Err(err) => {
-                println!("Could not get connection for KNN predictions.\n{}", err);
-                return;
-            }
-        };
-
-        match SqliteDatabase::delete_range(
-            &connection,
-            &format!("{}{}", T::table_name(), "_prediction_knn"),
-            from.and_hms_opt(0, 0, 0).unwrap(),
-            to.and_hms_opt(0, 0, 0).unwrap(),
-        ) {
-            Err(err) => println!("Could not delete KNN predictions.\n{}", err),
-            _ => (),
-        };
-        match SqliteDatabase::insert_many_occupancy(
-            &connection,
-            &format!("{}{}", T::table_name(), "_prediction_knn"),
-            final_predictions,
-        ) {
-            Err(err) => println!("Could not insert KNN predictions.\n{}", err),
-            _ => (),
+        let predictions = WriteCommand::Predictions {
+            table_name: format!("{}{}", T::table_name(), "_prediction_knn"),
+            from: from.and_hms_opt(0, 0, 0).unwrap(),
+            to: to.and_hms_opt(0, 0, 0).unwrap(),
+            rows: final_predictions,
+            generated_at: uk_datetime_now().naive_local(),
+            model_version: KNN_MODEL_VERSION,
         };
+        if let Err(err) = writer.write(predictions).await {
+            println!("Could not store KNN predictions.\n{}", err);
+            return false;
+        }
 
         // Update the last updated time
         target.set_last_updated(to);
@@ -441,18 +1467,28 @@ impl Scraper {
             Ok(_) => (),
             Err(err) => println!("Could not update KNN config.\n{}", err),
         };
+        true
     }
 }
 
+/// What a scrape found: the occupancy, the headcount behind it if the location publishes one,
+/// the schedule and when it was taken.
+pub type Scraped = (
+    Option<u16>,
+    Option<Headcount>,
+    Option<Schedule>,
+    DateTime<Tz>,
+);
+
 pub trait Scrape<T> {
     fn table_name() -> String;
 
+    /// Describes the location for clients.
+    fn metadata() -> LocationMetadata;
+
     fn get_request(&self) -> RequestBuilder;
 
-    async fn scrape(
-        &self,
-        request: RequestBuilder,
-    ) -> Result<(Option<u16>, Option<Schedule>, DateTime<Tz>), String> {
+    async fn scrape(&self, request: RequestBuilder) -> Result<Scraped, String> {
         let response = match request.send().await {
             Ok(data) => data,
             Err(err) => return Err(err.to_string()),
@@ -464,17 +1500,313 @@ pub trait Scrape<T> {
         };
         let timestamp = uk_datetime_now();
         Ok((
-            Self::parse_occupancy(&self, &body),
-            Self::parse_schedule(&self, &body),
+            Self::parse_occupancy(self, &body),
+            Self::parse_headcount(self, &body),
+            Self::parse_schedule(self, &body),
             timestamp,
         ))
     }
 
     fn parse_occupancy(&self, body: &str) -> Option<u16>;
 
+    /// The headcount behind the occupancy, for locations that publish one.
+    fn parse_headcount(&self, _body: &str) -> Option<Headcount> {
+        None
+    }
+
     fn parse_schedule(&self, body: &str) -> Option<Schedule>;
 
     fn get_last_updated(&self) -> Option<NaiveDate>;
 
     fn set_last_updated(&mut self, last_updated: NaiveDate);
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::Value;
+
+    use crate::{
+        database::test_support::{date, memory_pool, seed_predictions, seed_readings},
+        server::server::Server,
+        timing::daily::Daily,
+    };
+
+    use super::*;
+
+    /// A database as the first version of the scraper left it: times as text, the schedule as
+    /// JSON, and only the gym's tables.
+    fn fixture(schedule: &Schedule) -> Arc<Pool<SqliteConnectionManager>> {
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        let connection = pool.get().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE gym (id INTEGER PRIMARY KEY, time TEXT NOT NULL, occupancy INTEGER NOT NULL);
+                INSERT INTO gym (time, occupancy) VALUES
+                    ('2024-05-01T10:00:00', 40),
+                    ('2024-07-01T12:00:00', 55);
+                CREATE TABLE gym_schedule (id INTEGER PRIMARY KEY, date TEXT NOT NULL, schedule NOT NULL);
+                CREATE TABLE gym_prediction_knn (id INTEGER PRIMARY KEY, time TEXT NOT NULL,
+                    occupancy INTEGER NOT NULL);
+                INSERT INTO gym_prediction_knn (time, occupancy) VALUES ('2024-05-02T10:00:00', 30);
+                CREATE TABLE gym_prediction_lstm (id INTEGER PRIMARY KEY, time TEXT NOT NULL,
+                    occupancy INTEGER NOT NULL);",
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO gym_schedule (date, schedule) VALUES ('2024-05-01', ?1), ('2024-05-02', 'not json')",
+                [serde_json::to_string(schedule).unwrap()],
+            )
+            .unwrap();
+        drop(connection);
+        Arc::new(pool)
+    }
+
+    fn schedule() -> Schedule {
+        let mut timings = [Daily::new_open(630, 2200); 7];
+        timings[6] = Daily::new_closed();
+        Schedule::from_timings(timings)
+    }
+
+    /// The declared type of `column` in `table_name`.
+    fn column_type(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table_name: &str,
+        column: &str,
+    ) -> Option<String> {
+        connection
+            .query_row(
+                "SELECT type FROM pragma_table_info(?1) WHERE name = ?2",
+                [table_name, column],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    fn has_table(connection: &PooledConnection<SqliteConnectionManager>, table_name: &str) -> bool {
+        connection
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+                [table_name],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    /// The schema and every row of every table, to tell whether anything was changed.
+    fn dump(connection: &PooledConnection<SqliteConnectionManager>) -> Vec<String> {
+        let mut statement = connection
+            .prepare("SELECT name, sql FROM sqlite_master ORDER BY name")
+            .unwrap();
+        let schema: Vec<(String, Option<String>)> = statement
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let mut dump = Vec::new();
+        for (name, sql) in schema {
+            dump.push(format!("{}: {:?}", name, sql));
+            let Ok(mut rows) = connection.prepare(&format!("SELECT * FROM {}", name)) else {
+                // An index
+                continue;
+            };
+            let columns = rows.column_count();
+            let rows: Vec<String> = rows
+                .query_map((), |row| {
+                    let values: Vec<Value> = (0..columns)
+                        .map(|i| row.get(i))
+                        .collect::<rusqlite::Result<_>>()?;
+                    Ok(format!("{:?}", values))
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            dump.extend(rows);
+        }
+        dump
+    }
+
+    #[test]
+    fn an_old_database_is_migrated() {
+        let pool = fixture(&schedule());
+        let locations = Scraper::create_tables(&pool).unwrap();
+        assert_eq!(
+            locations,
+            HashSet::from(["gym".to_string(), "main_library".to_string()])
+        );
+
+        let connection = pool.get().unwrap();
+        let readings =
+            SqliteDatabase::query_single_day(&connection, "gym", date(2024, 7, 1)).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(
+            readings[0].time,
+            date(2024, 7, 1).and_hms_opt(12, 0, 0).unwrap()
+        );
+        assert_eq!(readings[0].occupancy, 55);
+        assert_eq!(
+            column_type(&connection, "gym", "time").as_deref(),
+            Some("INTEGER")
+        );
+
+        let stored =
+            SqliteDatabase::query_single_day_schedule(&connection, "gym", date(2024, 5, 8))
+                .unwrap();
+        assert_eq!(stored, Some(schedule()));
+        let quarantined: i64 = connection
+            .query_row("SELECT COUNT(*) FROM gym_schedule_quarantine", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(quarantined, 1);
+
+        for table_name in [
+            "gym_prediction_knn",
+            "gym_prediction_lstm",
+            "gym_prediction_gb",
+        ] {
+            assert_eq!(
+                column_type(&connection, table_name, "generated_at").as_deref(),
+                Some("INTEGER")
+            );
+            assert_eq!(
+                column_type(&connection, table_name, "model_version").as_deref(),
+                Some("TEXT")
+            );
+        }
+        let prediction =
+            SqliteDatabase::query_single_day(&connection, "gym_prediction_knn", date(2024, 5, 2))
+                .unwrap();
+        assert_eq!(prediction.len(), 1);
+        assert!(has_table(&connection, "main_library"));
+    }
+
+    #[test]
+    fn migrating_again_changes_nothing() {
+        let pool = fixture(&schedule());
+        Scraper::create_tables(&pool).unwrap();
+        let migrated = dump(&pool.get().unwrap());
+        Scraper::create_tables(&pool).unwrap();
+        assert_eq!(dump(&pool.get().unwrap()), migrated);
+    }
+
+    #[test]
+    fn a_new_database_is_ready_to_use() {
+        let pool = Arc::new(
+            Pool::builder()
+                .max_size(1)
+                .build(SqliteConnectionManager::memory())
+                .unwrap(),
+        );
+        Scraper::create_tables(&pool).unwrap();
+        let connection = pool.get().unwrap();
+        for name in LOCATIONS {
+            for suffix in [
+                "",
+                "_schedule",
+                "_hourly",
+                "_reports",
+                "_headcount",
+                "_feedback",
+            ]
+            .iter()
+            .chain(PREDICTION_TABLES)
+            {
+                let table_name = format!("{}{}", name, suffix);
+                assert!(
+                    has_table(&connection, &table_name),
+                    "{} is missing",
+                    table_name
+                );
+            }
+        }
+        assert!(has_table(&connection, "locations"));
+        assert!(has_table(&connection, "scraper_meta"));
+    }
+
+    /// The gym's page as it was on a Wednesday, trimmed down to what is scraped.
+    const GYM_PAGE: &str = r#"<html><body>
+<div class="occupancy-meter"><p>Occupancy: 42%</p></div>
+<dl class="paired-values-list">
+<dt>Monday</dt><dd class="paired-values-list__value">6.30am to 10.00pm</dd>
+<dt>Tuesday</dt><dd class="paired-values-list__value">6.30am to 10.00pm</dd>
+<dt>Wednesday</dt><dd class="paired-values-list__value">6.30am to 10.00pm</dd>
+<dt>Thursday</dt><dd class="paired-values-list__value">6.30am to 10.00pm</dd>
+<dt>Friday</dt><dd class="paired-values-list__value">6.30am to 9.00pm</dd>
+<dt>Saturday</dt><dd class="paired-values-list__value">9.00am to 6.00pm</dd>
+<dt>Sunday</dt><dd class="paired-values-list__value">9.00am to 6.00pm</dd>
+</dl>
+</body></html>"#;
+
+    #[tokio::test]
+    async fn a_scraped_page_is_served_back_for_its_day() {
+        let pool = memory_pool(2);
+        let day = date(2024, 5, 8);
+        let connection = pool.get().unwrap();
+        seed_readings(
+            &connection,
+            "gym",
+            &[(day.and_hms_opt(9, 45, 0).unwrap(), 30)],
+        );
+        seed_predictions(
+            &connection,
+            "gym",
+            "knn",
+            &[(day.and_hms_opt(11, 0, 0).unwrap(), 50)],
+        );
+
+        let gym = Gym::new(None);
+        let occupancy = gym.parse_occupancy(GYM_PAGE).unwrap();
+        let schedule = gym.parse_schedule(GYM_PAGE).unwrap();
+        assert_eq!(occupancy, 42);
+        assert_eq!(schedule.get_timings()[4], Daily::new_open(630, 2100));
+
+        // Written the way `run_scraper` does, on a connection of its own
+        let batching = Batching {
+            rows: 1,
+            interval: Duration::ZERO,
+        };
+        let (writer, written) = Writer::start::<SqliteDatabase>(pool.get().unwrap(), batching);
+        let time = day.and_hms_opt(10, 0, 0).unwrap();
+        writer
+            .write(WriteCommand::Reading(ScrapedReading {
+                table_name: "gym".to_string(),
+                time,
+                occupancy,
+                headcount: None,
+            }))
+            .await
+            .unwrap();
+        writer
+            .write(WriteCommand::Schedule {
+                table_name: "gym".to_string(),
+                date: day,
+                schedule: schedule.clone(),
+            })
+            .await
+            .unwrap();
+        drop(writer);
+        written.await.unwrap();
+
+        let response = Server::get_single_day::<SqliteDatabase>(&connection, day, "gym", &["knn"])
+            .unwrap()
+            .unwrap();
+        let response = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            response["data"],
+            serde_json::json!([["2024-05-08T09:45:00", 30], ["2024-05-08T10:00:00", 42]])
+        );
+        assert_eq!(
+            response["prediction_knn"],
+            serde_json::json!([["2024-05-08T11:00:00", 50]])
+        );
+        assert_eq!(
+            response["schedule"],
+            serde_json::to_value(&schedule).unwrap()
+        );
+        assert_eq!(response["meta"]["schedule_is_fallback"], false);
+    }
+}
@@ -1,13 +1,16 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::NaiveDate;
 use regex::Regex;
 use reqwest::Client;
 use reqwest::{Method, RequestBuilder};
 
+use crate::ISO_FORMAT_DATE;
 use crate::{
-    scraper::scraper::Scrape,
+    scraper::{
+        metadata::{Capacity, LocationMetadata},
+        scraper::{Scrape, SCRAPE_INTERVAL},
+    },
     timing::{daily::Daily, schedule::Schedule},
 };
-use crate::{ISO_FORMAT, ISO_FORMAT_DATE};
 
 pub struct Gym {
     url: String,
@@ -23,10 +26,8 @@ pub struct Gym {
 
 impl Gym {
     pub fn new(last_scraped: Option<String>) -> Self {
-        let last_scraped = match last_scraped {
-            Some(date) => Some(NaiveDate::parse_from_str(&date, ISO_FORMAT_DATE).unwrap()),
-            None => None,
-        };
+        let last_scraped =
+            last_scraped.map(|date| NaiveDate::parse_from_str(&date, ISO_FORMAT_DATE).unwrap());
 
         Self {
             url: "https://sport.wp.st-andrews.ac.uk/".to_string(),
@@ -63,8 +64,18 @@ impl Scrape<Gym> for Gym {
         "gym".to_string()
     }
 
+    fn metadata() -> LocationMetadata {
+        LocationMetadata {
+            name: "gym",
+            display_name: "St Andrews Sports Centre Gym",
+            source_url: "https://sport.wp.st-andrews.ac.uk/",
+            capacity: Capacity::Percentage,
+            scrape_interval_seconds: SCRAPE_INTERVAL.as_secs(),
+        }
+    }
+
     fn parse_occupancy(&self, body: &str) -> Option<u16> {
-        let regex_match = match self.occupancy_regex.captures(&body) {
+        let regex_match = match self.occupancy_regex.captures(body) {
             Some(data) => data,
             None => {
                 println!("Occupancy Scrape Error. Regex Fail");
@@ -84,9 +95,9 @@ impl Scrape<Gym> for Gym {
 
     fn parse_schedule(&self, body: &str) -> Option<Schedule> {
         // Captures the tags encompassing the Schedule
-        let mut schedules = self.schedule_regex.captures_iter(body);
+        let schedules = self.schedule_regex.captures_iter(body);
         let mut schedule = Schedule::new();
-        while let Some(inner_html) = schedules.next() {
+        for inner_html in schedules {
             // Capture each row
             let timings = self
                 .schedule_entry_regex
@@ -1,11 +1,14 @@
-use chrono::{DateTime, NaiveDate, NaiveDateTime};
-use chrono_tz::Tz;
+use chrono::NaiveDate;
 use regex::Regex;
 use reqwest::{Client, Method, RequestBuilder};
 use serde::Deserialize;
 
 use crate::{
-    scraper::scraper::Scrape,
+    scraper::{
+        headcount::Headcount,
+        metadata::{Capacity, LocationMetadata},
+        scraper::{Scrape, Scraped, SCRAPE_INTERVAL},
+    },
     timing::{daily::Daily, schedule::Schedule, uk_datetime_now::uk_datetime_now},
     ISO_FORMAT_DATE,
 };
@@ -32,10 +35,8 @@ struct APIResponse {
 
 impl MainLibrary {
     pub fn new(last_scraped: Option<String>) -> Self {
-        let last_scraped = match last_scraped {
-            Some(date) => Some(NaiveDate::parse_from_str(&date, ISO_FORMAT_DATE).unwrap()),
-            None => None,
-        };
+        let last_scraped =
+            last_scraped.map(|date| NaiveDate::parse_from_str(&date, ISO_FORMAT_DATE).unwrap());
 
         Self {
             url: "https://www.st-andrews.ac.uk/library/sentry-api/current-occupancy".to_string(),
@@ -66,6 +67,16 @@ impl Scrape<MainLibrary> for MainLibrary {
         "main_library".to_string()
     }
 
+    fn metadata() -> LocationMetadata {
+        LocationMetadata {
+            name: "main_library",
+            display_name: "St Andrews Main Library",
+            source_url: "https://www.st-andrews.ac.uk/library/sentry-api/current-occupancy",
+            capacity: Capacity::Headcount,
+            scrape_interval_seconds: SCRAPE_INTERVAL.as_secs(),
+        }
+    }
+
     fn get_request(&self) -> RequestBuilder {
         self.client
             .request(Method::GET, &self.url)
@@ -75,7 +86,7 @@ impl Scrape<MainLibrary> for MainLibrary {
     async fn scrape(
         &self,
         request: RequestBuilder,
-    ) -> Result<(Option<u16>, Option<Schedule>, DateTime<Tz>), String> {
+    ) -> Result<Scraped, String> {
         let response = match request.send().await {
             Ok(data) => data,
             Err(err) => return Err(err.to_string()),
@@ -103,8 +114,9 @@ impl Scrape<MainLibrary> for MainLibrary {
         };
 
         Ok((
-            Self::parse_occupancy(&self, &body),
-            Self::parse_schedule(&self, &schedule_body),
+            Self::parse_occupancy(self, &body),
+            Self::parse_headcount(self, &body),
+            Self::parse_schedule(self, &schedule_body),
             timestamp,
         ))
     }
@@ -117,15 +129,21 @@ impl Scrape<MainLibrary> for MainLibrary {
         Some(((response.total * 100) / response.capacity) as u16)
     }
 
+    fn parse_headcount(&self, body: &str) -> Option<Headcount> {
+        let response: APIResponse = serde_json::from_str(body).ok()?;
+        Some(Headcount {
+            total: response.total,
+            capacity: response.capacity,
+            staff: response.staff,
+            student: response.student,
+            other: response.other,
+        })
+    }
+
     fn parse_schedule(&self, body: &str) -> Option<Schedule> {
-        let mut schedules = self.schedule_regex.captures_iter(body);
+        let schedules = self.schedule_regex.captures_iter(body);
         let mut schedule = Schedule::new();
-        let mut counter = 0;
-        while let Some(inner_html) = schedules.next() {
-            if counter >= 7 {
-                break;
-            }
-            counter += 1;
+        for inner_html in schedules.take(7) {
             let timings = self
                 .schedule_entry_regex
                 .captures(inner_html.get(1)?.as_str())?;
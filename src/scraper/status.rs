@@ -0,0 +1,38 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+
+/// What the Scraper has done for a single target since startup that isn't stored.
+#[derive(Clone, Default)]
+pub struct TargetStatus {
+    /// When predictions were last generated for the target.
+    pub last_prediction: Option<DateTime<Tz>>,
+}
+
+/// What the Scraper has done for every target, recorded for the Server to report.
+///
+/// Only kept in memory, so it starts out empty after a restart. How the scrapes themselves went
+/// is stored in the `scraper_meta` table instead, see `SqliteDatabase::record_scrape`.
+#[derive(Default)]
+pub struct ScraperStatus {
+    targets: Mutex<HashMap<String, TargetStatus>>,
+}
+
+impl ScraperStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that predictions were generated for `name` at `time`.
+    pub fn predicted(&self, name: &str, time: DateTime<Tz>) {
+        let mut targets = self.targets.lock().unwrap();
+        targets.entry(name.to_string()).or_default().last_prediction = Some(time);
+    }
+
+    /// What was done for `name`, which is all empty until predictions are first generated.
+    pub fn get(&self, name: &str) -> TargetStatus {
+        let targets = self.targets.lock().unwrap();
+        targets.get(name).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,124 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use hyper::{header::REFERER, header::USER_AGENT, Request};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+
+use crate::timing::uk_datetime_now::uk_datetime_now;
+
+/// How many lines can be waiting to be written. Lines beyond that are dropped rather than
+/// holding up requests.
+const BUFFER_LINES: usize = 4096;
+
+/// The timestamp format of the combined log format, such as `15/Oct/2024:09:05:00 +0100`.
+const TIME_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+/// Appends a line per request to a file in the Apache combined log format.
+///
+/// Lines are handed to a background task that writes them through a buffer, so a slow disk
+/// never blocks a request. On SIGHUP the file is reopened, so it can be rotated by logrotate.
+pub struct AccessLog {
+    sender: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// Opens `path` for appending and starts the task that writes to it.
+    pub async fn open(path: PathBuf) -> Result<Self, String> {
+        let file = Self::open_file(&path).await?;
+        let (sender, receiver) = mpsc::channel(BUFFER_LINES);
+        tokio::spawn(Self::write(path, file, receiver));
+        Ok(Self { sender })
+    }
+
+    async fn open_file(path: &PathBuf) -> Result<File, String> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|err| format!("Could not open access log '{}'.\n{}", path.display(), err))
+    }
+
+    /// Logs a finished request. `size` is the length of the body, if it was known up front.
+    pub fn record(&self, request: &RequestLine, status: u16, size: Option<u64>) {
+        let _ = self.sender.try_send(request.format(status, size));
+    }
+
+    async fn write(path: PathBuf, file: File, mut receiver: mpsc::Receiver<String>) {
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        let mut writer = BufWriter::new(file);
+        loop {
+            tokio::select! {
+                line = receiver.recv() => {
+                    let Some(line) = line else {
+                        let _ = writer.flush().await;
+                        return;
+                    };
+                    if let Err(err) = writer.write_all(line.as_bytes()).await {
+                        println!("Could not write to the access log.\n{}", err);
+                    }
+                    // Flushed whenever we catch up, so lines don't sit in the buffer
+                    if receiver.is_empty() {
+                        let _ = writer.flush().await;
+                    }
+                }
+                _ = hangup.recv() => {
+                    let _ = writer.flush().await;
+                    match Self::open_file(&path).await {
+                        Ok(file) => writer = BufWriter::new(file),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What the access log needs from a request, taken before the request is handled.
+pub struct RequestLine {
+    client: Option<IpAddr>,
+    request: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl RequestLine {
+    pub fn new<B>(req: &Request<B>, client: Option<IpAddr>) -> Self {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            client,
+            request: format!("{} {} {:?}", req.method(), req.uri(), req.version()),
+            referer: header(REFERER),
+            user_agent: header(USER_AGENT),
+        }
+    }
+
+    /// The line in the combined log format, with `-` for anything missing.
+    fn format(&self, status: u16, size: Option<u64>) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"\n",
+            self.client
+                .map_or_else(|| "-".to_string(), |client| client.to_string()),
+            uk_datetime_now().format(TIME_FORMAT),
+            escape(&self.request),
+            status,
+            size.map_or_else(|| "-".to_string(), |size| size.to_string()),
+            escape(self.referer.as_deref().unwrap_or("-")),
+            escape(self.user_agent.as_deref().unwrap_or("-")),
+        )
+    }
+}
+
+/// Escapes a quoted field so a client can't break the line apart.
+fn escape(field: &str) -> String {
+    field.escape_default().to_string()
+}
@@ -0,0 +1,31 @@
+use hyper::Request;
+
+/// The header the admin API key is expected in.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Checks whether the request carries the configured admin API key.
+///
+/// Always fails when no key is configured, so the admin endpoints are locked by default.
+pub fn is_authorized<B>(req: &Request<B>, admin_key: Option<&str>) -> bool {
+    let Some(admin_key) = admin_key else {
+        return false;
+    };
+    match req.headers().get(API_KEY_HEADER) {
+        Some(provided) => constant_time_eq(provided.as_bytes(), admin_key.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compares two byte strings without short circuiting on the first mismatch, so the time taken
+/// does not leak how much of the key was guessed correctly.
+///
+/// Only the length is leaked, which is fine for an API key.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
@@ -0,0 +1,123 @@
+use std::{
+    convert::Infallible,
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::body::{Body, Frame};
+use tokio::sync::mpsc;
+
+use super::request_id;
+
+/// How many chunks a streamed body gets ahead of the client.
+const STREAM_BUFFER_CHUNKS: usize = 4;
+
+/// How many bytes a `ChunkWriter` collects before sending them.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The body of every response the Server sends.
+///
+/// Most responses are a single `Full` chunk, large ones are streamed with a `ChannelBody`.
+pub type ServerBody = BoxBody<Bytes, Infallible>;
+
+pub fn full(body: Full<Bytes>) -> ServerBody {
+    body.boxed()
+}
+
+/// A body made of the chunks `produce` sends, which runs on a blocking thread so it can read from
+/// SQLite as it goes.
+///
+/// `produce` is given the function to send each chunk with, which returns false once the client
+/// has gone away so it can stop. It runs with the request ID of the request it was started for.
+pub fn stream_blocking<F>(produce: F) -> ServerBody
+where
+    F: FnOnce(&dyn Fn(Bytes) -> bool) + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+    let id = request_id::current().unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        request_id::sync_scope(id, || {
+            produce(&|chunk| sender.blocking_send(chunk).is_ok());
+        })
+    });
+    ChannelBody::new(receiver).boxed()
+}
+
+/// Lets what is written with `io::Write` be sent by a `stream_blocking` producer, in chunks of
+/// about `CHUNK_SIZE` bytes. Writes fail with `BrokenPipe` once the client has gone away.
+pub struct ChunkWriter<'a> {
+    send: &'a dyn Fn(Bytes) -> bool,
+    buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl<'a> ChunkWriter<'a> {
+    pub fn new(send: &'a dyn Fn(Bytes) -> bool) -> Self {
+        Self {
+            send,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            closed: false,
+        }
+    }
+
+    /// Whether the client went away before everything was sent.
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        if !(self.send)(Bytes::from(chunk)) {
+            self.closed = true;
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        Ok(())
+    }
+}
+
+/// A body streamed from a channel, one chunk per message.
+///
+/// The body ends once the sender is dropped. If the client goes away the receiver is dropped,
+/// so the producer finds out the next time it tries to send.
+pub struct ChannelBody {
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl ChannelBody {
+    pub fn new(receiver: mpsc::Receiver<Bytes>) -> Self {
+        Self { receiver }
+    }
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+    }
+}
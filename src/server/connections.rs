@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The response written to a connection that is turned away because the limit is reached.
+///
+/// Written by hand since the connection is closed before hyper ever sees it.
+pub const BUSY_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Retry-After: 1\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+
+/// Limits how many connections are served at once.
+///
+/// Each connection holds a permit until it is closed, so slow or idle clients can't pile up
+/// without bound.
+pub struct ConnectionLimit {
+    semaphore: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl ConnectionLimit {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+
+    /// Takes a permit for a new connection, or `None` if the limit is reached.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+
+    /// How many connections are open.
+    pub fn in_flight(&self) -> usize {
+        self.limit - self.semaphore.available_permits()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
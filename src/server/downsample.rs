@@ -0,0 +1,104 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::ISO_FORMAT;
+
+/// How the readings in one bucket are combined into a single value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Aggregate {
+    Mean,
+    Min,
+    Max,
+}
+
+impl FromStr for Aggregate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Self::Mean),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Buckets a series into fixed intervals and keeps one value per bucket.
+#[derive(Copy, Clone, Debug)]
+pub struct Downsample {
+    interval: Duration,
+    aggregate: Aggregate,
+}
+
+impl Downsample {
+    pub fn new(interval: Duration, aggregate: Aggregate) -> Self {
+        Self {
+            interval,
+            aggregate,
+        }
+    }
+
+    /// The size of each bucket.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Parses a resolution such as `15m` or `1h`.
+    ///
+    /// The interval has to be at least a minute and at most a day.
+    pub fn parse_resolution(resolution: &str) -> Option<Duration> {
+        let interval = if let Some(minutes) = resolution.strip_suffix('m') {
+            Duration::try_minutes(minutes.parse().ok()?)?
+        } else if let Some(hours) = resolution.strip_suffix('h') {
+            Duration::try_hours(hours.parse().ok()?)?
+        } else {
+            return None;
+        };
+        if interval < Duration::minutes(1) || interval > Duration::days(1) {
+            return None;
+        }
+        Some(interval)
+    }
+
+    /**
+    Downsamples a series of (time, occupancy).
+
+    Each reading goes into the bucket its time falls in. Buckets are aligned to midnight as long
+    as the interval divides a day evenly. The returned time is the start of the bucket.
+    Buckets without any readings are left out rather than filled with zeros.
+    Times that can't be parsed are dropped.
+    */
+    pub fn apply(&self, series: &[(String, u16)]) -> Vec<(String, u16)> {
+        let interval = self.interval.num_seconds();
+        let mut buckets: BTreeMap<i64, Vec<u16>> = BTreeMap::new();
+        for (time, occupancy) in series {
+            let Ok(time) = NaiveDateTime::parse_from_str(time, ISO_FORMAT) else {
+                continue;
+            };
+            let seconds = time.and_utc().timestamp();
+            let bucket = seconds - seconds.rem_euclid(interval);
+            buckets.entry(bucket).or_default().push(*occupancy);
+        }
+
+        buckets
+            .into_iter()
+            .filter_map(|(bucket, values)| {
+                let time = chrono::DateTime::from_timestamp(bucket, 0)?.naive_utc();
+                Some((time.format(ISO_FORMAT).to_string(), self.combine(&values)))
+            })
+            .collect()
+    }
+
+    fn combine(&self, values: &[u16]) -> u16 {
+        match self.aggregate {
+            Aggregate::Mean => {
+                let sum: u32 = values.iter().map(|value| *value as u32).sum();
+                (sum as f64 / values.len() as f64).round() as u16
+            }
+            Aggregate::Min => *values.iter().min().unwrap(),
+            Aggregate::Max => *values.iter().max().unwrap(),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::ISO_FORMAT;
+
+/**
+Expands a series of (time, occupancy) onto a regular grid from `start` to `end` (inclusive).
+
+The grid has a point every `interval` starting at `start`. Each point takes the readings from
+its time up to the next point, averaged if there are several, and is `None` where there are none.
+Readings outside the grid are dropped, as are times that can't be parsed.
+*/
+pub fn fill(
+    series: &[(String, u16)],
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    interval: Duration,
+) -> Vec<(String, Option<u16>)> {
+    let step = interval.num_seconds();
+    if step <= 0 || end < start {
+        return Vec::new();
+    }
+    let slots = (end - start).num_seconds() / step;
+
+    let mut readings: BTreeMap<i64, Vec<u16>> = BTreeMap::new();
+    for (time, occupancy) in series {
+        let Ok(time) = NaiveDateTime::parse_from_str(time, ISO_FORMAT) else {
+            continue;
+        };
+        if time < start {
+            continue;
+        }
+        let slot = (time - start).num_seconds() / step;
+        if slot <= slots {
+            readings.entry(slot).or_default().push(*occupancy);
+        }
+    }
+
+    (0..=slots)
+        .map(|slot| {
+            let time = start + Duration::seconds(slot * step);
+            let occupancy = readings.get(&slot).map(|values| {
+                let sum: u32 = values.iter().map(|value| *value as u32).sum();
+                (sum as f64 / values.len() as f64).round() as u16
+            });
+            (time.format(ISO_FORMAT).to_string(), occupancy)
+        })
+        .collect()
+}
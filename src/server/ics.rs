@@ -0,0 +1,235 @@
+use chrono::{DateTime, Datelike, Days, Duration, NaiveDate, NaiveDateTime, Utc};
+
+use crate::timing::schedule::Schedule;
+
+/// The longest a line may be in octets, not counting the CRLF.
+const MAX_LINE_LENGTH: usize = 75;
+
+/// The days of the week as iCalendar writes them, starting on Monday like `Schedule`.
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+/// UK time, which the schedule is in, with the current daylight saving rules.
+const VTIMEZONE: &[&str] = &[
+    "BEGIN:VTIMEZONE",
+    "TZID:Europe/London",
+    "BEGIN:DAYLIGHT",
+    "TZOFFSETFROM:+0000",
+    "TZOFFSETTO:+0100",
+    "TZNAME:BST",
+    "DTSTART:19700329T010000",
+    "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU",
+    "END:DAYLIGHT",
+    "BEGIN:STANDARD",
+    "TZOFFSETFROM:+0100",
+    "TZOFFSETTO:+0000",
+    "TZNAME:GMT",
+    "DTSTART:19701025T020000",
+    "RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU",
+    "END:STANDARD",
+    "END:VTIMEZONE",
+];
+
+/**
+Converts a weekly Schedule into an iCalendar (RFC 5545) calendar.
+
+Every open weekday becomes an event from opening to closing that repeats weekly, starting in the
+week of `week_of`. Closed days have no event. `name` keeps the event UIDs stable between
+downloads so calendar apps update the events instead of duplicating them, and `stamp` is when
+the calendar was generated.
+*/
+pub fn calendar(
+    name: &str,
+    display_name: &str,
+    schedule: &Schedule,
+    week_of: NaiveDate,
+    stamp: DateTime<Utc>,
+) -> String {
+    let monday = week_of - Days::new(week_of.weekday().num_days_from_monday() as u64);
+    let mut lines: Vec<String> = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//occupancy-backend//Opening Hours//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(display_name)),
+    ];
+    lines.extend(VTIMEZONE.iter().map(|line| line.to_string()));
+
+    for (i, daily) in schedule.get_timings().iter().enumerate() {
+        let (Some(opening), Some(closing)) = (daily.opening(), daily.closing()) else {
+            continue;
+        };
+        let day = monday + Days::new(i as u64);
+        let start = hm_on(day, opening);
+        let mut end = hm_on(day, closing);
+        // Closing after midnight
+        if end <= start {
+            end += Duration::days(1);
+        }
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!(
+                "UID:{}-{}@occupancy-backend",
+                name,
+                WEEKDAYS[i].to_lowercase()
+            ),
+            format!("DTSTAMP:{}", stamp.format("%Y%m%dT%H%M%SZ")),
+            format!("DTSTART;TZID=Europe/London:{}", format_local(start)),
+            format!("DTEND;TZID=Europe/London:{}", format_local(end)),
+            format!("RRULE:FREQ=WEEKLY;BYDAY={}", WEEKDAYS[i]),
+            format!("SUMMARY:{}", escape_text(&format!("{} open", display_name))),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// An HHMM time on `day`. 2400 is midnight at the end of the day.
+fn hm_on(day: NaiveDate, hm: u16) -> NaiveDateTime {
+    day.and_hms_opt(0, 0, 0).unwrap()
+        + Duration::minutes((hm / 100) as i64 * 60 + (hm % 100) as i64)
+}
+
+/// A local date-time in the basic format, such as `20241015T063000`.
+fn format_local(time: NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%S").to_string()
+}
+
+/// Escapes a TEXT value.
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Ends a content line with CRLF, folding it onto continuation lines that start with a space
+/// when it is longer than `MAX_LINE_LENGTH` octets. Lines are never split inside a character.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the next line
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::timing::daily::Daily;
+
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// The unfolded lines of a calendar.
+    fn lines(calendar: &str) -> Vec<String> {
+        calendar
+            .replace("\r\n ", "")
+            .split("\r\n")
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn calendar_of(schedule: &Schedule) -> Vec<String> {
+        let stamp = Utc.with_ymd_and_hms(2024, 5, 8, 12, 0, 0).unwrap();
+        // A Wednesday, so the events start on Monday the 6th
+        lines(&calendar(
+            "gym",
+            "Gym, St Andrews",
+            schedule,
+            date(2024, 5, 8),
+            stamp,
+        ))
+    }
+
+    #[test]
+    fn hhmm_times_are_on_the_day() {
+        let day = date(2024, 5, 6);
+        assert_eq!(format_local(hm_on(day, 630)), "20240506T063000");
+        assert_eq!(format_local(hm_on(day, 0)), "20240506T000000");
+        assert_eq!(format_local(hm_on(day, 2400)), "20240507T000000");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(escape_text("a, b; c\\d\ne"), r"a\, b\; c\\d\ne");
+    }
+
+    #[test]
+    fn short_lines_are_only_ended() {
+        assert_eq!(fold("BEGIN:VCALENDAR"), "BEGIN:VCALENDAR\r\n");
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let line = "X".repeat(160);
+        let folded = fold(&line);
+        let parts: Vec<&str> = folded.trim_end_matches("\r\n").split("\r\n").collect();
+        assert_eq!(
+            parts.iter().map(|part| part.len()).collect::<Vec<_>>(),
+            [75, 75, 12]
+        );
+        assert!(parts[1..].iter().all(|part| part.starts_with(' ')));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn lines_are_never_folded_inside_a_character() {
+        // 74 octets then a two octet character, which would end at octet 76
+        let line = format!("{}é", "X".repeat(74));
+        assert_eq!(fold(&line), format!("{}\r\n é\r\n", "X".repeat(74)));
+    }
+
+    #[test]
+    fn every_open_day_repeats_weekly_from_this_week() {
+        let mut timings = [Daily::new_open(630, 2200); 7];
+        timings[6] = Daily::new_closed();
+        let lines = calendar_of(&Schedule::from_timings(timings));
+
+        let starts: Vec<&String> = lines
+            .iter()
+            .filter(|line| line.starts_with("DTSTART;TZID="))
+            .collect();
+        assert_eq!(starts.len(), 6);
+        assert_eq!(starts[0], "DTSTART;TZID=Europe/London:20240506T063000");
+        assert_eq!(starts[5], "DTSTART;TZID=Europe/London:20240511T063000");
+        assert!(lines.contains(&"DTEND;TZID=Europe/London:20240506T220000".to_string()));
+        assert!(lines.contains(&"RRULE:FREQ=WEEKLY;BYDAY=MO".to_string()));
+        assert!(lines.contains(&"UID:gym-sa@occupancy-backend".to_string()));
+        // Sunday is closed
+        assert!(!lines.iter().any(|line| line.contains("BYDAY=SU")));
+        assert!(lines.contains(&"DTSTAMP:20240508T120000Z".to_string()));
+        assert!(lines.contains(&"X-WR-CALNAME:Gym\\, St Andrews".to_string()));
+    }
+
+    #[test]
+    fn closing_after_midnight_ends_the_next_day() {
+        let timings = [Daily::new_open(1800, 100); 7];
+        let lines = calendar_of(&Schedule::from_timings(timings));
+        assert!(lines.contains(&"DTSTART;TZID=Europe/London:20240512T180000".to_string()));
+        assert!(lines.contains(&"DTEND;TZID=Europe/London:20240513T010000".to_string()));
+    }
+
+    #[test]
+    fn a_closed_week_has_only_the_calendar() {
+        let lines = calendar_of(&Schedule::from_timings([Daily::new_closed(); 7]));
+        assert!(!lines.iter().any(|line| line == "BEGIN:VEVENT"));
+        assert_eq!(lines.first().unwrap(), "BEGIN:VCALENDAR");
+        assert_eq!(lines.last().unwrap(), "END:VCALENDAR");
+        assert!(lines.contains(&"TZID:Europe/London".to_string()));
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fmt::{self, Display},
+    fs::{self, Permissions},
+    future::poll_fn,
+    io,
+    net::{IpAddr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+/// How many connections can be waiting to be accepted on a TCP listener.
+const TCP_BACKLOG: i32 = 1024;
+
+/// Where the server listens, from `--listen`.
+#[derive(Debug, Clone)]
+pub enum Listen {
+    /// A TCP address such as `127.0.0.1:7878`.
+    Tcp(SocketAddr),
+    /// A unix socket at the path, written as `unix:/run/occupancy.sock`.
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(listen: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = listen.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("--listen unix: needs a socket path.".to_string());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        listen.parse().map(Self::Tcp).map_err(|_| {
+            format!(
+                "Could not parse --listen '{}', expected HOST:PORT or unix:PATH.",
+                listen
+            )
+        })
+    }
+}
+
+impl Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound TCP or unix socket listener.
+///
+/// A unix socket file is removed again when the listener is dropped.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Binds to `listen`. A unix socket is given the permissions `mode`.
+    ///
+    /// A socket file left behind by a previous run is replaced, but not one that is still
+    /// being listened on.
+    pub fn bind(listen: &Listen, mode: u32) -> Result<Self, String> {
+        let bind_error = |err: io::Error| format!("Could not listen on {}.\n{}", listen, err);
+        match listen {
+            Listen::Tcp(address) => Self::bind_tcp(*address).map(Self::Tcp).map_err(bind_error),
+            Listen::Unix(path) => {
+                Self::remove_stale_socket(listen, path)?;
+                let listener = UnixListener::bind(path).map_err(bind_error)?;
+                // Made before the permissions are set, so it is cleaned up if they can't be
+                let listener = Self::Unix(listener, path.clone());
+                fs::set_permissions(path, Permissions::from_mode(mode)).map_err(|err| {
+                    format!("Could not set the permissions of {}.\n{}", listen, err)
+                })?;
+                Ok(listener)
+            }
+        }
+    }
+
+    /// Binds a TCP listener to `address`.
+    ///
+    /// An IPv6 address only listens for IPv6, so `[::]` and `0.0.0.0` can be listened on side by
+    /// side instead of `[::]` taking both.
+    fn bind_tcp(address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        socket.set_reuse_address(true)?;
+        if address.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&address.into())?;
+        socket.listen(TCP_BACKLOG)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    fn remove_stale_socket(listen: &Listen, path: &PathBuf) -> Result<(), String> {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return Ok(());
+        };
+        if !metadata.file_type().is_socket() {
+            return Err(format!(
+                "Could not listen on {}, the file exists and is not a socket.",
+                listen
+            ));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!(
+                "Could not listen on {}, another server is listening on it.",
+                listen
+            ));
+        }
+        fs::remove_file(path).map_err(|err| {
+            format!(
+                "Could not remove the stale socket {}.\n{}",
+                path.display(),
+                err
+            )
+        })
+    }
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(Stream, Peer)>> {
+        match self {
+            Self::Tcp(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, peer)| (Stream::Tcp(stream), Peer::Tcp(peer))),
+            Self::Unix(listener, _) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, _)| (Stream::Unix(stream), Peer::Unix)),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Every address the server listens on, accepted from as one.
+pub struct Listeners {
+    listeners: Vec<Listener>,
+    /// The listener to look at first on the next accept, so a busy one can't starve the others.
+    next: usize,
+}
+
+impl Listeners {
+    /// Binds to each of `listens`, see `Listener::bind`. Fails on the first that can't be bound.
+    pub fn bind(listens: &[Listen], mode: u32) -> Result<Self, String> {
+        let listeners = listens
+            .iter()
+            .map(|listen| Listener::bind(listen, mode))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { listeners, next: 0 })
+    }
+
+    /// Accepts the next connection on any of the listeners.
+    pub async fn accept(&mut self) -> io::Result<(Stream, Peer)> {
+        poll_fn(|cx| {
+            let count = self.listeners.len();
+            for offset in 0..count {
+                let index = (self.next + offset) % count;
+                if let Poll::Ready(accepted) = self.listeners[index].poll_accept(cx) {
+                    self.next = (index + 1) % count;
+                    return Poll::Ready(accepted);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Who is on the other end of an accepted connection.
+#[derive(Debug, Clone, Copy)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// Unix socket clients have no address worth knowing.
+    Unix,
+}
+
+impl Peer {
+    /// The client's IP address, which a unix socket client doesn't have.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(address) => Some(address.ip()),
+            Self::Unix => None,
+        }
+    }
+}
+
+impl Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix => write!(f, "a unix socket client"),
+        }
+    }
+}
+
+/// An accepted connection from either kind of listener.
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Tcp(stream) => stream.is_write_vectored(),
+            Self::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::BodyExt;
+    use hyper::{client::conn::http1, server::conn::http1 as server_http1, Method, StatusCode};
+    use hyper_util::rt::TokioIo;
+
+    use crate::server::test_support::{request, TestServer};
+
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("occupancy-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn a_request_is_served_over_a_unix_socket() {
+        let path = socket_path("serve");
+        let listen = Listen::Unix(path.clone());
+        let mut listeners = Listeners::bind(&[listen], 0o660).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let test = TestServer::new();
+        let client = tokio::spawn(UnixStream::connect(path.clone()));
+        let (stream, peer) = listeners.accept().await.unwrap();
+        assert!(matches!(stream, Stream::Unix(_)));
+        assert_eq!(peer.ip(), None);
+        let server = test.server.for_peer(peer.ip());
+        tokio::spawn(server_http1::Builder::new().serve_connection(TokioIo::new(stream), server));
+
+        let client = TokioIo::new(client.await.unwrap().unwrap());
+        let (mut sender, connection) = http1::handshake(client).await.unwrap();
+        tokio::spawn(connection);
+        let response = sender
+            .send_request(request(Method::GET, "/api/locations"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Bytes = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("gym"));
+
+        drop(listeners);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn a_stale_socket_is_replaced_but_not_one_being_listened_on() {
+        let path = socket_path("stale");
+        let listen = Listen::Unix(path.clone());
+        // A socket file nobody listens on any more, as a crashed server leaves behind
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = Listener::bind(&listen, 0o600).unwrap();
+        let err = Listener::bind(&listen, 0o600).err().unwrap();
+        assert!(err.contains("another server is listening on it"), "{}", err);
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
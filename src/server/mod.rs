@@ -1,2 +1,22 @@
-pub mod server;
+pub mod access_log;
+mod auth;
+mod body;
+pub mod connections;
+mod downsample;
+mod gap_fill;
+mod ics;
+pub mod listener;
 mod myresponse;
+mod options;
+mod pool;
+mod rate_limit;
+mod request_id;
+#[allow(clippy::module_inception)]
+pub mod server;
+mod routes;
+mod smoothing;
+mod status_page;
+#[cfg(test)]
+pub mod test_support;
+pub mod tls;
+mod validation;
@@ -1,31 +1,741 @@
-use serde::Serialize;
+use std::collections::BTreeMap;
 
-use crate::timing::schedule::Schedule;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use serde::{
+    ser::{SerializeSeq, SerializeStruct},
+    Serialize, Serializer,
+};
 
-/// The Response struct that is used to send data back to the client.
-///
-/// This is pretty basic so far.
+use super::{downsample::Downsample, gap_fill, smoothing};
+
+use crate::{
+    database::sqlite::{Gap, OccupancyReading, PeakRow},
+    scraper::{headcount::Headcount, metadata::LocationMetadata},
+    timing::{
+        daily::Daily,
+        schedule::Schedule,
+        timezone::{uk_local_to_epoch, uk_local_to_timezone},
+    },
+    ISO_FORMAT, ISO_FORMAT_OFFSET,
+};
+
+/// How the times in the series of a response are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimeFormat {
+    /// The stored `ISO_FORMAT` string, or `ISO_FORMAT_OFFSET` after a timezone conversion.
+    #[default]
+    Iso,
+    /// Integer Unix timestamps.
+    Epoch,
+}
+
+/// A series of (time, value) that is serialized with its times in `format`.
+struct TimedSeries<'a, T> {
+    series: &'a [(String, T)],
+    format: TimeFormat,
+}
+
+impl<'a, T> TimedSeries<'a, T> {
+    fn new(series: &'a [(String, T)], format: TimeFormat) -> Self {
+        Self { series, format }
+    }
+}
+
+impl<T: Serialize> Serialize for TimedSeries<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.format == TimeFormat::Iso {
+            return self.series.serialize(serializer);
+        }
+        let mut seq = serializer.serialize_seq(Some(self.series.len()))?;
+        let mut previous = None;
+        for (time, value) in self.series {
+            // Times that can't be converted can't come from the scraper, so they are left out
+            // rather than mixing strings in with the numbers.
+            if let Some(epoch) = epoch(time, previous) {
+                seq.serialize_element(&(epoch, value))?;
+                previous = Some(epoch);
+            }
+        }
+        seq.end()
+    }
+}
 
+/// The Unix timestamp of a time string, which is either a stored UK time or has been converted
+/// to one with an offset. `previous` is the timestamp before it, see `uk_local_to_epoch`.
+fn epoch(time: &str, previous: Option<i64>) -> Option<i64> {
+    if let Ok(time) = DateTime::parse_from_str(time, ISO_FORMAT_OFFSET) {
+        return Some(time.timestamp());
+    }
+    let time = NaiveDateTime::parse_from_str(time, ISO_FORMAT).ok()?;
+    uk_local_to_epoch(time, previous)
+}
+
+/// Information about the response itself rather than the occupancy.
 #[derive(Serialize, Clone)]
+pub struct ResponseMeta {
+    /// The day the response is for
+    date: String,
+    /// When the newest reading for the location was taken, on any day
+    latest_reading: Option<String>,
+    /// The prediction models that have rows in the response
+    models: Vec<&'static str>,
+    /// When the newest predictions of each model in the response were generated, left out for
+    /// models whose predictions are from before this was stored
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    generated_at: BTreeMap<&'static str, String>,
+    /// Set when the day is before the first recorded schedule and the oldest one is used instead
+    schedule_is_fallback: bool,
+    /// Set when the schedule has the day as closed, which is why there may be no data
+    closed: bool,
+    /// What the location is, when it is a known one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<LocationMetadata>,
+}
+
+impl ResponseMeta {
+    pub fn new(
+        date: NaiveDate,
+        latest_reading: Option<String>,
+        schedule_is_fallback: bool,
+        closed: bool,
+        location: Option<LocationMetadata>,
+    ) -> Self {
+        Self {
+            date: date.to_string(),
+            latest_reading,
+            models: Vec::new(),
+            generated_at: BTreeMap::new(),
+            schedule_is_fallback,
+            closed,
+            location,
+        }
+    }
+}
+
+/// The Response struct that is used to send data back to the client.
+///
+/// Every series, `data` and each of the predictions, is strictly increasing in time. The queries
+/// they come from are ordered by time and keep one reading per minute, and nothing done to them
+/// afterwards reorders them.
+#[derive(Clone)]
 pub struct MyResponse {
-    data: Vec<(String, u16)>,
+    /// The readings. Only `None` in the empty slots of a filled series, see `fill`.
+    data: Vec<(String, Option<u16>)>,
     prediction_knn: Vec<(String, u16)>,
     prediction_lstm: Vec<(String, u16)>,
+    prediction_gb: Vec<(String, u16)>,
     schedule: Schedule,
+    meta: ResponseMeta,
+    /// The headcount behind each reading, for locations that publish one. Left out of the
+    /// response entirely for the others.
+    headcount: Option<Vec<(String, Headcount)>>,
+    /// The readings marked as anomalies, which are in `data` as well. Only in /api/day
+    /// responses.
+    anomalies: Option<Vec<(String, u16)>>,
+    /// How the times in `data` and the predictions are serialized.
+    time_format: TimeFormat,
+}
+
+impl Serialize for MyResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields =
+            6 + usize::from(self.headcount.is_some()) + usize::from(self.anomalies.is_some());
+        let mut response = serializer.serialize_struct("MyResponse", fields)?;
+        response.serialize_field("data", &TimedSeries::new(&self.data, self.time_format))?;
+        for (key, series) in [
+            ("prediction_knn", &self.prediction_knn),
+            ("prediction_lstm", &self.prediction_lstm),
+            ("prediction_gb", &self.prediction_gb),
+        ] {
+            response.serialize_field(key, &TimedSeries::new(series, self.time_format))?;
+        }
+        match &self.headcount {
+            Some(headcount) => response
+                .serialize_field("headcount", &TimedSeries::new(headcount, self.time_format))?,
+            None => response.skip_field("headcount")?,
+        }
+        match &self.anomalies {
+            Some(anomalies) => response
+                .serialize_field("anomalies", &TimedSeries::new(anomalies, self.time_format))?,
+            None => response.skip_field("anomalies")?,
+        }
+        response.serialize_field("schedule", &self.schedule)?;
+        response.serialize_field("meta", &self.meta)?;
+        response.end()
+    }
 }
 
 impl MyResponse {
     pub fn new(
-        data: Vec<(String, u16)>,
+        data: Vec<OccupancyReading>,
         schedule: Schedule,
-        prediction_knn: Vec<(String, u16)>,
-        prediction_lstm: Vec<(String, u16)>,
+        prediction_knn: Vec<OccupancyReading>,
+        prediction_lstm: Vec<OccupancyReading>,
+        prediction_gb: Vec<OccupancyReading>,
+        mut meta: ResponseMeta,
     ) -> Self {
+        let prediction_knn = Self::iso(prediction_knn);
+        let prediction_lstm = Self::iso(prediction_lstm);
+        let prediction_gb = Self::iso(prediction_gb);
+        meta.models = [
+            ("knn", &prediction_knn),
+            ("lstm", &prediction_lstm),
+            ("gb", &prediction_gb),
+        ]
+        .iter()
+        .filter(|(_, series)| !series.is_empty())
+        .map(|(model, _)| *model)
+        .collect();
         Self {
-            data,
+            data: Self::present(Self::iso(data)),
             schedule,
             prediction_knn,
             prediction_lstm,
+            prediction_gb,
+            meta,
+            headcount: None,
+            anomalies: None,
+            time_format: TimeFormat::Iso,
         }
     }
+
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.time_format = time_format;
+    }
+
+    /// Adds the headcounts of the day. They are the raw values, so they are converted into
+    /// another timezone but not smoothed, downsampled or filled like the readings.
+    pub fn set_headcount(&mut self, headcount: Vec<(String, Headcount)>) {
+        self.headcount = Some(headcount);
+    }
+
+    /// Adds the readings of the day marked as anomalies, see `SqliteDatabase::mark_anomaly`. Like
+    /// the headcounts they are converted into another timezone but otherwise left as they are.
+    pub fn set_anomalies(&mut self, anomalies: Vec<OccupancyReading>) {
+        self.anomalies = Some(Self::iso(anomalies));
+    }
+
+    /// Records when the predictions of `model` were generated, see
+    /// `SqliteDatabase::query_generated_at`.
+    pub fn set_generated_at(&mut self, model: &'static str, generated_at: String) {
+        self.meta.generated_at.insert(model, generated_at);
+    }
+
+    /// Smooths the readings, see `smoothing::smooth`. Predictions are left as they are.
+    pub fn smooth(&mut self, window: usize, max_gap: Duration) {
+        self.data = Self::present(smoothing::smooth(&self.readings(), window, max_gap));
+    }
+
+    /// Expands the readings onto a grid with a point every `interval` through the opening hours
+    /// of `date`, with the points that have no reading left empty. A closed day has an empty
+    /// grid.
+    pub fn fill(&mut self, date: NaiveDate, interval: Duration) {
+        let daily = &self.schedule.get_timings()[date.weekday().num_days_from_monday() as usize];
+        self.data = match daily.hours_on(date) {
+            Some((opening, closing)) => {
+                gap_fill::fill(&self.readings(), opening, closing, interval)
+            }
+            None => Vec::new(),
+        };
+    }
+
+    /// The readings that exist.
+    fn readings(&self) -> Vec<(String, u16)> {
+        self.data
+            .iter()
+            .filter_map(|(time, occupancy)| Some((time.clone(), (*occupancy)?)))
+            .collect()
+    }
+
+    /// The series with its times in `ISO_FORMAT`, as every series starts out.
+    fn iso(series: Vec<OccupancyReading>) -> Vec<(String, u16)> {
+        series
+            .into_iter()
+            .map(|reading| {
+                (
+                    reading.time.format(ISO_FORMAT).to_string(),
+                    reading.occupancy,
+                )
+            })
+            .collect()
+    }
+
+    fn present(series: Vec<(String, u16)>) -> Vec<(String, Option<u16>)> {
+        series
+            .into_iter()
+            .map(|(time, occupancy)| (time, Some(occupancy)))
+            .collect()
+    }
+
+    /// Downsamples the readings and every prediction series the same way.
+    pub fn downsample(&mut self, downsample: &Downsample) {
+        self.data = Self::present(downsample.apply(&self.readings()));
+        for series in [
+            &mut self.prediction_knn,
+            &mut self.prediction_lstm,
+            &mut self.prediction_gb,
+        ] {
+            *series = downsample.apply(series);
+        }
+    }
+
+    /// Converts every timestamp, including the schedule's opening and closing times, from UK
+    /// time into `tz`. The time strings carry their offset afterwards.
+    ///
+    /// `date` is the day the response is for, which decides the offset used for the schedule.
+    pub fn convert_timezone(&mut self, date: NaiveDate, tz: Tz) {
+        Self::convert_series(&mut self.data, tz);
+        for series in [
+            &mut self.prediction_knn,
+            &mut self.prediction_lstm,
+            &mut self.prediction_gb,
+        ] {
+            Self::convert_series(series, tz);
+        }
+        if let Some(headcount) = self.headcount.as_mut() {
+            Self::convert_series(headcount, tz);
+        }
+        if let Some(anomalies) = self.anomalies.as_mut() {
+            Self::convert_series(anomalies, tz);
+        }
+        if let Some(time) = self.meta.latest_reading.as_mut() {
+            Self::convert_time(time, tz);
+        }
+        for time in self.meta.generated_at.values_mut() {
+            Self::convert_time(time, tz);
+        }
+        self.schedule = self.schedule.in_timezone(date, tz);
+    }
+
+    /// Converts the stored UK times of a series into `tz` in place. The series is taken to be in
+    /// the order the times were taken, so both passes through the hour repeated when the clocks
+    /// go back are converted correctly, see `uk_local_to_epoch`.
+    pub fn convert_series<T>(series: &mut [(String, T)], tz: Tz) {
+        let mut previous = None;
+        for (time, _) in series.iter_mut() {
+            let Some(epoch) = NaiveDateTime::parse_from_str(time, ISO_FORMAT)
+                .ok()
+                .and_then(|naive| uk_local_to_epoch(naive, previous))
+            else {
+                continue;
+            };
+            if let Some(converted) = DateTime::from_timestamp(epoch, 0) {
+                *time = converted
+                    .with_timezone(&tz)
+                    .format(ISO_FORMAT_OFFSET)
+                    .to_string();
+            }
+            previous = Some(epoch);
+        }
+    }
+
+    /// Converts a stored UK time string into `tz` in place, leaving it alone if it can't be.
+    pub fn convert_time(time: &mut String, tz: Tz) {
+        let converted = NaiveDateTime::parse_from_str(time, ISO_FORMAT)
+            .ok()
+            .and_then(|naive| uk_local_to_timezone(naive, tz));
+        if let Some(converted) = converted {
+            *time = converted.format(ISO_FORMAT_OFFSET).to_string();
+        }
+    }
+}
+
+/**
+The readings taken after a client's last one, the compact response to /api/day?since=.
+
+Written a page of readings at a time as `{"since": ..., "data": [...], "predictions": {...}}`,
+so a delta spanning weeks is never held in memory. The times are converted to `tz` and written
+in `time_format` the same way as the series of a `MyResponse`.
+*/
+pub struct DeltaStream {
+    tz: Option<Tz>,
+    time_format: TimeFormat,
+    /// The timestamp of the last reading written, see `uk_local_to_epoch`.
+    previous: Option<i64>,
+    /// Whether any reading has been written yet, which need separating by commas.
+    started: bool,
+}
+
+impl DeltaStream {
+    pub fn new(tz: Option<Tz>, time_format: TimeFormat) -> Self {
+        Self {
+            tz,
+            time_format,
+            previous: None,
+            started: false,
+        }
+    }
+
+    /// The start of the response, up to the opening bracket of `data`.
+    pub fn start(&self, since: &str) -> String {
+        let mut since = since.to_string();
+        if let Some(tz) = self.tz {
+            MyResponse::convert_time(&mut since, tz);
+        }
+        format!(
+            "{{\"since\":{},\"data\":[",
+            serde_json::to_string(&since).unwrap()
+        )
+    }
+
+    /// The next readings of `data`, which have to come in order.
+    pub fn page<'a>(&mut self, readings: impl IntoIterator<Item = (&'a str, u16)>) -> String {
+        let mut chunk = String::new();
+        for (time, occupancy) in readings {
+            let epoch = NaiveDateTime::parse_from_str(time, ISO_FORMAT)
+                .ok()
+                .and_then(|naive| uk_local_to_epoch(naive, self.previous));
+            if epoch.is_some() {
+                self.previous = epoch;
+            }
+            let converted = match (self.tz, epoch) {
+                (Some(tz), Some(epoch)) => DateTime::from_timestamp(epoch, 0).map(|time| {
+                    time.with_timezone(&tz)
+                        .format(ISO_FORMAT_OFFSET)
+                        .to_string()
+                }),
+                _ => None,
+            };
+            let element = match (self.time_format, epoch) {
+                (TimeFormat::Epoch, Some(epoch)) => serde_json::to_string(&(epoch, occupancy)),
+                // Left out like in `TimedSeries`
+                (TimeFormat::Epoch, None) => continue,
+                (TimeFormat::Iso, _) => {
+                    serde_json::to_string(&(converted.as_deref().unwrap_or(time), occupancy))
+                }
+            };
+            if self.started {
+                chunk.push(',');
+            }
+            chunk.push_str(&element.unwrap());
+            self.started = true;
+        }
+        chunk
+    }
+
+    /// The end of the response, with the predictions regenerated since, as `{"knn": [...]}`.
+    pub fn end(&self, predictions: &[(&str, Vec<OccupancyReading>)]) -> String {
+        let mut end = String::from("],\"predictions\":{");
+        for (i, (model, rows)) in predictions.iter().enumerate() {
+            if i > 0 {
+                end.push(',');
+            }
+            // Each model is its own series, written the same way as `data`
+            let mut series = DeltaStream::new(self.tz, self.time_format);
+            let times: Vec<String> = rows
+                .iter()
+                .map(|row| row.time.format(ISO_FORMAT).to_string())
+                .collect();
+            let rows = times
+                .iter()
+                .zip(rows)
+                .map(|(time, row)| (time.as_str(), row.occupancy));
+            end.push_str(&format!(
+                "{}:[{}]",
+                serde_json::to_string(model).unwrap(),
+                series.page(rows)
+            ));
+        }
+        end.push_str("}}");
+        end
+    }
+}
+
+/// One location's entry in a batched response.
+///
+/// Serialized as the plain response, `null` when there is no data, or an `{"error": ...}` object.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum BatchEntry {
+    Data(Box<MyResponse>),
+    Error { error: String },
+    NoData,
+}
+
+/// A single (time, occupancy) reading.
+#[derive(Serialize, Clone)]
+pub struct Reading {
+    time: String,
+    occupancy: u16,
+}
+
+impl Reading {
+    pub fn new(time: String, occupancy: u16) -> Self {
+        Self { time, occupancy }
+    }
+}
+
+/// The most recent reading along with how many seconds ago it was taken.
+#[derive(Serialize, Clone)]
+pub struct CurrentReading {
+    #[serde(flatten)]
+    pub reading: Reading,
+    pub age_seconds: Option<i64>,
+}
+
+/// The /api/latest response: the most recent reading and whether the location is open.
+///
+/// `open` is null until the scraper has seen a Schedule for the location.
+#[derive(Serialize)]
+pub struct LatestResponse {
+    #[serde(flatten)]
+    pub reading: CurrentReading,
+    pub open: Option<bool>,
+}
+
+/// One location's entry in the /api/overview response.
+///
+/// Everything but the names is null for a location without any readings, so every location is
+/// always listed.
+#[derive(Serialize)]
+pub struct OverviewEntry {
+    name: &'static str,
+    display_name: &'static str,
+    time: Option<String>,
+    occupancy: Option<u16>,
+    age_seconds: Option<i64>,
+    open: Option<bool>,
+}
+
+impl OverviewEntry {
+    pub fn new(
+        location: &LocationMetadata,
+        reading: Option<CurrentReading>,
+        open: Option<bool>,
+    ) -> Self {
+        let (time, occupancy, age_seconds) = match reading {
+            Some(current) => (
+                Some(current.reading.time),
+                Some(current.reading.occupancy),
+                current.age_seconds,
+            ),
+            None => (None, None, None),
+        };
+        Self {
+            name: location.name,
+            display_name: location.display_name,
+            time,
+            occupancy,
+            age_seconds,
+            open,
+        }
+    }
+}
+
+/// A day's opening hours in HHMM. Both are null when closed.
+#[derive(Serialize, Clone)]
+pub struct OpeningHours {
+    open: bool,
+    opening: Option<u16>,
+    closing: Option<u16>,
+}
+
+impl OpeningHours {
+    pub fn from_daily(daily: &Daily) -> Self {
+        match (daily.open(), daily.opening(), daily.closing()) {
+            (true, Some(opening), Some(closing)) => Self {
+                open: true,
+                opening: Some(opening),
+                closing: Some(closing),
+            },
+            _ => Self {
+                open: false,
+                opening: None,
+                closing: None,
+            },
+        }
+    }
+}
+
+/// The /api/summary response.
+#[derive(Serialize, Clone)]
+pub struct SummaryResponse {
+    pub current: Option<CurrentReading>,
+    pub peak_today: Option<Reading>,
+    pub predicted_peak: Option<Reading>,
+    pub hours: OpeningHours,
+}
+
+/// How much data there is for a day.
+#[derive(Serialize, Clone)]
+pub struct DayCoverage {
+    date: String,
+    /// How many readings were taken
+    readings: usize,
+    /// Whether any model has predictions for the day
+    predictions: bool,
+    /// Where readings are missing during opening hours
+    gaps: Vec<CoverageGap>,
+}
+
+impl DayCoverage {
+    pub fn new(date: String, readings: usize, predictions: bool, gaps: Vec<CoverageGap>) -> Self {
+        Self {
+            date,
+            readings,
+            predictions,
+            gaps,
+        }
+    }
+}
+
+/// A stretch of opening hours without readings.
+#[derive(Serialize, Clone)]
+pub struct CoverageGap {
+    from: String,
+    to: String,
+    minutes: i64,
+}
+
+impl From<Gap> for CoverageGap {
+    fn from(gap: Gap) -> Self {
+        Self {
+            from: gap.from.format(ISO_FORMAT).to_string(),
+            to: gap.to.format(ISO_FORMAT).to_string(),
+            minutes: gap.seconds / 60,
+        }
+    }
+}
+
+/// The highest occupancy of a day and when it occurred.
+#[derive(Serialize, Clone)]
+pub struct DailyPeak {
+    date: String,
+    time: String,
+    occupancy: u16,
+    /// The headcount at the time, for locations that publish it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capacity: Option<u32>,
+}
+
+impl From<PeakRow> for DailyPeak {
+    fn from(row: PeakRow) -> Self {
+        Self {
+            date: row.date,
+            time: row.time,
+            occupancy: row.occupancy,
+            total: row.total,
+            capacity: row.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    fn series(times: &[&str]) -> Vec<(String, u16)> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| (time.to_string(), i as u16))
+            .collect()
+    }
+
+    /// A message like a database error's, which quotes names and can have paths and lines in it.
+    const AWKWARD: &str = "no such table: \"gym\"\nat C:\\data\\occupancy.db\t\u{1}";
+
+    fn epochs(series: &[(String, u16)]) -> String {
+        serde_json::to_string(&TimedSeries::new(series, TimeFormat::Epoch)).unwrap()
+    }
+
+    #[test]
+    fn epoch_series_go_round_the_repeated_hour_twice() {
+        let series = series(&[
+            "2024-10-27T00:55:00",
+            "2024-10-27T01:30:00",
+            "2024-10-27T01:55:00",
+            "2024-10-27T01:05:00",
+            "2024-10-27T01:30:00",
+            "2024-10-27T02:10:00",
+        ]);
+        let expected = [
+            utc(10, 26, 23, 55),
+            utc(10, 27, 0, 30),
+            utc(10, 27, 0, 55),
+            utc(10, 27, 1, 5),
+            utc(10, 27, 1, 30),
+            utc(10, 27, 2, 10),
+        ];
+        let expected: Vec<(i64, u16)> = expected.into_iter().zip(0..).collect();
+        assert_eq!(epochs(&series), serde_json::to_string(&expected).unwrap());
+    }
+
+    #[test]
+    fn epoch_series_leave_out_the_skipped_hour() {
+        let series = series(&[
+            "2024-03-31T00:55:00",
+            "2024-03-31T01:30:00",
+            "2024-03-31T02:00:00",
+        ]);
+        let expected = [(utc(3, 31, 0, 55), 0), (utc(3, 31, 1, 0), 2)];
+        assert_eq!(epochs(&series), serde_json::to_string(&expected).unwrap());
+    }
+
+    #[test]
+    fn epoch_series_take_times_already_converted_as_they_are() {
+        let series = series(&["2024-10-27T01:30:00+00:00", "2024-10-27T01:30:00+01:00"]);
+        let expected = [(utc(10, 27, 1, 30), 0), (utc(10, 27, 0, 30), 1)];
+        assert_eq!(epochs(&series), serde_json::to_string(&expected).unwrap());
+    }
+
+    #[test]
+    fn iso_series_are_left_as_they_are() {
+        let series = series(&["2024-10-27T01:30:00"]);
+        let iso = serde_json::to_string(&TimedSeries::new(&series, TimeFormat::Iso)).unwrap();
+        assert_eq!(iso, r#"[["2024-10-27T01:30:00",0]]"#);
+    }
+
+    #[test]
+    fn error_entries_with_quotes_backslashes_and_newlines_are_valid_json() {
+        let entry = BatchEntry::Error {
+            error: AWKWARD.to_string(),
+        };
+        let json = serde_json::to_string(&entry).unwrap();
+        assert!(!json.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, serde_json::json!({ "error": AWKWARD }));
+    }
+
+    #[test]
+    fn delta_streams_with_quotes_backslashes_and_newlines_are_valid_json() {
+        let mut delta = DeltaStream::new(None, TimeFormat::Iso);
+        let body = format!(
+            "{}{}{}",
+            delta.start(AWKWARD),
+            delta.page([(AWKWARD, 5)]),
+            delta.end(&[])
+        );
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["since"], AWKWARD);
+        assert_eq!(parsed["data"], serde_json::json!([[AWKWARD, 5]]));
+    }
+
+    #[test]
+    fn delta_pages_go_round_the_repeated_hour_across_pages() {
+        let mut delta = DeltaStream::new(None, TimeFormat::Epoch);
+        let first = delta.page([("2024-10-27T01:30:00", 10), ("2024-10-27T01:55:00", 11)]);
+        let second = delta.page([("2024-10-27T01:05:00", 12), ("2024-10-27T01:30:00", 13)]);
+        assert_eq!(
+            format!("{}{}", first, second),
+            format!(
+                "[{},10],[{},11],[{},12],[{},13]",
+                utc(10, 27, 0, 30),
+                utc(10, 27, 0, 55),
+                utc(10, 27, 1, 5),
+                utc(10, 27, 1, 30)
+            )
+        );
+    }
 }
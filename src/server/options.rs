@@ -0,0 +1,148 @@
+use std::{collections::HashMap, str::FromStr};
+
+use chrono::{Duration, NaiveDate};
+use chrono_tz::Tz;
+
+use crate::{
+    scraper::scraper::SCRAPE_INTERVAL,
+    timing::timezone::{parse_timezone, UK_TIMEZONE},
+};
+
+use super::{
+    downsample::{Aggregate, Downsample},
+    myresponse::{DeltaStream, MyResponse, TimeFormat},
+    smoothing,
+    validation::ParamErrors,
+};
+
+/// Optional query parameters that change how a `MyResponse` is presented, without changing
+/// what is fetched from the database.
+pub struct ResponseOptions {
+    tz: Option<Tz>,
+    downsample: Option<Downsample>,
+    smooth: Option<usize>,
+    fill: bool,
+    time_format: TimeFormat,
+}
+
+impl ResponseOptions {
+    /// Parses the options out of the query parameters.
+    ///
+    /// `tz` is an IANA timezone name, defaulting to UK time which is what we store.
+    /// `resolution` such as `15m` or `1h` downsamples every series, with `aggregate` being one of
+    /// mean (default), min or max.
+    /// `smooth` is the window of a moving average applied to the readings, see
+    /// `smoothing::smooth`.
+    /// `fill=true` expands the readings onto a regular grid through the day's opening hours, with
+    /// a point every scrape interval or every `resolution` if there is one.
+    /// `time_format=epoch` writes the times of the series as Unix timestamps instead of strings.
+    ///
+    /// Any that are malformed are added to `errors` and left at their default.
+    pub fn from_params(map: &HashMap<String, String>, errors: &mut ParamErrors) -> Self {
+        let tz = match map.get("tz") {
+            None => None,
+            Some(tz) => match parse_timezone(tz) {
+                None => {
+                    errors.push("Unknown Timezone");
+                    None
+                }
+                Some(tz) if tz == UK_TIMEZONE => None,
+                Some(tz) => Some(tz),
+            },
+        };
+
+        let aggregate = match map.get("aggregate") {
+            None => Aggregate::Mean,
+            Some(aggregate) => match Aggregate::from_str(aggregate) {
+                Ok(aggregate) => aggregate,
+                Err(_) => {
+                    errors.push("Malformed aggregate. Expected mean, min or max.");
+                    Aggregate::Mean
+                }
+            },
+        };
+
+        let downsample = match map.get("resolution") {
+            None => None,
+            Some(resolution) => match Downsample::parse_resolution(resolution) {
+                Some(interval) => Some(Downsample::new(interval, aggregate)),
+                None => {
+                    errors
+                        .push("Malformed resolution. Expected minutes or hours such as 15m or 1h.");
+                    None
+                }
+            },
+        };
+
+        let smooth = match map.get("smooth") {
+            None => None,
+            Some(window) => {
+                let window = smoothing::parse_window(window);
+                if window.is_none() {
+                    errors.push(format!(
+                        "Malformed smooth. Expected a window of 1 to {} readings.",
+                        smoothing::MAX_WINDOW
+                    ));
+                }
+                window
+            }
+        };
+
+        let fill = match map.get("fill").map(String::as_str) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(_) => {
+                errors.push("Malformed fill. Expected true or false.");
+                false
+            }
+        };
+
+        let time_format = match map.get("time_format").map(String::as_str) {
+            None | Some("iso") => TimeFormat::Iso,
+            Some("epoch") => TimeFormat::Epoch,
+            Some(_) => {
+                errors.push("Malformed time_format. Expected iso or epoch.");
+                TimeFormat::Iso
+            }
+        };
+
+        Self {
+            tz,
+            downsample,
+            smooth,
+            fill,
+            time_format,
+        }
+    }
+
+    /// Applies the options to a response for `date`.
+    pub fn apply(&self, response: &mut MyResponse, date: NaiveDate) {
+        // Smoothing, downsampling and filling work on the stored format, so they have to happen
+        // before the conversion. Smoothing goes first so it sees every reading.
+        if let Some(window) = self.smooth {
+            // Allow for some jitter in when scrapes happen before calling it a gap
+            let max_gap = Duration::from_std(SCRAPE_INTERVAL * 3 / 2).unwrap();
+            response.smooth(window, max_gap);
+        }
+        if let Some(downsample) = &self.downsample {
+            response.downsample(downsample);
+        }
+        if self.fill {
+            let interval = match &self.downsample {
+                Some(downsample) => downsample.interval(),
+                None => Duration::from_std(SCRAPE_INTERVAL).unwrap(),
+            };
+            response.fill(date, interval);
+        }
+        if let Some(tz) = self.tz {
+            response.convert_timezone(date, tz);
+        }
+        response.set_time_format(self.time_format);
+    }
+
+    /// A writer for a delta response with the options applied. Deltas are never smoothed or
+    /// downsampled.
+    pub fn delta_stream(&self) -> DeltaStream {
+        DeltaStream::new(self.tz, self.time_format)
+    }
+}
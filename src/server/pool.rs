@@ -0,0 +1,30 @@
+/// What r2d2 reports when no connection came free in time and none failed to open.
+const TIMEOUT_MESSAGE: &str = "timed out waiting for connection";
+
+/// Why a connection could not be taken from the pool.
+#[derive(Debug, PartialEq)]
+pub enum PoolError {
+    /// Every connection was busy for the whole checkout timeout. Retrying shortly should work.
+    Exhausted,
+    /// Opening a connection failed, so retrying is unlikely to help.
+    Failed(String),
+}
+
+impl PoolError {
+    /// Classifies the message of an `r2d2::Error`.
+    ///
+    /// r2d2 only has the one error type. When opening a connection failed during the checkout,
+    /// the message carries that error after the timeout message.
+    pub fn classify(message: &str) -> Self {
+        if message == TIMEOUT_MESSAGE {
+            return Self::Exhausted;
+        }
+        Self::Failed(message.to_string())
+    }
+}
+
+impl From<r2d2::Error> for PoolError {
+    fn from(err: r2d2::Error) -> Self {
+        Self::classify(&err.to_string())
+    }
+}
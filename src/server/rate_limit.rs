@@ -0,0 +1,72 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hyper::Request;
+
+/// The header a reverse proxy puts the client's address in.
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// Allows each client `limit` requests in any `window`.
+///
+/// Clients are told how long to wait until their oldest request falls out of the window.
+pub struct RateLimiter {
+    limit: usize,
+    window: Duration,
+    hits: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a request from `client`.
+    ///
+    /// Returns how long to wait if it is over the limit, in which case it is not recorded.
+    pub fn check(&self, client: IpAddr) -> Option<Duration> {
+        let mut hits = self.hits.lock().unwrap();
+        let now = Instant::now();
+        // Forget clients that haven't been seen for a whole window so the map doesn't grow forever
+        hits.retain(|_, times| {
+            while times.front().is_some_and(|time| now - *time >= self.window) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = hits.entry(client).or_default();
+        if times.len() >= self.limit {
+            return Some(self.window - (now - times[0]));
+        }
+        times.push_back(now);
+        None
+    }
+}
+
+/// The address of the client that made `req`, given the address of the connection it came on.
+///
+/// We listen on loopback or a unix socket, behind a reverse proxy, so requests from there use the
+/// last address in `X-Forwarded-For`. That is the one the proxy added, earlier ones come from the
+/// client and can't be trusted.
+pub fn client_ip<B>(req: &Request<B>, peer: Option<IpAddr>) -> Option<IpAddr> {
+    if peer.is_some_and(|peer| !peer.is_loopback()) {
+        return peer;
+    }
+    let forwarded = req
+        .headers()
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|address| address.trim().parse().ok());
+    forwarded.or(peer)
+}
@@ -0,0 +1,75 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Arguments,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use hyper::Request;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The longest request ID we accept from a client.
+const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/**
+The ID of a request, used to tie log lines and error responses together.
+
+A valid `X-Request-Id` from the client is used as is, so IDs can be followed across services.
+Otherwise a short random one is generated.
+*/
+pub fn from_request<B>(req: &Request<B>) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate)
+}
+
+/// Client IDs end up in logs and headers, so only a conservative set of characters is allowed.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_CLIENT_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// 12 hex characters. Not cryptographically random, just unique enough to find a request.
+fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:012x}", hasher.finish() & 0xffff_ffff_ffff)
+}
+
+/// Runs `future` with `id` as the current request ID.
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// Runs `f` with `id` as the current request ID, for work on threads outside the request's task.
+pub fn sync_scope<R>(id: String, f: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(id, f)
+}
+
+/// The ID of the request being handled, if there is one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// `println!` with the current request ID in front.
+///
+/// Use with `format_args!`.
+pub fn log(args: Arguments) {
+    match current() {
+        Some(id) => println!("[{}] {}", id, args),
+        None => println!("{}", args),
+    }
+}
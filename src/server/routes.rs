@@ -0,0 +1,574 @@
+use hyper::Method;
+use serde::Serialize;
+
+/// Every endpoint the Server knows how to handle.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    Day,
+    From,
+    Compare,
+    Summary,
+    Latest,
+    Wait,
+    Overview,
+    Peaks,
+    Hourly,
+    Coverage,
+    Accuracy,
+    BestTimes,
+    Export,
+    Repredict,
+    CorrectOccupancy,
+    DeleteData,
+    MarkAnomaly,
+    UnmarkAnomaly,
+    Backup,
+    Maintenance,
+    Status,
+    ScheduleIcs,
+    Locations,
+    Location,
+    Report,
+    Reports,
+    Feedback,
+    FeedbackPage,
+    Weekday,
+    Typical,
+    Health,
+    ScraperStatus,
+}
+
+/// One entry of the route table.
+///
+/// A `{name}` segment in `path` matches any single path segment, see `path_param`.
+#[derive(Serialize)]
+pub struct Route {
+    #[serde(serialize_with = "serialize_method")]
+    pub method: Method,
+    pub path: &'static str,
+    pub required: &'static [&'static str],
+    pub optional: &'static [&'static str],
+    #[serde(skip)]
+    pub endpoint: Endpoint,
+}
+
+fn serialize_method<S: serde::Serializer>(
+    method: &Method,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(method.as_str())
+}
+
+impl Route {
+    /// Whether the route is under the key protected /admin namespace.
+    pub fn is_admin(&self) -> bool {
+        is_admin_path(self.path)
+    }
+}
+
+/// The optional parameters of /api/day, in both of its forms.
+const DAY_OPTIONAL: &[&str] = &[
+    "date",
+    "since",
+    "tz",
+    "resolution",
+    "aggregate",
+    "smooth",
+    "fill",
+    "models",
+    "time_format",
+];
+
+/// The optional parameters of /api/from, in both of its forms.
+const FROM_OPTIONAL: &[&str] = &["tz", "resolution", "aggregate", "smooth", "time_format"];
+
+/// The route table. This is the single place endpoints are declared, everything else (dispatch,
+/// the 404 listing, Allow headers) is derived from it.
+pub static ROUTES: &[Route] = &[
+    Route {
+        method: Method::GET,
+        path: "/",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Status,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/health",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Health,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/day",
+        required: &["name"],
+        optional: DAY_OPTIONAL,
+        endpoint: Endpoint::Day,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/from",
+        required: &["name", "from"],
+        optional: FROM_OPTIONAL,
+        endpoint: Endpoint::From,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/compare",
+        required: &["name", "date", "model"],
+        optional: &["tolerance"],
+        endpoint: Endpoint::Compare,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/summary",
+        required: &["name"],
+        optional: &[],
+        endpoint: Endpoint::Summary,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/latest",
+        required: &["name"],
+        optional: &[],
+        endpoint: Endpoint::Latest,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/wait",
+        required: &["name", "after"],
+        optional: &[],
+        endpoint: Endpoint::Wait,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/overview",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Overview,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/peaks",
+        required: &["name", "from", "to"],
+        optional: &[],
+        endpoint: Endpoint::Peaks,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/hourly",
+        required: &["name", "from", "to"],
+        optional: &[],
+        endpoint: Endpoint::Hourly,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/coverage",
+        required: &["name", "from", "to"],
+        optional: &[],
+        endpoint: Endpoint::Coverage,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/weekday",
+        required: &["name", "weekday"],
+        optional: &["weeks"],
+        endpoint: Endpoint::Weekday,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/typical",
+        required: &["name", "weekday"],
+        optional: &["weeks"],
+        endpoint: Endpoint::Typical,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/accuracy",
+        required: &["name", "model"],
+        optional: &["weeks"],
+        endpoint: Endpoint::Accuracy,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/best-times",
+        required: &["name"],
+        optional: &["date", "model"],
+        endpoint: Endpoint::BestTimes,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/schedule.ics",
+        required: &["name"],
+        optional: &[],
+        endpoint: Endpoint::ScheduleIcs,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/locations",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Locations,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/locations/{name}",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Location,
+    },
+    // The same as /api/day, /api/from and /api/latest with the name in the path. Declared after
+    // the routes above so that a fixed segment such as /api/locations wins over a name.
+    Route {
+        method: Method::GET,
+        path: "/api/{name}/day",
+        required: &[],
+        optional: DAY_OPTIONAL,
+        endpoint: Endpoint::Day,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/{name}/from",
+        required: &["from"],
+        optional: FROM_OPTIONAL,
+        endpoint: Endpoint::From,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/{name}/latest",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Latest,
+    },
+    Route {
+        method: Method::POST,
+        path: "/api/report",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Report,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/report",
+        required: &["name"],
+        optional: &["date"],
+        endpoint: Endpoint::Reports,
+    },
+    Route {
+        method: Method::POST,
+        path: "/api/feedback",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Feedback,
+    },
+    Route {
+        method: Method::GET,
+        path: "/api/export",
+        required: &["name"],
+        optional: &["format", "table"],
+        endpoint: Endpoint::Export,
+    },
+    Route {
+        method: Method::POST,
+        path: "/admin/repredict",
+        required: &["name"],
+        optional: &["model"],
+        endpoint: Endpoint::Repredict,
+    },
+    Route {
+        method: Method::POST,
+        path: "/admin/occupancy",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::CorrectOccupancy,
+    },
+    Route {
+        method: Method::GET,
+        path: "/admin/feedback",
+        required: &["name"],
+        optional: &["before", "limit"],
+        endpoint: Endpoint::FeedbackPage,
+    },
+    Route {
+        method: Method::GET,
+        path: "/admin/status",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::ScraperStatus,
+    },
+    Route {
+        method: Method::DELETE,
+        path: "/admin/data",
+        required: &["name"],
+        optional: &["from", "to", "date", "predictions"],
+        endpoint: Endpoint::DeleteData,
+    },
+    Route {
+        method: Method::POST,
+        path: "/admin/anomaly",
+        required: &["name", "from", "to"],
+        optional: &[],
+        endpoint: Endpoint::MarkAnomaly,
+    },
+    Route {
+        method: Method::DELETE,
+        path: "/admin/anomaly",
+        required: &["name", "from", "to"],
+        optional: &[],
+        endpoint: Endpoint::UnmarkAnomaly,
+    },
+    Route {
+        method: Method::POST,
+        path: "/admin/backup",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Backup,
+    },
+    Route {
+        method: Method::POST,
+        path: "/admin/maintenance",
+        required: &[],
+        optional: &[],
+        endpoint: Endpoint::Maintenance,
+    },
+];
+
+/// The API contract a request was made against.
+///
+/// Every route is served both under the `/v1` prefix and at its original unversioned path. The
+/// unversioned paths keep the original response shapes while clients move over.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ApiVersion {
+    Unversioned,
+    V1,
+}
+
+impl ApiVersion {
+    /// The value of the `X-Api-Version` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unversioned => "0",
+            Self::V1 => "1",
+        }
+    }
+}
+
+pub const API_VERSION_HEADER: &str = "X-Api-Version";
+
+/// Splits the version prefix off a path, returning the version and the path within it.
+pub fn split_version(path: &str) -> (ApiVersion, &str) {
+    match path.strip_prefix("/v1") {
+        Some(rest) if rest.starts_with('/') => (ApiVersion::V1, rest),
+        _ => (ApiVersion::Unversioned, path),
+    }
+}
+
+/// The outcome of looking up a request in the route table.
+pub enum Routing {
+    Found(&'static Route),
+    /// The path exists but not with this method. Holds the methods that are allowed.
+    MethodNotAllowed(Vec<Method>),
+    /// An OPTIONS request for a path that exists. Holds the methods that are allowed.
+    Options(Vec<Method>),
+    NotFound,
+}
+
+pub fn is_admin_path(path: &str) -> bool {
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+/// Looks up the route for a request. HEAD is accepted wherever GET is, and OPTIONS on every path
+/// that exists.
+pub fn route(method: &Method, path: &str) -> Routing {
+    if method == Method::OPTIONS {
+        let allowed = allowed_methods(path);
+        if allowed.is_empty() {
+            return Routing::NotFound;
+        }
+        return Routing::Options(allowed);
+    }
+    let lookup = if method == Method::HEAD {
+        &Method::GET
+    } else {
+        method
+    };
+    if let Some(route) = ROUTES
+        .iter()
+        .find(|route| path_matches(route.path, path) && route.method == lookup)
+    {
+        return Routing::Found(route);
+    }
+    let allowed = allowed_methods(path);
+    if allowed.is_empty() {
+        return Routing::NotFound;
+    }
+    Routing::MethodNotAllowed(allowed)
+}
+
+/// The methods implemented for `path`, including HEAD wherever GET is and OPTIONS. Empty when
+/// the path doesn't exist.
+pub fn allowed_methods(path: &str) -> Vec<Method> {
+    let mut allowed = Vec::new();
+    for route in ROUTES.iter().filter(|route| path_matches(route.path, path)) {
+        // A path can match more than one route, such as /api/locations/day
+        if allowed.contains(&route.method) {
+            continue;
+        }
+        allowed.push(route.method.clone());
+        if route.method == Method::GET {
+            allowed.push(Method::HEAD);
+        }
+    }
+    if !allowed.is_empty() {
+        allowed.push(Method::OPTIONS);
+    }
+    allowed
+}
+
+/// Whether `path` matches the path of a route, `pattern`.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut patterns = pattern.split('/');
+    let mut segments = path.split('/');
+    loop {
+        match (patterns.next(), segments.next()) {
+            (None, None) => return true,
+            (Some("{name}"), Some(segment)) if !segment.is_empty() => (),
+            (Some(pattern), Some(segment)) if pattern == segment => (),
+            _ => return false,
+        }
+    }
+}
+
+/// The segment of `path` in the place of `{name}` in `pattern`.
+pub fn path_param<'a>(pattern: &str, path: &'a str) -> Option<&'a str> {
+    pattern
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(pattern, _)| *pattern == "{name}")
+        .map(|(_, segment)| segment)
+}
+
+/// The public routes, for listing to clients.
+pub fn public_routes() -> Vec<&'static Route> {
+    ROUTES.iter().filter(|route| !route.is_admin()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every method a route is declared with, and one none is.
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+    ];
+
+    /// A path `route` matches, with `gym` as the location.
+    fn concrete(route: &Route) -> String {
+        route.path.replace("{name}", "gym")
+    }
+
+    #[test]
+    fn every_route_is_found_with_its_method() {
+        for declared in ROUTES {
+            let path = concrete(declared);
+            match route(&declared.method, &path) {
+                Routing::Found(found) => {
+                    assert_eq!(
+                        found.endpoint, declared.endpoint,
+                        "{} {}",
+                        declared.method, path
+                    )
+                }
+                _ => panic!("{} {} was not found", declared.method, path),
+            }
+        }
+    }
+
+    #[test]
+    fn every_other_method_is_not_allowed_and_told_the_allowed_ones() {
+        for declared in ROUTES {
+            let path = concrete(declared);
+            let declared_methods: Vec<&Method> = ROUTES
+                .iter()
+                .filter(|route| path_matches(route.path, &path))
+                .map(|route| &route.method)
+                .collect();
+            for method in &METHODS {
+                match route(method, &path) {
+                    Routing::Found(_) => assert!(declared_methods.contains(&method)),
+                    Routing::MethodNotAllowed(allowed) => {
+                        assert!(!declared_methods.contains(&method), "{} {}", method, path);
+                        assert!(declared_methods
+                            .iter()
+                            .all(|&method| allowed.contains(method)));
+                        assert!(allowed.contains(&Method::OPTIONS));
+                        assert_eq!(
+                            allowed.contains(&Method::HEAD),
+                            allowed.contains(&Method::GET)
+                        );
+                    }
+                    _ => panic!("{} {} was not found", method, path),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn head_is_routed_like_get_and_options_everywhere() {
+        for declared in ROUTES {
+            let path = concrete(declared);
+            let head = matches!(route(&Method::HEAD, &path), Routing::Found(_));
+            assert_eq!(
+                head,
+                allowed_methods(&path).contains(&Method::GET),
+                "{}",
+                path
+            );
+            match route(&Method::OPTIONS, &path) {
+                Routing::Options(allowed) => assert_eq!(allowed, allowed_methods(&path)),
+                _ => panic!("OPTIONS {} was not answered", path),
+            }
+        }
+    }
+
+    #[test]
+    fn paths_without_a_route_are_not_found_with_any_method() {
+        let paths = [
+            "/nope",
+            "/api",
+            "/api/",
+            "/api/day/",
+            "/api/day/extra",
+            "/api/locations/",
+            "/admin",
+        ];
+        for path in paths {
+            for method in METHODS.iter().chain([&Method::HEAD, &Method::OPTIONS]) {
+                assert!(
+                    matches!(route(method, path), Routing::NotFound),
+                    "{} {}",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_location_segment_has_to_be_there() {
+        assert!(matches!(
+            route(&Method::GET, "/api/gym/day"),
+            Routing::Found(_)
+        ));
+        assert!(matches!(
+            route(&Method::GET, "/api//day"),
+            Routing::NotFound
+        ));
+        assert_eq!(path_param("/api/{name}/day", "/api/gym/day"), Some("gym"));
+    }
+}
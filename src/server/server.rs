@@ -1,18 +1,176 @@
 use bytes::Bytes;
-use chrono::{NaiveDate, NaiveDateTime};
-use http_body_util::Full;
-use hyper::{body::Incoming, service::Service, Method, Request, Response, StatusCode};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, Utc, Weekday};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Body, Incoming},
+    header::{
+        HeaderValue, ACCEPT, ALLOW, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH,
+        CONTENT_TYPE, IF_MODIFIED_SINCE, LAST_MODIFIED, RETRY_AFTER, SERVER,
+    },
+    http::response::Builder,
+    service::Service,
+    Method, Request, Response, StatusCode, Uri,
+};
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use regex::Regex;
-use serde::Serialize;
-use url_escape::decode;
+use rusqlite::{Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast::error::RecvError, watch};
 
-use std::{collections::HashMap, future::Future, pin::Pin, str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr},
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use crate::{database::sqlite::SqliteDatabase, timing::schedule::Schedule};
+use crate::{
+    database::{
+        backup::Backups,
+        error::{DatabaseError, DatabaseResult},
+        integrity::HealthCheck,
+        pool::{PoolStats, Pools},
+        sqlite::{
+            ExportFormat, FeedbackRow, HourlyRow, LocationRow, OccupancyReading, PageRow,
+            SqliteDatabase,
+        },
+        storage::Database,
+    },
+    predictor::best_times::find_best_times,
+    predictor::evaluation::{
+        match_nearest, metrics_by_day, ComparedPoint, DayMetrics, ErrorMetrics,
+    },
+    predictor::typical::{typical_bands, TypicalBand},
+    scraper::{
+        metadata::Capacity,
+        new_readings::{NewReading, NewReadings},
+        repredict::{PredictionModel, RepredictQueue},
+        schedule_cache::ScheduleCache,
+        scraper::{
+            location_metadata, locations_metadata, occupancy_range, Scraper, LOCATIONS,
+            SCRAPE_INTERVAL,
+        },
+        status::ScraperStatus,
+    },
+    settings::settings::Settings,
+    timing::{
+        daily::Daily,
+        schedule::Schedule,
+        timezone::{format_http_date, parse_http_date, uk_local_to_stored, uk_local_to_utc},
+        uk_datetime_now::uk_datetime_now,
+    },
+    ISO_FORMAT,
+};
 
-use super::myresponse::MyResponse;
+use super::{
+    access_log::{AccessLog, RequestLine},
+    auth,
+    body::{self, ChunkWriter, ServerBody},
+    connections::ConnectionLimit,
+    ics,
+    myresponse::{
+        BatchEntry, CoverageGap, CurrentReading, DailyPeak, DayCoverage, LatestResponse,
+        MyResponse, OpeningHours, OverviewEntry, Reading, ResponseMeta, SummaryResponse,
+    },
+    options::ResponseOptions,
+    pool::PoolError,
+    rate_limit::{self, RateLimiter},
+    request_id,
+    routes::{self, is_admin_path, ApiVersion, Endpoint, Route, Routing},
+    status_page::{self, LocationStatus, ScraperTargetStatus},
+    validation::{sanitize_name, strict_check, ParamErrors, QueryParams},
+};
+
+/// The validated parameters of /api/day and /api/from.
+struct DataParams {
+    names: Vec<String>,
+    date: Option<NaiveDate>,
+    from: Option<NaiveDateTime>,
+    since: Option<NaiveDateTime>,
+    models: Vec<&'static str>,
+    options: ResponseOptions,
+}
+
+/// The prediction models /api/day can return, each stored in a `{name}_prediction_{model}`
+/// table.
+const PREDICTION_MODELS: &[&str] = &["knn", "lstm", "gb"];
+
+/// The error for a `model` parameter that isn't one of `PREDICTION_MODELS`.
+fn unknown_model() -> String {
+    format!(
+        "Unknown model. Expected one of {}.",
+        PREDICTION_MODELS.join(", ")
+    )
+}
+
+/// The largest request body we are willing to read.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// How many readings streamed responses, such as /api/export, read from the database at a time.
+const STREAM_PAGE_SIZE: usize = 1000;
+
+/// How often an export can be started without the admin key, across all clients.
+const PUBLIC_EXPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How many reports a client can make in `REPORT_WINDOW`.
+const REPORT_LIMIT: usize = 3;
+
+const REPORT_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// The longest note a report or comment on feedback can have, in characters. Longer ones are
+/// cut off.
+const MAX_NOTE_LENGTH: usize = 280;
+
+/// Feedback from the same client on the same predictions within this long of the last one
+/// replaces it instead of being added.
+const FEEDBACK_COALESCE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// How many rows of feedback /admin/feedback returns at most, and by default.
+const MAX_FEEDBACK_PAGE: usize = 500;
+const DEFAULT_FEEDBACK_PAGE: usize = 50;
+
+/// The client, location, day and model a piece of feedback is about.
+type FeedbackKey = (IpAddr, String, NaiveDate, &'static str);
+
+/// How many weeks /api/weekday and /api/typical go back at most.
+const MAX_WEEKDAY_WEEKS: u64 = 12;
+
+/// How much of the day each band of /api/typical covers.
+const TYPICAL_BUCKET_MINUTES: u32 = 15;
+
+/// How many days /api/hourly covers at most. It reads the hourly aggregates rather than the
+/// readings, so it can go much further back than `max_query_span`.
+const MAX_HOURLY_DAYS: i64 = 366;
+
+/// How long /api/wait holds a request before answering that there is no new reading, short of
+/// the 60 seconds many proxies give up after.
+const WAIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(55);
+
+/// How long /api/coverage may be cached; only corrections change past days.
+const PAST_COVERAGE_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// The Server header sent with every response.
+const SERVER_NAME: &str = concat!("occupancy-backend/", env!("CARGO_PKG_VERSION"));
+
+/// The Content-Type of JSON responses.
+const JSON: &str = "application/json; charset=utf-8";
+
+/// The Content-Type of the status page.
+const HTML: &str = "text/html; charset=utf-8";
+
+/// The Content-Type of /api/schedule.ics responses.
+const CALENDAR: &str = "text/calendar; charset=utf-8";
+
+/// The Content-Types of /api/export responses.
+const NDJSON: &str = "application/x-ndjson";
+const CSV: &str = "text/csv; charset=utf-8";
+
+/// How long a client should wait before retrying when the connection pool is exhausted.
+const POOL_RETRY_AFTER_SECS: u64 = 1;
 
 /// The Server
 ///
@@ -24,46 +182,168 @@ use super::myresponse::MyResponse;
 ///
 /// For each TCP connection or Client, a new thread is assigned to handle that request. We have to
 /// clone this struct for each thread to make it thread save and avoid race conditions.
+///
+/// Everything under /api is public. Everything under /admin requires the admin API key in the
+/// `X-Api-Key` header.
 
 #[derive(Clone)]
 pub struct Server {
+    /// Read-only, for every request that doesn't write.
     connection_pool: Arc<Pool<SqliteConnectionManager>>,
+    /// For the admin endpoints, reports and feedback, which do.
+    write_pool: Arc<Pool<SqliteConnectionManager>>,
     name_sanitizer: Regex,
+    settings: Arc<Settings>,
+    repredict: Arc<RepredictQueue>,
+    schedules: Arc<ScheduleCache>,
+    scraper_status: Arc<ScraperStatus>,
+    new_readings: Arc<NewReadings>,
+    /// The names in the `locations` table, the only ones used as table names.
+    locations: Arc<HashSet<String>>,
+    /// Set when the server is shutting down, so requests that wait can stop early.
+    shutdown: watch::Receiver<bool>,
+    last_public_export: Arc<Mutex<Option<Instant>>>,
+    report_limiter: Arc<RateLimiter>,
+    connections: Arc<ConnectionLimit>,
+    pool_stats: Arc<PoolStats>,
+    access_log: Option<Arc<AccessLog>>,
+    /// When each client last gave feedback and the id of the row it went into.
+    recent_feedback: Arc<Mutex<HashMap<FeedbackKey, (Instant, i64)>>>,
+    /// The last integrity check made for /api/health.
+    health_check: Arc<HealthCheck>,
+    /// The address of the connection this clone is serving, see `for_peer`.
+    peer: Option<IpAddr>,
 }
 
 impl Server {
-    pub fn setup(connection_pool: Arc<Pool<SqliteConnectionManager>>) -> Self {
+    /// Sets up the server, sharing the state the `scraper` exposes to it.
+    pub fn setup(
+        pools: &Pools,
+        settings: Arc<Settings>,
+        scraper: &Scraper,
+        connections: Arc<ConnectionLimit>,
+        access_log: Option<Arc<AccessLog>>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
         Self {
-            connection_pool,
+            connection_pool: pools.read_only.clone(),
+            write_pool: pools.read_write.clone(),
             name_sanitizer: Regex::new(r"(\w+)").unwrap(),
+            settings,
+            repredict: scraper.repredict_queue(),
+            schedules: scraper.schedule_cache(),
+            scraper_status: scraper.status(),
+            new_readings: scraper.new_readings(),
+            locations: scraper.locations(),
+            shutdown,
+            last_public_export: Arc::new(Mutex::new(None)),
+            report_limiter: Arc::new(RateLimiter::new(REPORT_LIMIT, REPORT_WINDOW)),
+            connections,
+            pool_stats: pools.read_only_stats.clone(),
+            access_log,
+            recent_feedback: Arc::new(Mutex::new(HashMap::new())),
+            health_check: Arc::new(HealthCheck::new()),
+            peer: None,
         }
     }
 
-    /// Parses the query parameters and returns a `hashmap` of key pair values
-    /// Returns `None` if the parameters are malformed
-    fn parse_params(text: &str) -> Option<HashMap<String, String>> {
-        let mut map: HashMap<String, String> = HashMap::new();
-        for pairs in text.split('&').into_iter() {
-            let mut iterator = pairs.split('=').into_iter();
-            map.insert(
-                iterator.next()?.to_string(),
-                decode(iterator.next()?).to_string(),
-            );
+    /// A clone of the server for the connection from `peer`, `None` for a unix socket.
+    /// A server on `pools` with the state a scraper that hasn't run yet would share, for tests
+    /// that don't want to set one up.
+    #[cfg(test)]
+    pub fn for_tests(pools: &Pools, settings: Settings, shutdown: watch::Receiver<bool>) -> Self {
+        Self {
+            connection_pool: pools.read_only.clone(),
+            write_pool: pools.read_write.clone(),
+            name_sanitizer: Regex::new(r"(\w+)").unwrap(),
+            settings: Arc::new(settings),
+            repredict: Arc::new(RepredictQueue::new(LOCATIONS)),
+            schedules: Arc::new(ScheduleCache::new()),
+            scraper_status: Arc::new(ScraperStatus::new()),
+            new_readings: Arc::new(NewReadings::new()),
+            locations: Arc::new(Scraper::create_tables(&pools.read_write).unwrap()),
+            shutdown,
+            last_public_export: Arc::new(Mutex::new(None)),
+            report_limiter: Arc::new(RateLimiter::new(REPORT_LIMIT, REPORT_WINDOW)),
+            connections: Arc::new(ConnectionLimit::new(16)),
+            pool_stats: pools.read_only_stats.clone(),
+            access_log: None,
+            recent_feedback: Arc::new(Mutex::new(HashMap::new())),
+            health_check: Arc::new(HealthCheck::new()),
+            peer: None,
         }
-        Some(map)
     }
 
-    /// Obtain a connection from the connection pool.
-    fn get_connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, String> {
-        match self.connection_pool.get() {
-            Err(err) => {
-                return Err(format!(
-                    "Could not get connection - Server.\n{}",
-                    err.to_string()
-                ));
+    pub fn for_peer(&self, peer: Option<IpAddr>) -> Self {
+        Self {
+            peer,
+            ..self.clone()
+        }
+    }
+
+    /**
+    The shared validation step of /api/day and /api/from.
+
+    Every problem with the parameters is collected rather than returning on the first one, so a
+    client with several mistakes finds out about all of them at once.
+    /api/day takes a comma separated list of names, /api/from a single one.
+    */
+    fn validate_data_params(&self, uri: &Uri, route: &Route) -> Result<DataParams, ParamErrors> {
+        let mut params = QueryParams::parse(uri, route, &self.name_sanitizer);
+
+        let names = params.require_names();
+        if route.endpoint != Endpoint::Day && names.len() > 1 {
+            params.error("Only one name can be given.");
+        }
+
+        let date = params.optional_date("date");
+        let from = params.require_datetime("from");
+        let since = params.optional_datetime("since");
+        if let Some(since) = since {
+            if names.len() > 1 {
+                params.error("since can only be used with one name.");
+            }
+            // Deltas are returned as they are stored
+            if ["date", "resolution", "smooth", "fill"]
+                .iter()
+                .any(|key| params.contains(key))
+            {
+                params.error("since can't be combined with date, resolution, smooth or fill.");
             }
-            Ok(conn) => Ok(conn),
+            params.check_range(
+                since,
+                uk_datetime_now().naive_local().max(since),
+                self.settings.max_query_span(),
+            );
         }
+
+        let models = params
+            .optional_list("models", PREDICTION_MODELS)
+            .unwrap_or_else(|| PREDICTION_MODELS.to_vec());
+
+        let options = params.response_options();
+
+        params.finish()?;
+        Ok(DataParams {
+            names,
+            date,
+            from,
+            since,
+            models,
+            options,
+        })
+    }
+
+    /// Obtain a connection from the connection pool.
+    ///
+    /// The connection is read-only, writing through it fails. See `get_write_connection`.
+    fn get_connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, PoolError> {
+        Ok(self.connection_pool.get()?)
+    }
+
+    /// Obtain a connection that can write, for the few endpoints that do.
+    fn get_write_connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, PoolError> {
+        Ok(self.write_pool.get()?)
     }
 
     /// Fetches the data for a single day.
@@ -72,75 +352,194 @@ impl Server {
     /// Takes in a `connection` to query the database
     /// `date` to fetch the data for
     /// `name` of the table to fetch the data from
+    /// `models` to fetch the predictions of, the other prediction series are left empty
     ///
-    /// Will return a 200 as long as there is at least a prediction for that day.
     /// If there is no Schedule data, the last recorded Schedule will be returned.
     ///
-    /// Will return a 204 when there is no data and no prediction.
-    fn get_single_day(
-        connection: &PooledConnection<SqliteConnectionManager>,
+    /// Will return `Ok(None)` when there is no Schedule at all, or when the Schedule has the day
+    /// open but nothing was recorded or predicted for it, which is sent as a 204. A closed day
+    /// is returned with whatever there is, and `closed` set in the meta.
+    pub(crate) fn get_single_day<D: Database>(
+        connection: &D::Connection,
         date: NaiveDate,
         name: &str,
-    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let data: Vec<(String, u16)> =
-            match SqliteDatabase::query_single_day(connection, name, date) {
-                Ok(data) => data,
-                Err(err) => match err {
-                    rusqlite::Error::QueryReturnedNoRows => Vec::new(),
-                    _ => return Self::server_error(&err.to_string()),
-                },
-            };
-        // If there is no prediction at all, return a 204, otherwise proceed
-        let knn_prediction: Vec<(String, u16)> = match SqliteDatabase::query_single_day(
-            connection,
-            &format!("{}{}", name, "_prediction_knn"),
-            date,
-        ) {
+        models: &[&str],
+    ) -> DatabaseResult<Option<MyResponse>> {
+        let data: Vec<OccupancyReading> = match D::query_single_day(connection, name, date) {
             Ok(data) => data,
-            Err(err) => match err {
-                rusqlite::Error::QueryReturnedNoRows => {
-                    if data.is_empty() {
-                        return Self::no_data();
-                    }
-                    Vec::new()
-                }
-                _ => return Self::server_error(&err.to_string()),
-            },
+            Err(DatabaseError::NotFound) => Vec::new(),
+            Err(err) => return Err(err),
         };
-        let lstm_prediction: Vec<(String, u16)> = match SqliteDatabase::query_single_day(
-            connection,
-            &format!("{}{}", name, "_prediction_lstm"),
-            date,
-        ) {
-            Ok(data) => data,
-            Err(err) => match err {
-                rusqlite::Error::QueryReturnedNoRows => {
-                    if data.is_empty() {
-                        return Self::no_data();
-                    }
-                    Vec::new()
-                }
-                _ => return Self::server_error(&err.to_string()),
+        // Only the requested prediction tables are read at all
+        let prediction = |model: &str| -> DatabaseResult<Vec<OccupancyReading>> {
+            if !models.contains(&model) {
+                return Ok(Vec::new());
+            }
+            match D::query_single_day(connection, &format!("{}_prediction_{}", name, model), date) {
+                Ok(data) => Ok(data),
+                Err(DatabaseError::NotFound) => Ok(Vec::new()),
+                Err(err) => Err(err),
+            }
+        };
+        let knn_prediction = prediction("knn")?;
+        let lstm_prediction = prediction("lstm")?;
+        let gb_prediction = prediction("gb")?;
+        let mut generated_at = Vec::new();
+        for (model, series) in [
+            ("knn", &knn_prediction),
+            ("lstm", &lstm_prediction),
+            ("gb", &gb_prediction),
+        ] {
+            if series.is_empty() {
+                continue;
+            }
+            let table_name = format!("{}_prediction_{}", name, model);
+            if let Some(time) = D::query_generated_at(connection, &table_name, date)? {
+                generated_at.push((model, time));
+            }
+        }
+        let Some((schedule, schedule_is_fallback)) =
+            Self::get_schedule::<D>(connection, name, date)?
+        else {
+            return Ok(None);
+        };
+
+        let latest_reading = D::query_last_reading(connection, name)?.map(|(time, _)| time);
+
+        let location = location_metadata(name);
+        let headcount = match &location {
+            Some(location) if location.capacity == Capacity::Headcount => {
+                Some(D::query_headcount_on_day(connection, name, date)?)
+            }
+            _ => None,
+        };
+        let anomalies = D::query_anomalies_on_day(connection, name, date)?;
+
+        // An empty closed day is an answer in itself, an empty open one means data is missing
+        let closed = schedule.is_closed_on(date);
+        let empty = data.is_empty()
+            && knn_prediction.is_empty()
+            && lstm_prediction.is_empty()
+            && gb_prediction.is_empty()
+            && headcount.as_ref().is_none_or(Vec::is_empty);
+        if empty && !closed {
+            return Ok(None);
+        }
+
+        let mut response = MyResponse::new(
+            data,
+            schedule,
+            knn_prediction,
+            lstm_prediction,
+            gb_prediction,
+            ResponseMeta::new(date, latest_reading, schedule_is_fallback, closed, location),
+        );
+        if let Some(headcount) = headcount {
+            response.set_headcount(headcount);
+        }
+        response.set_anomalies(anomalies);
+        for (model, time) in generated_at {
+            response.set_generated_at(model, time);
+        }
+        Ok(Some(response))
+    }
+
+    /// Fetches the schedule in effect on `date`, so past days keep the opening hours they had.
+    ///
+    /// Defaults to the oldest stored Schedule for a day before the first one was scraped, the same
+    /// as `Database::query_schedule_range`, in which case the flag returned alongside it is set.
+    /// Returns `Ok(None)` if there is no schedule at all.
+    fn get_schedule<D: Database>(
+        connection: &D::Connection,
+        name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<(Schedule, bool)>> {
+        match D::query_single_day_schedule(connection, name, date)? {
+            None => Ok(D::query_schedule_range(connection, name, date, date)?
+                .remove(&date)
+                .map(|schedule| (schedule, true))),
+            Some(schedule) => Ok(Some((schedule, false))),
+        }
+    }
+
+    /// The hours of each day of the week of `date`, from the schedule in effect on that day.
+    ///
+    /// Returns `Ok(None)` if there is no schedule at all.
+    fn week_schedule<D: Database>(
+        connection: &D::Connection,
+        name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<Schedule>> {
+        let monday = date - Days::new(date.weekday().num_days_from_monday() as u64);
+        let week = D::query_schedule_range(connection, name, monday, monday + Days::new(6))?;
+        if week.is_empty() {
+            return Ok(None);
+        }
+        let mut timings = [Daily::new_closed(); 7];
+        for (day, schedule) in &week {
+            let weekday = day.weekday().num_days_from_monday() as usize;
+            timings[weekday] = schedule.get_timings()[weekday];
+        }
+        Ok(Some(Schedule::from_timings(timings)))
+    }
+
+    /// The day /api/day returns data for: `date`, or the last recorded day if no date is given.
+    ///
+    /// Returns `Ok(None)` if no date is given and nothing has been recorded yet.
+    fn resolve_date<D: Database>(
+        connection: &D::Connection,
+        date: Option<NaiveDate>,
+        name: &str,
+    ) -> DatabaseResult<Option<NaiveDate>> {
+        if date.is_some() {
+            return Ok(date);
+        }
+        // Fetch the last recorded day's data instead
+        match D::query_last_day(connection, name)? {
+            None => Ok(None),
+            Some(data) => match NaiveDate::from_str(&data) {
+                Err(_) => Err(DatabaseError::Other("Could not parse date".to_string())),
+                Ok(date) => Ok(Some(date)),
             },
+        }
+    }
+
+    /// Fetches the data for `date`, or for the last recorded day if no date is given.
+    ///
+    /// The response `options` are applied to the result.
+    fn get_day_or_last<D: Database>(
+        connection: &D::Connection,
+        date: Option<NaiveDate>,
+        name: &str,
+        models: &[&str],
+        options: &ResponseOptions,
+    ) -> DatabaseResult<Option<MyResponse>> {
+        let Some(date) = Self::resolve_date::<D>(connection, date, name)? else {
+            return Ok(None);
         };
-        // Default to the last scraped Schedule if there is no schedule for the day
-        let schedule: Schedule =
-            match SqliteDatabase::query_single_day_schedule(connection, name, date) {
-                Ok(schedule) => match schedule {
-                    None => match SqliteDatabase::query_last_day_schedule(connection, name) {
-                        Ok(schedule) => match schedule {
-                            None => return Self::no_data(),
-                            Some(schedule) => schedule,
-                        },
-                        Err(err) => return Self::server_error(&err.to_string()),
-                    },
-                    Some(schedule) => serde_json::from_str(&schedule).unwrap(),
-                },
-                Err(err) => return Self::server_error(&err.to_string()),
-            };
+        let mut result = Self::get_single_day::<D>(connection, date, name, models)?;
+        if let Some(result) = result.as_mut() {
+            options.apply(result, date);
+        }
+        Ok(result)
+    }
 
-        let result = MyResponse::new(data, schedule, knn_prediction, lstm_prediction);
-        Self::ok_data(result)
+    /// Sanitizes a table name with the Server's sanitizer, see `validation::sanitize_name`.
+    fn sanitize_name<'a>(&self, name: &'a str) -> Option<&'a str> {
+        sanitize_name(&self.name_sanitizer, name)
+    }
+
+    /// The first location named in the path or query that isn't in the `locations` table, so
+    /// it is never used as a table name. Names that don't sanitize are left to the handler.
+    fn requested_unknown_location(&self, uri: &Uri, route: &Route) -> Option<String> {
+        let mut params = QueryParams::parse(uri, route, &self.name_sanitizer);
+        let names = match route.endpoint {
+            Endpoint::Day => params.require_names(),
+            _ => params.require_name().into_iter().collect(),
+        };
+        names
+            .into_iter()
+            .find(|name| !self.locations.contains(name))
     }
 
     /// The /api/day API endpoint.
@@ -150,93 +549,268 @@ impl Server {
     ///
     /// At the end, it calls the `get_single_day` function to fetch the data if a date is provided,
     /// otherwise gets the last recorded day's data using `query_last_day` into `get_single_day`.
-    fn day_data(&self, res: Request<Incoming>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    ///
+    /// `name` can also be a comma separated list of names, in which case a JSON object keyed by
+    /// name is returned. Each value is either the usual response, `null` when there is no data,
+    /// or an `{"error": ...}` object, so one location failing doesn't fail the others.
+    fn day_data(
+        &self,
+        res: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
         // Not my proudest function
+        let params = match self.validate_data_params(res.uri(), route) {
+            Ok(params) => params,
+            Err(errors) => return Self::boxed(Self::invalid_params(&errors)),
+        };
+        let DataParams {
+            names,
+            date,
+            since,
+            models,
+            options,
+            ..
+        } = params;
+        let sanitized: Vec<&str> = names.iter().map(String::as_str).collect();
+
         let connection = match self.get_connection() {
             Ok(conn) => conn,
-            Err(err) => return Self::server_error(&err),
+            Err(err) => return Self::boxed(Self::connection_error(err)),
         };
 
-        let Some(params) = res.uri().query() else {
-            return Self::bad_request("Parameters not provided. Required name + Optional date.");
-        };
+        if let (Some(since), [name]) = (since, &sanitized[..]) {
+            return Self::day_delta(connection, since, name, &options);
+        }
 
-        let Some(map) = Self::parse_params(params) else {
-            return Self::bad_request("Malformed Parameters.");
-        };
+        Self::boxed(Self::days::<SqliteDatabase>(
+            &connection,
+            &sanitized,
+            date,
+            &models,
+            &options,
+            &res,
+        ))
+    }
 
-        let Some(name) = map.get("name") else {
-            return Self::bad_request("name not provided.");
-        };
+    /// The /api/day response for one or more locations, see `day_data`.
+    fn days<D: Database>(
+        connection: &D::Connection,
+        sanitized: &[&str],
+        date: Option<NaiveDate>,
+        models: &[&str],
+        options: &ResponseOptions,
+        res: &Request<Bytes>,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        if let [name] = sanitized[..] {
+            let date = match Self::resolve_date::<D>(connection, date, name) {
+                Ok(Some(date)) => date,
+                Ok(None) => return Self::no_data(),
+                Err(err) => return Self::database_error(err),
+            };
+            let last_modified = match Self::last_modified::<D>(connection, name, date) {
+                Ok(last_modified) => last_modified,
+                Err(err) => return Self::database_error(err),
+            };
+            if Self::is_unmodified(res, last_modified) {
+                return Self::with_last_modified(Self::not_modified(), last_modified);
+            }
+            let res =
+                match Self::get_day_or_last::<D>(connection, Some(date), name, models, options) {
+                    Ok(Some(result)) => Self::ok_data(result),
+                    Ok(None) => Self::no_data(),
+                    Err(err) => Self::database_error(err),
+                };
+            return Self::with_last_modified(res, last_modified);
+        }
 
-        // SQL Injections are automatically handled by rusqlite
-        // Handle the table name manually
-        let name = match self.name_sanitizer.captures(name) {
-            None => return Self::bad_request("Malformed Name"),
-            Some(captures) => captures,
-        };
-        let name = name.get(0).unwrap().as_str();
-        if name.is_empty() {
-            return Self::bad_request("Malformed Name");
+        let mut results: BTreeMap<&str, BatchEntry> = BTreeMap::new();
+        for &name in sanitized {
+            let entry = match Self::get_day_or_last::<D>(connection, date, name, models, options) {
+                Ok(Some(result)) => BatchEntry::Data(Box::new(result)),
+                Ok(None) => BatchEntry::NoData,
+                Err(err) => BatchEntry::Error {
+                    error: err.to_string(),
+                },
+            };
+            results.insert(name, entry);
+        }
+        Self::ok_data(results)
+    }
+
+    /// When the data for `date` last changed: the time of the newest reading on that day or, for
+    /// days without readings yet, of the newest prediction.
+    fn last_modified<D: Database>(
+        connection: &D::Connection,
+        name: &str,
+        date: NaiveDate,
+    ) -> DatabaseResult<Option<DateTime<Utc>>> {
+        let mut time = D::query_last_time_on_day(connection, name, date)?;
+        if time.is_none() {
+            time = D::query_last_time_on_day(
+                connection,
+                &format!("{}{}", name, "_prediction_knn"),
+                date,
+            )?;
         }
+        Ok(time
+            .and_then(|time| NaiveDateTime::from_str(&time).ok())
+            .and_then(uk_local_to_utc))
+    }
+
+    /// Whether the request's If-Modified-Since is no earlier than `last_modified`.
+    fn is_unmodified(req: &Request<Bytes>, last_modified: Option<DateTime<Utc>>) -> bool {
+        let Some(last_modified) = last_modified else {
+            return false;
+        };
+        req.headers()
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|since| since.to_str().ok())
+            .and_then(parse_http_date)
+            .is_some_and(|since| since >= last_modified)
+    }
 
-        if let Some(date) = map.get("date") {
-            if let Ok(date) = NaiveDate::from_str(date) {
-                return Self::get_single_day(&connection, date, &name);
+    /**
+    The delta form of /api/day, for clients that poll and already have everything up to `since`.
+
+    Returns only the readings newer than `since` and the predictions of each model generated
+    after it, or a 304 if there are neither. The cheap existence check runs first so that with
+    no new readings, an unchanged location costs a query per table.
+
+    `since` can be weeks back, so the readings are streamed `STREAM_PAGE_SIZE` at a time rather
+    than built into one string. If reading a later page fails the JSON is left unfinished, so
+    the client can tell it is incomplete. A regeneration replaces at most a week of predictions,
+    so those are read up front.
+    */
+    fn day_delta(
+        connection: PooledConnection<SqliteConnectionManager>,
+        since: NaiveDateTime,
+        name: &str,
+        options: &ResponseOptions,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
+        let has_newer = match SqliteDatabase::query_has_newer(&connection, name, since) {
+            Ok(has_newer) => has_newer,
+            Err(err) => return Self::boxed(Self::database_error(err)),
+        };
+        let mut predictions = Vec::new();
+        for model in PREDICTION_MODELS {
+            let table = format!("{}_prediction_{}", name, model);
+            match SqliteDatabase::query_regenerated(&connection, &table, since) {
+                Ok(rows) if rows.is_empty() => (),
+                Ok(rows) => predictions.push((*model, rows)),
+                Err(err) => return Self::boxed(Self::database_error(err)),
             }
-            return Self::bad_request("Malformed Date");
         }
-        // Fetch the last recorded day's data instead
+        if !has_newer && predictions.is_empty() {
+            return Self::boxed(Self::not_modified());
+        }
 
-        match SqliteDatabase::query_last_day(&connection, &name) {
-            Err(err) => return Self::server_error(&err.to_string()),
-            Ok(data) => match data {
-                None => return Self::no_data(),
-                Some(data) => match NaiveDate::from_str(&data) {
-                    Err(_) => return Self::server_error("Could not parse date"),
-                    Ok(date) => {
-                        return Self::get_single_day(&connection, date, &name);
-                    }
-                },
-            },
+        // Every id is smaller, so this pages from the first reading after `since`
+        let after = Some((i64::MAX, uk_local_to_stored(since)));
+        let since = since.format(ISO_FORMAT).to_string();
+        // The first page is read straight away so that an error is still a proper response
+        let first = match SqliteDatabase::query_page(&connection, name, after, STREAM_PAGE_SIZE) {
+            Ok(page) => page,
+            Err(err) => return Self::boxed(Self::database_error(err)),
         };
+
+        let mut delta = options.delta_stream();
+        let table = name.to_string();
+        let body = body::stream_blocking(move |send| {
+            if !send(Bytes::from(delta.start(&since))) {
+                return;
+            }
+            let streamed = Self::for_each_page(&connection, &table, first, |page| {
+                let readings = page
+                    .iter()
+                    .map(|reading| (reading.time.as_str(), reading.occupancy));
+                send(Bytes::from(delta.page(readings)))
+            });
+            match streamed {
+                Ok(true) => {
+                    send(Bytes::from(delta.end(&predictions)));
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    request_id::log(format_args!("Delta of {} failed.\n{}", table, err));
+                }
+            }
+        });
+        Ok(Self::response(StatusCode::OK, Some(JSON))
+            .body(body)
+            .unwrap())
+    }
+
+    /**
+    Hands `first` and then every following page of `table` to `send`, as read by `query_page`.
+
+    Returns `Ok(true)` once the last page has been sent, or `Ok(false)` if `send` returned false
+    because the client went away.
+    */
+    fn for_each_page(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        table: &str,
+        first: Vec<PageRow>,
+        mut send: impl FnMut(&[PageRow]) -> bool,
+    ) -> DatabaseResult<bool> {
+        let mut page = first;
+        while let Some(after) = page.last().map(|last| (last.id, last.timestamp)) {
+            if !send(&page) {
+                return Ok(false);
+            }
+            if page.len() < STREAM_PAGE_SIZE {
+                break;
+            }
+            page = SqliteDatabase::query_page(connection, table, Some(after), STREAM_PAGE_SIZE)?;
+        }
+        Ok(true)
     }
 
     /// Fetches the data from a specific time onwards till the end of the day or the data that's
     /// collected so far.
     ///
     /// It uses the `query_range` function to fetch the data and the `query_single_day_schedule`
-    /// for the schedule.
-    fn query_from(
-        connection: &PooledConnection<SqliteConnectionManager>,
+    /// for the schedule. The response `options` are applied to the result.
+    fn query_from<D: Database>(
+        connection: &D::Connection,
         from: NaiveDateTime,
         name: &str,
+        options: &ResponseOptions,
     ) -> Result<Response<Full<Bytes>>, hyper::Error> {
         let to = from + chrono::Duration::days(1);
 
-        let occupancy_data = match SqliteDatabase::query_range(connection, name, from, to) {
+        let occupancy_data = match D::query_range(connection, name, from, to) {
             Ok(data) => data,
-            Err(err) => match err {
-                rusqlite::Error::QueryReturnedNoRows => return Self::no_data(),
-                _ => return Self::server_error(&err.to_string()),
+            Err(err) => return Self::database_error(err),
+        };
+
+        let schedule = match D::query_single_day_schedule(connection, name, from.date()) {
+            Ok(schedule) => match schedule {
+                None => return Self::no_data(),
+                Some(schedule) => schedule,
             },
+            Err(err) => return Self::database_error(err),
         };
 
-        let schedule =
-            match SqliteDatabase::query_single_day_schedule(connection, name, from.date()) {
-                Ok(schedule) => match schedule {
-                    None => return Self::no_data(),
-                    Some(schedule) => schedule,
-                },
-                Err(err) => return Self::server_error(&err.to_string()),
-            };
+        let latest_reading = match D::query_last_reading(connection, name) {
+            Ok(reading) => reading.map(|(time, _)| time),
+            Err(err) => return Self::database_error(err),
+        };
 
-        let result = MyResponse::new(
+        let mut result = MyResponse::new(
             occupancy_data,
-            serde_json::from_str(&schedule).unwrap(),
+            schedule,
             Vec::new(),
             Vec::new(),
+            Vec::new(),
+            ResponseMeta::new(
+                from.date(),
+                latest_reading,
+                false,
+                false,
+                location_metadata(name),
+            ),
         );
+        options.apply(&mut result, from.date());
         Self::ok_data(result)
     }
 
@@ -245,116 +819,3252 @@ impl Server {
     /// This is the endpoint the frontend should use when it already has some data for the day.
     /// It will take in a datetime and return the rest of the data collected for that day.
     /// Again, this handles all the preprocessing, the actual data fetching is done by `query_from`.
-    fn from_last(&self, res: Request<Incoming>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    #[allow(clippy::wrong_self_convention)]
+    fn from_last(
+        &self,
+        res: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let params = match self.validate_data_params(res.uri(), route) {
+            Ok(params) => params,
+            Err(errors) => return Self::invalid_params(&errors),
+        };
+        let DataParams {
+            names,
+            from,
+            options,
+            ..
+        } = params;
+        // Both are required, so validation fails without them
+        let (Some(name), Some(from)) = (names.first(), from) else {
+            return Self::bad_request("name and from must both be provided.");
+        };
+
         let connection = match self.get_connection() {
             Ok(conn) => conn,
-            Err(err) => return Self::server_error(&err),
+            Err(err) => return Self::connection_error(err),
         };
+        Self::query_from::<SqliteDatabase>(&connection, from, name, &options)
+    }
 
-        let Some(params) = res.uri().query() else {
-            return Self::bad_request("Parameters not provided. Required name + Required from.");
-        };
+    /// The `(time, occupancy)` pairs of a series, as the evaluation takes them.
+    fn pairs(series: Vec<OccupancyReading>) -> Vec<(NaiveDateTime, u16)> {
+        series.into_iter().map(Into::into).collect()
+    }
 
-        let Some(map) = Self::parse_params(params) else {
-            return Self::bad_request("Malformed Parameters.");
+    /// The table suffix of the predictions of `model`, for /api/compare and /api/accuracy.
+    /// `all` isn't a single model, so it is reported as unknown along with anything else.
+    fn model_suffix(params: &mut QueryParams) -> Option<&'static str> {
+        let suffix = PredictionModel::from_str(params.get("model")?)
+            .ok()
+            .and_then(|model| model.table_suffix());
+        if suffix.is_none() {
+            params.error(unknown_model());
+        }
+        suffix
+    }
+
+    /// The /api/compare API endpoint.
+    ///
+    /// Compares the actual readings of a day against a model's predictions for it. Each reading
+    /// is paired with the nearest prediction within `tolerance` minutes (default 3), and the
+    /// pairs are returned together with the mean absolute error and the max error.
+    ///
+    /// Will return a 204 if either the readings or the predictions are missing for that day.
+    fn compare(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let date = params.require_date("date");
+        let suffix = Self::model_suffix(&mut params);
+        let tolerance = match params
+            .get("tolerance")
+            .map(|minutes| minutes.parse::<u16>())
+        {
+            None => chrono::Duration::minutes(3),
+            Some(Ok(minutes)) if (1..=60).contains(&minutes) => {
+                chrono::Duration::minutes(minutes as i64)
+            }
+            Some(_) => {
+                params.error("tolerance must be between 1 and 60 minutes.");
+                chrono::Duration::minutes(3)
+            }
         };
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(date), Some(suffix)) = (name, date, suffix) else {
+            return Self::bad_request("name, date and model must all be provided.");
+        };
+        let name = name.as_str();
 
-        let Some(name) = map.get("name") else {
-            return Self::bad_request("name not provided.");
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
         };
 
-        let Some(from) = map.get("from") else {
-            return Self::bad_request("from not provided.");
+        let actual = match SqliteDatabase::query_single_day(&connection, name, date) {
+            Ok(data) => Self::pairs(data),
+            Err(err) => return Self::database_error(err),
+        };
+        let predicted = match SqliteDatabase::query_single_day(
+            &connection,
+            &format!("{}{}", name, suffix),
+            date,
+        ) {
+            Ok(data) => Self::pairs(data),
+            Err(err) => return Self::database_error(err),
         };
 
-        let name = match self.name_sanitizer.captures(name) {
-            None => return Self::bad_request("Malformed Name"),
-            Some(captures) => captures,
+        let pairs = match_nearest(&actual, &predicted, tolerance);
+        match ErrorMetrics::from_points(&pairs) {
+            None => Self::no_data(),
+            Some(metrics) => Self::ok_data(CompareResponse { pairs, metrics }),
+        }
+    }
+
+    /// The /api/accuracy API endpoint.
+    ///
+    /// Reports how well a model's predictions matched the readings over the last `weeks` weeks
+    /// (1 to 12, default 4), per day and overall. Readings are paired with predictions the same
+    /// way as /api/compare, with the default tolerance.
+    ///
+    /// Will return a 204 if there is nothing to evaluate yet.
+    fn accuracy(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let suffix = Self::model_suffix(&mut params);
+        let weeks = match params.get("weeks").map(|weeks| weeks.parse::<i64>()) {
+            None => 4,
+            Some(Ok(weeks)) if (1..=12).contains(&weeks) => weeks,
+            Some(_) => {
+                params.error("weeks must be between 1 and 12.");
+                4
+            }
         };
-        let name = name.get(0).unwrap().as_str();
-        if name.is_empty() {
-            return Self::bad_request("Malformed Name");
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
         }
-        let from: NaiveDateTime = match NaiveDateTime::from_str(from) {
-            Ok(date) => date,
-            Err(_) => return Self::bad_request("Malformed Date"),
+        let (Some(name), Some(suffix)) = (name, suffix) else {
+            return Self::bad_request("name and model must both be provided.");
         };
-        Self::query_from(&connection, from, name)
-    }
+        let name = name.as_str();
 
-    /// Return a 200 OK response with the data provided.
-    fn ok_data<T: Serialize>(body: T) -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let data = serde_json::to_string(&body).unwrap();
-        let res = Response::builder()
-            .status(StatusCode::OK)
-            .body(Full::new(Bytes::from(data)))
-            .unwrap();
-        Ok(res)
-    }
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
 
-    /// Return a 500 Internal Server Error response with the message provided.
-    fn server_error(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let res = Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Full::new(Bytes::from(format!(
-                "{{\"error\": \"{}\" }}",
-                message
-            ))))
+        let to = uk_datetime_now().naive_local();
+        let from = (to.date() - chrono::Duration::weeks(weeks))
+            .and_hms_opt(0, 0, 0)
             .unwrap();
-        Ok(res)
-    }
 
-    /// Return a 404 Not Found response with the message provided. The message here is optional.
-    /// Leave it empty for no message.
-    fn not_found(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let res = Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Full::new(if message.is_empty() {
-                Bytes::new()
-            } else {
-                Bytes::from(format!("{{\"error\": \"{}\" }}", message))
-            }))
-            .unwrap();
-        Ok(res)
-    }
+        let actual = match SqliteDatabase::query_range(&connection, name, from, to) {
+            Ok(data) => Self::pairs(data),
+            Err(err) => return Self::database_error(err),
+        };
+        let predicted = match SqliteDatabase::query_range(
+            &connection,
+            &format!("{}{}", name, suffix),
+            from,
+            to,
+        ) {
+            Ok(data) => Self::pairs(data),
+            Err(err) => return Self::database_error(err),
+        };
 
-    /// Return a 400 Bad Request response with the message provided.
-    fn bad_request(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let res = Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Full::new(Bytes::from(format!(
-                "{{\"error\": \"{}\" }}",
-                message
-            ))))
-            .unwrap();
-        Ok(res)
+        let pairs = match_nearest(&actual, &predicted, chrono::Duration::minutes(3));
+        match ErrorMetrics::from_points(&pairs) {
+            None => Self::no_data(),
+            Some(overall) => Self::ok_data(AccuracyResponse {
+                days: metrics_by_day(&pairs),
+                overall,
+            }),
+        }
     }
 
-    /// Return a 204 No Content response.
-    fn no_data() -> Result<Response<Full<Bytes>>, hyper::Error> {
-        let res = Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .body(Full::new(Bytes::new()))
-            .unwrap();
-        Ok(res)
+    /// The reading with the highest occupancy in a series. Ties go to the earliest one.
+    fn peak(series: &[OccupancyReading]) -> Option<Reading> {
+        series
+            .iter()
+            .rev()
+            .max_by_key(|reading| reading.occupancy)
+            .map(|reading| {
+                Reading::new(
+                    reading.time.format(ISO_FORMAT).to_string(),
+                    reading.occupancy,
+                )
+            })
     }
-}
 
-impl Service<Request<Incoming>> for Server {
-    type Response = Response<Full<Bytes>>;
-    type Error = hyper::Error;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+    /// The /api/summary API endpoint.
+    ///
+    /// A single call for dashboard widgets with, for one location: the current occupancy and how
+    /// old it is, today's peak so far, the KNN predicted peak for the rest of today and today's
+    /// opening hours.
+    ///
+    /// Any of the readings can be null, e.g. before opening there are no readings yet. Only if
+    /// there is no schedule at all is a 204 returned.
+    fn summary(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+        let name = name.as_str();
 
-    fn call(&self, req: Request<Incoming>) -> Self::Future {
-        let res = match req.method() {
-            &Method::GET => match req.uri().path() {
-                "/api/day" => self.day_data(req),
-                "/api/from" => self.from_last(req),
-                _ => Server::not_found(""),
-            },
-            _ => Server::not_found(""),
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let now = uk_datetime_now().naive_local();
+        let today = now.date();
+
+        let (schedule, _) = match Self::get_schedule::<SqliteDatabase>(&connection, name, today) {
+            Ok(Some(schedule)) => schedule,
+            Ok(None) => return Self::no_data(),
+            Err(err) => return Self::database_error(err),
+        };
+        let daily = schedule.get_timings()[today.weekday().num_days_from_monday() as usize];
+
+        let current = match self.last_reading(&connection, name) {
+            Ok(reading) => {
+                reading.map(|(time, occupancy)| Self::current_reading(time, occupancy, now))
+            }
+            Err(err) => return Self::database_error(err),
+        };
+
+        let peak_today = match SqliteDatabase::query_single_day(&connection, name, today) {
+            Ok(data) => Self::peak(&data),
+            Err(err) => return Self::database_error(err),
+        };
+
+        let end_of_day = today.and_hms_opt(23, 59, 59).unwrap();
+        let predicted_peak = match SqliteDatabase::query_range(
+            &connection,
+            &format!("{}{}", name, "_prediction_knn"),
+            now,
+            end_of_day,
+        ) {
+            Ok(data) => Self::peak(&data),
+            Err(err) => return Self::database_error(err),
+        };
+
+        Self::ok_data(SummaryResponse {
+            current,
+            peak_today,
+            predicted_peak,
+            hours: OpeningHours::from_daily(&daily),
+        })
+    }
+
+    /// The /api/latest API endpoint.
+    ///
+    /// A deliberately small version of /api/summary for widgets that poll often: the newest
+    /// reading, its age and whether the location is open. Whether it is open comes from the
+    /// Schedule the scraper last saw, so neither the schedule nor the prediction tables are read.
+    ///
+    /// The response may be cached until the next reading is due.
+    fn latest(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let (time, occupancy) = match self.last_reading(&connection, &name) {
+            Ok(Some(reading)) => reading,
+            Ok(None) => return Self::no_data(),
+            Err(err) => return Self::database_error(err),
+        };
+
+        let now = uk_datetime_now();
+        let reading = Self::current_reading(time, occupancy, now.naive_local());
+        // A new reading is due one scrape interval after the last one
+        let max_age = reading.age_seconds.map_or(0, |age| {
+            (SCRAPE_INTERVAL.as_secs() as i64 - age).clamp(0, SCRAPE_INTERVAL.as_secs() as i64)
+        });
+
+        let res = Self::ok_data(LatestResponse {
+            reading,
+            open: self.schedules.is_open(&name, now),
+        });
+        Self::with_max_age(res, max_age as u64)
+    }
+
+    /// A reading along with how long before `now` it was taken.
+    fn current_reading(time: String, occupancy: u16, now: NaiveDateTime) -> CurrentReading {
+        let age = NaiveDateTime::parse_from_str(&time, ISO_FORMAT)
+            .map(|time| (now - time).num_seconds())
+            .ok();
+        CurrentReading {
+            reading: Reading::new(time, occupancy),
+            age_seconds: age,
+        }
+    }
+
+    /// The most recent reading of `name` as (time, occupancy), the last one scraped if it is
+    /// newer than the last one stored, as it can still be waiting to be written in a batch.
+    fn last_reading(
+        &self,
+        connection: &PooledConnection<SqliteConnectionManager>,
+        name: &str,
+    ) -> DatabaseResult<Option<(String, u16)>> {
+        let stored = SqliteDatabase::query_last_reading(connection, name)?;
+        let Some(scraped) = self.new_readings.latest(name) else {
+            return Ok(stored);
+        };
+        let newer = stored
+            .as_ref()
+            .and_then(|(time, _)| NaiveDateTime::parse_from_str(time, ISO_FORMAT).ok())
+            .is_none_or(|time| scraped.time > time);
+        if newer {
+            Ok(Some((
+                scraped.time.format(ISO_FORMAT).to_string(),
+                scraped.occupancy,
+            )))
+        } else {
+            Ok(stored)
+        }
+    }
+
+    /// The /api/overview API endpoint.
+    ///
+    /// /api/latest for every location at once, sorted by name, for displays that show them all.
+    /// Locations without readings are listed with nulls. One that can't be read is logged and
+    /// listed the same way rather than failing the whole response.
+    fn overview(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let now = uk_datetime_now();
+        let mut locations = locations_metadata();
+        locations.sort_by_key(|location| location.name);
+        let overview: Vec<OverviewEntry> = locations
+            .iter()
+            .map(|location| {
+                let reading = match self.last_reading(&connection, location.name) {
+                    Ok(reading) => reading,
+                    Err(err) => {
+                        request_id::log(format_args!(
+                            "Could not read {} for the overview.\n{}",
+                            location.name, err
+                        ));
+                        None
+                    }
+                };
+                let reading = reading.map(|(time, occupancy)| {
+                    Self::current_reading(time, occupancy, now.naive_local())
+                });
+                OverviewEntry::new(
+                    location,
+                    reading,
+                    self.schedules.is_open(location.name, now),
+                )
+            })
+            .collect();
+        Self::ok_data(overview)
+    }
+
+    /// The status page at `/`, for checking on the service from a browser.
+    ///
+    /// Shows each location's newest reading and when it was last scraped. A location that can't
+    /// be read is shown as having no data rather than failing the whole page.
+    fn status_page(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let mut locations = Vec::new();
+        for name in LOCATIONS {
+            let latest = match self.last_reading(&connection, name) {
+                Ok(latest) => latest,
+                Err(err) => {
+                    request_id::log(format_args!(
+                        "Could not read {} for the status page.\n{}",
+                        name, err
+                    ));
+                    None
+                }
+            };
+            locations.push(LocationStatus {
+                name,
+                latest,
+                last_scrape: self
+                    .schedules
+                    .last_scrape(name)
+                    .map(|time| time.format(ISO_FORMAT).to_string()),
+            });
+        }
+
+        let res = Self::response(StatusCode::OK, Some(HTML))
+            .body(Full::new(Bytes::from(status_page::render(&locations))))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// The /admin/status API endpoint.
+    ///
+    /// How scraping each location has been going: the last successful scrape, the last error and
+    /// how many scrapes in a row have failed, as stored in `scraper_meta`, and when predictions
+    /// were last made since startup. JSON by default, or a page for the browser when the request
+    /// accepts text/html.
+    fn scraper_status(&self, req: Request<Bytes>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+        let mut targets = Vec::new();
+        for name in LOCATIONS {
+            let meta = match SqliteDatabase::query_scraper_meta(&connection, name) {
+                Ok(meta) => meta,
+                Err(err) => return Self::database_error(err),
+            };
+            let last_prediction = self.scraper_status.get(name).last_prediction;
+            targets.push(ScraperTargetStatus {
+                name,
+                last_success: meta.as_ref().and_then(|meta| meta.last_success_at.clone()),
+                last_error: meta.as_ref().and_then(|meta| meta.last_error_text.clone()),
+                last_error_at: meta.as_ref().and_then(|meta| meta.last_error_at.clone()),
+                consecutive_failures: meta.map_or(0, |meta| meta.consecutive_failures),
+                last_prediction: last_prediction.map(|time| time.format(ISO_FORMAT).to_string()),
+            });
+        }
+
+        if !Self::accepts_html(&req) {
+            return Self::ok_data(targets);
+        }
+        let res = Self::response(StatusCode::OK, Some(HTML))
+            .body(Full::new(Bytes::from(status_page::render_scraper(
+                &targets,
+            ))))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Whether text/html is one of the types in the request's Accept header.
+    fn accepts_html(req: &Request<Bytes>) -> bool {
+        req.headers()
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|range| range.split(';').next().unwrap_or("").trim() == "text/html")
+    }
+
+    /**
+    The /api/wait API endpoint.
+
+    Long-polling for clients that can't stream: holds the request until a reading newer than
+    `after` is stored and returns it as `{"time", "occupancy"}`, or returns a 204 once
+    `WAIT_TIMEOUT` has passed without one. If there already is a newer reading the newest one is
+    returned straight away.
+
+    The wait happens in the request's own future, so a client that goes away takes it with it.
+    It also ends with a 204 when the server shuts down.
+    */
+    async fn wait(&self, uri: &Uri, route: &Route) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        if let Err(errors) = strict_check(uri.query(), route) {
+            return Self::invalid_params(&errors);
+        }
+        if let Some(name) = self.requested_unknown_location(uri, route) {
+            return Self::unknown_location(&name);
+        }
+        let mut params = QueryParams::parse(uri, route, &self.name_sanitizer);
+        let name = params.require_name();
+        let after = params.require_datetime("after");
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(after)) = (name, after) else {
+            return Self::bad_request("name and after must both be provided.");
+        };
+
+        // Listening before looking in the database means a reading stored in between is seen
+        let mut readings = self.new_readings.subscribe();
+        let server = self.clone();
+        let table = name.clone();
+        let id = request_id::current().unwrap_or_default();
+        let stored = tokio::task::spawn_blocking(move || {
+            request_id::sync_scope(id, || server.newer_reading(&table, after))
+        });
+        match stored.await {
+            Ok(Some(res)) => return res,
+            Ok(None) => (),
+            Err(err) => return Self::server_error(&format!("Handler failed: {}", err)),
+        }
+
+        let next = async {
+            loop {
+                match readings.recv().await {
+                    Ok(reading) if reading.name == name && reading.time > after => {
+                        return Some(reading)
+                    }
+                    // Readings only come a few per scrape, so falling behind doesn't happen
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        };
+        let mut shutdown = self.shutdown.clone();
+        tokio::select! {
+            reading = tokio::time::timeout(WAIT_TIMEOUT, next) => match reading {
+                Ok(Some(NewReading { time, occupancy, .. })) => {
+                    Self::ok_data(Reading::new(time.format(ISO_FORMAT).to_string(), occupancy))
+                }
+                _ => Self::no_data(),
+            },
+            _ = shutdown.wait_for(|shutdown| *shutdown) => Self::no_data(),
+        }
+    }
+
+    /// The response to /api/wait if `name` already has a reading newer than `after`, see `wait`.
+    fn newer_reading(
+        &self,
+        name: &str,
+        after: NaiveDateTime,
+    ) -> Option<Result<Response<Full<Bytes>>, hyper::Error>> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Some(Self::connection_error(err)),
+        };
+        match self.last_reading(&connection, name) {
+            Ok(Some((time, occupancy))) => {
+                let newer =
+                    NaiveDateTime::parse_from_str(&time, ISO_FORMAT).is_ok_and(|time| time > after);
+                newer.then(|| Self::ok_data(Reading::new(time, occupancy)))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Self::database_error(err)),
+        }
+    }
+
+    /// The /api/health API endpoint.
+    ///
+    /// Reports how many connections are open out of the limit so it is visible when the limit
+    /// is being hit, the same for the database connection pool along with how many checkouts
+    /// had to wait or timed out, and whether the database passes `integrity::quick_check`. The check is only
+    /// made every so often, see `HealthCheck`. A damaged database is a 503 so monitoring notices.
+    /// Also reports when each location was last scraped and how many scrapes in a row have
+    /// failed, as stored in `scraper_meta`.
+    fn health(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = self.get_connection();
+        let scraper = match &connection {
+            Ok(connection) => LOCATIONS
+                .iter()
+                .map(|name| {
+                    let meta = SqliteDatabase::query_scraper_meta(connection, name)
+                        .ok()
+                        .flatten();
+                    ScraperHealth {
+                        name,
+                        last_success: meta.as_ref().and_then(|meta| meta.last_success_at.clone()),
+                        consecutive_failures: meta.map_or(0, |meta| meta.consecutive_failures),
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let database = match connection {
+            Err(_) => DatabaseHealth {
+                status: "unavailable",
+                problems: Vec::new(),
+            },
+            Ok(connection) => match self.health_check.problems(&connection) {
+                Ok(problems) if problems.is_empty() => DatabaseHealth {
+                    status: "ok",
+                    problems,
+                },
+                Ok(problems) => DatabaseHealth {
+                    status: "damaged",
+                    problems,
+                },
+                Err(err) => DatabaseHealth {
+                    status: "unavailable",
+                    problems: vec![err.to_string()],
+                },
+            },
+        };
+        let damaged = database.status == "damaged";
+        let health = HealthResponse {
+            status: if damaged { "degraded" } else { "ok" },
+            database,
+            scraper,
+            connections: ConnectionStats {
+                in_flight: self.connections.in_flight(),
+                limit: self.connections.limit(),
+            },
+            pool: self.pool_health(),
+        };
+        if !damaged {
+            return Self::ok_data(health);
+        }
+        let res = Self::response(StatusCode::SERVICE_UNAVAILABLE, Some(JSON))
+            .body(Full::new(Bytes::from(
+                serde_json::to_string(&health).unwrap(),
+            )))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// The state of the database connection pool, for /api/health.
+    fn pool_health(&self) -> PoolHealth {
+        let state = self.connection_pool.state();
+        PoolHealth {
+            in_use: state.connections - state.idle_connections,
+            idle: state.idle_connections,
+            max_size: self.connection_pool.max_size(),
+            checkouts: self.pool_stats.checkouts(),
+            waits: self.pool_stats.waits(),
+            timeouts: self.pool_stats.timeouts(),
+        }
+    }
+
+    /// The /api/schedule.ics API endpoint.
+    ///
+    /// The current weekly schedule as an iCalendar file that calendar apps can subscribe to, see
+    /// `ics::calendar`. Each day of this week has the hours of the schedule in effect on it, so a
+    /// change that takes effect later in the week is already in.
+    fn schedule_ics(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let today = uk_datetime_now().date_naive();
+        let schedule = match Self::week_schedule::<SqliteDatabase>(&connection, &name, today) {
+            Ok(Some(schedule)) => schedule,
+            Ok(None) => return Self::no_data(),
+            Err(err) => return Self::database_error(err),
+        };
+        let display_name = location_metadata(&name)
+            .map(|location| location.display_name.to_string())
+            .unwrap_or_else(|| name.clone());
+
+        let calendar = ics::calendar(&name, &display_name, &schedule, today, Utc::now());
+        let res = Self::response(StatusCode::OK, Some(CALENDAR))
+            .header(
+                CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}.ics\"", name),
+            )
+            .body(Full::new(Bytes::from(calendar)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// The /api/weekday API endpoint.
+    ///
+    /// The readings of each of the last `weeks` occurrences of `weekday` (1 to
+    /// `MAX_WEEKDAY_WEEKS`, default 4), keyed by date. Today counts if it is that weekday. Days
+    /// without readings are left out rather than returned empty.
+    fn weekday(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let (weekday, weeks) = Self::weekday_params(&mut params, 4);
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(weekday)) = (name, weekday) else {
+            return Self::bad_request("name and weekday must both be provided.");
+        };
+
+        let today = uk_datetime_now().date_naive();
+        let days_since =
+            (7 + today.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+        let to = today - Days::new(days_since as u64);
+        let from = to - Days::new((weeks - 1) * 7);
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let data = match SqliteDatabase::query_weekday(&connection, &name, weekday, from, to) {
+            Ok(data) => data,
+            Err(err) => return Self::database_error(err),
+        };
+
+        let mut days: BTreeMap<String, Vec<OccupancyReading>> = BTreeMap::new();
+        for reading in data {
+            let date = reading.time.date().to_string();
+            days.entry(date).or_default().push(reading);
+        }
+
+        Self::ok_data(WeekdayResponse {
+            weekday: weekday.to_string(),
+            days,
+        })
+    }
+
+    /// The `weekday` and `weeks` parameters of /api/weekday and /api/typical. `weeks` is
+    /// `default_weeks` when not given.
+    fn weekday_params(params: &mut QueryParams, default_weeks: u64) -> (Option<Weekday>, u64) {
+        let weekday = match params.get("weekday").map(Weekday::from_str) {
+            Some(Ok(weekday)) => Some(weekday),
+            Some(Err(_)) => {
+                params.error("Unknown weekday. Expected mon, tue, wed, thu, fri, sat or sun.");
+                None
+            }
+            None => None,
+        };
+        let weeks = match params.get("weeks").map(|weeks| weeks.parse::<u64>()) {
+            None => default_weeks,
+            Some(Ok(weeks)) if (1..=MAX_WEEKDAY_WEEKS).contains(&weeks) => weeks,
+            Some(_) => {
+                params.error(format!(
+                    "weeks must be between 1 and {}.",
+                    MAX_WEEKDAY_WEEKS
+                ));
+                default_weeks
+            }
+        };
+        (weekday, weeks)
+    }
+
+    /**
+    The /api/typical API endpoint.
+
+    The 25th, 50th and 75th percentile of the readings in each `TYPICAL_BUCKET_MINUTES` of the
+    day, across the last `weeks` days that were `weekday`, for drawing a "usually between" band
+    behind the live readings. Today is never one of the days, it is the one being compared.
+    Buckets with too few readings are left out, see `typical_bands`.
+    */
+    fn typical(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let (weekday, weeks) = Self::weekday_params(&mut params, 8);
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(weekday)) = (name, weekday) else {
+            return Self::bad_request("name and weekday must both be provided.");
+        };
+
+        let today = uk_datetime_now().date_naive();
+        let days_since =
+            (7 + today.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+        let days_since = if days_since == 0 { 7 } else { days_since };
+        let to = today - Days::new(days_since as u64);
+        let from = to - Days::new((weeks - 1) * 7);
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let data = match SqliteDatabase::query_weekday(&connection, &name, weekday, from, to) {
+            Ok(data) => data,
+            Err(err) => return Self::database_error(err),
+        };
+        let readings = Self::pairs(data);
+
+        Self::ok_data(TypicalResponse {
+            weekday: weekday.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            bands: typical_bands(&readings, TYPICAL_BUCKET_MINUTES),
+        })
+    }
+
+    /// The POST /api/report API endpoint.
+    ///
+    /// Takes a JSON body of `{name, occupancy, note?}` from a user saying how busy a location
+    /// actually is, and stores it with the current time. Occupancy is clamped to 0 to 100 and
+    /// the note is cut off at `MAX_NOTE_LENGTH` characters. Each client can make `REPORT_LIMIT`
+    /// reports every `REPORT_WINDOW`, further ones get a 429.
+    fn report(&self, req: Request<Bytes>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let report: OccupancyReport = match serde_json::from_slice(req.body()) {
+            Ok(report) => report,
+            Err(_) => return Self::bad_request("Malformed Body. Required name, occupancy."),
+        };
+
+        if !self.locations.contains(&report.name) {
+            return Self::unknown_location(&report.name);
+        }
+
+        let occupancy = report.occupancy.clamp(0, 100) as u16;
+        let note = Self::clean_note(report.note.as_deref());
+
+        // Every connection has a peer address, clients without one would share a limit
+        let client =
+            rate_limit::client_ip(&req, self.peer).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        if let Some(wait) = self.report_limiter.check(client) {
+            return Self::too_many_requests("reports", wait);
+        }
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let time = uk_datetime_now().naive_local();
+        match SqliteDatabase::insert_report(
+            &connection,
+            &report.name,
+            time,
+            occupancy,
+            note.as_deref(),
+        ) {
+            Ok(()) => {
+                request_id::log(format_args!(
+                    "Report on {} from {}: {}",
+                    report.name, client, occupancy
+                ));
+                Self::ok_data(UserReport {
+                    time: time.format(ISO_FORMAT).to_string(),
+                    occupancy,
+                    note,
+                })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// Trims a note from a user and cuts it off at `MAX_NOTE_LENGTH` characters. Empty notes are
+    /// `None`.
+    fn clean_note(note: Option<&str>) -> Option<String> {
+        note.map(|note| {
+            note.trim()
+                .chars()
+                .take(MAX_NOTE_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|note| !note.is_empty())
+    }
+
+    /// The POST /api/feedback API endpoint.
+    ///
+    /// Takes a JSON body of `{name, date, model, rating, comment?}` rating a day of a model's
+    /// predictions `up` or `down`. The model has to be one of `PREDICTION_MODELS` and have
+    /// predictions for that day, so feedback can't be left on predictions we never served.
+    ///
+    /// Feedback from the same client on the same predictions within `FEEDBACK_COALESCE_WINDOW`
+    /// overwrites the previous one, so changing your mind or double tapping counts once.
+    fn feedback(&self, req: Request<Bytes>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let feedback: PredictionFeedback = match serde_json::from_slice(req.body()) {
+            Ok(feedback) => feedback,
+            Err(_) => {
+                return Self::bad_request(
+                    "Malformed Body. Required name, date, model, rating (up or down).",
+                )
+            }
+        };
+
+        if !self.locations.contains(&feedback.name) {
+            return Self::unknown_location(&feedback.name);
+        }
+        let Ok(date) = NaiveDate::from_str(&feedback.date) else {
+            return Self::bad_request("Malformed Date");
+        };
+        let Some(model) = PREDICTION_MODELS
+            .iter()
+            .find(|model| **model == feedback.model)
+        else {
+            return Self::bad_request(&unknown_model());
+        };
+        let comment = Self::clean_note(feedback.comment.as_deref());
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let predictions = format!("{}_prediction_{}", feedback.name, model);
+        match SqliteDatabase::query_last_time_on_day(&connection, &predictions, date) {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                return Self::bad_request(&format!(
+                    "There are no {} predictions for {}.",
+                    model, date
+                ))
+            }
+            Err(err) => return Self::database_error(err),
+        }
+
+        let client =
+            rate_limit::client_ip(&req, self.peer).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let key = (client, feedback.name.clone(), date, *model);
+        let time = uk_datetime_now().naive_local();
+        let rating = feedback.rating.as_str();
+
+        // Taking the write lock up front makes another request from the same client wait until
+        // this one is recorded, so they can't both insert. `recent_feedback` is only locked
+        // around looking it up and recording it, never while waiting on the database.
+        let transaction =
+            match Transaction::new_unchecked(&connection, TransactionBehavior::Immediate) {
+                Ok(transaction) => transaction,
+                Err(err) => return Self::database_error(err.into()),
+            };
+        let now = Instant::now();
+        let previous = {
+            let mut recent = self.recent_feedback.lock().unwrap();
+            recent.retain(|_, (given, _)| now - *given < FEEDBACK_COALESCE_WINDOW);
+            recent.get(&key).map(|(_, id)| *id)
+        };
+
+        let mut response = None;
+        if let Some(id) = previous {
+            match SqliteDatabase::update_feedback(
+                &connection,
+                &feedback.name,
+                id,
+                time,
+                rating,
+                comment.as_deref(),
+            ) {
+                Ok(true) => {
+                    response = Some(FeedbackResponse {
+                        id,
+                        coalesced: true,
+                    })
+                }
+                // The row is gone, so it is given again as new feedback
+                Ok(false) => (),
+                Err(err) => return Self::database_error(err),
+            }
+        }
+        let response = match response {
+            Some(response) => response,
+            None => match SqliteDatabase::insert_feedback(
+                &connection,
+                &feedback.name,
+                time,
+                date,
+                model,
+                rating,
+                comment.as_deref(),
+            ) {
+                Ok(id) => FeedbackResponse {
+                    id,
+                    coalesced: false,
+                },
+                Err(err) => return Self::database_error(err),
+            },
+        };
+
+        // Recorded before committing, so the next request can't get in before it is. Should the
+        // commit fail, the update to the row that isn't there starts over as new feedback.
+        self.recent_feedback
+            .lock()
+            .unwrap()
+            .insert(key, (now, response.id));
+        if let Err(err) = transaction.commit() {
+            return Self::database_error(err.into());
+        }
+        Self::ok_data(response)
+    }
+
+    /// The /admin/feedback API endpoint.
+    ///
+    /// Pages through the feedback on a location's predictions, newest first. `limit` rows are
+    /// returned (default `DEFAULT_FEEDBACK_PAGE`, at most `MAX_FEEDBACK_PAGE`) and `next` is the
+    /// `before` to pass for the next page, `null` on the last one.
+    fn feedback_page(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let before = match params.get("before").map(|before| before.parse::<i64>()) {
+            None => None,
+            Some(Ok(before)) => Some(before),
+            Some(Err(_)) => {
+                params.error("Malformed before");
+                None
+            }
+        };
+        let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+            None => DEFAULT_FEEDBACK_PAGE,
+            Some(Ok(limit)) if (1..=MAX_FEEDBACK_PAGE).contains(&limit) => limit,
+            Some(_) => {
+                params.error(format!(
+                    "limit must be between 1 and {}.",
+                    MAX_FEEDBACK_PAGE
+                ));
+                DEFAULT_FEEDBACK_PAGE
+            }
+        };
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::query_feedback_page(&connection, &name, before, limit) {
+            Ok(rows) => {
+                let next = match rows.last() {
+                    Some(row) if rows.len() == limit => Some(row.id),
+                    _ => None,
+                };
+                Self::ok_data(FeedbackPageResponse {
+                    feedback: rows.into_iter().map(FeedbackEntry::from).collect(),
+                    next,
+                })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The GET /api/report API endpoint.
+    ///
+    /// The reports users made on `date` (default today), oldest first. They are served apart from
+    /// the scraped readings so they can't be mistaken for them.
+    fn reports(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let date = params.optional_date("date");
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+        let date = date.unwrap_or_else(|| uk_datetime_now().date_naive());
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::query_reports_on_day(&connection, &name, date) {
+            Ok(reports) => Self::ok_data(ReportsResponse {
+                date: date.to_string(),
+                reports: reports
+                    .into_iter()
+                    .map(|(time, occupancy, note)| UserReport {
+                        time,
+                        occupancy,
+                        note,
+                    })
+                    .collect(),
+            }),
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /api/locations API endpoint.
+    ///
+    /// Every location in the `locations` table, with the metadata of its scraper. Locations that
+    /// aren't scraped any more are still listed, as their data can still be requested, with
+    /// nulls for the scraper's metadata.
+    fn locations_list(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+        match SqliteDatabase::query_locations(&connection) {
+            Ok(rows) => Self::ok_data(
+                rows.into_iter()
+                    .map(LocationEntry::from)
+                    .collect::<Vec<_>>(),
+            ),
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /api/locations/{name} API endpoint, describing a single location.
+    fn location(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let (_, path) = routes::split_version(req.uri().path());
+        let Some(name) =
+            routes::path_param(route.path, path).and_then(|name| self.sanitize_name(name))
+        else {
+            return Self::bad_request("Malformed Name");
+        };
+        match location_metadata(name) {
+            Some(metadata) => Self::ok_data(metadata),
+            None => Self::not_found(&format!("Unknown location '{}'.", name)),
+        }
+    }
+
+    /// The /api/peaks API endpoint.
+    ///
+    /// Returns the highest occupancy of every day from `from` to `to` (inclusive) along with the
+    /// time it occurred. Days without data are left out.
+    fn peaks(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let from = params.require_date("from");
+        let to = params.require_date("to");
+        if let (Some(from), Some(to)) = (from, to) {
+            params.check_range(
+                from.and_hms_opt(0, 0, 0).unwrap(),
+                to.and_hms_opt(23, 59, 59).unwrap(),
+                self.settings.max_query_span(),
+            );
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(from), Some(to)) = (name, from, to) else {
+            return Self::bad_request("name, from and to must all be provided.");
+        };
+        let name = name.as_str();
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::query_daily_peaks(&connection, name, from, to) {
+            Ok(peaks) => Self::ok_data(peaks.into_iter().map(DailyPeak::from).collect::<Vec<_>>()),
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /api/hourly API endpoint.
+    ///
+    /// The mean, min and max occupancy and number of readings of every hour from `from` to `to`
+    /// (inclusive), up to `MAX_HOURLY_DAYS` days. Hours without readings are left out.
+    fn hourly(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let from = params.require_date("from");
+        let to = params.require_date("to");
+        if let (Some(from), Some(to)) = (from, to) {
+            params.check_range(
+                from.and_hms_opt(0, 0, 0).unwrap(),
+                to.and_hms_opt(23, 59, 59).unwrap(),
+                chrono::Duration::days(MAX_HOURLY_DAYS),
+            );
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(from), Some(to)) = (name, from, to) else {
+            return Self::bad_request("name, from and to must all be provided.");
+        };
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::query_hourly(&connection, &name, from, to) {
+            Ok(rows) => Self::ok_data(rows.into_iter().map(HourlyEntry::from).collect::<Vec<_>>()),
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /api/coverage API endpoint.
+    ///
+    /// Lists every date from `from` to `to` (inclusive) with how many readings it has, whether
+    /// there are predictions for it and where readings are missing during opening hours, so
+    /// clients can tell which days have nothing to show. See `SqliteDatabase::find_gaps`, the hours
+    /// that haven't come yet aren't missing.
+    ///
+    /// Ranges that ended before today can be cached for `PAST_COVERAGE_MAX_AGE`, others only
+    /// until the next scrape.
+    fn coverage(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let from = params.require_date("from");
+        let to = params.require_date("to");
+        if let (Some(from), Some(to)) = (from, to) {
+            params.check_range(
+                from.and_hms_opt(0, 0, 0).unwrap(),
+                to.and_hms_opt(23, 59, 59).unwrap(),
+                self.settings.max_query_span(),
+            );
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(from), Some(to)) = (name, from, to) else {
+            return Self::bad_request("name, from and to must all be provided.");
+        };
+        let name = name.as_str();
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        let readings: HashMap<String, usize> =
+            match SqliteDatabase::query_daily_samples(&connection, name, from, to) {
+                Ok(counts) => counts.into_iter().collect(),
+                Err(err) => return Self::database_error(err),
+            };
+        let mut predicted: HashSet<String> = HashSet::new();
+        for model in PREDICTION_MODELS {
+            let table = format!("{}_prediction_{}", name, model);
+            match SqliteDatabase::query_daily_counts(&connection, &table, from, to) {
+                Ok(counts) => predicted.extend(counts.into_iter().map(|(date, _)| date)),
+                Err(err) => return Self::database_error(err),
+            }
+        }
+
+        let end = to.and_hms_opt(23, 59, 59).unwrap();
+        let end = end.min(uk_datetime_now().naive_local());
+        let start = from.and_hms_opt(0, 0, 0).unwrap();
+        let mut gaps: HashMap<String, Vec<CoverageGap>> = HashMap::new();
+        match SqliteDatabase::find_gaps(&connection, name, start, end, SCRAPE_INTERVAL) {
+            Ok(found) => {
+                for gap in found {
+                    let date = gap.from.date().to_string();
+                    gaps.entry(date).or_default().push(gap.into());
+                }
+            }
+            Err(err) => return Self::database_error(err),
+        }
+
+        let days: Vec<DayCoverage> = from
+            .iter_days()
+            .take_while(|date| *date <= to)
+            .map(|date| {
+                let date = date.to_string();
+                let count = readings.get(&date).copied().unwrap_or(0);
+                let predictions = predicted.contains(&date);
+                let gaps = gaps.remove(&date).unwrap_or_default();
+                DayCoverage::new(date, count, predictions, gaps)
+            })
+            .collect();
+
+        let max_age = if to < uk_datetime_now().date_naive() {
+            PAST_COVERAGE_MAX_AGE
+        } else {
+            SCRAPE_INTERVAL.as_secs()
+        };
+        Self::with_max_age(Self::ok_data(days), max_age)
+    }
+
+    /// The /api/best-times API endpoint.
+    ///
+    /// Suggests when to go on `date` (today by default): the quietest and busiest 30 minute
+    /// windows of a model's predictions (`knn` by default, `lstm` or `gb`) within that day's
+    /// opening hours.
+    ///
+    /// Will return a 204 if there are no predictions within opening hours, such as on a closed day.
+    fn best_times(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let date = params
+            .optional_date("date")
+            .unwrap_or_else(|| uk_datetime_now().date_naive());
+        let suffix = match params.get("model") {
+            None => Some("_prediction_knn"),
+            Some(model) => PredictionModel::from_str(model)
+                .ok()
+                .and_then(|model| model.table_suffix()),
+        };
+        if suffix.is_none() {
+            params.error(unknown_model());
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(suffix)) = (name, suffix) else {
+            return Self::bad_request("name must be provided.");
+        };
+        let name = name.as_str();
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        // Without a schedule the whole day is considered
+        let (opening, closing) = match Self::get_schedule::<SqliteDatabase>(&connection, name, date)
+        {
+            Ok(Some((schedule, _))) => {
+                let daily = schedule.get_timings()[date.weekday().num_days_from_monday() as usize];
+                match daily.hours_on(date) {
+                    Some(hours) => hours,
+                    None => return Self::no_data(),
+                }
+            }
+            Ok(None) => (
+                date.and_hms_opt(0, 0, 0).unwrap(),
+                date.and_hms_opt(23, 59, 59).unwrap(),
+            ),
+            Err(err) => return Self::database_error(err),
+        };
+
+        let predicted = match SqliteDatabase::query_single_day(
+            &connection,
+            &format!("{}{}", name, suffix),
+            date,
+        ) {
+            Ok(data) => Self::pairs(data),
+            Err(err) => return Self::database_error(err),
+        };
+
+        match find_best_times(&predicted, opening, closing, chrono::Duration::minutes(30)) {
+            Some(best) => Self::ok_data(best),
+            None => Self::no_data(),
+        }
+    }
+
+    /// The /api/export API endpoint.
+    ///
+    /// Streams every reading of a location as newline delimited JSON or CSV, oldest first, or
+    /// its stored schedules with `table=schedule`. See `SqliteDatabase::export`, the table is
+    /// never held in memory all at once.
+    ///
+    /// Exports are heavy, so without the admin key only one can be started every
+    /// `PUBLIC_EXPORT_INTERVAL` across all clients. Everyone else gets a 429.
+    fn export(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let format = match params.get("format") {
+            None | Some("ndjson") => ExportFormat::Ndjson,
+            Some("csv") => ExportFormat::Csv,
+            Some(_) => {
+                params.error("Malformed format. Expected ndjson or csv.");
+                ExportFormat::Ndjson
+            }
+        };
+        let schedule = match params.get("table") {
+            None | Some("readings") => false,
+            Some("schedule") => true,
+            Some(_) => {
+                params.error("Malformed table. Expected readings or schedule.");
+                false
+            }
+        };
+        if let Err(errors) = params.finish() {
+            return Self::boxed(Self::invalid_params(&errors));
+        }
+        let Some(name) = name else {
+            return Self::boxed(Self::bad_request("name not provided."));
+        };
+        let name = name.as_str();
+
+        if !auth::is_authorized(&req, self.settings.admin_key()) {
+            if let Some(wait) = self.claim_public_export() {
+                return Self::boxed(Self::too_many_requests("exports", wait));
+            }
+        }
+
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::boxed(Self::connection_error(err)),
+        };
+
+        let table = name.to_string();
+        let body = body::stream_blocking(move |send| {
+            let mut writer = ChunkWriter::new(send);
+            let exported = match schedule {
+                false => SqliteDatabase::export(&connection, &table, &mut writer, format),
+                true => SqliteDatabase::export_schedule(&connection, &table, &mut writer, format),
+            };
+            // A client that went away isn't worth logging
+            if let (Err(err), false) = (exported, writer.closed()) {
+                request_id::log(format_args!("Export of {} failed.\n{}", table, err));
+            }
+        });
+
+        let (content_type, extension) = match format {
+            ExportFormat::Ndjson => (NDJSON, "ndjson"),
+            ExportFormat::Csv => (CSV, "csv"),
+        };
+        let file_name = match schedule {
+            false => format!("{}.{}", name, extension),
+            true => format!("{}_schedule.{}", name, extension),
+        };
+        let res = Self::response(StatusCode::OK, Some(content_type))
+            .header(
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            )
+            .body(body)
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Claims the slot for an export without the admin key.
+    ///
+    /// Returns how long to wait if the last one was started less than `PUBLIC_EXPORT_INTERVAL`
+    /// ago.
+    fn claim_public_export(&self) -> Option<std::time::Duration> {
+        let mut last = self.last_public_export.lock().unwrap();
+        let now = Instant::now();
+        if let Some(last) = *last {
+            let elapsed = now - last;
+            if elapsed < PUBLIC_EXPORT_INTERVAL {
+                return Some(PUBLIC_EXPORT_INTERVAL - elapsed);
+            }
+        }
+        *last = Some(now);
+        None
+    }
+
+    /// Reads the whole request body, up to `MAX_BODY_SIZE` bytes.
+    ///
+    /// Returns `None` if the body could not be read or is too large.
+    async fn read_body(body: Incoming) -> Option<Bytes> {
+        match Limited::new(body, MAX_BODY_SIZE).collect().await {
+            Ok(body) => Some(body.to_bytes()),
+            Err(_) => None,
+        }
+    }
+
+    /// Runs the handler for `endpoint`.
+    ///
+    /// The handlers query SQLite synchronously, so this has to be called on a blocking thread.
+    fn dispatch(
+        &self,
+        route: &Route,
+        req: Request<Bytes>,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
+        if let Err(errors) = strict_check(req.uri().query(), route) {
+            return Self::boxed(Self::invalid_params(&errors));
+        }
+        if let Some(name) = self.requested_unknown_location(req.uri(), route) {
+            return Self::boxed(Self::unknown_location(&name));
+        }
+        let res = match route.endpoint {
+            Endpoint::Export => return self.export(req, route),
+            Endpoint::Day => return self.day_data(req, route),
+            Endpoint::From => self.from_last(req, route),
+            Endpoint::Compare => self.compare(req, route),
+            Endpoint::Summary => self.summary(req, route),
+            Endpoint::Latest => self.latest(req, route),
+            Endpoint::Overview => self.overview(),
+            Endpoint::Status => self.status_page(),
+            Endpoint::Health => self.health(),
+            // Answered in `handle`, it has to wait without holding a blocking thread
+            Endpoint::Wait => Self::server_error("/api/wait can't be dispatched."),
+            Endpoint::Backup => Self::server_error("/admin/backup can't be dispatched."),
+            Endpoint::Maintenance => Self::server_error("/admin/maintenance can't be dispatched."),
+            Endpoint::ScraperStatus => self.scraper_status(req),
+            Endpoint::ScheduleIcs => self.schedule_ics(req, route),
+            Endpoint::Locations => self.locations_list(),
+            Endpoint::Location => self.location(req, route),
+            Endpoint::Report => self.report(req),
+            Endpoint::Reports => self.reports(req, route),
+            Endpoint::Feedback => self.feedback(req),
+            Endpoint::FeedbackPage => self.feedback_page(req, route),
+            Endpoint::Peaks => self.peaks(req, route),
+            Endpoint::Hourly => self.hourly(req, route),
+            Endpoint::Coverage => self.coverage(req, route),
+            Endpoint::Weekday => self.weekday(req, route),
+            Endpoint::Accuracy => self.accuracy(req, route),
+            Endpoint::BestTimes => self.best_times(req, route),
+            Endpoint::Typical => self.typical(req, route),
+            Endpoint::Repredict => self.repredict(req, route),
+            Endpoint::CorrectOccupancy => self.correct_occupancy(req),
+            Endpoint::DeleteData => self.delete_data(req, route),
+            Endpoint::MarkAnomaly => self.anomaly(req, route, true),
+            Endpoint::UnmarkAnomaly => self.anomaly(req, route, false),
+        };
+        Self::boxed(res)
+    }
+
+    /// The /admin/occupancy API endpoint.
+    ///
+    /// Takes a JSON body of `{name, time, occupancy}` and inserts or overwrites the reading at
+    /// exactly that time. The previous value, if any, is logged and sent back so corrections can
+    /// be audited.
+    fn correct_occupancy(
+        &self,
+        req: Request<Bytes>,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let correction: OccupancyCorrection = match serde_json::from_slice(req.body()) {
+            Ok(correction) => correction,
+            Err(_) => return Self::bad_request("Malformed Body. Required name, time, occupancy."),
+        };
+
+        if !self.locations.contains(&correction.name) {
+            return Self::unknown_location(&correction.name);
+        }
+
+        let range = occupancy_range(&correction.name);
+        if !range.contains(&correction.occupancy) {
+            return Self::bad_request(&format!(
+                "occupancy must be between {} and {}.",
+                range.start(),
+                range.end()
+            ));
+        }
+
+        let time = match NaiveDateTime::from_str(&correction.time) {
+            Ok(time) => time,
+            Err(_) => return Self::bad_request("Malformed Time"),
+        };
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::upsert_occupancy(
+            &connection,
+            &correction.name,
+            time,
+            correction.occupancy,
+        ) {
+            Ok(previous) => {
+                if let Err(err) =
+                    SqliteDatabase::refresh_hourly(&connection, &correction.name, time, time)
+                {
+                    return Self::database_error(err);
+                }
+                request_id::log(format_args!(
+                    "Admin correction on {} at {}: {:?} -> {}",
+                    correction.name,
+                    time.format(ISO_FORMAT),
+                    previous,
+                    correction.occupancy
+                ));
+                Self::ok_data(CorrectionResponse { previous })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /admin/repredict API endpoint.
+    ///
+    /// Queues a prediction run for the next 7 days on the scraper side, ignoring when the
+    /// predictions were last updated. `model` is one of knn, lstm or all and defaults to all.
+    /// GB predictions aren't made by the scraper, so gb is refused rather than queued for nothing.
+    ///
+    /// Reports whether the job was queued. It won't be if one is already pending or running for
+    /// that name.
+    fn repredict(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let model = match params.get("model").map(PredictionModel::from_str) {
+            None => PredictionModel::All,
+            Some(Ok(PredictionModel::Gb)) => {
+                params.error("gb predictions aren't made here, so they can't be regenerated.");
+                PredictionModel::All
+            }
+            Some(Ok(model)) => model,
+            Some(Err(err)) => {
+                params.error(err);
+                PredictionModel::All
+            }
+        };
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+
+        match self.repredict.request(&name, model) {
+            Ok(queued) => Self::ok_data(RepredictResponse { queued }),
+            Err(err) => Self::bad_request(&err),
+        }
+    }
+
+    /// The POST /admin/backup API endpoint.
+    ///
+    /// Backs up the database straight away, see `Backups::backup`, and returns the path and
+    /// size of the file. 404 if backups aren't set up with `--backup-dir`.
+    async fn backup(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let Some(backups) = self.settings.backups().cloned() else {
+            return Self::not_found("Backups are not set up, start with --backup-dir.");
+        };
+        let server = self.clone();
+        let id = request_id::current().unwrap_or_default();
+        let made = tokio::task::spawn_blocking(move || {
+            request_id::sync_scope(id, || server.make_backup(&backups))
+        });
+        match made.await {
+            Ok(res) => res,
+            Err(err) => Self::server_error(&format!("Handler failed: {}", err)),
+        }
+    }
+
+    /// Makes the backup for /admin/backup, see `backup`.
+    fn make_backup(&self, backups: &Backups) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+        match backups.backup(&connection) {
+            Ok(file) => {
+                request_id::log(format_args!(
+                    "Admin backup to {}, {} bytes",
+                    file.path.display(),
+                    file.size
+                ));
+                Self::ok_data(BackupResponse {
+                    path: file.path.display().to_string(),
+                    size: file.size,
+                })
+            }
+            Err(err) => Self::server_error(&err),
+        }
+    }
+
+    /// The POST /admin/maintenance API endpoint.
+    ///
+    /// Runs the weekly maintenance straight away, see `Maintenance::maintain`, and returns what
+    /// it did. 409 if a backup is being made.
+    async fn maintenance(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let server = self.clone();
+        let id = request_id::current().unwrap_or_default();
+        let ran = tokio::task::spawn_blocking(move || {
+            request_id::sync_scope(id, || server.run_maintenance())
+        });
+        match ran.await {
+            Ok(res) => res,
+            Err(err) => Self::server_error(&format!("Handler failed: {}", err)),
+        }
+    }
+
+    /// Runs the maintenance for /admin/maintenance, see `maintenance`.
+    fn run_maintenance(&self) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+        match self.settings.maintenance().maintain(&connection) {
+            Ok(Some(report)) => Self::ok_data(MaintenanceResponse {
+                vacuum: report.vacuum.as_str(),
+                reclaimed: report.reclaimed,
+                duration_ms: report.duration.as_millis() as u64,
+            }),
+            Ok(None) => {
+                let res = Self::response(StatusCode::CONFLICT, Some(JSON))
+                    .body(Full::new(Self::error_body(
+                        "A backup is being made. Try again once it is done.",
+                    )))
+                    .unwrap();
+                Ok(res)
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /admin/data API endpoint.
+    ///
+    /// Deletes the raw readings between `from` and `to` (inclusive) for `name`. The prediction
+    /// tables are left alone. Ranges longer than the configured limit are refused to avoid wiping
+    /// out more than intended. A `date` instead deletes that whole day, see `delete_day`.
+    fn delete_data(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let date = params.optional_date("date");
+        let predictions = match params.get("predictions") {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(_) => {
+                params.error("Malformed predictions. Expected true or false.");
+                false
+            }
+        };
+        let from = params.optional_datetime("from");
+        let to = params.optional_datetime("to");
+        if let (Some(from), Some(to)) = (from, to) {
+            if to < from {
+                params.error("from must not be after to.");
+            }
+            let max_span = self.settings.admin_delete_max_span();
+            if to - from > max_span {
+                params.error(format!(
+                    "Range is longer than the limit of {} hours.",
+                    max_span.num_hours()
+                ));
+            }
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let Some(name) = name else {
+            return Self::bad_request("name not provided.");
+        };
+        if !self.locations.contains(&name) {
+            return Self::unknown_location(&name);
+        }
+
+        if let Some(date) = date {
+            return self.delete_day(&name, date, predictions);
+        }
+
+        let (Some(from), Some(to)) = (from, to) else {
+            return Self::bad_request("from and to, or date, must be provided.");
+        };
+        let name = name.as_str();
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+        Self::delete_readings::<SqliteDatabase>(&connection, name, from, to)
+    }
+
+    /// Deletes the readings of `name` from `from` to `to` (inclusive) for /admin/data, once the
+    /// range has been checked.
+    fn delete_readings<D: Database>(
+        connection: &D::Connection,
+        name: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        match D::delete_range(connection, name, from, to) {
+            Ok(deleted) => {
+                request_id::log(format_args!(
+                    "Admin deleted {} rows from {} between {} and {}",
+                    deleted,
+                    name,
+                    from.format(ISO_FORMAT),
+                    to.format(ISO_FORMAT)
+                ));
+                Self::ok_data(DeleteResponse {
+                    deleted,
+                    tables: BTreeMap::from([(name.to_string(), deleted)]),
+                })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// The /admin/anomaly API endpoint.
+    ///
+    /// Marks the readings of `name` between `from` and `to` (inclusive) as anomalies with a POST,
+    /// or unmarks them with a DELETE, see `SqliteDatabase::mark_anomaly`. Marked readings are kept
+    /// and still in /api/day, but the predictions aren't made from them. The same range limit as
+    /// the public endpoints applies.
+    fn anomaly(
+        &self,
+        req: Request<Bytes>,
+        route: &Route,
+        is_anomaly: bool,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut params = QueryParams::parse(req.uri(), route, &self.name_sanitizer);
+        let name = params.require_name();
+        let from = params.require_datetime("from");
+        let to = params.require_datetime("to");
+        if let (Some(from), Some(to)) = (from, to) {
+            params.check_range(from, to, self.settings.max_query_span());
+        }
+        if let Err(errors) = params.finish() {
+            return Self::invalid_params(&errors);
+        }
+        let (Some(name), Some(from), Some(to)) = (name, from, to) else {
+            return Self::bad_request("name, from and to must all be provided.");
+        };
+        if !self.locations.contains(&name) {
+            return Self::unknown_location(&name);
+        }
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::mark_anomaly(&connection, &name, from, to, is_anomaly) {
+            Ok(readings) => {
+                request_id::log(format_args!(
+                    "Admin {} {} readings of {} between {} and {} as anomalies",
+                    if is_anomaly { "marked" } else { "unmarked" },
+                    readings,
+                    name,
+                    from.format(ISO_FORMAT),
+                    to.format(ISO_FORMAT)
+                ));
+                Self::ok_data(AnomalyResponse {
+                    readings,
+                    is_anomaly,
+                })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// Deletes all of `date` for /admin/data, from the prediction tables of `name` too if
+    /// `predictions` is true.
+    ///
+    /// Refused if a day is longer than the configured limit, the same as a range would be.
+    fn delete_day(
+        &self,
+        name: &str,
+        date: NaiveDate,
+        include_predictions: bool,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let from = date.and_hms_opt(0, 0, 0).unwrap();
+        let to = date.and_hms_opt(23, 59, 59).unwrap();
+        let max_span = self.settings.admin_delete_max_span();
+        if to - from > max_span {
+            return Self::bad_request(&format!(
+                "A day is longer than the limit of {} hours.",
+                max_span.num_hours()
+            ));
+        }
+
+        let connection = match self.get_write_connection() {
+            Ok(conn) => conn,
+            Err(err) => return Self::connection_error(err),
+        };
+
+        match SqliteDatabase::delete_day(&connection, name, date, include_predictions) {
+            Ok(tables) => {
+                if let Err(err) = SqliteDatabase::refresh_hourly(&connection, name, from, to) {
+                    return Self::database_error(err);
+                }
+                let deleted = tables.iter().map(|(_, count)| count).sum();
+                request_id::log(format_args!(
+                    "Admin deleted {} on {}: {}",
+                    name,
+                    date,
+                    tables
+                        .iter()
+                        .map(|(table, count)| format!("{} rows from {}", count, table))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ));
+                Self::ok_data(DeleteResponse {
+                    deleted,
+                    tables: tables.into_iter().collect(),
+                })
+            }
+            Err(err) => Self::database_error(err),
+        }
+    }
+
+    /// Drop the body of a response for a HEAD request.
+    ///
+    /// The status and headers are kept as they are, and Content-Length is set to the length the
+    /// body would have had so the response matches what a GET would have returned.
+    fn strip_body(res: Response<ServerBody>) -> Response<ServerBody> {
+        let (mut parts, body) = res.into_parts();
+        if parts.status != StatusCode::NO_CONTENT {
+            if let Some(length) = body.size_hint().exact() {
+                parts
+                    .headers
+                    .insert(CONTENT_LENGTH, HeaderValue::from(length));
+            }
+        }
+        Response::from_parts(parts, body::full(Full::new(Bytes::new())))
+    }
+
+    /// Authorizes, routes and runs a request.
+    async fn handle(self, req: Request<Incoming>) -> Result<Response<ServerBody>, hyper::Error> {
+        let (_, path) = routes::split_version(req.uri().path());
+        // Every request under /admin is checked before it is routed any further
+        if is_admin_path(path) && !auth::is_authorized(&req, self.settings.admin_key()) {
+            return Self::boxed(Self::unauthorized());
+        }
+
+        let route = match routes::route(req.method(), path) {
+            Routing::Found(route) => route,
+            Routing::MethodNotAllowed(allowed) => {
+                return Self::boxed(Self::method_not_allowed(&allowed))
+            }
+            Routing::Options(allowed) => return Self::boxed(Self::options(&allowed)),
+            Routing::NotFound => return Self::boxed(Self::unknown_route()),
+        };
+
+        // Waiting is the whole point, so it can't be under the request timeout
+        if route.endpoint == Endpoint::Wait {
+            return Self::boxed(self.wait(req.uri(), route).await);
+        }
+        // Neither can a backup or maintenance, copying or vacuuming a large database takes a while
+        if route.endpoint == Endpoint::Backup {
+            return Self::boxed(self.backup().await);
+        }
+        if route.endpoint == Endpoint::Maintenance {
+            return Self::boxed(self.maintenance().await);
+        }
+
+        // The body is read here, while waiting on the client doesn't tie up a thread
+        let (parts, body) = req.into_parts();
+        let Some(body) = Self::read_body(body).await else {
+            return Self::boxed(Self::bad_request("Could not read body."));
+        };
+        let req = Request::from_parts(parts, body);
+
+        // The handlers query SQLite synchronously, so they run on a blocking thread where
+        // they can't hold up other connections or the timer. If one takes too long we stop
+        // waiting for it, but it still runs to completion, so any pooled connection it holds is
+        // returned in a good state rather than dropped halfway through a query.
+        let timeout = self.settings.request_timeout();
+        let id = request_id::current().unwrap_or_default();
+        let handler = tokio::task::spawn_blocking(move || {
+            request_id::sync_scope(id, || self.dispatch(route, req))
+        });
+        match tokio::time::timeout(timeout, handler).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(err)) => Self::boxed(Self::server_error(&format!("Handler failed: {}", err))),
+            Err(_) => {
+                request_id::log(format_args!(
+                    "Request to {} timed out after {:?}",
+                    route.path, timeout
+                ));
+                Self::boxed(Self::timed_out())
+            }
+        }
+    }
+
+    /// Tag a response with the API version the request was made against and its request ID.
+    fn tagged(
+        res: Result<Response<ServerBody>, hyper::Error>,
+        version: ApiVersion,
+        id: &str,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
+        res.map(|mut res| {
+            let headers = res.headers_mut();
+            headers.insert(
+                routes::API_VERSION_HEADER,
+                HeaderValue::from_static(version.as_str()),
+            );
+            if let Ok(id) = HeaderValue::from_str(id) {
+                headers.insert(request_id::REQUEST_ID_HEADER, id);
+            }
+            res
+        })
+    }
+
+    /// Add a Last-Modified header to a response, if there is a time for one.
+    fn with_last_modified(
+        res: Result<Response<Full<Bytes>>, hyper::Error>,
+        last_modified: Option<DateTime<Utc>>,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        res.map(|mut res| {
+            if let Some(value) =
+                last_modified.and_then(|time| HeaderValue::from_str(&format_http_date(time)).ok())
+            {
+                res.headers_mut().insert(LAST_MODIFIED, value);
+            }
+            res
+        })
+    }
+
+    /// Add a Cache-Control header letting anyone cache the response for `max_age` seconds.
+    fn with_max_age(
+        res: Result<Response<Full<Bytes>>, hyper::Error>,
+        max_age: u64,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        res.map(|mut res| {
+            res.headers_mut().insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&format!("public, max-age={}", max_age)).unwrap(),
+            );
+            res
+        })
+    }
+
+    /// Box the body of a response into a `ServerBody`.
+    fn boxed(
+        res: Result<Response<Full<Bytes>>, hyper::Error>,
+    ) -> Result<Response<ServerBody>, hyper::Error> {
+        res.map(|res| res.map(body::full))
+    }
+
+    /// Start a response with `status` and the headers every response carries.
+    ///
+    /// `content_type` is the type of the body, `None` for responses that have no body.
+    fn response(status: StatusCode, content_type: Option<&'static str>) -> Builder {
+        let builder = Response::builder()
+            .status(status)
+            .header(SERVER, SERVER_NAME);
+        match content_type {
+            Some(content_type) => builder.header(CONTENT_TYPE, content_type),
+            None => builder,
+        }
+    }
+
+    /// Return a 200 OK response with the data provided.
+    fn ok_data<T: Serialize>(body: T) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let data = serde_json::to_string(&body).unwrap();
+        let res = Self::response(StatusCode::OK, Some(JSON))
+            .body(Full::new(Bytes::from(data)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// The body of an error response, `{"error": message}` with the request ID added.
+    ///
+    /// Messages often carry database errors, which can quote table names, so they are always
+    /// serialized rather than pasted into the JSON.
+    fn error_body(message: &str) -> Bytes {
+        Self::json_error_body(&ErrorMessage { error: message })
+    }
+
+    /// Serializes a structured error body with the request ID added.
+    fn json_error_body<T: Serialize>(body: &T) -> Bytes {
+        let mut body = serde_json::to_value(body).unwrap();
+        if let (Some(object), Some(id)) = (body.as_object_mut(), request_id::current()) {
+            object.insert("request_id".to_string(), serde_json::Value::String(id));
+        }
+        Bytes::from(body.to_string())
+    }
+
+    /// Return a 500 Internal Server Error response with the message provided.
+    fn server_error(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::INTERNAL_SERVER_ERROR, Some(JSON))
+            .body(Full::new(Self::error_body(message)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return the response for a connection that could not be taken from the pool.
+    ///
+    /// A busy pool is a 503 the client can retry after a moment, anything else is a 500.
+    fn connection_error(err: PoolError) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        match err {
+            PoolError::Exhausted => {
+                request_id::log(format_args!("Connection pool exhausted."));
+                let res = Self::response(StatusCode::SERVICE_UNAVAILABLE, Some(JSON))
+                    .header(RETRY_AFTER, POOL_RETRY_AFTER_SECS)
+                    .body(Full::new(Self::error_body(
+                        "The server is busy. Try again shortly.",
+                    )))
+                    .unwrap();
+                Ok(res)
+            }
+            PoolError::Failed(err) => {
+                Self::server_error(&format!("Could not get connection - Server.\n{}", err))
+            }
+        }
+    }
+
+    /**
+    Return the response for a failed database call.
+
+    The status is the error's, see `DatabaseError::status`. A database that stayed locked gets
+    the same 503 as an exhausted pool, with the same Retry-After.
+    */
+    fn database_error(err: DatabaseError) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let mut res = Self::response(err.status(), Some(JSON));
+        let message = match err {
+            DatabaseError::Busy => {
+                request_id::log(format_args!("The database stayed locked."));
+                res = res.header(RETRY_AFTER, POOL_RETRY_AFTER_SECS);
+                "The server is busy. Try again shortly.".to_string()
+            }
+            err => err.to_string(),
+        };
+        Ok(res.body(Full::new(Self::error_body(&message))).unwrap())
+    }
+
+    /// Return the 404 for a location that isn't in the `locations` table.
+    fn unknown_location(name: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        Self::not_found(&format!("Unknown location '{}'.", name))
+    }
+
+    /// Return a 404 Not Found response with the message provided.
+    fn not_found(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::NOT_FOUND, Some(JSON))
+            .body(Full::new(Self::error_body(message)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 404 Not Found response listing the public endpoints and their parameters.
+    fn unknown_route() -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let body = Self::json_error_body(&UnknownRoute {
+            error: "Not Found",
+            routes: routes::public_routes(),
+        });
+        let res = Self::response(StatusCode::NOT_FOUND, Some(JSON))
+            .body(Full::new(body))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 405 Method Not Allowed response with an Allow header listing `allowed`.
+    ///
+    /// The methods are in the body as well, so it is clear the path itself exists.
+    fn method_not_allowed(allowed: &[Method]) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let allowed: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+        let body = Self::json_error_body(&MethodNotAllowed {
+            error: "Method Not Allowed",
+            allowed: &allowed,
+        });
+        let res = Self::response(StatusCode::METHOD_NOT_ALLOWED, Some(JSON))
+            .header(ALLOW, allowed.join(", "))
+            .body(Full::new(body))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return the 204 No Content response to an OPTIONS request, with an Allow header listing
+    /// `allowed`.
+    fn options(allowed: &[Method]) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let allowed: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+        let res = Self::response(StatusCode::NO_CONTENT, None)
+            .header(ALLOW, allowed.join(", "))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 400 Bad Request response with the message provided.
+    fn bad_request(message: &str) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::BAD_REQUEST, Some(JSON))
+            .body(Full::new(Self::error_body(message)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 400 Bad Request response listing every problem with the parameters.
+    fn invalid_params(errors: &ParamErrors) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::BAD_REQUEST, Some(JSON))
+            .body(Full::new(Self::json_error_body(errors)))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 401 Unauthorized response.
+    fn unauthorized() -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::UNAUTHORIZED, Some(JSON))
+            .body(Full::new(Self::error_body(&format!(
+                "Missing or invalid {} header.",
+                auth::API_KEY_HEADER
+            ))))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 503 Service Unavailable response for a request that took too long.
+    fn timed_out() -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::SERVICE_UNAVAILABLE, Some(JSON))
+            .body(Full::new(Self::error_body(
+                "Request timed out. Try again later or ask for less data.",
+            )))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 429 Too Many Requests response, telling the client to retry after `wait`.
+    ///
+    /// `what` names what there were too many of, such as "exports".
+    fn too_many_requests(
+        what: &str,
+        wait: std::time::Duration,
+    ) -> Result<Response<Full<Bytes>>, hyper::Error> {
+        // Round up so the client never retries too early
+        let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        let res = Self::response(StatusCode::TOO_MANY_REQUESTS, Some(JSON))
+            .header(RETRY_AFTER, seconds)
+            .body(Full::new(Self::error_body(&format!(
+                "Too many {}. Try again in {} seconds.",
+                what, seconds
+            ))))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 304 Not Modified response.
+    fn not_modified() -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::NOT_MODIFIED, None)
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        Ok(res)
+    }
+
+    /// Return a 204 No Content response.
+    fn no_data() -> Result<Response<Full<Bytes>>, hyper::Error> {
+        let res = Self::response(StatusCode::NO_CONTENT, None)
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        Ok(res)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorMessage<'a> {
+    error: &'a str,
+}
+
+#[derive(Serialize)]
+struct UnknownRoute {
+    error: &'static str,
+    routes: Vec<&'static Route>,
+}
+
+#[derive(Serialize)]
+struct MethodNotAllowed<'a> {
+    error: &'static str,
+    allowed: &'a [&'a str],
+}
+
+#[derive(Serialize)]
+struct CompareResponse {
+    pairs: Vec<ComparedPoint>,
+    #[serde(flatten)]
+    metrics: ErrorMetrics,
+}
+
+#[derive(Serialize)]
+struct AccuracyResponse {
+    days: Vec<DayMetrics>,
+    overall: ErrorMetrics,
+}
+
+#[derive(Serialize)]
+struct RepredictResponse {
+    queued: bool,
+}
+
+#[derive(Deserialize)]
+struct OccupancyCorrection {
+    name: String,
+    time: String,
+    occupancy: u16,
+}
+
+#[derive(Deserialize)]
+struct OccupancyReport {
+    name: String,
+    occupancy: i64,
+    note: Option<String>,
+}
+
+/// A report a user made on how busy a location is.
+#[derive(Serialize)]
+struct UserReport {
+    time: String,
+    occupancy: u16,
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReportsResponse {
+    date: String,
+    reports: Vec<UserReport>,
+}
+
+/// A thumbs up or down on predictions.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Down => "down",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PredictionFeedback {
+    name: String,
+    date: String,
+    model: String,
+    rating: Rating,
+    comment: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FeedbackResponse {
+    id: i64,
+    /// Whether it replaced earlier feedback from the same client.
+    coalesced: bool,
+}
+
+#[derive(Serialize)]
+struct FeedbackEntry {
+    id: i64,
+    time: String,
+    date: String,
+    model: String,
+    rating: String,
+    comment: Option<String>,
+}
+
+impl From<FeedbackRow> for FeedbackEntry {
+    fn from(row: FeedbackRow) -> Self {
+        Self {
+            id: row.id,
+            time: row.time,
+            date: row.date,
+            model: row.model,
+            rating: row.rating,
+            comment: row.comment,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LocationEntry {
+    name: String,
+    display_name: String,
+    /// When the location was first set up.
+    created_at: String,
+    source_url: Option<&'static str>,
+    capacity: Option<Capacity>,
+    scrape_interval_seconds: Option<u64>,
+}
+
+impl From<LocationRow> for LocationEntry {
+    fn from(row: LocationRow) -> Self {
+        let metadata = location_metadata(&row.name);
+        Self {
+            source_url: metadata.as_ref().map(|metadata| metadata.source_url),
+            capacity: metadata.as_ref().map(|metadata| metadata.capacity),
+            scrape_interval_seconds: metadata.map(|metadata| metadata.scrape_interval_seconds),
+            name: row.name,
+            display_name: row.display_name,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HourlyEntry {
+    date: String,
+    hour: u8,
+    mean: f64,
+    min: u16,
+    max: u16,
+    samples: usize,
+}
+
+impl From<HourlyRow> for HourlyEntry {
+    fn from(row: HourlyRow) -> Self {
+        Self {
+            date: row.date,
+            hour: row.hour,
+            mean: row.mean,
+            min: row.min,
+            max: row.max,
+            samples: row.samples,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FeedbackPageResponse {
+    feedback: Vec<FeedbackEntry>,
+    next: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    database: DatabaseHealth,
+    scraper: Vec<ScraperHealth>,
+    connections: ConnectionStats,
+    pool: PoolHealth,
+}
+
+#[derive(Serialize)]
+struct PoolHealth {
+    in_use: u32,
+    idle: u32,
+    max_size: u32,
+    /// Since startup, as are `waits` and `timeouts`.
+    checkouts: u64,
+    /// Checkouts that found no idle connection.
+    waits: u64,
+    /// Checkouts that gave up waiting, which for a request is a 503.
+    timeouts: u64,
+}
+
+#[derive(Serialize)]
+struct ScraperHealth {
+    name: &'static str,
+    /// When it was last scraped and stored without any errors.
+    last_success: Option<String>,
+    consecutive_failures: u32,
+}
+
+#[derive(Serialize)]
+struct DatabaseHealth {
+    /// ok, damaged, or unavailable if it couldn't be checked right now.
+    status: &'static str,
+    problems: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ConnectionStats {
+    in_flight: usize,
+    limit: usize,
+}
+
+#[derive(Serialize)]
+struct WeekdayResponse {
+    weekday: String,
+    /// The readings of each day, keyed by date.
+    days: BTreeMap<String, Vec<OccupancyReading>>,
+}
+
+#[derive(Serialize)]
+struct TypicalResponse {
+    weekday: String,
+    /// The first and last of the days the bands are computed from.
+    from: String,
+    to: String,
+    bands: Vec<TypicalBand>,
+}
+
+#[derive(Serialize)]
+struct CorrectionResponse {
+    previous: Option<u16>,
+}
+
+#[derive(Serialize)]
+struct DeleteResponse {
+    /// The total over all tables.
+    deleted: usize,
+    /// How many rows were deleted from each table.
+    tables: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct AnomalyResponse {
+    /// How many readings were in the range.
+    readings: usize,
+    /// Whether they are marked now.
+    is_anomaly: bool,
+}
+
+#[derive(Serialize)]
+struct MaintenanceResponse {
+    vacuum: &'static str,
+    /// How many bytes smaller the database is.
+    reclaimed: u64,
+    duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct BackupResponse {
+    path: String,
+    size: u64,
+}
+
+impl Service<Request<Incoming>> for Server {
+    type Response = Response<ServerBody>;
+    type Error = hyper::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        let id = request_id::from_request(&req);
+        let (version, _) = routes::split_version(req.uri().path());
+        // HEAD is answered exactly like GET, the body is only dropped at the end
+        let head = req.method() == Method::HEAD;
+        let access_log = self.access_log.clone().map(|access_log| {
+            let line = RequestLine::new(&req, rate_limit::client_ip(&req, self.peer));
+            (access_log, line)
+        });
+        let server = self.clone();
+        Box::pin(async move {
+            let res = request_id::scope(id.clone(), server.handle(req)).await;
+            let mut res = Server::tagged(res, version, &id);
+            if head {
+                res = res.map(Server::strip_body);
+            }
+            if let (Some((access_log, line)), Ok(res)) = (access_log, &res) {
+                access_log.record(&line, res.status().as_u16(), res.body().size_hint().exact());
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{HeaderName, DATE};
+    use rusqlite::Connection;
+
+    use crate::{
+        database::{
+            test_support::{
+                date, memory_pool, seed_predictions, seed_readings, seed_schedule, week,
+                MemoryDatabase, MemoryTables,
+            },
+            writer::ScrapedReading,
+        },
+        server::test_support::{body_json, request, TestServer},
+    };
+
+    use super::*;
+
+    #[test]
+    fn a_week_crossing_a_schedule_change_has_each_day_from_its_schedule() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        // A Monday and the Friday after
+        seed_schedule(&connection, "gym", date(2024, 5, 6), &week(700));
+        seed_schedule(&connection, "gym", date(2024, 5, 10), &week(800));
+
+        let schedule =
+            Server::week_schedule::<SqliteDatabase>(&connection, "gym", date(2024, 5, 8))
+                .unwrap()
+                .unwrap();
+        let openings: Vec<Option<u16>> = schedule
+            .get_timings()
+            .iter()
+            .map(|daily| daily.opening())
+            .collect();
+        assert_eq!(
+            openings,
+            [700, 700, 700, 700, 800, 800, 800].map(Some).to_vec()
+        );
+    }
+
+    #[test]
+    fn a_past_day_keeps_the_schedule_in_effect_on_it() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        seed_schedule(&connection, "gym", date(2024, 5, 1), &week(700));
+        seed_schedule(&connection, "gym", date(2024, 5, 10), &week(800));
+
+        let schedule = |day| {
+            Server::get_schedule::<SqliteDatabase>(&connection, "gym", day)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(schedule(date(2024, 5, 9)), (week(700), false));
+        assert_eq!(schedule(date(2024, 5, 10)), (week(800), false));
+        // Before the first was scraped it is the oldest, like in `query_schedule_range`
+        assert_eq!(schedule(date(2024, 4, 30)), (week(700), true));
+    }
+
+    fn local(date: NaiveDate, hour: u32, minute: u32) -> NaiveDateTime {
+        date.and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn last_modified(
+        connection: &PooledConnection<SqliteConnectionManager>,
+        date: NaiveDate,
+    ) -> Option<DateTime<Utc>> {
+        Server::last_modified::<SqliteDatabase>(connection, "gym", date).unwrap()
+    }
+
+    #[test]
+    fn last_modified_when_the_clocks_go_forward_is_in_gmt() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 3, 31);
+        SqliteDatabase::upsert_occupancy(&connection, "gym", local(day, 0, 30), 10).unwrap();
+        assert_eq!(
+            last_modified(&connection, day)
+                .map(format_http_date)
+                .as_deref(),
+            Some("Sun, 31 Mar 2024 00:30:00 GMT")
+        );
+        // 02:30 BST, the first hour after the clocks went forward
+        SqliteDatabase::upsert_occupancy(&connection, "gym", local(day, 2, 30), 20).unwrap();
+        assert_eq!(
+            last_modified(&connection, day)
+                .map(format_http_date)
+                .as_deref(),
+            Some("Sun, 31 Mar 2024 01:30:00 GMT")
+        );
+    }
+
+    #[test]
+    fn last_modified_in_the_hour_repeated_when_the_clocks_go_back_is_never_too_early() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 10, 27);
+        SqliteDatabase::upsert_occupancy(&connection, "gym", local(day, 0, 30), 10).unwrap();
+        assert_eq!(
+            last_modified(&connection, day)
+                .map(format_http_date)
+                .as_deref(),
+            Some("Sat, 26 Oct 2024 23:30:00 GMT")
+        );
+        // Either of the two 01:30s, so it is taken to be the later one
+        SqliteDatabase::upsert_occupancy(&connection, "gym", local(day, 1, 30), 20).unwrap();
+        assert_eq!(
+            last_modified(&connection, day)
+                .map(format_http_date)
+                .as_deref(),
+            Some("Sun, 27 Oct 2024 01:30:00 GMT")
+        );
+    }
+
+    #[test]
+    fn last_modified_without_readings_is_from_the_predictions() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let day = date(2024, 7, 1);
+        assert_eq!(last_modified(&connection, day), None);
+        connection
+            .execute(
+                "INSERT INTO gym_prediction_knn (time, occupancy) VALUES (?1, 30)",
+                [uk_local_to_stored(local(day, 21, 0))],
+            )
+            .unwrap();
+        assert_eq!(
+            last_modified(&connection, day)
+                .map(format_http_date)
+                .as_deref(),
+            Some("Mon, 01 Jul 2024 20:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn if_modified_since_has_to_be_no_earlier_than_last_modified() {
+        let request = |since: &str| {
+            Request::builder()
+                .header(IF_MODIFIED_SINCE, since)
+                .body(Bytes::new())
+                .unwrap()
+        };
+        let last_modified = parse_http_date("Sun, 27 Oct 2024 01:30:00 GMT");
+        assert!(Server::is_unmodified(
+            &request("Sun, 27 Oct 2024 01:30:00 GMT"),
+            last_modified
+        ));
+        assert!(Server::is_unmodified(
+            &request("Sun, 27 Oct 2024 02:00:00 GMT"),
+            last_modified
+        ));
+        // The same time in BST is an hour earlier
+        assert!(!Server::is_unmodified(
+            &request("Sun, 27 Oct 2024 01:30:00 +0100"),
+            last_modified
+        ));
+        assert!(!Server::is_unmodified(&request("yesterday"), last_modified));
+        assert!(!Server::is_unmodified(
+            &request("Sun, 27 Oct 2024 01:30:00 GMT"),
+            None
+        ));
+    }
+
+    #[test]
+    fn a_week_without_any_schedule_has_none() {
+        let pool = memory_pool(1);
+        let connection = pool.get().unwrap();
+        let schedule =
+            Server::week_schedule::<SqliteDatabase>(&connection, "gym", date(2024, 5, 8));
+        assert!(schedule.unwrap().is_none());
+    }
+
+    /// A `MemoryDatabase` with the gym open from 07:00 since May, and two readings on the 8th.
+    fn memory_database() -> Arc<Mutex<MemoryTables>> {
+        let connection = Arc::default();
+        MemoryDatabase::insert_one_schedule(&connection, "gym", date(2024, 5, 1), &week(700))
+            .unwrap();
+        let readings: Vec<ScrapedReading> = [(10, 40), (11, 45)]
+            .into_iter()
+            .map(|(hour, occupancy)| ScrapedReading {
+                table_name: "gym".to_string(),
+                time: local(date(2024, 5, 8), hour, 0),
+                occupancy,
+                headcount: None,
+            })
+            .collect();
+        MemoryDatabase::insert_readings(&connection, &readings).unwrap();
+        connection
+    }
+
+    fn default_options() -> ResponseOptions {
+        ResponseOptions::from_params(&HashMap::new(), &mut ParamErrors::default())
+    }
+
+    fn get(headers: &[(HeaderName, &str)]) -> Request<Bytes> {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.body(Bytes::new()).unwrap()
+    }
+
+    async fn json(response: Response<Full<Bytes>>) -> serde_json::Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_day_is_served_from_any_database() {
+        let connection = memory_database();
+        let response = Server::days::<MemoryDatabase>(
+            &connection,
+            &["gym"],
+            Some(date(2024, 5, 8)),
+            &["knn"],
+            &default_options(),
+            &get(&[]),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[LAST_MODIFIED],
+            "Wed, 08 May 2024 10:00:00 GMT"
+        );
+        let body = json(response).await;
+        assert_eq!(
+            body["data"],
+            serde_json::json!([["2024-05-08T10:00:00", 40], ["2024-05-08T11:00:00", 45]])
+        );
+        assert_eq!(body["meta"]["date"], "2024-05-08");
+    }
+
+    #[tokio::test]
+    async fn a_day_without_a_date_is_the_last_one_with_readings() {
+        let connection = memory_database();
+        let response = Server::days::<MemoryDatabase>(
+            &connection,
+            &["gym"],
+            None,
+            &[],
+            &default_options(),
+            &get(&[]),
+        )
+        .unwrap();
+        assert_eq!(json(response).await["meta"]["date"], "2024-05-08");
+    }
+
+    #[test]
+    fn a_day_is_not_modified_since_its_last_reading() {
+        let connection = memory_database();
+        let request = get(&[(IF_MODIFIED_SINCE, "Wed, 08 May 2024 10:00:00 GMT")]);
+        let response = Server::days::<MemoryDatabase>(
+            &connection,
+            &["gym"],
+            Some(date(2024, 5, 8)),
+            &[],
+            &default_options(),
+            &request,
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn an_open_day_without_readings_has_no_content() {
+        let connection = memory_database();
+        let response = Server::days::<MemoryDatabase>(
+            &connection,
+            &["gym"],
+            Some(date(2024, 5, 9)),
+            &[],
+            &default_options(),
+            &get(&[]),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn from_serves_only_the_readings_since() {
+        let connection = memory_database();
+        let from = local(date(2024, 5, 8), 10, 30);
+        let response =
+            Server::query_from::<MemoryDatabase>(&connection, from, "gym", &default_options())
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(response).await["data"],
+            serde_json::json!([["2024-05-08T11:00:00", 45]])
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_readings_reports_how_many_went() {
+        let connection = memory_database();
+        let day = date(2024, 5, 8);
+        let response = Server::delete_readings::<MemoryDatabase>(
+            &connection,
+            "gym",
+            local(day, 9, 0),
+            local(day, 10, 30),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(response).await,
+            serde_json::json!({"deleted": 1, "tables": {"gym": 1}})
+        );
+        let left = MemoryDatabase::query_single_day(&connection, "gym", day).unwrap();
+        assert_eq!(left.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_slow_request_does_not_hold_up_a_fast_one() {
+        let test = TestServer::new();
+        // Deleting has to wait for the write lock, which another process is holding on to
+        let blocker = Connection::open(&test.database.path).unwrap();
+        blocker.execute_batch("BEGIN IMMEDIATE").unwrap();
+        let slow = tokio::spawn(test.send(request(
+            Method::DELETE,
+            "/admin/data?name=gym&from=2024-05-08T09:00:00&to=2024-05-08T10:00:00",
+        )));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // The test runs on a single thread, which the slow handler would have blocked
+        let fast = test
+            .send(request(Method::GET, "/api/day?name=gym&date=2024-05-08"))
+            .await;
+        assert_eq!(fast.status(), StatusCode::NO_CONTENT);
+        assert!(!slow.is_finished());
+
+        blocker.execute_batch("COMMIT").unwrap();
+        assert_eq!(slow.await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn admin_names_are_sanitized_like_every_other_one() {
+        let test = TestServer::new();
+        let repredict = test
+            .send(request(Method::POST, "/admin/repredict?name=gym%3Bdrop"))
+            .await;
+        assert_eq!(repredict.status(), StatusCode::OK);
+        assert_eq!(body_json(&repredict), serde_json::json!({"queued": true}));
+
+        let delete = test
+            .send(request(
+                Method::DELETE,
+                "/admin/data?name=gym'--&from=2024-05-08T09:00:00&to=2024-05-08T10:00:00",
+            ))
+            .await;
+        assert_eq!(delete.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(&delete),
+            serde_json::json!({"deleted": 0, "tables": {"gym": 0}})
+        );
+    }
+
+    #[tokio::test]
+    async fn gb_predictions_are_not_queued_to_be_regenerated() {
+        let test = TestServer::new();
+        let gb = test
+            .send(request(Method::POST, "/admin/repredict?name=gym&model=gb"))
+            .await;
+        assert_eq!(gb.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_json(&gb)["errors"],
+            serde_json::json!(["gb predictions aren't made here, so they can't be regenerated."])
+        );
+        assert_eq!(test.server.repredict.take("gym"), None);
+
+        let knn = test
+            .send(request(Method::POST, "/admin/repredict?name=gym&model=knn"))
+            .await;
+        assert_eq!(body_json(&knn), serde_json::json!({"queued": true}));
+        assert_eq!(
+            test.server.repredict.take("gym"),
+            Some(PredictionModel::Knn)
+        );
+    }
+
+    #[tokio::test]
+    async fn every_problem_with_the_parameters_is_reported_at_once() {
+        let test = TestServer::new();
+        let cases = [
+            (
+                "/api/compare?name=gym&date=nope&model=all&tolerance=0",
+                vec![
+                    "Malformed date".to_string(),
+                    unknown_model(),
+                    "tolerance must be between 1 and 60 minutes.".to_string(),
+                ],
+            ),
+            (
+                "/api/accuracy?name=gym&weeks=13",
+                vec![
+                    "model not provided.".to_string(),
+                    "weeks must be between 1 and 12.".to_string(),
+                ],
+            ),
+            (
+                "/api/summary?name=gym&nmae=gym",
+                vec!["Unknown parameter 'nmae'.".to_string()],
+            ),
+        ];
+        for (uri, errors) in cases {
+            let response = test.send(request(Method::GET, uri)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(body_json(&response)["errors"], serde_json::json!(errors));
+        }
+
+        let response = test
+            .send(request(
+                Method::DELETE,
+                "/admin/data?name=gym&from=2024-05-08T10:00:00&to=2024-05-08T09:00:00&predictions=yes",
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_json(&response)["errors"],
+            serde_json::json!([
+                "Malformed predictions. Expected true or false.",
+                "from must not be after to."
+            ])
+        );
+    }
+
+    /// Every `step` minutes of `date` from `from` until `to` (inclusive), HHMM, with the
+    /// occupancy going up by one each time from `occupancy`.
+    fn series(
+        date: NaiveDate,
+        from: u32,
+        to: u32,
+        step: u32,
+        occupancy: u16,
+    ) -> Vec<(NaiveDateTime, u16)> {
+        let minutes = |hm: u32| hm / 100 * 60 + hm % 100;
+        (minutes(from)..=minutes(to))
+            .step_by(step as usize)
+            .zip(occupancy..)
+            .map(|(minute, occupancy)| {
+                (
+                    date.and_hms_opt(minute / 60, minute % 60, 0).unwrap(),
+                    occupancy,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn only_the_readings_overlapping_the_predictions_are_compared() {
+        let test = TestServer::new();
+        let connection = test.database.pools.read_write.get().unwrap();
+        let (both, actual_only, predicted_only) =
+            (date(2024, 5, 8), date(2024, 5, 9), date(2024, 5, 10));
+        // Readings every 10 minutes, predictions every 15 from half way through them
+        seed_readings(&connection, "gym", &series(both, 900, 1200, 10, 20));
+        seed_readings(&connection, "gym", &series(actual_only, 900, 1200, 10, 20));
+        seed_predictions(&connection, "gym", "knn", &series(both, 1030, 1400, 15, 30));
+        seed_predictions(
+            &connection,
+            "gym",
+            "knn",
+            &series(predicted_only, 900, 1200, 15, 30),
+        );
+
+        let compare = |date: NaiveDate| {
+            test.send(request(
+                Method::GET,
+                &format!("/api/compare?name=gym&date={}&model=knn", date),
+            ))
+        };
+        let response = compare(both).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_json(&response);
+        let pairs: Vec<(&str, &str)> = body["pairs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|pair| {
+                (
+                    pair["time"].as_str().unwrap(),
+                    pair["predicted_time"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        // Readings 5 minutes from the nearest prediction are too far from it
+        assert_eq!(
+            pairs,
+            [
+                ("2024-05-08T10:30:00", "2024-05-08T10:30:00"),
+                ("2024-05-08T11:00:00", "2024-05-08T11:00:00"),
+                ("2024-05-08T11:30:00", "2024-05-08T11:30:00"),
+                ("2024-05-08T12:00:00", "2024-05-08T12:00:00"),
+            ]
+        );
+        assert_eq!(body["count"], 4);
+
+        assert_eq!(compare(actual_only).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            compare(predicted_only).await.status(),
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[tokio::test]
+    async fn accuracy_is_only_of_the_days_with_both_readings_and_predictions() {
+        let test = TestServer::new();
+        let connection = test.database.pools.read_write.get().unwrap();
+        let today = uk_datetime_now().date_naive();
+        let day = |ago: u64| today.checked_sub_days(chrono::Days::new(ago)).unwrap();
+        let (both, actual_only, predicted_only) = (day(1), day(2), day(3));
+        seed_readings(&connection, "gym", &series(both, 900, 1200, 10, 20));
+        seed_readings(&connection, "gym", &series(actual_only, 900, 1200, 10, 20));
+        seed_predictions(
+            &connection,
+            "gym",
+            "knn",
+            &series(predicted_only, 900, 1200, 10, 30),
+        );
+        seed_predictions(&connection, "gym", "knn", &series(both, 1030, 1400, 15, 30));
+
+        let accuracy = test
+            .send(request(Method::GET, "/api/accuracy?name=gym&model=knn"))
+            .await;
+        assert_eq!(accuracy.status(), StatusCode::OK);
+        let body = body_json(&accuracy);
+        assert_eq!(body["days"].as_array().unwrap().len(), 1);
+        assert_eq!(body["days"][0]["date"], both.to_string());
+        assert_eq!(body["days"][0]["count"], 4);
+        assert_eq!(body["overall"]["count"], 4);
+
+        let lstm = test
+            .send(request(Method::GET, "/api/accuracy?name=gym&model=lstm"))
+            .await;
+        assert_eq!(lstm.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[test]
+    fn database_errors_are_answered_with_their_status() {
+        for err in [
+            DatabaseError::NotFound,
+            DatabaseError::Busy,
+            DatabaseError::Other("no such table: gym".to_string()),
+        ] {
+            let status = err.status();
+            let busy = err == DatabaseError::Busy;
+            let response = Server::database_error(err).unwrap();
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers().contains_key(RETRY_AFTER), busy);
+        }
+    }
+
+    #[tokio::test]
+    async fn error_bodies_are_valid_json_whatever_the_message() {
+        let message = "UNIQUE constraint failed: \"gym\".time\nat C:\\data\\occupancy.db";
+        for response in [
+            Server::server_error(message).unwrap(),
+            Server::bad_request(message).unwrap(),
+        ] {
+            assert_eq!(
+                json(response).await,
+                serde_json::json!({ "error": message })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn each_route_and_method_is_answered_with_its_status() {
+        let test = TestServer::new();
+        let allow_get = Some("GET, HEAD, OPTIONS");
+        let cases = [
+            (Method::GET, "/", StatusCode::OK, None),
+            (Method::GET, "/api/locations", StatusCode::OK, None),
+            (Method::GET, "/v1/api/locations", StatusCode::OK, None),
+            (Method::GET, "/api/locations/gym", StatusCode::OK, None),
+            (
+                Method::GET,
+                "/api/day?name=gym",
+                StatusCode::NO_CONTENT,
+                None,
+            ),
+            (Method::GET, "/api/gym/day", StatusCode::NO_CONTENT, None),
+            (Method::GET, "/admin/status", StatusCode::OK, None),
+            (
+                Method::POST,
+                "/api/day",
+                StatusCode::METHOD_NOT_ALLOWED,
+                allow_get,
+            ),
+            (
+                Method::DELETE,
+                "/v1/api/locations",
+                StatusCode::METHOD_NOT_ALLOWED,
+                allow_get,
+            ),
+            (
+                Method::PUT,
+                "/api/gym/day",
+                StatusCode::METHOD_NOT_ALLOWED,
+                allow_get,
+            ),
+            (
+                Method::GET,
+                "/api/feedback",
+                StatusCode::METHOD_NOT_ALLOWED,
+                Some("POST, OPTIONS"),
+            ),
+            (
+                Method::PATCH,
+                "/api/report",
+                StatusCode::METHOD_NOT_ALLOWED,
+                Some("POST, GET, HEAD, OPTIONS"),
+            ),
+            (
+                Method::GET,
+                "/admin/anomaly",
+                StatusCode::METHOD_NOT_ALLOWED,
+                Some("POST, DELETE, OPTIONS"),
+            ),
+            (
+                Method::OPTIONS,
+                "/api/day",
+                StatusCode::NO_CONTENT,
+                allow_get,
+            ),
+            (Method::GET, "/api/nope", StatusCode::NOT_FOUND, None),
+            (Method::POST, "/api/day/extra", StatusCode::NOT_FOUND, None),
+            (Method::OPTIONS, "/nope", StatusCode::NOT_FOUND, None),
+        ];
+        for (method, uri, status, allow) in cases {
+            let response = test.send(request(method.clone(), uri)).await;
+            assert_eq!(response.status(), status, "{} {}", method, uri);
+            assert_eq!(
+                response
+                    .headers()
+                    .get(ALLOW)
+                    .map(|allow| allow.to_str().unwrap()),
+                allow,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn head_is_answered_like_get_without_the_body() {
+        let test = TestServer::new();
+        let connection = test.database.pools.read_write.get().unwrap();
+        seed_readings(
+            &connection,
+            "gym",
+            &series(date(2024, 5, 6), 900, 1200, 10, 20),
+        );
+        drop(connection);
+
+        for uri in [
+            "/",
+            "/api/locations",
+            "/api/day?name=gym&date=2024-05-06",
+            "/api/gym/day?date=2024-05-06",
+            "/api/day?name=gym",
+            "/api/day?name=gym&date=nope",
+            "/api/nope",
+        ] {
+            // Error bodies have the request ID in them, so both get the same one
+            let send = |method| {
+                let mut request = request(method, uri);
+                let id = HeaderValue::from_static("same-request");
+                request
+                    .headers_mut()
+                    .insert(request_id::REQUEST_ID_HEADER, id);
+                test.send(request)
+            };
+            let get = send(Method::GET).await;
+            let head = send(Method::HEAD).await;
+            assert_eq!(head.status(), get.status(), "{}", uri);
+            // Only the Date can differ, when the second ticks over in between
+            let headers = |response: &Response<Bytes>| {
+                let mut headers = response.headers().clone();
+                headers.remove(DATE);
+                headers
+            };
+            assert_eq!(headers(&head), headers(&get), "{}", uri);
+            assert!(head.body().is_empty(), "{}", uri);
+            if get.status() != StatusCode::NO_CONTENT {
+                assert!(!get.body().is_empty(), "{}", uri);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn feedback_given_again_straight_away_replaces_the_first() {
+        let test = TestServer::new();
+        let connection = test.database.pools.read_write.get().unwrap();
+        let day = date(2024, 5, 6);
+        seed_predictions(&connection, "gym", "knn", &series(day, 900, 1200, 30, 20));
+        drop(connection);
+
+        let post = |model: &str, rating: &str| {
+            let body = serde_json::json!({
+                "name": "gym",
+                "date": "2024-05-06",
+                "model": model,
+                "rating": rating,
+            });
+            let mut request = request(Method::POST, "/api/feedback");
+            *request.body_mut() = Full::new(Bytes::from(body.to_string()));
+            test.send(request)
         };
+        let first = body_json(&post("knn", "up").await);
+        assert_eq!(first["coalesced"], false);
+        let second = body_json(&post("knn", "down").await);
+        assert_eq!(second["coalesced"], true);
+        assert_eq!(second["id"], first["id"]);
 
-        Box::pin(async { res })
+        let unknown = post("nope", "up").await;
+        assert_eq!(unknown.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(&unknown)["error"], unknown_model());
     }
 }
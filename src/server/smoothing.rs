@@ -0,0 +1,134 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::ISO_FORMAT;
+
+/// The largest window `smooth` accepts, five hours of readings.
+pub const MAX_WINDOW: usize = 60;
+
+/// Parses the `smooth` parameter, a window of 1 to `MAX_WINDOW` readings.
+pub fn parse_window(window: &str) -> Option<usize> {
+    let window: usize = window.parse().ok()?;
+    if window == 0 || window > MAX_WINDOW {
+        return None;
+    }
+    Some(window)
+}
+
+/**
+Smooths a series of (time, occupancy) with a centered moving average over `window` readings.
+
+Each reading is replaced by the mean of itself and its neighbours, `(window - 1) / 2` before and
+`window / 2` after. Near the ends of the series fewer neighbours are available and the mean is
+taken over those. Readings more than `max_gap` apart are never averaged together, so smoothing
+stops at a gap instead of bridging it.
+
+The result is sorted by time. Times that can't be parsed are dropped.
+*/
+pub fn smooth(series: &[(String, u16)], window: usize, max_gap: Duration) -> Vec<(String, u16)> {
+    let mut points: Vec<(NaiveDateTime, &str, u16)> = series
+        .iter()
+        .filter_map(|(time, occupancy)| {
+            let parsed = NaiveDateTime::parse_from_str(time, ISO_FORMAT).ok()?;
+            Some((parsed, time.as_str(), *occupancy))
+        })
+        .collect();
+    points.sort_by_key(|(time, _, _)| *time);
+
+    let before = window.saturating_sub(1) / 2;
+    let after = window / 2;
+    let mut smoothed = Vec::with_capacity(points.len());
+    for run in points.chunk_by(|a, b| b.0 - a.0 <= max_gap) {
+        for i in 0..run.len() {
+            let neighbours = &run[i.saturating_sub(before)..(i + after + 1).min(run.len())];
+            let sum: u32 = neighbours.iter().map(|(_, _, value)| *value as u32).sum();
+            let mean = (sum as f64 / neighbours.len() as f64).round() as u16;
+            smoothed.push((run[i].1.to_string(), mean));
+        }
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Readings every 5 minutes from 10:00, with the occupancies `values`.
+    fn series(values: &[u16]) -> Vec<(String, u16)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (format!("2024-05-01T10:{:02}:00", i * 5), *value))
+            .collect()
+    }
+
+    fn values(series: &[(String, u16)]) -> Vec<u16> {
+        series.iter().map(|(_, value)| *value).collect()
+    }
+
+    #[test]
+    fn windows_have_to_be_between_1_and_the_max() {
+        assert_eq!(parse_window("1"), Some(1));
+        assert_eq!(parse_window("60"), Some(MAX_WINDOW));
+        assert_eq!(parse_window("0"), None);
+        assert_eq!(parse_window("61"), None);
+        assert_eq!(parse_window("-3"), None);
+        assert_eq!(parse_window("three"), None);
+    }
+
+    #[test]
+    fn readings_are_averaged_with_their_neighbours() {
+        let smoothed = smooth(&series(&[10, 20, 30, 40, 50]), 3, Duration::minutes(10));
+        assert_eq!(values(&smoothed), [15, 20, 30, 40, 45]);
+        assert_eq!(smoothed[0].0, "2024-05-01T10:00:00");
+    }
+
+    #[test]
+    fn the_ends_are_averaged_over_the_neighbours_there_are() {
+        let smoothed = smooth(&series(&[0, 30, 60, 90]), 5, Duration::minutes(10));
+        // 0 has 30 and 60 after it, 90 only 60 and 30 before it
+        assert_eq!(values(&smoothed), [30, 45, 45, 60]);
+    }
+
+    #[test]
+    fn even_windows_take_one_more_after() {
+        let smoothed = smooth(&series(&[10, 20, 30, 40]), 2, Duration::minutes(10));
+        assert_eq!(values(&smoothed), [15, 25, 35, 40]);
+    }
+
+    #[test]
+    fn a_window_of_one_changes_nothing() {
+        let series = series(&[10, 80, 20]);
+        assert_eq!(smooth(&series, 1, Duration::minutes(10)), series);
+    }
+
+    #[test]
+    fn smoothing_stops_at_a_gap() {
+        let mut series = series(&[10, 20]);
+        series.push(("2024-05-01T11:00:00".to_string(), 90));
+        series.push(("2024-05-01T11:05:00".to_string(), 100));
+        let smoothed = smooth(&series, 3, Duration::minutes(10));
+        assert_eq!(values(&smoothed), [15, 15, 95, 95]);
+    }
+
+    #[test]
+    fn the_result_is_sorted_and_unreadable_times_are_dropped() {
+        let series = vec![
+            ("2024-05-01T10:05:00".to_string(), 20),
+            ("not a time".to_string(), 50),
+            ("2024-05-01T10:00:00".to_string(), 10),
+        ];
+        let smoothed = smooth(&series, 1, Duration::minutes(10));
+        assert_eq!(
+            smoothed,
+            [
+                ("2024-05-01T10:00:00".to_string(), 10),
+                ("2024-05-01T10:05:00".to_string(), 20)
+            ]
+        );
+    }
+
+    #[test]
+    fn an_empty_series_stays_empty() {
+        assert!(smooth(&[], 5, Duration::minutes(10)).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+
+/// The page served at `/`. `{title}` is replaced with the title of the page and `{locations}`
+/// with one section per location.
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body { font-family: sans-serif; margin: 1em auto; max-width: 36em; padding: 0 1em; }
+section { border-bottom: 1px solid #ccc; padding: 0.5em 0; }
+.occupancy { font-size: 2em; margin: 0.2em 0; }
+.muted { color: #666; }
+.failing { color: #b00; font-weight: bold; }
+pre { white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{locations}
+</body>
+</html>
+"#;
+
+/// What the status page shows for one location.
+pub struct LocationStatus<'a> {
+    pub name: &'a str,
+    /// The newest reading as (time, occupancy).
+    pub latest: Option<(String, u16)>,
+    /// When the location was last scraped successfully.
+    pub last_scrape: Option<String>,
+}
+
+/// Renders the status page.
+///
+/// It is a plain page without JavaScript so it works on anything. Locations without data say so
+/// instead of being left out.
+pub fn render(locations: &[LocationStatus]) -> String {
+    let sections: Vec<String> = locations.iter().map(section).collect();
+    page("Occupancy", &sections)
+}
+
+/// How scraping one location has been going, as /admin/status reports it.
+#[derive(Serialize)]
+pub struct ScraperTargetStatus {
+    pub name: &'static str,
+    /// When it was last scraped and stored without any errors.
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+    /// When `last_error` happened.
+    pub last_error_at: Option<String>,
+    /// How many scrapes in a row have failed.
+    pub consecutive_failures: u32,
+    /// When predictions were last generated.
+    pub last_prediction: Option<String>,
+}
+
+/// Renders /admin/status for viewing in a browser.
+pub fn render_scraper(targets: &[ScraperTargetStatus]) -> String {
+    let sections: Vec<String> = targets.iter().map(scraper_section).collect();
+    page("Scraper status", &sections)
+}
+
+fn page(title: &str, sections: &[String]) -> String {
+    TEMPLATE
+        .replace("{title}", title)
+        .replace("{locations}", &sections.join("\n"))
+}
+
+fn section(location: &LocationStatus) -> String {
+    let latest = match &location.latest {
+        Some((time, occupancy)) => format!(
+            "<p class=\"occupancy\">{}%</p>\n<p>Latest reading at {}</p>",
+            occupancy,
+            escape(time)
+        ),
+        None => "<p class=\"occupancy muted\">No data yet</p>".to_string(),
+    };
+    let last_scrape = match &location.last_scrape {
+        Some(time) => format!("Last scraped at {}", escape(time)),
+        None => "Not scraped since startup".to_string(),
+    };
+    let name = escape(location.name);
+    format!(
+        "<section>\n<h2>{name}</h2>\n{latest}\n<p class=\"muted\">{last_scrape}</p>\n<p>\
+         <a href=\"/api/latest?name={name}\">latest</a> \
+         <a href=\"/api/summary?name={name}\">summary</a> \
+         <a href=\"/api/day?name={name}\">day</a></p>\n</section>"
+    )
+}
+
+fn scraper_section(target: &ScraperTargetStatus) -> String {
+    let last_success = match &target.last_success {
+        Some(time) => format!("<p>Last scraped at {}</p>", escape(time)),
+        None => "<p class=\"failing\">Never scraped successfully</p>".to_string(),
+    };
+    let failures = match target.consecutive_failures {
+        0 => String::new(),
+        1 => "<p class=\"failing\">The last scrape failed</p>\n".to_string(),
+        count => format!(
+            "<p class=\"failing\">The last {} scrapes failed</p>\n",
+            count
+        ),
+    };
+    let last_error = match (&target.last_error_at, &target.last_error) {
+        (Some(time), Some(error)) => format!(
+            "<p class=\"muted\">Last error at {}:</p>\n<pre>{}</pre>\n",
+            escape(time),
+            escape(error)
+        ),
+        _ => String::new(),
+    };
+    let last_prediction = match &target.last_prediction {
+        Some(time) => format!("Predictions last generated at {}", escape(time)),
+        None => "No predictions generated since startup".to_string(),
+    };
+    format!(
+        "<section>\n<h2>{}</h2>\n{}\n{}{}<p class=\"muted\">{}</p>\n</section>",
+        escape(target.name),
+        last_success,
+        failures,
+        last_error,
+        last_prediction
+    )
+}
+
+/// Escapes text for use in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,72 @@
+use std::future::Future;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{client::conn::http1, server::conn::http1 as server_http1, Method, Request, Response};
+use hyper_util::rt::TokioIo;
+use tokio::sync::watch;
+
+use crate::{database::test_support::TempDatabase, settings::settings::Settings};
+
+use super::{auth::API_KEY_HEADER, server::Server};
+
+/// The admin API key of a `TestServer`.
+pub const ADMIN_KEY: &str = "test-admin-key";
+
+/// A `Server` on a `TempDatabase` of its own, which goes with it.
+pub struct TestServer {
+    pub server: Server,
+    pub database: TempDatabase,
+    /// Shuts the server down, the way `main` does.
+    pub shutdown: watch::Sender<bool>,
+}
+
+impl TestServer {
+    pub fn new() -> Self {
+        let database = TempDatabase::new();
+        let (shutdown, receiver) = watch::channel(false);
+        let server = Server::for_tests(&database.pools, Settings::for_tests(ADMIN_KEY), receiver);
+        Self {
+            server,
+            database,
+            shutdown,
+        }
+    }
+
+    /// Sends `request` over a connection of its own, see `send`. It doesn't borrow the
+    /// `TestServer`, so it can be spawned.
+    pub fn send(
+        &self,
+        request: Request<Full<Bytes>>,
+    ) -> impl Future<Output = Response<Bytes>> + Send + 'static {
+        send(self.server.clone(), request)
+    }
+}
+
+/// A request with no body and the admin API key, so it gets past the check on /admin.
+pub fn request(method: Method, uri: &str) -> Request<Full<Bytes>> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Host", "localhost")
+        .header(API_KEY_HEADER, ADMIN_KEY)
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+/// The JSON body of a response from `send`.
+pub fn body_json(response: &Response<Bytes>) -> serde_json::Value {
+    serde_json::from_slice(response.body()).unwrap()
+}
+
+/// Serves `request` with `server` over an in-memory HTTP/1.1 connection, the way `main` serves
+/// one from a client, and returns the response with its whole body.
+pub async fn send(server: Server, request: Request<Full<Bytes>>) -> Response<Bytes> {
+    let (client, connection) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(server_http1::Builder::new().serve_connection(TokioIo::new(connection), server));
+    let (mut sender, connection) = http1::handshake(TokioIo::new(client)).await.unwrap();
+    tokio::spawn(connection);
+    let (parts, body) = sender.send_request(request).await.unwrap().into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::{
+    rustls::{crypto::ring, ServerConfig},
+    TlsAcceptor,
+};
+
+/// The certificate and key the server speaks TLS with.
+///
+/// They can be re-read from disk while running, so renewed certificates are picked up without a
+/// restart. Connections already open keep the certificate they started with.
+pub struct TlsCertificates {
+    cert: PathBuf,
+    key: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+impl TlsCertificates {
+    /// Loads the PEM encoded certificate chain at `cert` and private key at `key`.
+    pub fn load(cert: &Path, key: &Path) -> Result<Self, String> {
+        Ok(Self {
+            cert: cert.to_path_buf(),
+            key: key.to_path_buf(),
+            acceptor: RwLock::new(Self::acceptor_from(cert, key)?),
+        })
+    }
+
+    /// The acceptor to do the handshake of a new connection with.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Re-reads the certificate and key. The old ones are kept if they can't be loaded.
+    pub fn reload(&self) -> Result<(), String> {
+        let acceptor = Self::acceptor_from(&self.cert, &self.key)?;
+        *self.acceptor.write().unwrap() = acceptor;
+        Ok(())
+    }
+
+    /// Reloads the certificate and key on every SIGHUP.
+    pub fn reload_on_hangup(self: Arc<Self>) {
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match self.reload() {
+                    Ok(()) => println!("Reloaded the TLS certificate."),
+                    Err(err) => println!("{}\nStill using the previous one.", err),
+                }
+            }
+        });
+    }
+
+    fn acceptor_from(cert: &Path, key: &Path) -> Result<TlsAcceptor, String> {
+        let open = |path: &Path| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|err| format!("Could not read TLS file '{}'.\n{}", path.display(), err))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Could not parse '{}'.\n{}", cert.display(), err))?;
+        if certs.is_empty() {
+            return Err(format!("No certificates found in '{}'.", cert.display()));
+        }
+        let private_key = rustls_pemfile::private_key(&mut open(key)?)
+            .map_err(|err| format!("Could not parse '{}'.\n{}", key.display(), err))?
+            .ok_or_else(|| format!("No private key found in '{}'.", key.display()))?;
+
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, private_key)
+            .map_err(|err| format!("Invalid TLS certificate or key.\n{}", err))?;
+        // Offer HTTP/2 as well, hyper picks whichever the client starts speaking
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
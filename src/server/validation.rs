@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use hyper::Uri;
+use regex::Regex;
+use serde::Serialize;
+use url_escape::decode_to_vec;
+
+use crate::timing::timezone::parse_uk_local;
+
+use super::{
+    options::ResponseOptions,
+    routes::{self, Route},
+};
+
+/// Every problem found with a request's parameters, so they can all be reported in one response
+/// instead of one per round trip.
+#[derive(Serialize)]
+pub struct ParamErrors {
+    error: &'static str,
+    errors: Vec<String>,
+}
+
+impl Default for ParamErrors {
+    fn default() -> Self {
+        Self {
+            error: "Invalid Parameters",
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl ParamErrors {
+    pub fn push(&mut self, message: impl Into<String>) {
+        self.errors.push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/**
+The query parameters of a request, with typed accessors that check them as they are read.
+
+The `{name}` segment of routes such as /api/{name}/day is read as the `name` parameter, so
+handlers don't need to know which form of the route was requested.
+
+Every problem found along the way, from the query string itself to each accessor, is collected
+so they can all be reported at once by `finish`.
+*/
+pub struct QueryParams<'a> {
+    map: HashMap<String, String>,
+    errors: ParamErrors,
+    sanitizer: &'a Regex,
+}
+
+impl<'a> QueryParams<'a> {
+    /// Parses the parameters of a request for `uri` to `route`. Names are sanitized with
+    /// `sanitizer`.
+    pub fn parse(uri: &Uri, route: &Route, sanitizer: &'a Regex) -> Self {
+        let mut errors = ParamErrors::default();
+        let (_, path) = routes::split_version(uri.path());
+        let name = routes::path_param(route.path, path);
+        let map = check_params(uri.query(), name, route, &mut errors);
+        Self {
+            map,
+            errors,
+            sanitizer,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(String::as_str)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Record a problem that isn't about a single parameter's format.
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(message);
+    }
+
+    /// The sanitized `name`. Whether it is missing has already been checked by `parse`.
+    pub fn require_name(&mut self) -> Option<String> {
+        let name = self.map.get("name")?;
+        match sanitize_name(self.sanitizer, name) {
+            Some(name) => Some(name.to_string()),
+            None => {
+                self.errors.push("Malformed Name");
+                None
+            }
+        }
+    }
+
+    /// The sanitized comma separated list of names in `name`.
+    pub fn require_names(&mut self) -> Vec<String> {
+        let Some(list) = self.map.get("name") else {
+            return Vec::new();
+        };
+        let mut names = Vec::new();
+        for name in list.split(',') {
+            match sanitize_name(self.sanitizer, name) {
+                Some(name) => names.push(name.to_string()),
+                None => self.errors.push(format!("Malformed Name '{}'.", name)),
+            }
+        }
+        names
+    }
+
+    /// The date in `key`, if there is one.
+    pub fn optional_date(&mut self, key: &str) -> Option<NaiveDate> {
+        let date = self.map.get(key)?;
+        let date = NaiveDate::from_str(date).ok();
+        if date.is_none() {
+            self.errors.push(format!("Malformed {}", key));
+        }
+        date
+    }
+
+    /// The date in `key`. Whether it is missing has already been checked by `parse`.
+    pub fn require_date(&mut self, key: &str) -> Option<NaiveDate> {
+        self.optional_date(key)
+    }
+
+    /// The time in `key` as UK local time, if there is one. Offsets are accepted as well, see
+    /// `parse_uk_local`.
+    pub fn optional_datetime(&mut self, key: &str) -> Option<NaiveDateTime> {
+        // A + in an offset that wasn't percent-encoded arrives as a space. Times have no spaces
+        // otherwise, so it can be put back.
+        let time = parse_uk_local(&self.map.get(key)?.replace(' ', "+"));
+        if time.is_none() {
+            self.errors.push(format!("Malformed {}", key));
+        }
+        time
+    }
+
+    /// The time in `key` as UK local time. Whether it is missing has already been checked by
+    /// `parse`.
+    pub fn require_datetime(&mut self, key: &str) -> Option<NaiveDateTime> {
+        self.optional_datetime(key)
+    }
+
+    /// The comma separated list in `key`, each of which has to be one of `allowed`. `none` is
+    /// the empty list.
+    pub fn optional_list(
+        &mut self,
+        key: &str,
+        allowed: &[&'static str],
+    ) -> Option<Vec<&'static str>> {
+        let list = self.map.get(key)?;
+        if list == "none" {
+            return Some(Vec::new());
+        }
+        let mut values = Vec::new();
+        for value in list.split(',') {
+            match allowed.iter().find(|allowed| **allowed == value) {
+                Some(allowed) => values.push(*allowed),
+                None => self.errors.push(format!(
+                    "Unknown value '{}' in {}. Expected none or any of {}.",
+                    value,
+                    key,
+                    allowed.join(", ")
+                )),
+            }
+        }
+        Some(values)
+    }
+
+    /// Checks a range with `check_range`.
+    pub fn check_range(&mut self, from: NaiveDateTime, to: NaiveDateTime, max_span: Duration) {
+        check_range(from, to, max_span, &mut self.errors);
+    }
+
+    /// The `ResponseOptions` in the parameters.
+    pub fn response_options(&mut self) -> ResponseOptions {
+        ResponseOptions::from_params(&self.map, &mut self.errors)
+    }
+
+    /// Returns every problem found, if there were any.
+    pub fn finish(self) -> Result<(), ParamErrors> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(self.errors)
+    }
+}
+
+/// The parameter that turns on strict checking, accepted by every endpoint.
+const STRICT: &str = "strict";
+
+/**
+With `strict=true`, rejects parameters `route` doesn't declare.
+
+Endpoints read with `QueryParams` always reject unknown parameters, and this makes the rest do
+the same when asked to, so typos such as `nmae=gym` are reported instead of falling through to
+whatever the endpoint does without the parameter.
+*/
+pub fn strict_check(query: Option<&str>, route: &Route) -> Result<(), ParamErrors> {
+    let mut strict = false;
+    let mut unknown = Vec::new();
+    let mut errors = ParamErrors::default();
+    // A query string that can't be decoded is reported by the endpoint itself
+    let pairs = parse_pairs(query.unwrap_or_default()).unwrap_or_default();
+    for (key, value) in pairs {
+        if key == STRICT {
+            match value.as_str() {
+                "true" => strict = true,
+                "false" => (),
+                _ => errors.push("Malformed strict. Expected true or false."),
+            }
+        } else if !route.required.contains(&key.as_str()) && !route.optional.contains(&key.as_str())
+        {
+            unknown.push(format!("Unknown parameter '{}'.", key));
+        }
+    }
+    if strict {
+        for message in unknown {
+            errors.push(message);
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors)
+}
+
+/// Sanitizes a table name. Only the first run of word characters is kept.
+///
+/// Returns `None` if there is nothing left.
+pub fn sanitize_name<'a>(sanitizer: &Regex, name: &'a str) -> Option<&'a str> {
+    // SQL Injections are automatically handled by rusqlite
+    // Handle the table name manually
+    let name = sanitizer.captures(name)?.get(0)?.as_str();
+    if name.is_empty() {
+        return None;
+    }
+    Some(name)
+}
+
+/**
+Decodes a key or value of a query string.
+
+`+` is a space, as in HTML forms, and percent-encoded bytes are decoded, so `%2B` is a literal `+`
+and `%C3%A9` is `é`. Returns `None` if the result isn't valid UTF-8.
+*/
+fn decode_component(text: &str) -> Option<String> {
+    let text = text.replace('+', " ");
+    let mut bytes = Vec::with_capacity(text.len());
+    decode_to_vec(&text, &mut bytes);
+    String::from_utf8(bytes).ok()
+}
+
+/**
+Splits a query string into its decoded (key, value) pairs, in the order they were given.
+
+Empty pairs, such as the one after a trailing `&`, are skipped. A key without an `=` and a key
+with nothing after it (`name=`) both have an empty value. Only the first `=` separates the key
+from the value. Duplicate keys are all returned, it is up to the caller which one wins.
+
+Returns `None` if a key or value isn't valid UTF-8 once decoded.
+*/
+pub fn parse_pairs(query: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for pair in query.split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        pairs.push((decode_component(key)?, decode_component(value)?));
+    }
+    Some(pairs)
+}
+
+/**
+Parses a query string against the parameters `route` declares.
+
+This does not give up on the first problem. Parameters the route doesn't know about and missing
+required parameters are all added to `errors`, and whatever could be parsed is returned. When a
+key is given more than once the first value is kept.
+*/
+fn check_params(
+    query: Option<&str>,
+    path_name: Option<&str>,
+    route: &Route,
+    errors: &mut ParamErrors,
+) -> HashMap<String, String> {
+    let mut map: HashMap<String, String> = HashMap::new();
+    // A name in the path can't also be given in the query, the route doesn't declare it there
+    if let Some(name) = path_name {
+        map.insert("name".to_string(), name.to_string());
+    }
+    let Some(pairs) = parse_pairs(query.unwrap_or_default()) else {
+        errors.push("Malformed query string. It has to be valid UTF-8 once decoded.");
+        return map;
+    };
+    for (key, value) in pairs {
+        // Checked by `strict_check`
+        if key == STRICT {
+            continue;
+        }
+        if !route.required.contains(&key.as_str()) && !route.optional.contains(&key.as_str()) {
+            errors.push(format!("Unknown parameter '{}'.", key));
+            continue;
+        }
+        map.entry(key).or_insert(value);
+    }
+
+    for required in route.required {
+        if !map.contains_key(*required) {
+            errors.push(format!("{} not provided.", required));
+        }
+    }
+    map
+}
+
+/// Checks that `from` is not after `to` and that the range between them is no longer than
+/// `max_span`, so no single request can ask for an unbounded amount of data.
+fn check_range(
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    max_span: Duration,
+    errors: &mut ParamErrors,
+) {
+    if to < from {
+        errors.push("from must not be after to.");
+    } else if to - from > max_span {
+        errors.push(format!(
+            "Range is longer than the limit of {} days. Split it into several requests.",
+            max_span.num_days()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+    use crate::server::routes::Endpoint;
+
+    const ROUTE: Route = Route {
+        method: Method::GET,
+        path: "/api/day",
+        required: &["name"],
+        optional: &["since"],
+        endpoint: Endpoint::Day,
+    };
+
+    fn sanitizer() -> Regex {
+        Regex::new(r"(\w+)").unwrap()
+    }
+
+    /// The name of a request to /api/day with `query`, or the errors found.
+    fn name(query: &str) -> Result<Option<String>, Vec<String>> {
+        let sanitizer = sanitizer();
+        let uri: Uri = format!("/api/day?{}", query).parse().unwrap();
+        let mut params = QueryParams::parse(&uri, &ROUTE, &sanitizer);
+        let name = params.require_name();
+        params
+            .finish()
+            .map(|_| name)
+            .map_err(|errors| errors.errors)
+    }
+
+    #[test]
+    fn sanitizing_keeps_only_the_first_word() {
+        let sanitizer = sanitizer();
+        assert_eq!(sanitize_name(&sanitizer, "gym;DROP"), Some("gym"));
+        assert_eq!(sanitize_name(&sanitizer, "gym'--"), Some("gym"));
+        assert_eq!(
+            sanitize_name(&sanitizer, "gym; DROP TABLE gym"),
+            Some("gym")
+        );
+    }
+
+    #[test]
+    fn sanitizing_without_any_word_is_none() {
+        let sanitizer = sanitizer();
+        assert_eq!(sanitize_name(&sanitizer, ""), None);
+        assert_eq!(sanitize_name(&sanitizer, "';--"), None);
+        assert_eq!(sanitize_name(&sanitizer, "\0"), None);
+    }
+
+    #[test]
+    fn injected_names_are_cut_down_to_the_table_name() {
+        assert_eq!(name("name=gym;DROP"), Ok(Some("gym".to_string())));
+        assert_eq!(name("name=gym'--"), Ok(Some("gym".to_string())));
+        assert_eq!(
+            name("name=gym%3BDROP%20TABLE%20gym"),
+            Ok(Some("gym".to_string()))
+        );
+    }
+
+    #[test]
+    fn a_name_without_any_word_is_malformed() {
+        assert_eq!(name("name=%00"), Err(vec!["Malformed Name".to_string()]));
+        assert_eq!(name("name="), Err(vec!["Malformed Name".to_string()]));
+        assert_eq!(name("name=%27--"), Err(vec!["Malformed Name".to_string()]));
+    }
+
+    #[test]
+    fn plus_and_percent_20_decode_to_a_space() {
+        let pairs = parse_pairs("name=main+library&since=a%20b&plus=%2B").unwrap();
+        assert_eq!(
+            pairs,
+            [("name", "main library"), ("since", "a b"), ("plus", "+")]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        // The space ends the name
+        assert_eq!(name("name=main+library"), Ok(Some("main".to_string())));
+        assert_eq!(name("name=main%20library"), Ok(Some("main".to_string())));
+    }
+
+    /// `pairs` as `parse_pairs` returns them.
+    fn pairs(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn query_strings_are_split_and_decoded() {
+        let cases = [
+            ("", pairs(&[])),
+            ("&&", pairs(&[])),
+            ("name=gym", pairs(&[("name", "gym")])),
+            ("name=gym&", pairs(&[("name", "gym")])),
+            ("name", pairs(&[("name", "")])),
+            ("name=", pairs(&[("name", "")])),
+            ("=gym", pairs(&[("", "gym")])),
+            ("a=1=2", pairs(&[("a", "1=2")])),
+            (
+                "name=gym&name=pool",
+                pairs(&[("name", "gym"), ("name", "pool")]),
+            ),
+            ("n%61me=gym", pairs(&[("name", "gym")])),
+            ("name=caf%C3%A9", pairs(&[("name", "café")])),
+            ("name=%E2%82%AC%F0%9F%8F%8B", pairs(&[("name", "€🏋")])),
+            ("name=%26%3D%25", pairs(&[("name", "&=%")])),
+            // Escapes that aren't two hex digits are kept as they are
+            (
+                "name=100%&x=%zz%4",
+                pairs(&[("name", "100%"), ("x", "%zz%4")]),
+            ),
+            // Bytes that aren't UTF-8, on their own or cut short
+            ("name=%FF", None),
+            ("name=caf%C3", None),
+            ("%C3%28=gym", None),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(parse_pairs(query), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn names_are_read_from_any_query_string() {
+        let malformed = || Err(vec!["Malformed Name".to_string()]);
+        let utf8 = || {
+            Err(vec![
+                "Malformed query string. It has to be valid UTF-8 once decoded.".to_string(),
+            ])
+        };
+        let cases = [
+            ("name=gym", Ok(Some("gym".to_string()))),
+            ("name=gym&name=pool", Ok(Some("gym".to_string()))),
+            ("name=%67ym", Ok(Some("gym".to_string()))),
+            ("name=caf%C3%A9", Ok(Some("café".to_string()))),
+            ("name=%F0%9F%8F%8Bgym", Ok(Some("gym".to_string()))),
+            ("name=%zz", Ok(Some("zz".to_string()))),
+            ("name=%25", malformed()),
+            ("name=%FF", utf8()),
+            ("name=gym&since=%C3", utf8()),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(name(query), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        assert_eq!(
+            name("nmae=gym"),
+            Err(vec![
+                "Unknown parameter 'nmae'.".to_string(),
+                "name not provided.".to_string()
+            ])
+        );
+    }
+}
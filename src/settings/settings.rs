@@ -0,0 +1,324 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{Duration, Weekday};
+
+use crate::{
+    database::{backup::Backups, maintenance::Maintenance, pool::PoolConfig, writer::Batching},
+    scraper::retention::Retention,
+    server::listener::Listen,
+};
+
+/// Runtime settings shared by the server and the scraper.
+///
+/// Everything is loaded once at startup in `main` and handed out behind an `Arc`.
+/// Values come from environment variables, with a few secrets also being readable from files in
+/// the working directory so they don't have to live in the service definition.
+#[derive(Debug)]
+pub struct Settings {
+    admin_key: Option<String>,
+    admin_delete_max_span: Duration,
+    request_timeout: std::time::Duration,
+    max_query_span: Duration,
+    shutdown_grace: std::time::Duration,
+    max_connections: usize,
+    pool: PoolConfig,
+    database: PathBuf,
+    access_log: Option<PathBuf>,
+    tls: Option<(PathBuf, PathBuf)>,
+    listen: Vec<Listen>,
+    socket_mode: u32,
+    retention: Retention,
+    write_batching: Batching,
+    backups: Option<Backups>,
+    maintenance: Maintenance,
+    recover: bool,
+}
+
+impl Settings {
+    /// Load the settings from the environment.
+    ///
+    /// The admin API key is read from `OCCUPANCY_ADMIN_KEY`, falling back to the `admin_key`
+    /// file. If neither exists the admin endpoints stay locked.
+    pub fn load() -> Result<Self, String> {
+        let backups = Self::read_backups()?;
+        Ok(Self {
+            admin_key: Self::read_secret("OCCUPANCY_ADMIN_KEY", "admin_key")?,
+            admin_delete_max_span: Duration::hours(Self::read_env(
+                "OCCUPANCY_ADMIN_DELETE_MAX_HOURS",
+                24,
+            )?),
+            request_timeout: std::time::Duration::from_secs(Self::read_env(
+                "OCCUPANCY_REQUEST_TIMEOUT_SECS",
+                5,
+            )?),
+            max_query_span: Duration::days(Self::read_env("OCCUPANCY_MAX_QUERY_DAYS", 31)?),
+            shutdown_grace: std::time::Duration::from_secs(Self::read_env(
+                "OCCUPANCY_SHUTDOWN_GRACE_SECS",
+                10,
+            )?),
+            max_connections: Self::read_env("OCCUPANCY_MAX_CONNECTIONS", 256)?,
+            pool: PoolConfig {
+                max_size: Self::read_env("OCCUPANCY_POOL_SIZE", 8)?,
+                min_idle: Self::read_env("OCCUPANCY_POOL_MIN_IDLE", 2)?,
+                connection_timeout: std::time::Duration::from_millis(Self::read_env(
+                    "OCCUPANCY_POOL_TIMEOUT_MS",
+                    500,
+                )?),
+            },
+            database: Self::read_arg("--database")?
+                .unwrap_or_else(|| "data.db".to_string())
+                .into(),
+            access_log: Self::read_arg("--access-log")?.map(PathBuf::from),
+            tls: Self::read_tls()?,
+            listen: Self::read_listen()?,
+            socket_mode: match Self::read_arg("--socket-mode")? {
+                Some(mode) => Self::parse_mode(&mode)?,
+                None => 0o660,
+            },
+            retention: Retention {
+                reading_months: Self::read_env("OCCUPANCY_RETENTION_MONTHS", 0)?,
+                prediction_weeks: Self::read_env("OCCUPANCY_PREDICTION_RETENTION_WEEKS", 0)?,
+            },
+            write_batching: Batching {
+                rows: Self::read_env("OCCUPANCY_WRITE_BATCH_ROWS", 1)?,
+                interval: std::time::Duration::from_secs(Self::read_env(
+                    "OCCUPANCY_WRITE_BATCH_SECS",
+                    60,
+                )?),
+            },
+            maintenance: Maintenance::new(
+                Self::read_env("OCCUPANCY_MAINTENANCE_DAY", Weekday::Sun)?,
+                Self::read_env("OCCUPANCY_MAINTENANCE_HOUR", 4)?,
+                Self::read_env("OCCUPANCY_VACUUM_FREE_PERCENT", 20)?,
+                backups.clone(),
+            ),
+            backups,
+            recover: Self::read_flag("--recover"),
+        })
+    }
+
+    /// The settings `load` reads, with `admin_key` as the admin API key whatever the
+    /// environment says.
+    #[cfg(test)]
+    pub fn for_tests(admin_key: &str) -> Self {
+        Self {
+            admin_key: Some(admin_key.to_string()),
+            ..Self::load().unwrap()
+        }
+    }
+
+    /// Read `--tls-cert` and `--tls-key`, which have to be given together.
+    fn read_tls() -> Result<Option<(PathBuf, PathBuf)>, String> {
+        match (Self::read_arg("--tls-cert")?, Self::read_arg("--tls-key")?) {
+            (Some(cert), Some(key)) => Ok(Some((cert.into(), key.into()))),
+            (None, None) => Ok(None),
+            _ => Err("--tls-cert and --tls-key have to be given together.".to_string()),
+        }
+    }
+
+    /// Read `--backup-dir` along with how often backups are made and how many are kept, which
+    /// only matter when it is given.
+    fn read_backups() -> Result<Option<Backups>, String> {
+        let Some(dir) = Self::read_arg("--backup-dir")? else {
+            return Ok(None);
+        };
+        let keep = Self::read_env("OCCUPANCY_BACKUP_KEEP", 7)?;
+        let hours: u64 = Self::read_env("OCCUPANCY_BACKUP_INTERVAL_HOURS", 24)?;
+        Ok(Some(Backups::new(
+            dir.into(),
+            keep,
+            std::time::Duration::from_secs(hours * 60 * 60),
+        )))
+    }
+
+    /// Read every `--listen`, 127.0.0.1:7878 if there are none.
+    fn read_listen() -> Result<Vec<Listen>, String> {
+        let listen = Self::read_args("--listen")?;
+        if listen.is_empty() {
+            return Ok(vec![Listen::Tcp(([127, 0, 0, 1], 7878).into())]);
+        }
+        listen.iter().map(|listen| listen.parse()).collect()
+    }
+
+    /// Read the value of the command line option `name`, given as `name VALUE` or `name=VALUE`.
+    /// The first one wins if it is given more than once.
+    pub fn read_arg(name: &str) -> Result<Option<String>, String> {
+        Ok(Self::read_args(name)?.into_iter().next())
+    }
+
+    /// Read the values of every occurrence of the command line option `name`, see `read_arg`.
+    fn read_args(name: &str) -> Result<Vec<String>, String> {
+        let mut values = Vec::new();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == name {
+                match args.next() {
+                    Some(value) => values.push(value),
+                    None => return Err(format!("{} needs a value.", name)),
+                }
+            } else if let Some(value) = arg
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                values.push(value.to_string());
+            }
+        }
+        Ok(values)
+    }
+
+    /// Whether the command line flag `name` is given.
+    pub fn read_flag(name: &str) -> bool {
+        env::args().skip(1).any(|arg| arg == name)
+    }
+
+    /// Parse octal permission bits such as `660` or `0o660`.
+    fn parse_mode(mode: &str) -> Result<u32, String> {
+        let digits = mode.strip_prefix("0o").unwrap_or(mode);
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(mode),
+            _ => Err(format!("Could not parse --socket-mode '{}'.", mode)),
+        }
+    }
+
+    /// Read and parse the environment variable `key`, using `default` if it is not set.
+    fn read_env<T: FromStr>(key: &str, default: T) -> Result<T, String> {
+        match env::var(key) {
+            Ok(value) => match value.trim().parse() {
+                Ok(value) => Ok(value),
+                Err(_) => Err(format!("Could not parse {}='{}'.", key, value)),
+            },
+            Err(_) => Ok(default),
+        }
+    }
+
+    /// Read a secret from the environment variable `key` or from the file at `path`.
+    ///
+    /// Surrounding whitespace is trimmed, and an empty secret is treated as not set.
+    fn read_secret(key: &str, path: &str) -> Result<Option<String>, String> {
+        let secret = match env::var(key) {
+            Ok(secret) => secret,
+            Err(_) => {
+                let path = Path::new(path);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                match fs::read_to_string(path) {
+                    Ok(secret) => secret,
+                    Err(err) => {
+                        return Err(format!("Could not read '{}'.\n{}", path.display(), err))
+                    }
+                }
+            }
+        };
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(secret.to_string()))
+    }
+
+    pub fn admin_key(&self) -> Option<&str> {
+        self.admin_key.as_deref()
+    }
+
+    /// The longest range /admin/data is allowed to delete in one go.
+    pub fn admin_delete_max_span(&self) -> Duration {
+        self.admin_delete_max_span
+    }
+
+    /// How long a request may take before it is answered with a 503.
+    pub fn request_timeout(&self) -> std::time::Duration {
+        self.request_timeout
+    }
+
+    /// The longest range a public endpoint will return data for in one response.
+    pub fn max_query_span(&self) -> Duration {
+        self.max_query_span
+    }
+
+    /// How long in-flight requests and the scraper get to finish after a shutdown signal.
+    pub fn shutdown_grace(&self) -> std::time::Duration {
+        self.shutdown_grace
+    }
+
+    /// How many connections are served at once. Further ones get a 503 and are closed.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// How the database connection pool is sized, from `OCCUPANCY_POOL_SIZE` (default 8),
+    /// `OCCUPANCY_POOL_MIN_IDLE` (default 2) and `OCCUPANCY_POOL_TIMEOUT_MS` (default 500), how
+    /// long a request waits for a connection before it is answered with a 503.
+    pub fn pool(&self) -> &PoolConfig {
+        &self.pool
+    }
+
+    /// The database, from `--database PATH`. `data.db` in the working directory by default.
+    /// It is created along with its tables if it doesn't exist.
+    pub fn database(&self) -> &Path {
+        &self.database
+    }
+
+    /// Where to append the access log, from `--access-log PATH`. There is none by default.
+    pub fn access_log(&self) -> Option<&Path> {
+        self.access_log.as_deref()
+    }
+
+    /// The PEM certificate chain and private key to serve TLS with, from `--tls-cert PATH` and
+    /// `--tls-key PATH`. Plain HTTP is served without them.
+    pub fn tls(&self) -> Option<(&Path, &Path)> {
+        self.tls
+            .as_ref()
+            .map(|(cert, key)| (cert.as_path(), key.as_path()))
+    }
+
+    /// Where to listen, from `--listen HOST:PORT` or `--listen unix:PATH`, which can be given
+    /// several times. 127.0.0.1:7878 by default.
+    pub fn listen(&self) -> &[Listen] {
+        &self.listen
+    }
+
+    /// The permissions of the unix socket, from `--socket-mode` in octal. 660 by default, so
+    /// the socket's group can connect.
+    pub fn socket_mode(&self) -> u32 {
+        self.socket_mode
+    }
+
+    /// How long rows are kept, from `OCCUPANCY_RETENTION_MONTHS` for the readings and
+    /// `OCCUPANCY_PREDICTION_RETENTION_WEEKS` for the predictions. Both default to 0, which
+    /// keeps everything.
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// How the scraper batches the readings it writes, from `OCCUPANCY_WRITE_BATCH_ROWS`
+    /// (default 1, which writes each as it is scraped) readings at once or after
+    /// `OCCUPANCY_WRITE_BATCH_SECS` (default 60), whichever comes first.
+    pub fn write_batching(&self) -> Batching {
+        self.write_batching
+    }
+
+    /// Where and how often the database is backed up, `None` without `--backup-dir`. A backup
+    /// is made every `OCCUPANCY_BACKUP_INTERVAL_HOURS` (default 24, 0 only on demand) and the
+    /// newest `OCCUPANCY_BACKUP_KEEP` (default 7) are kept.
+    pub fn backups(&self) -> Option<&Backups> {
+        self.backups.as_ref()
+    }
+
+    /// When and how the database is maintained. Every week on `OCCUPANCY_MAINTENANCE_DAY`
+    /// (default sun) at `OCCUPANCY_MAINTENANCE_HOUR` (default 4) UK time, vacuuming once
+    /// `OCCUPANCY_VACUUM_FREE_PERCENT` (default 20, 0 never) of the file is free.
+    pub fn maintenance(&self) -> &Maintenance {
+        &self.maintenance
+    }
+
+    /// Whether a database that fails its integrity check at startup is moved aside for an empty
+    /// one, set with `--recover`. Without it the server doesn't start.
+    pub fn recover(&self) -> bool {
+        self.recover
+    }
+}
@@ -1,7 +1,8 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
 use serde::{Deserialize, Serialize};
 
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Daily {
     opening: Option<u16>,
     closing: Option<u16>,
@@ -25,6 +26,15 @@ impl Daily {
         }
     }
 
+    /// A day as stored, see `SqliteDatabase::insert_one_schedule`.
+    pub fn from_parts(open: bool, opening: Option<u16>, closing: Option<u16>) -> Self {
+        Self {
+            opening,
+            closing,
+            open,
+        }
+    }
+
     pub fn open(&self) -> bool {
         self.open
     }
@@ -36,4 +46,66 @@ impl Daily {
     pub fn closing(&self) -> Option<u16> {
         self.closing
     }
+
+    /// Whether the day has no opening hours. Days made with `new_closed` are still marked open,
+    /// so the times are what count.
+    pub fn is_closed(&self) -> bool {
+        !self.open || self.opening.is_none() || self.closing.is_none()
+    }
+
+    /// The opening and closing times on `date`, or `None` when it is closed.
+    ///
+    /// 2400 is midnight at the end of the day, and a closing time that isn't after the opening
+    /// time is past midnight, on the day after.
+    pub fn hours_on(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if self.is_closed() {
+            return None;
+        }
+        // Opening and closing are HHMM
+        let time = |hm: u16| {
+            date.and_time(NaiveTime::MIN)
+                + Duration::minutes((hm / 100) as i64 * 60 + (hm % 100) as i64)
+        };
+        let opening = time(self.opening?);
+        let mut closing = time(self.closing?);
+        if closing <= opening {
+            closing += Duration::days(1);
+        }
+        Some((opening, closing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn hours_are_on_the_day_they_are_for() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 8).unwrap();
+        assert_eq!(Daily::new_open(630, 2230).hours_on(date), Some((at(8, 6, 30), at(8, 22, 30))));
+    }
+
+    #[test]
+    fn closing_at_2400_is_the_midnight_ending_the_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 8).unwrap();
+        assert_eq!(Daily::new_open(700, 2400).hours_on(date), Some((at(8, 7, 0), at(9, 0, 0))));
+    }
+
+    #[test]
+    fn closing_past_midnight_is_on_the_day_after() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 8).unwrap();
+        assert_eq!(Daily::new_open(1800, 130).hours_on(date), Some((at(8, 18, 0), at(9, 1, 30))));
+        assert_eq!(Daily::new_open(0, 0).hours_on(date), Some((at(8, 0, 0), at(9, 0, 0))));
+    }
+
+    #[test]
+    fn a_closed_day_has_no_hours() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 8).unwrap();
+        assert_eq!(Daily::new_closed().hours_on(date), None);
+        assert_eq!(Daily::from_parts(false, Some(600), Some(2200)).hours_on(date), None);
+    }
 }
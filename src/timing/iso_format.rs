@@ -0,0 +1,14 @@
+use chrono::NaiveDateTime;
+use serde::Serializer;
+
+use crate::ISO_FORMAT;
+
+/// Serializes a `NaiveDateTime` with `ISO_FORMAT`, the same format the times are stored in.
+///
+/// Use with `#[serde(serialize_with = "serialize_iso")]`.
+pub fn serialize_iso<S: Serializer>(
+    time: &NaiveDateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&time.format(ISO_FORMAT))
+}
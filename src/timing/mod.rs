@@ -2,3 +2,5 @@ pub mod schedule;
 pub mod uk_datetime_now;
 pub mod daily;
 
+pub mod timezone;
+pub mod iso_format;
@@ -1,9 +1,8 @@
-use chrono::{DateTime, Datelike, Timelike};
-use chrono::Weekday;
+use chrono::{DateTime, Datelike, Days, NaiveDate, Timelike};
 use chrono_tz::Tz;
 
 use serde::{Deserialize, Serialize};
-use super::daily::Daily;
+use super::{daily::Daily, timezone::uk_local_to_timezone};
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,6 +23,11 @@ impl Schedule {
         }
     }
 
+    /// A schedule with the timings of every weekday, Monday first.
+    pub fn from_timings(timings: [Daily; 7]) -> Self {
+        Self { timings, count: 7 }
+    }
+
     pub fn get_timings(&self) -> &[Daily; 7] {
         &self.timings
     }
@@ -35,6 +39,11 @@ impl Schedule {
         Ok(())
     }
 
+    /// Whether the weekday of `date` is a closed day.
+    pub fn is_closed_on(&self, date: NaiveDate) -> bool {
+        self.timings[date.weekday().num_days_from_monday() as usize].is_closed()
+    }
+
     pub fn is_open(&self, timestamp: DateTime<Tz>) -> bool {
         let weekday = timestamp.weekday().number_from_monday() - 1;
         let daily = self.timings[weekday as usize];
@@ -46,9 +55,37 @@ impl Schedule {
         if daily.opening().unwrap() <= hm && hm <= daily.closing().unwrap() {
             return true;
         }
-        return false;
+        false
     }
 
+    /**
+    Converts the opening and closing times into `tz`.
+
+    The offset between UK time and `tz` depends on the date, so each weekday is converted using
+    its date in the week of `date`. Times that don't exist in UK time are left as they are.
+    */
+    pub fn in_timezone(&self, date: NaiveDate, tz: Tz) -> Self {
+        let monday = date
+            .checked_sub_days(Days::new(date.weekday().num_days_from_monday() as u64))
+            .unwrap();
+        let mut schedule = self.clone();
+        for (i, daily) in self.timings.iter().enumerate() {
+            let (Some(opening), Some(closing)) = (daily.opening(), daily.closing()) else {
+                continue;
+            };
+            let day = monday.checked_add_days(Days::new(i as u64)).unwrap();
+            let convert = |hm: u16| {
+                day.and_hms_opt((hm / 100) as u32, (hm % 100) as u32, 0)
+                    .and_then(|time| uk_local_to_timezone(time, tz))
+                    .map(|time| (time.hour() * 100 + time.minute()) as u16)
+                    .unwrap_or(hm)
+            };
+            schedule.timings[i] = Daily::new_open(convert(opening), convert(closing));
+        }
+        schedule
+    }
+
+    #[allow(dead_code)]
     fn convert_hm_to_sec(hm: u16) -> u64 {
         let min_part = hm % 100;
         let mut total: u64 = min_part as u64 * 60;
@@ -56,3 +93,10 @@ impl Schedule {
         total
     }
 }
+
+/// Schedules are the same when their timings are, however many were added one by one.
+impl PartialEq for Schedule {
+    fn eq(&self, other: &Self) -> bool {
+        self.timings == other.timings
+    }
+}
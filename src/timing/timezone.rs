@@ -0,0 +1,281 @@
+use std::str::FromStr;
+
+use chrono::{offset::LocalResult, DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// All times are scraped and stored as UK local time without an offset.
+pub const UK_TIMEZONE: Tz = chrono_tz::Europe::London;
+
+/// Parses an IANA timezone name such as `Europe/London`.
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.parse().ok()
+}
+
+/// Converts a naive UK local time into `tz`.
+///
+/// When the clocks go back the hour happens twice, in which case the earlier one is used.
+/// When the clocks go forward the skipped hour does not exist and `None` is returned.
+pub fn uk_local_to_timezone(time: NaiveDateTime, tz: Tz) -> Option<DateTime<Tz>> {
+    let uk_time = UK_TIMEZONE.from_local_datetime(&time).earliest()?;
+    Some(uk_time.with_timezone(&tz))
+}
+
+/// Parses a time given by a client into naive UK local time, the format it is stored in.
+///
+/// Accepts RFC3339 with an offset such as `2024-03-01T10:00:00Z` or `2024-07-01T10:00:00+01:00`,
+/// which is converted to UK time, and the bare naive format which is taken to already be UK time.
+pub fn parse_uk_local(time: &str) -> Option<NaiveDateTime> {
+    if let Ok(time) = NaiveDateTime::from_str(time) {
+        return Some(time);
+    }
+    let time = DateTime::parse_from_rfc3339(time).ok()?;
+    Some(time.with_timezone(&UK_TIMEZONE).naive_local())
+}
+
+/// The format of dates in HTTP headers such as Last-Modified, which are always in GMT.
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Converts a naive UK local time into UTC.
+///
+/// When the clocks go back the hour happens twice, in which case the later one is used so that
+/// a Last-Modified built from it is never too early. Returns `None` for times in the hour skipped
+/// when the clocks go forward.
+pub fn uk_local_to_utc(time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    let uk_time = UK_TIMEZONE.from_local_datetime(&time).latest()?;
+    Some(uk_time.with_timezone(&Utc))
+}
+
+/// Converts a naive UK local time into a Unix timestamp, given the timestamp of the time before
+/// it in a series in the order they were taken.
+///
+/// When the clocks go back the hour happens twice. The earlier one is used unless it would put
+/// the time at or before `previous`, which means the series has gone round the hour a second time.
+/// Returns `None` for times in the hour skipped when the clocks go forward.
+pub fn uk_local_to_epoch(time: NaiveDateTime, previous: Option<i64>) -> Option<i64> {
+    match UK_TIMEZONE.from_local_datetime(&time) {
+        LocalResult::Single(time) => Some(time.timestamp()),
+        LocalResult::Ambiguous(earlier, later) => {
+            if previous.is_some_and(|previous| earlier.timestamp() <= previous) {
+                Some(later.timestamp())
+            } else {
+                Some(earlier.timestamp())
+            }
+        }
+        LocalResult::None => None,
+    }
+}
+
+/// Converts a naive UK local time into the Unix timestamp it is stored as.
+///
+/// When the clocks go back the hour happens twice, in which case the earlier one is used. A time
+/// in the hour skipped when the clocks go forward is taken to be GMT, as if they hadn't gone
+/// forward yet.
+pub fn uk_local_to_stored(time: NaiveDateTime) -> i64 {
+    uk_local_to_epoch(time, None)
+        .or_else(|| {
+            let later = time.checked_add_signed(Duration::hours(1))?;
+            uk_local_to_epoch(later, None)
+        })
+        .unwrap_or_else(|| time.and_utc().timestamp())
+}
+
+/// Converts a stored Unix timestamp back into naive UK local time. Returns `None` for one too far
+/// from today to be a date.
+pub fn stored_to_uk_local(timestamp: i64) -> Option<NaiveDateTime> {
+    let time = DateTime::from_timestamp(timestamp, 0)?;
+    Some(time.with_timezone(&UK_TIMEZONE).naive_local())
+}
+
+/// Formats a time for an HTTP header, such as `Tue, 15 Oct 2024 09:05:00 GMT`.
+pub fn format_http_date(time: DateTime<Utc>) -> String {
+    time.format(HTTP_DATE_FORMAT).to_string()
+}
+
+/// Parses a date from an HTTP header such as If-Modified-Since.
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = DateTime::parse_from_rfc2822(date).ok()?;
+    Some(date.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn local(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn naive_times_are_taken_as_uk_local() {
+        assert_eq!(
+            parse_uk_local("2024-03-01T10:00:00"),
+            Some(local(3, 1, 10, 0))
+        );
+        assert_eq!(
+            parse_uk_local("2024-07-01T10:00:00"),
+            Some(local(7, 1, 10, 0))
+        );
+    }
+
+    #[test]
+    fn offsets_in_winter_are_converted_to_gmt() {
+        assert_eq!(
+            parse_uk_local("2024-03-01T10:00:00Z"),
+            Some(local(3, 1, 10, 0))
+        );
+        assert_eq!(
+            parse_uk_local("2024-03-01T10:00:00+00:00"),
+            Some(local(3, 1, 10, 0))
+        );
+        assert_eq!(
+            parse_uk_local("2024-03-01T10:00:00+01:00"),
+            Some(local(3, 1, 9, 0))
+        );
+    }
+
+    #[test]
+    fn offsets_in_summer_are_converted_to_bst() {
+        assert_eq!(
+            parse_uk_local("2024-07-01T10:00:00Z"),
+            Some(local(7, 1, 11, 0))
+        );
+        assert_eq!(
+            parse_uk_local("2024-07-01T10:00:00+01:00"),
+            Some(local(7, 1, 10, 0))
+        );
+        assert_eq!(
+            parse_uk_local("2024-07-01T10:00:00-04:00"),
+            Some(local(7, 1, 15, 0))
+        );
+    }
+
+    #[test]
+    fn offsets_when_the_clocks_go_forward_skip_the_hour() {
+        // The clocks go forward at 01:00 GMT on the 31st of March
+        assert_eq!(
+            parse_uk_local("2024-03-31T00:59:00Z"),
+            Some(local(3, 31, 0, 59))
+        );
+        assert_eq!(
+            parse_uk_local("2024-03-31T01:00:00Z"),
+            Some(local(3, 31, 2, 0))
+        );
+    }
+
+    #[test]
+    fn offsets_when_the_clocks_go_back_repeat_the_hour() {
+        // The clocks go back at 01:00 GMT on the 27th of October, so both are 01:30 in the UK
+        assert_eq!(
+            parse_uk_local("2024-10-27T00:30:00Z"),
+            Some(local(10, 27, 1, 30))
+        );
+        assert_eq!(
+            parse_uk_local("2024-10-27T01:30:00Z"),
+            Some(local(10, 27, 1, 30))
+        );
+        assert_eq!(
+            parse_uk_local("2024-10-27T02:30:00Z"),
+            Some(local(10, 27, 2, 30))
+        );
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn uk_times_are_gmt_in_winter_and_an_hour_ahead_in_summer() {
+        assert_eq!(
+            uk_local_to_utc(local(1, 15, 10, 0)),
+            Some(utc(1, 15, 10, 0))
+        );
+        assert_eq!(uk_local_to_utc(local(7, 1, 10, 0)), Some(utc(7, 1, 9, 0)));
+    }
+
+    #[test]
+    fn the_hour_skipped_when_the_clocks_go_forward_has_no_utc_time() {
+        assert_eq!(
+            uk_local_to_utc(local(3, 31, 0, 59)),
+            Some(utc(3, 31, 0, 59))
+        );
+        assert_eq!(uk_local_to_utc(local(3, 31, 1, 30)), None);
+        assert_eq!(uk_local_to_utc(local(3, 31, 2, 0)), Some(utc(3, 31, 1, 0)));
+    }
+
+    #[test]
+    fn the_hour_repeated_when_the_clocks_go_back_is_the_later_one() {
+        assert_eq!(
+            uk_local_to_utc(local(10, 27, 0, 59)),
+            Some(utc(10, 26, 23, 59))
+        );
+        assert_eq!(
+            uk_local_to_utc(local(10, 27, 1, 30)),
+            Some(utc(10, 27, 1, 30))
+        );
+        assert_eq!(
+            uk_local_to_utc(local(10, 27, 2, 0)),
+            Some(utc(10, 27, 2, 0))
+        );
+    }
+
+    #[test]
+    fn epochs_skip_the_hour_when_the_clocks_go_forward() {
+        let epoch = |time| uk_local_to_epoch(time, None);
+        assert_eq!(
+            epoch(local(3, 31, 0, 59)),
+            Some(utc(3, 31, 0, 59).timestamp())
+        );
+        assert_eq!(epoch(local(3, 31, 1, 30)), None);
+        assert_eq!(
+            epoch(local(3, 31, 2, 0)),
+            Some(utc(3, 31, 1, 0).timestamp())
+        );
+    }
+
+    #[test]
+    fn epochs_in_the_repeated_hour_follow_the_series_round_it() {
+        let first = utc(10, 27, 0, 30).timestamp();
+        let second = utc(10, 27, 1, 30).timestamp();
+        // The first time round unless the time before it is already past that
+        assert_eq!(uk_local_to_epoch(local(10, 27, 1, 30), None), Some(first));
+        let before = utc(10, 27, 0, 5).timestamp();
+        assert_eq!(
+            uk_local_to_epoch(local(10, 27, 1, 30), Some(before)),
+            Some(first)
+        );
+        let after = utc(10, 27, 0, 55).timestamp();
+        assert_eq!(
+            uk_local_to_epoch(local(10, 27, 1, 30), Some(after)),
+            Some(second)
+        );
+        assert_eq!(
+            uk_local_to_epoch(local(10, 27, 1, 30), Some(first)),
+            Some(second)
+        );
+    }
+
+    #[test]
+    fn http_dates_are_in_gmt() {
+        let time = uk_local_to_utc(local(7, 1, 10, 5)).unwrap();
+        assert_eq!(format_http_date(time), "Mon, 01 Jul 2024 09:05:00 GMT");
+        assert_eq!(parse_http_date("Mon, 01 Jul 2024 09:05:00 GMT"), Some(time));
+        assert_eq!(
+            parse_http_date("Mon, 01 Jul 2024 10:05:00 +0100"),
+            Some(time)
+        );
+        assert_eq!(parse_http_date("2024-07-01T09:05:00Z"), None);
+    }
+
+    #[test]
+    fn malformed_times_are_none() {
+        assert_eq!(parse_uk_local("2024-03-01"), None);
+        assert_eq!(parse_uk_local("2024-03-01T10:00:00 01:00"), None);
+        assert_eq!(parse_uk_local("yesterday"), None);
+    }
+}